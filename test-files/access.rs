@@ -0,0 +1,164 @@
+use super::*;
+
+/// A mutating operation a caller may be allowed to perform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Create,
+    Update,
+    Delete,
+    Erase,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 4] = [
+        Permission::Create,
+        Permission::Update,
+        Permission::Delete,
+        Permission::Erase,
+    ];
+
+    pub fn for_mutation(mutation: &Mutation) -> Self {
+        match mutation {
+            Mutation::Create(_) => Permission::Create,
+            Mutation::Update { .. } => Permission::Update,
+            Mutation::Delete { .. } => Permission::Delete,
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Permission::Create => "create",
+            Permission::Update => "update",
+            Permission::Delete => "delete",
+            Permission::Erase => "erase",
+        })
+    }
+}
+
+// Who is calling: roles resolved through the policy, plus permissions granted directly
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Caller {
+    pub id: String,
+    pub roles: Vec<String>,
+    pub permissions: Vec<Permission>,
+}
+
+impl Caller {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.push(role.into());
+        self
+    }
+
+    pub fn with_permission(mut self, permission: Permission) -> Self {
+        self.permissions.push(permission);
+        self
+    }
+}
+
+/// Permissions granted to each role; deserializes from `{"roles": {"editor": ["update"]}}`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct AccessPolicy {
+    roles: HashMap<String, Vec<Permission>>,
+}
+
+impl AccessPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn grant(
+        mut self,
+        role: impl Into<String>,
+        permissions: impl IntoIterator<Item = Permission>,
+    ) -> Self {
+        self.roles
+            .entry(role.into())
+            .or_default()
+            .extend(permissions);
+        self
+    }
+
+    pub fn allows(&self, caller: &Caller, permission: Permission) -> bool {
+        caller.permissions.contains(&permission)
+            || caller.roles.iter().any(|role| {
+                self.roles
+                    .get(role)
+                    .is_some_and(|granted| granted.contains(&permission))
+            })
+    }
+}
+
+/// What a `ScopedManager` may do: the mutations it is granted and, optionally, the one
+/// tenant it is pinned to. Reads are always allowed within the tenant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagerScope {
+    /// Names the holder in logs and `Forbidden` errors
+    pub name: String,
+    pub permissions: Vec<Permission>,
+    pub tenant: Option<TenantId>,
+}
+
+impl ManagerScope {
+    /// Every mutation, in the manager's own tenant
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            permissions: Permission::ALL.to_vec(),
+            tenant: None,
+        }
+    }
+
+    /// No mutations at all
+    pub fn read_only(name: impl Into<String>) -> Self {
+        Self {
+            permissions: Vec::new(),
+            ..Self::new(name)
+        }
+    }
+
+    pub fn with_permission(mut self, permission: Permission) -> Self {
+        if !self.permissions.contains(&permission) {
+            self.permissions.push(permission);
+        }
+        self
+    }
+
+    pub fn without(mut self, permission: Permission) -> Self {
+        self.permissions.retain(|granted| *granted != permission);
+        self
+    }
+
+    /// Pin every call to `tenant`
+    pub fn with_tenant(mut self, tenant: impl Into<TenantId>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+/// Run `operation` as `caller`, overriding the manager's default identity
+pub async fn as_caller<F: std::future::Future>(caller: Caller, operation: F) -> F::Output {
+    CALLER.scope(caller, operation).await
+}
+
+/// Identity set by an enclosing `as_caller`, if any
+pub fn current_caller() -> Option<Caller> {
+    CALLER.try_with(Clone::clone).ok()
+}
@@ -0,0 +1,246 @@
+use super::*;
+use std::collections::VecDeque;
+
+// What a user did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActivityKind {
+    Login,
+    /// Any other application action, named by the caller
+    Action {
+        name: String,
+    },
+}
+
+// One thing a user did, when and from where
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Activity {
+    pub user_id: String,
+    #[serde(flatten)]
+    pub kind: ActivityKind,
+    pub timestamp: DateTime<Utc>,
+    /// Where it came from, e.g. "web", "api" or a client name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl Activity {
+    pub fn login(user_id: impl Into<String>) -> Self {
+        Self::new(user_id, ActivityKind::Login)
+    }
+
+    pub fn action(user_id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self::new(user_id, ActivityKind::Action { name: name.into() })
+    }
+
+    fn new(user_id: impl Into<String>, kind: ActivityKind) -> Self {
+        Self {
+            user_id: user_id.into(),
+            kind,
+            timestamp: Utc::now(),
+            source: None,
+        }
+    }
+
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Backdate or replay an activity at a known time
+    pub fn at(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+}
+
+// Engagement of a user set over the last `window_days`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ActivityStatistics {
+    pub window_days: i64,
+    /// Users with any activity inside the window
+    pub active_in_window: usize,
+    /// Users last seen before the window
+    pub dormant: usize,
+    /// Users with no recorded activity
+    pub never_seen: usize,
+}
+
+#[derive(Debug, Default)]
+struct UserActivity {
+    recent: VecDeque<Activity>,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+// Recent activity per user, kept in memory. Only the newest `per_user` activities of each
+// user are kept, but last-seen times survive trimming. Past `max_users` users, those seen
+// longest ago are forgotten and count as never seen.
+#[derive(Debug)]
+pub struct ActivityLog {
+    per_user: usize,
+    max_users: usize,
+    users: std::sync::Mutex<HashMap<(Option<TenantId>, String), UserActivity>>,
+}
+
+impl Default for ActivityLog {
+    fn default() -> Self {
+        Self {
+            per_user: Self::DEFAULT_PER_USER,
+            max_users: Self::DEFAULT_MAX_USERS,
+            users: Default::default(),
+        }
+    }
+}
+
+impl ActivityLog {
+    pub const DEFAULT_PER_USER: usize = 100;
+    pub const DEFAULT_MAX_USERS: usize = 100_000;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `per_user` activities for each user
+    pub fn with_retention(mut self, per_user: usize) -> Self {
+        self.per_user = per_user.max(1);
+        self
+    }
+
+    /// Track at most about `max_users` users, forgetting the longest idle beyond that
+    pub fn with_max_users(mut self, max_users: usize) -> Self {
+        self.max_users = max_users.max(1);
+        self
+    }
+
+    pub fn record(&self, tenant: Option<&TenantId>, activity: Activity) {
+        let mut users = self.users.lock().expect("activity log poisoned");
+        let entry = users
+            .entry((tenant.cloned(), activity.user_id.clone()))
+            .or_default();
+        entry.last_seen = entry.last_seen.max(Some(activity.timestamp));
+        // Kept newest first; replayed activities may arrive out of order
+        let position = entry
+            .recent
+            .partition_point(|existing| existing.timestamp > activity.timestamp);
+        entry.recent.insert(position, activity);
+        entry.recent.truncate(self.per_user);
+        // Evicting only once a tenth over the limit keeps the sort amortized
+        if users.len() > self.max_users + self.max_users / 10 {
+            let mut idle: Vec<_> = users
+                .iter()
+                .map(|(key, entry)| (entry.last_seen, key.clone()))
+                .collect();
+            idle.sort_unstable();
+            let excess = users.len() - self.max_users;
+            for (_, key) in idle.into_iter().take(excess) {
+                users.remove(&key);
+            }
+        }
+    }
+
+    /// Up to `limit` of the user's activities, newest first
+    pub fn recent(&self, tenant: Option<&TenantId>, user_id: &str, limit: usize) -> Vec<Activity> {
+        let users = self.users.lock().expect("activity log poisoned");
+        users
+            .get(&(tenant.cloned(), user_id.to_string()))
+            .map(|entry| entry.recent.iter().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn last_seen(&self, tenant: Option<&TenantId>, user_id: &str) -> Option<DateTime<Utc>> {
+        let users = self.users.lock().expect("activity log poisoned");
+        users
+            .get(&(tenant.cloned(), user_id.to_string()))
+            .and_then(|entry| entry.last_seen)
+    }
+
+    /// Forget a user's activity, e.g. when erasing them
+    pub fn remove(&self, tenant: Option<&TenantId>, user_id: &str) -> bool {
+        let mut users = self.users.lock().expect("activity log poisoned");
+        users
+            .remove(&(tenant.cloned(), user_id.to_string()))
+            .is_some()
+    }
+
+    /// How many of `users` were active in the `window_days` before `now`; a window reaching
+    /// past the earliest representable time covers all activity
+    pub fn statistics_at<'a>(
+        &self,
+        tenant: Option<&TenantId>,
+        users: impl IntoIterator<Item = &'a User>,
+        window_days: i64,
+        now: DateTime<Utc>,
+    ) -> ActivityStatistics {
+        let since = chrono::Duration::try_days(window_days)
+            .and_then(|window| now.checked_sub_signed(window))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+        let mut statistics = ActivityStatistics {
+            window_days,
+            active_in_window: 0,
+            dormant: 0,
+            never_seen: 0,
+        };
+        for user in users {
+            match self.last_seen(tenant, &user.id) {
+                Some(seen) if seen >= since => statistics.active_in_window += 1,
+                Some(_) => statistics.dormant += 1,
+                None => statistics.never_seen += 1,
+            }
+        }
+        statistics
+    }
+}
+
+#[cfg(feature = "client")]
+impl UserManager {
+    fn require_activity_log(&self) -> Result<&Arc<ActivityLog>> {
+        self.activity_log().ok_or_else(|| {
+            UserError::InvalidConfig {
+                field: "activity_log".to_string(),
+                message: "no activity log configured".to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Record an activity against this manager's tenant
+    pub fn record_activity(&self, activity: Activity) -> Result<()> {
+        let log = self.require_activity_log()?;
+        metrics::counter!(
+            metric_names::ACTIVITIES,
+            "kind" => match &activity.kind {
+                ActivityKind::Login => "login",
+                ActivityKind::Action { .. } => "action",
+            }
+        )
+        .increment(1);
+        log.record(self.tenant(), activity);
+        Ok(())
+    }
+
+    /// Up to `limit` of a user's activities, newest first
+    pub fn recent_activity(&self, user_id: &str, limit: usize) -> Result<Vec<Activity>> {
+        Ok(self
+            .require_activity_log()?
+            .recent(self.tenant(), user_id, limit))
+    }
+
+    pub fn last_seen(&self, user_id: &str) -> Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .require_activity_log()?
+            .last_seen(self.tenant(), user_id))
+    }
+
+    /// Statistics over `users` including how many were active in the last `window_days`
+    pub fn statistics_with_activity(
+        &self,
+        users: &[User],
+        window_days: i64,
+    ) -> Result<UserStatistics> {
+        let now = Utc::now();
+        let activity =
+            self.require_activity_log()?
+                .statistics_at(self.tenant(), users, window_days, now);
+        Ok(UserStatistics::from_users_at(users, now).with_activity(activity))
+    }
+}
@@ -0,0 +1,132 @@
+use super::*;
+use lapin::options::{BasicPublishOptions, ConfirmSelectOptions};
+use lapin::publisher_confirm::Confirmation;
+use lapin::BasicProperties;
+
+// Publishes user mutation events to an AMQP exchange with publisher confirms. Messages
+// are persistent and mandatory, so an event no queue is bound for fails instead of
+// vanishing; nacked and unroutable publishes are retried under the retry policy.
+#[derive(Clone)]
+pub struct AmqpEventSink {
+    // Held so the connection stays open as long as the channel is in use
+    _connection: Arc<lapin::Connection>,
+    channel: lapin::Channel,
+    exchange: String,
+    routing_key: SubjectTemplate,
+    retry_policy: RetryPolicy,
+}
+
+impl fmt::Debug for AmqpEventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AmqpEventSink")
+            .field("exchange", &self.exchange)
+            .field("routing_key", &self.routing_key)
+            .field("retry_policy", &self.retry_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AmqpEventSink {
+    /// Connect to the broker at `uri` and publish to the existing `exchange`; events are
+    /// routed with `users.{tenant}.{event}` until changed
+    pub async fn connect(uri: &str, exchange: impl Into<String>) -> Result<Self> {
+        let connection = lapin::Connection::connect(uri, lapin::ConnectionProperties::default())
+            .await
+            .context("Failed to connect to AMQP broker")?;
+        let channel = connection
+            .create_channel()
+            .await
+            .context("Failed to open AMQP channel")?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .context("AMQP broker refused publisher confirms")?;
+        Ok(Self {
+            _connection: Arc::new(connection),
+            channel,
+            exchange: exchange.into(),
+            routing_key: SubjectTemplate::default(),
+            retry_policy: RetryPolicy::new(3),
+        })
+    }
+
+    pub fn with_routing_key(mut self, routing_key: SubjectTemplate) -> Self {
+        self.routing_key = routing_key;
+        self
+    }
+
+    /// Retries for publishes the broker nacks or cannot route
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn exchange(&self) -> &str {
+        &self.exchange
+    }
+
+    async fn publish(
+        &self,
+        routing_key: &str,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<()> {
+        let options = BasicPublishOptions {
+            mandatory: true,
+            ..Default::default()
+        };
+        let confirmation = self
+            .channel
+            .basic_publish(&self.exchange, routing_key, options, payload, properties)
+            .await?
+            .await?;
+        match confirmation {
+            Confirmation::Ack(None) => Ok(()),
+            Confirmation::Ack(Some(_)) => anyhow::bail!("No queue is bound for {}", routing_key),
+            Confirmation::Nack(_) => anyhow::bail!("Broker nacked the event"),
+            Confirmation::NotRequested => anyhow::bail!("Publisher confirms are not enabled"),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for AmqpEventSink {
+    fn name(&self) -> &'static str {
+        "amqp"
+    }
+
+    /// Publish one event and wait for the broker's confirm, retrying under the policy
+    async fn send(&self, event: &UserEvent) -> Result<()> {
+        let routing_key = self.routing_key.render(event);
+        let payload = event.to_stored()?.to_string();
+        let properties = BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_delivery_mode(2)
+            .with_message_id(uuid::Uuid::new_v4().to_string().into())
+            .with_type(event.kind().into());
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self
+                .publish(&routing_key, payload.as_bytes(), properties.clone())
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(e) if attempts > self.retry_policy.max_retries => {
+                    return Err(e).with_context(|| {
+                        format!(
+                            "Failed to publish event to {}/{}",
+                            self.exchange, routing_key
+                        )
+                    });
+                }
+                Err(e) => {
+                    metrics::counter!(metric_names::RETRIES, "reason" => "amqp").increment(1);
+                    tracing::debug!(attempts, error = %e, "Retrying AMQP publish");
+                    tokio::time::sleep(self.retry_policy.backoff(attempts - 1)).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use super::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Granularity timestamps are coarsened to in analytics exports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum TimeBucket {
+    Hour,
+    #[default]
+    Day,
+    /// Weeks start on Monday
+    Week,
+    Month,
+}
+
+impl TimeBucket {
+    /// Start of the bucket containing `at`
+    pub fn truncate(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        use chrono::{Datelike, Timelike};
+
+        let day = at.date_naive();
+        let start = match self {
+            TimeBucket::Hour => day.and_hms_opt(at.hour(), 0, 0),
+            TimeBucket::Day => day.and_hms_opt(0, 0, 0),
+            TimeBucket::Week => (day
+                - chrono::Duration::days(i64::from(day.weekday().num_days_from_monday())))
+            .and_hms_opt(0, 0, 0),
+            TimeBucket::Month => day.with_day(1).and_then(|day| day.and_hms_opt(0, 0, 0)),
+        };
+        start.expect("bucket starts are valid times").and_utc()
+    }
+}
+
+// A sampled user with the personal data stripped
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AnonymizedUser {
+    /// Keyed hash of the id; stable across exports under the same key, so rows can be
+    /// joined between exports but not traced back to a user without the key
+    pub pseudonym: String,
+    pub status: UserStatus,
+    /// Start of the bucket the account was created in
+    pub created: DateTime<Utc>,
+    pub two_factor_enrolled: bool,
+    /// Only the allowlisted keys
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+/// Picks a deterministic sample of users and anonymizes them for product analytics. A user
+/// is in the sample when the keyed hash of their id falls below `rate`, so the same key
+/// always picks the same users, and raising the rate only adds users. Names, emails, ids,
+/// consents and two-factor secrets never leave; metadata only does for allowlisted keys.
+#[derive(Debug, Clone)]
+pub struct AnalyticsSampler {
+    key: Secret<Vec<u8>>,
+    rate: f64,
+    bucket: TimeBucket,
+    metadata_keys: BTreeSet<String>,
+}
+
+impl AnalyticsSampler {
+    /// Where the CLI reads the sampling key from
+    pub const KEY_ENV: &'static str = "USERS_ANALYTICS_KEY";
+
+    /// Sample `rate` of users (clamped to 0..=1), pseudonymized under `key`
+    pub fn new(key: impl Into<Vec<u8>>, rate: f64) -> Self {
+        Self {
+            key: Secret::new(key.into()),
+            rate: if rate.is_nan() {
+                0.0
+            } else {
+                rate.clamp(0.0, 1.0)
+            },
+            bucket: TimeBucket::default(),
+            metadata_keys: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_bucket(mut self, bucket: TimeBucket) -> Self {
+        self.bucket = bucket;
+        self
+    }
+
+    /// Keep metadata `key`; only add keys known to hold no personal data
+    pub fn with_metadata_key(mut self, key: impl Into<String>) -> Self {
+        self.metadata_keys.insert(key.into());
+        self
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    fn digest(&self, user_id: &str) -> [u8; 32] {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.expose())
+            .expect("HMAC accepts keys of any length");
+        mac.update(user_id.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    /// Whether the user falls in the sample
+    pub fn includes(&self, user_id: &str) -> bool {
+        let digest = self.digest(user_id);
+        let position = u64::from_be_bytes(digest[..8].try_into().expect("digest has 8 bytes"));
+        self.rate >= 1.0 || (position as f64) < self.rate * u64::MAX as f64
+    }
+
+    pub fn pseudonym(&self, user_id: &str) -> String {
+        encode_hex(&self.digest(user_id)[..16])
+    }
+
+    pub fn anonymize(&self, user: &User) -> AnonymizedUser {
+        AnonymizedUser {
+            pseudonym: self.pseudonym(&user.id),
+            status: user.status,
+            created: self.bucket.truncate(user.created_at),
+            two_factor_enrolled: user.two_factor.is_some(),
+            metadata: user
+                .metadata
+                .iter()
+                .filter(|(key, _)| self.metadata_keys.contains(key.as_str()))
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// The sampled users, anonymized and ordered by pseudonym so input order leaks nothing
+    pub fn sample<'a>(&self, users: impl IntoIterator<Item = &'a User>) -> Vec<AnonymizedUser> {
+        let mut sampled: Vec<AnonymizedUser> = users
+            .into_iter()
+            .filter(|user| self.includes(&user.id))
+            .map(|user| self.anonymize(user))
+            .collect();
+        sampled.sort_by(|a, b| a.pseudonym.cmp(&b.pseudonym));
+        sampled
+    }
+}
+
+#[cfg(feature = "client")]
+impl UserManager {
+    /// Export an anonymized sample of users as canonical JSON, for product analytics
+    pub fn export_users_sampled(users: &[User], sampler: &AnalyticsSampler) -> Result<String> {
+        let value = serde_json::to_value(sampler.sample(users))
+            .context("Failed to serialize users to JSON")?;
+        serde_json::to_string_pretty(&canonical_value(value))
+            .context("Failed to serialize users to JSON")
+    }
+}
@@ -0,0 +1,146 @@
+use super::*;
+use proptest::prelude::*;
+
+/// Emails that pass `User` validation
+pub fn email() -> impl Strategy<Value = String> {
+    (
+        "[a-z][a-z0-9._+-]{0,15}",
+        "[a-z][a-z0-9-]{0,10}",
+        "(com|org|net|io|dev)",
+    )
+        .prop_map(|(local, domain, tld)| format!("{}@{}.{}", local, domain, tld))
+}
+
+/// Non-empty display names
+pub fn name() -> impl Strategy<Value = String> {
+    "\\PC{1,30}"
+}
+
+/// Timestamps between 2000 and 2100, at whole-microsecond precision
+pub fn timestamp() -> impl Strategy<Value = DateTime<Utc>> {
+    (946_684_800_000_000i64..4_102_444_800_000_000i64)
+        .prop_map(|micros| DateTime::from_timestamp_micros(micros).expect("in range"))
+}
+
+/// Scalar JSON values of the kinds stored in metadata
+pub fn metadata_value() -> impl Strategy<Value = serde_json::Value> {
+    prop_oneof![
+        Just(serde_json::Value::Null),
+        any::<bool>().prop_map(serde_json::Value::from),
+        any::<i64>().prop_map(serde_json::Value::from),
+        "\\PC{0,20}".prop_map(serde_json::Value::from),
+    ]
+}
+
+pub fn metadata() -> impl Strategy<Value = HashMap<MetadataKey, serde_json::Value>> {
+    prop::collection::hash_map(
+        "[a-z_]{1,12}".prop_map(MetadataKey::from),
+        metadata_value(),
+        0..4,
+    )
+}
+
+/// Consent records, withdrawn or still in force
+pub fn consent() -> impl Strategy<Value = Consent> {
+    (
+        "[a-z_]{1,12}",
+        "v[0-9]{1,2}",
+        timestamp(),
+        prop::option::of(timestamp()),
+    )
+        .prop_map(
+            |(purpose, terms_version, granted_at, withdrawn_at)| Consent {
+                purpose,
+                granted_at,
+                withdrawn_at,
+                terms_version,
+            },
+        )
+}
+
+/// Partial updates over mutable fields, valid for `User::apply_updates`
+pub fn user_updates() -> impl Strategy<Value = HashMap<String, serde_json::Value>> {
+    (
+        prop::option::of(name()),
+        prop::option::of(email()),
+        prop::option::of(any::<UserStatus>()),
+        prop::option::of(metadata()),
+    )
+        .prop_map(|(name, email, status, metadata)| {
+            let mut updates = HashMap::new();
+            if let Some(name) = name {
+                updates.insert("name".to_string(), serde_json::json!(name));
+            }
+            if let Some(email) = email {
+                updates.insert("email".to_string(), serde_json::json!(email));
+            }
+            if let Some(status) = status {
+                updates.insert("status".to_string(), serde_json::json!(status));
+            }
+            if let Some(metadata) = metadata {
+                updates.insert("metadata".to_string(), serde_json::json!(metadata));
+            }
+            updates
+        })
+}
+
+impl Arbitrary for UserStatus {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(UserStatus::Active),
+            Just(UserStatus::Inactive),
+            Just(UserStatus::Pending),
+            Just(UserStatus::Suspended),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for User {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            "[a-zA-Z0-9-]{1,24}",
+            name(),
+            email(),
+            any::<UserStatus>(),
+            timestamp(),
+            metadata(),
+            prop::collection::vec(consent(), 0..3),
+        )
+            .prop_map(
+                |(id, name, email, status, created_at, metadata, consents)| User {
+                    id,
+                    name,
+                    email,
+                    status,
+                    created_at,
+                    metadata,
+                    consents,
+                    two_factor: None,
+                },
+            )
+            .boxed()
+    }
+}
+
+impl<T: Arbitrary + 'static> Arbitrary for ApiResponse<T> {
+    type Parameters = T::Parameters;
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(params: T::Parameters) -> Self::Strategy {
+        let success = any_with::<T>(params).prop_map(ApiResponse::success);
+        let failure = "\\PC{1,40}".prop_map(ApiResponse::error);
+        (prop_oneof![success, failure], timestamp())
+            .prop_map(|(mut response, timestamp)| {
+                response.timestamp = timestamp;
+                response
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,51 @@
+use super::*;
+use criterion::{BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+fn sample_users(count: usize) -> Vec<User> {
+    let statuses = [
+        UserStatus::Active,
+        UserStatus::Inactive,
+        UserStatus::Pending,
+        UserStatus::Suspended,
+    ];
+    let now = Utc::now();
+    (0..count)
+        .map(|i| User {
+            id: i.to_string(),
+            name: format!("User {}", i),
+            email: format!("user{}@example.com", i),
+            status: statuses[i % statuses.len()],
+            created_at: now - chrono::Duration::days((i % 1000) as i64),
+            metadata: HashMap::new(),
+            consents: Vec::new(),
+            two_factor: None,
+        })
+        .collect()
+}
+
+fn bench_user_statistics(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_user_statistics");
+    for size in [1_000, 100_000] {
+        let users = sample_users(size);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &users, |b, users| {
+            b.iter(|| UserManager::get_user_statistics(black_box(users)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_filter_by_status(c: &mut Criterion) {
+    let users = sample_users(100_000);
+    c.bench_function("filter_users_by_status/100000", |b| {
+        b.iter(|| UserManager::filter_users_by_status(black_box(&users), UserStatus::Active))
+    });
+}
+
+criterion::criterion_group!(user_benches, bench_user_statistics, bench_filter_by_status);
+
+pub fn run() {
+    user_benches();
+    Criterion::default().configure_from_args().final_summary();
+}
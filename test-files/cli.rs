@@ -0,0 +1,405 @@
+use super::*;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::{Read, Write};
+use std::process::ExitCode;
+
+const PAGE_SIZE: usize = 500;
+
+/// Administer users through the user API
+#[derive(Debug, Parser)]
+#[command(name = "users", version, about)]
+pub struct Cli {
+    /// TOML or YAML config file; USERS_API_* environment variables are read otherwise
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Show one user
+    Get { id: String },
+    /// Create a user
+    Create {
+        id: String,
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        email: String,
+        #[arg(long, default_value = "pending")]
+        status: UserStatus,
+        /// Metadata entry; VALUE is parsed as JSON, else kept as a string
+        #[arg(long = "meta", value_name = "KEY=VALUE", value_parser = parse_field)]
+        metadata: Vec<(String, serde_json::Value)>,
+    },
+    /// Change fields of a user
+    Update {
+        id: String,
+        /// Field to set; VALUE is parsed as JSON, else kept as a string
+        #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_field, required_unless_present = "metadata")]
+        fields: Vec<(String, serde_json::Value)>,
+        /// Metadata entry to set, keeping the others
+        #[arg(long = "meta", value_name = "KEY=VALUE", value_parser = parse_field)]
+        metadata: Vec<(String, serde_json::Value)>,
+    },
+    /// Delete a user
+    Delete { id: String },
+    /// List a page of users; `--status` and `--search` apply client-side
+    List {
+        /// Filter expression applied by the backend, e.g. `status eq "active"`
+        #[arg(long)]
+        filter: Option<Filter>,
+        #[arg(long)]
+        status: Option<UserStatus>,
+        /// Case-insensitive substring of the name or email
+        #[arg(long)]
+        search: Option<String>,
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+    /// Create users from NDJSON, one per line; `-` reads stdin
+    Import {
+        path: PathBuf,
+        /// Parse and validate without creating anything
+        #[arg(long)]
+        dry_run: bool,
+        /// JSON import pipeline (`{"stages": [...]}`) cleaning each record first
+        #[arg(long)]
+        pipeline: Option<PathBuf>,
+    },
+    /// Write every user as canonical JSON
+    Export {
+        /// File to write instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Wrap each user in a `{user_version, payload}` envelope for later migration
+        #[arg(long)]
+        versioned: bool,
+        /// Write an anonymized sample of this fraction of users (0 to 1) for analytics,
+        /// keyed by the `USERS_ANALYTICS_KEY` environment variable
+        #[arg(long, value_name = "RATE", conflicts_with = "versioned")]
+        sample: Option<f64>,
+        /// Granularity of timestamps in a `--sample` export
+        #[arg(long, value_enum, default_value_t = TimeBucket::Day, requires = "sample")]
+        bucket: TimeBucket,
+    },
+    /// Statistics over every user
+    Stats,
+    /// Data-quality issues across every user; fails when any reach `--fail-on`
+    Quality {
+        /// Days a pending or inactive account may sit before it is reported as stale
+        #[arg(long, default_value_t = QualityAnalyzer::DEFAULT_STALE_DAYS)]
+        stale_days: i64,
+        /// Hide issues below this severity
+        #[arg(long, default_value = "info")]
+        min_severity: Severity,
+        #[arg(long, default_value = "error")]
+        fail_on: Severity,
+        /// Also score each user from 0 to 100 and list those scoring below this
+        #[arg(long, value_name = "SCORE", value_parser = clap::value_parser!(u8).range(0..=100))]
+        below: Option<u8>,
+        /// Metadata key every user should have filled; counts toward `--below` scores
+        #[arg(long = "expect-metadata", value_name = "KEY", requires = "below")]
+        expect_metadata: Vec<String>,
+    },
+    /// Cache contents, health and configuration of the manager
+    Cache,
+    /// Interactive dashboard of cache stats, recent operations and users
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+fn parse_field(input: &str) -> std::result::Result<(String, serde_json::Value), String> {
+    let (key, value) = input
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got {:?}", input))?;
+    if key.is_empty() {
+        return Err("empty key".to_string());
+    }
+    let value = serde_json::from_str(value)
+        .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+    Ok((key.to_string(), value))
+}
+
+/// Parse arguments, build the manager from config or environment, and run
+pub async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let manager = match &cli.config {
+        Some(path) => ManagerConfig::load(path).and_then(UserManager::from_config),
+        None => UserManager::from_env(),
+    };
+    let result = match manager {
+        Ok(manager) => run(&cli, &manager, &mut std::io::stdout().lock()).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", redact_error(&e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Execute a parsed command against `manager`, writing results to `out`
+pub async fn run(cli: &Cli, manager: &UserManager, out: &mut dyn Write) -> Result<()> {
+    run_with_lookup(cli, manager, out, &|name| std::env::var(name).ok()).await
+}
+
+/// `run`, reading environment variables through `lookup`
+pub(crate) async fn run_with_lookup(
+    cli: &Cli,
+    manager: &UserManager,
+    out: &mut dyn Write,
+    lookup: &(dyn Fn(&str) -> Option<String> + Sync),
+) -> Result<()> {
+    match &cli.command {
+        Command::Get { id } => match manager.fetch_user(id).await? {
+            Some(user) => write_users(out, cli.format, &[user]),
+            None => Err(UserError::NotFound { id: id.clone() }.into()),
+        },
+        Command::Create {
+            id,
+            name,
+            email,
+            status,
+            metadata,
+        } => {
+            let mut user = User::new(id.clone(), name.clone(), email.clone())?;
+            user.status = *status;
+            for (key, value) in metadata {
+                user.add_metadata(key.as_str(), value.clone());
+            }
+            let created = manager.create_user(&user).await?;
+            write_users(out, cli.format, &[created])
+        }
+        Command::Update {
+            id,
+            fields,
+            metadata,
+        } => {
+            let mut update = UserUpdate::from_fields(fields.iter().cloned())?;
+            for (key, value) in metadata {
+                update = update.set_metadata(key.as_str(), value.clone());
+            }
+            if !manager.update_user(id, update).await? {
+                return Err(UserError::NotFound { id: id.clone() }.into());
+            }
+            writeln!(out, "Updated {}", id)?;
+            Ok(())
+        }
+        Command::Delete { id } => {
+            if !manager.delete_user(id).await? {
+                return Err(UserError::NotFound { id: id.clone() }.into());
+            }
+            writeln!(out, "Deleted {}", id)?;
+            Ok(())
+        }
+        Command::List {
+            filter,
+            status,
+            search,
+            offset,
+            limit,
+        } => {
+            let search = search.as_deref().map(str::to_lowercase);
+            let page = match filter {
+                Some(filter) => manager.list_users_filtered(filter, *offset, *limit).await?,
+                None => manager.list_users(*offset, *limit).await?,
+            };
+            let users: Vec<User> = page
+                .into_iter()
+                .filter(|user| status.is_none_or(|status| user.status == status))
+                .filter(|user| {
+                    search.as_deref().is_none_or(|search| {
+                        user.name.to_lowercase().contains(search)
+                            || user.email.to_lowercase().contains(search)
+                    })
+                })
+                .collect();
+            write_users(out, cli.format, &users)
+        }
+        Command::Import {
+            path,
+            dry_run,
+            pipeline,
+        } => {
+            let mut input = String::new();
+            if path.as_os_str() == "-" {
+                std::io::stdin().read_to_string(&mut input)?;
+            } else {
+                input = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+            }
+            let pipeline = match pipeline {
+                Some(path) => ImportPipeline::from_json(
+                    &std::fs::read_to_string(path)
+                        .with_context(|| format!("Failed to read {}", path.display()))?,
+                )?,
+                None => ImportPipeline::default(),
+            };
+            import(manager, &input, &pipeline, *dry_run, out).await
+        }
+        Command::Export {
+            output,
+            versioned,
+            sample,
+            bucket,
+        } => {
+            let users = all_users(manager).await?;
+            let json = if let Some(rate) = sample {
+                let key = lookup(AnalyticsSampler::KEY_ENV)
+                    .filter(|key| !key.is_empty())
+                    .ok_or_else(|| UserError::InvalidConfig {
+                        field: AnalyticsSampler::KEY_ENV.to_string(),
+                        message: "a key is needed to pseudonymize a sample".to_string(),
+                    })?;
+                let sampler = AnalyticsSampler::new(key, *rate).with_bucket(*bucket);
+                UserManager::export_users_sampled(&users, &sampler)?
+            } else if *versioned {
+                UserManager::export_users_versioned(&users)?
+            } else {
+                UserManager::export_users_json(&users)?
+            };
+            match output {
+                Some(path) => std::fs::write(path, json + "\n")
+                    .with_context(|| format!("Failed to write {}", path.display()))?,
+                None => writeln!(out, "{}", json)?,
+            }
+            Ok(())
+        }
+        Command::Stats => {
+            let stats = UserManager::get_user_statistics(&all_users(manager).await?);
+            match cli.format {
+                OutputFormat::Text => writeln!(out, "{}", stats)?,
+                OutputFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&stats)?)?,
+            }
+            Ok(())
+        }
+        Command::Quality {
+            stale_days,
+            min_severity,
+            fail_on,
+            below,
+            expect_metadata,
+        } => {
+            let users = all_users(manager).await?;
+            let mut report = QualityAnalyzer::new()
+                .with_stale_after(chrono::Duration::days(*stale_days))
+                .analyze(&users);
+            report
+                .issues
+                .retain(|issue| issue.severity >= *min_severity);
+            if let Some(below) = below {
+                let scorer = QualityScorer::new().with_expected_metadata(expect_metadata.clone());
+                report.scores = manager.quality_scores(&users, &scorer);
+                report.scores.retain(|score| score.score < *below);
+            }
+            match cli.format {
+                OutputFormat::Text => writeln!(out, "{}", report)?,
+                OutputFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&report)?)?,
+            }
+            if report.worst().is_some_and(|worst| worst >= *fail_on) {
+                return Err(anyhow::anyhow!(
+                    "Data-quality issues at or above {} severity",
+                    fail_on
+                ));
+            }
+            Ok(())
+        }
+        Command::Cache => {
+            manager.health_check().await;
+            let snapshot = manager.debug_snapshot().await;
+            writeln!(out, "{}", serde_json::to_string_pretty(&snapshot)?)?;
+            Ok(())
+        }
+        #[cfg(feature = "tui")]
+        Command::Tui => tui::run(manager).await,
+    }
+}
+
+async fn import(
+    manager: &UserManager,
+    input: &str,
+    pipeline: &ImportPipeline,
+    dry_run: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let report = UserManager::import_users_ndjson_with(input, pipeline);
+    let mut failed = report.errors.len();
+    for error in &report.errors {
+        writeln!(out, "line {}: {}", error.line, error.message)?;
+    }
+    for skipped in &report.skipped {
+        writeln!(out, "line {}: skipped, {}", skipped.line, skipped.message)?;
+    }
+    let mut created = 0;
+    for user in &report.users {
+        if dry_run {
+            continue;
+        }
+        match manager.create_user(user).await {
+            Ok(_) => created += 1,
+            Err(e) => {
+                failed += 1;
+                writeln!(out, "{}: {}", user.id, redact_error(&e))?;
+            }
+        }
+    }
+    if dry_run {
+        writeln!(
+            out,
+            "{} users valid, {} lines failed",
+            report.users.len(),
+            failed
+        )?;
+    } else {
+        writeln!(out, "Imported {} users, {} failed", created, failed)?;
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!("{} users could not be imported", failed));
+    }
+    Ok(())
+}
+
+async fn all_users(manager: &UserManager) -> Result<Vec<User>> {
+    let mut users = Vec::new();
+    loop {
+        let page = manager.list_users(users.len(), PAGE_SIZE).await?;
+        let done = page.len() < PAGE_SIZE;
+        users.extend(page);
+        if done {
+            return Ok(users);
+        }
+    }
+}
+
+fn write_users(out: &mut dyn Write, format: OutputFormat, users: &[User]) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            for user in users {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    user.id, user.status, user.name, user.email
+                )?;
+            }
+        }
+        OutputFormat::Json => {
+            let value = canonical_value(serde_json::to_value(users)?);
+            writeln!(out, "{}", serde_json::to_string_pretty(&value)?)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,8510 @@
+use super::*;
+
+/// Environment variables read by `UserManager::from_env`; empty values count as unset
+pub mod env_vars {
+    /// Required base URL of the user API
+    pub const BASE_URL: &str = "USERS_API_URL";
+    /// Read replica URL; reads go there, writes to the base URL
+    pub const REPLICA_URL: &str = "USERS_API_REPLICA_URL";
+    /// Bearer token sent on every request
+    pub const TOKEN: &str = "USERS_API_TOKEN";
+    /// Whole-request timeout in seconds
+    pub const TIMEOUT_SECS: &str = "USERS_API_TIMEOUT_SECS";
+    pub const CONNECT_TIMEOUT_SECS: &str = "USERS_API_CONNECT_TIMEOUT_SECS";
+    /// Retries for idempotent requests
+    pub const MAX_RETRIES: &str = "USERS_API_MAX_RETRIES";
+    pub const PROXY: &str = "USERS_API_PROXY";
+    pub const USER_AGENT: &str = "USERS_API_USER_AGENT";
+    /// Most users kept in the cache
+    pub const CACHE_CAPACITY: &str = "USERS_CACHE_CAPACITY";
+    /// `false` disables the cache entirely
+    pub const CACHE_ENABLED: &str = "USERS_CACHE_ENABLED";
+    /// Most bytes the cache may hold, by estimate
+    pub const CACHE_BUDGET_BYTES: &str = "USERS_CACHE_BUDGET_BYTES";
+}
+
+/// Metric names emitted through the `metrics` facade; install any recorder to collect them
+pub mod metric_names {
+    /// Counter labelled by `operation` and `status`
+    pub const REQUESTS: &str = "users_requests_total";
+    /// Histogram in seconds, labelled by `operation`
+    pub const REQUEST_DURATION: &str = "users_request_duration_seconds";
+    /// Counter labelled by `operation` and `kind`
+    pub const ERRORS: &str = "users_errors_total";
+    /// Counter labelled by `reason`
+    pub const RETRIES: &str = "users_retries_total";
+    /// Counter labelled by `operation`, for calls over their slow threshold
+    pub const SLOW_OPERATIONS: &str = "users_slow_operations_total";
+    pub const CACHE_HITS: &str = "users_cache_hits_total";
+    pub const CACHE_MISSES: &str = "users_cache_misses_total";
+    /// Users left uncached because they would exceed the cache's memory budget
+    pub const CACHE_REJECTED: &str = "users_cache_rejected_total";
+    /// Counter labelled by `tenant` and `reason` (`rate` or `quota`)
+    pub const RATE_LIMITED: &str = "users_rate_limited_total";
+    /// Counter labelled by `event` and `outcome` (`delivered` or `dead_lettered`)
+    pub const WEBHOOK_DELIVERIES: &str = "users_webhook_deliveries_total";
+    /// Histogram in seconds from first attempt to outcome, labelled by `outcome`
+    pub const WEBHOOK_DURATION: &str = "users_webhook_delivery_duration_seconds";
+    /// Counter labelled by `sink`, `event` and `outcome` (`sent` or `failed`)
+    pub const SINK_EVENTS: &str = "users_event_sink_events_total";
+    /// Counter labelled by `job` and `outcome` (`ok` or `failed`)
+    pub const SCHEDULED_JOBS: &str = "users_scheduled_jobs_total";
+    /// Counter labelled by `kind`
+    pub const ACTIVITIES: &str = "users_activities_total";
+    pub const SESSIONS_REVOKED: &str = "users_sessions_revoked_total";
+    /// Counter labelled by `sender` and `outcome` (`sent` or `failed`)
+    pub const NOTIFICATIONS: &str = "users_notifications_total";
+    /// Counter labelled by `outcome` (`requested`, `completed` or `rejected`)
+    pub const PASSWORD_RESETS: &str = "users_password_resets_total";
+    /// Counter labelled by `kind` and `fixed`
+    pub const RECONCILE_DISCREPANCIES: &str = "users_reconcile_discrepancies_total";
+    /// Histogram of distinct ids per coalesced `POST /users:batchGet`
+    pub const BATCH_GET_SIZE: &str = "users_batch_get_size";
+    /// Counter labelled by `operation` and `kind`
+    pub const DUAL_READ_MISMATCHES: &str = "users_dual_read_mismatches_total";
+    /// Counter of dual-read comparisons dropped because too many were running
+    pub const DUAL_READ_SKIPPED: &str = "users_dual_read_skipped_total";
+
+    /// Register units and descriptions with the installed recorder
+    pub fn describe() {
+        metrics::describe_counter!(REQUESTS, "Manager operations by outcome");
+        metrics::describe_histogram!(
+            REQUEST_DURATION,
+            metrics::Unit::Seconds,
+            "Manager operation latency"
+        );
+        metrics::describe_counter!(ERRORS, "Failed manager operations by error kind");
+        metrics::describe_counter!(RETRIES, "Operations retried against another backend");
+        metrics::describe_counter!(SLOW_OPERATIONS, "Operations exceeding their slow threshold");
+        metrics::describe_counter!(CACHE_HITS, "User lookups served from the cache");
+        metrics::describe_counter!(
+            RATE_LIMITED,
+            "Operations refused by a tenant's rate limit or quota"
+        );
+        metrics::describe_counter!(CACHE_MISSES, "User lookups that went to the backend");
+        metrics::describe_counter!(CACHE_REJECTED, "Users not cached for lack of memory budget");
+        metrics::describe_counter!(WEBHOOK_DELIVERIES, "Webhook deliveries by outcome");
+        metrics::describe_histogram!(
+            WEBHOOK_DURATION,
+            metrics::Unit::Seconds,
+            "Time to deliver or give up on a webhook, retries included"
+        );
+        metrics::describe_counter!(SINK_EVENTS, "Events sent to external sinks by outcome");
+        metrics::describe_counter!(SCHEDULED_JOBS, "Scheduled job runs by outcome");
+        metrics::describe_counter!(ACTIVITIES, "User activities recorded by kind");
+        metrics::describe_counter!(SESSIONS_REVOKED, "User sessions revoked");
+        metrics::describe_counter!(NOTIFICATIONS, "Notifications sent to users by outcome");
+        metrics::describe_counter!(PASSWORD_RESETS, "Password reset requests and completions");
+        metrics::describe_counter!(
+            RECONCILE_DISCREPANCIES,
+            "Drift between local state and the backend found by reconciliation"
+        );
+        metrics::describe_histogram!(
+            BATCH_GET_SIZE,
+            metrics::Unit::Count,
+            "Users fetched per coalesced batch request"
+        );
+        metrics::describe_counter!(
+            DUAL_READ_MISMATCHES,
+            "Reads where the migration target disagreed with the primary"
+        );
+        metrics::describe_counter!(
+            DUAL_READ_SKIPPED,
+            "Reads not compared against the migration target because it was saturated"
+        );
+    }
+}
+
+// Storage backend for users
+#[async_trait]
+pub trait UserRepository: fmt::Debug + Send + Sync {
+    /// Load a user, returning `None` when it does not exist
+    async fn get(&self, user_id: &str) -> Result<Option<User>>;
+
+    /// Persist a new user and return the stored record
+    async fn create(&self, user: &User) -> Result<User>;
+
+    /// Apply partial updates, returning whether the user was updated
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool>;
+
+    /// Replace a user's second-factor enrollment with `replacement` if it still equals
+    /// `expected`, returning whether it did. Enrollments never travel through `update`, so
+    /// the default, for backends that do not store them, refuses.
+    async fn swap_two_factor(
+        &self,
+        user_id: &str,
+        expected: Option<&TwoFactor>,
+        replacement: Option<&TwoFactor>,
+    ) -> Result<bool> {
+        let _ = (user_id, expected, replacement);
+        Err(anyhow::anyhow!(
+            "{} does not store second factors",
+            self.describe()
+        ))
+    }
+
+    /// Remove a user, returning whether it existed
+    async fn delete(&self, user_id: &str) -> Result<bool>;
+
+    /// List users in a stable order
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>>;
+
+    /// Users matching `filter`, paged after filtering. The default scans `list` and filters
+    /// locally; backends that can filter server-side should override it.
+    async fn list_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        const SCAN_PAGE: usize = 100;
+        let mut matched = Vec::new();
+        let mut skip = offset;
+        let mut position = 0;
+        loop {
+            let page = self.list(position, SCAN_PAGE).await?;
+            let done = page.len() < SCAN_PAGE;
+            position += page.len();
+            for user in page.into_iter().filter(|user| filter.matches(user)) {
+                if skip > 0 {
+                    skip -= 1;
+                } else if matched.len() < limit {
+                    matched.push(user);
+                }
+            }
+            if done || matched.len() >= limit {
+                return Ok(matched);
+            }
+        }
+    }
+
+    /// Apply mutations in order, returning whether each one took effect.
+    /// Backends that report `supports_transactions` apply all or nothing;
+    /// the default applies them one by one and stops at the first failure.
+    async fn apply_batch(&self, mutations: &[Mutation]) -> Result<Vec<bool>> {
+        let mut outcomes = Vec::with_capacity(mutations.len());
+        for (index, mutation) in mutations.iter().enumerate() {
+            let applied = match mutation {
+                Mutation::Create(user) => self.create(user).await.map(|_| true),
+                Mutation::Update { user_id, updates } => self.update(user_id, updates).await,
+                Mutation::Delete { user_id } => self.delete(user_id).await,
+            }
+            .with_context(|| {
+                format!(
+                    "Batch failed at mutation {} of {} ({} already applied)",
+                    index + 1,
+                    mutations.len(),
+                    index
+                )
+            })?;
+            outcomes.push(applied);
+        }
+        Ok(outcomes)
+    }
+
+    /// Whether `apply_batch` is atomic
+    fn supports_transactions(&self) -> bool {
+        false
+    }
+
+    /// Whether each applied mutation leaves its event in an outbox, committed with it, for
+    /// a relay to publish; the manager then does not publish the event itself
+    fn writes_outbox(&self) -> bool {
+        false
+    }
+
+    /// Users changed since `cursor`. The default lists everything and marks the page complete,
+    /// so callers can infer deletions; backends with a change feed should override it.
+    async fn changes_since(&self, cursor: Option<&str>, limit: usize) -> Result<ChangeSet> {
+        let offset = match cursor {
+            Some(cursor) => cursor
+                .strip_prefix("offset:")
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid change cursor: {}", cursor))?,
+            None => 0,
+        };
+        let changed = self.list(offset, limit).await?;
+        Ok(ChangeSet {
+            next_cursor: Some(format!("offset:{}", offset + changed.len())),
+            has_more: changed.len() == limit,
+            changed,
+            deleted: Vec::new(),
+            complete: true,
+        })
+    }
+
+    /// Apply one mutation tagged with an idempotency key so retries are not applied twice.
+    /// The default ignores the key; backends that can deduplicate should override this.
+    async fn apply_idempotent(&self, mutation: &Mutation, idempotency_key: &str) -> Result<bool> {
+        let _ = idempotency_key;
+        let outcomes = self.apply_batch(std::slice::from_ref(mutation)).await?;
+        Ok(outcomes.first().copied().unwrap_or(false))
+    }
+
+    /// Human-readable backend description for diagnostics; must not contain secrets
+    fn describe(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+
+    /// Cheap liveness probe; the default lists a single user
+    async fn ping(&self) -> Result<()> {
+        self.list(0, 1).await.map(|_| ())
+    }
+
+    /// Tell the backend a user was erased so it can purge copies held elsewhere,
+    /// returning whether it acknowledged. The default has nobody to tell.
+    async fn notify_erasure(&self, user_id: &str) -> Result<bool> {
+        let _ = user_id;
+        Ok(false)
+    }
+}
+
+// A page of changes pulled from a repository
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChangeSet {
+    pub changed: Vec<User>,
+    pub deleted: Vec<String>,
+    /// Cursor to resume from after this page
+    pub next_cursor: Option<String>,
+    pub has_more: bool,
+    /// Pages form a full listing rather than a delta, so absent users were deleted
+    pub complete: bool,
+}
+
+// A single write against a repository
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Mutation {
+    Create(User),
+    Update {
+        user_id: String,
+        updates: HashMap<String, serde_json::Value>,
+    },
+    Delete {
+        user_id: String,
+    },
+}
+
+impl Mutation {
+    pub fn user_id(&self) -> &str {
+        match self {
+            Mutation::Create(user) => &user.id,
+            Mutation::Update { user_id, .. } | Mutation::Delete { user_id } => user_id,
+        }
+    }
+}
+
+// Staged writes collected inside `UserManager::transaction`
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    repository: Arc<dyn UserRepository>,
+    staged: Arc<Mutex<Vec<StagedWrite>>>,
+}
+
+// A write staged in a `Transaction`; typed updates become wire fields at commit, once the
+// stored metadata they build on can be read
+#[derive(Debug, Clone)]
+enum StagedWrite {
+    Mutation(Mutation),
+    Update { user_id: String, update: UserUpdate },
+}
+
+impl StagedWrite {
+    fn user_id(&self) -> &str {
+        match self {
+            StagedWrite::Mutation(mutation) => mutation.user_id(),
+            StagedWrite::Update { user_id, .. } => user_id,
+        }
+    }
+
+    fn needs_current(&self) -> bool {
+        matches!(self, StagedWrite::Update { update, .. } if update.needs_current())
+    }
+
+    fn permission(&self) -> Permission {
+        match self {
+            StagedWrite::Mutation(mutation) => Permission::for_mutation(mutation),
+            StagedWrite::Update { .. } => Permission::Update,
+        }
+    }
+}
+
+impl Transaction {
+    fn stage(&self, mutation: Mutation) {
+        self.staged
+            .lock()
+            .expect("transaction state poisoned")
+            .push(StagedWrite::Mutation(mutation));
+    }
+
+    /// Read committed state from the backend; staged writes are not visible
+    pub async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        self.repository.get(user_id).await
+    }
+
+    pub fn create(&self, user: User) {
+        self.stage(Mutation::Create(user));
+    }
+
+    /// Metadata ops apply on top of the stored user as changed by the writes staged before
+    pub fn update(&self, user_id: &str, update: UserUpdate) {
+        self.staged
+            .lock()
+            .expect("transaction state poisoned")
+            .push(StagedWrite::Update {
+                user_id: user_id.to_string(),
+                update,
+            });
+    }
+
+    pub fn delete(&self, user_id: &str) {
+        self.stage(Mutation::Delete {
+            user_id: user_id.to_string(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.staged
+            .lock()
+            .expect("transaction state poisoned")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The staged writes as mutations, reading stored users only for updates that build
+    /// on their metadata
+    async fn resolve(&self, staged: Vec<StagedWrite>) -> Result<Vec<Mutation>> {
+        let mut mutations: Vec<Mutation> = Vec::with_capacity(staged.len());
+        for write in staged {
+            let mutation = match write {
+                StagedWrite::Mutation(mutation) => mutation,
+                StagedWrite::Update { user_id, update } if update.needs_current() => {
+                    let mut current = self.repository.get(&user_id).await?;
+                    for earlier in mutations.iter().filter(|m| m.user_id() == user_id) {
+                        current = match earlier {
+                            Mutation::Create(user) => Some(user.clone()),
+                            Mutation::Update { updates, .. } => match current {
+                                Some(mut user) => {
+                                    user.apply_updates(updates)?;
+                                    Some(user)
+                                }
+                                None => None,
+                            },
+                            Mutation::Delete { .. } => None,
+                        };
+                    }
+                    let updates = update.to_fields(current.as_ref());
+                    Mutation::Update { user_id, updates }
+                }
+                StagedWrite::Update { user_id, update } => Mutation::Update {
+                    user_id,
+                    updates: update.to_fields(None),
+                },
+            };
+            mutations.push(mutation);
+        }
+        Ok(mutations)
+    }
+}
+
+// Sends built requests; wrap it to intercept traffic (fault injection, recording)
+#[async_trait]
+pub trait HttpTransport: fmt::Debug + Send + Sync {
+    async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response>;
+}
+
+#[async_trait]
+impl HttpTransport for reqwest::Client {
+    async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        Ok(reqwest::Client::execute(self, request).await?)
+    }
+}
+
+// How HTTP requests are retried after transport failures, 429 and 5xx responses. A
+// `Retry-After` header replaces the backoff, unless it asks for more than `max_backoff`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Retry up to `max_retries` times, backing off from 100ms to at most 2s
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::new(0)
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Delay before the given 0-based retry, doubling up to `max_backoff`
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// Wait a response's `Retry-After` header asks for, in seconds or as an HTTP date
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+// Token bucket: `burst` requests at once, refilled at `per_second`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Steady rate with a burst of one second's worth of requests; the rate must be a
+    /// positive, finite number
+    pub fn per_second(per_second: f64) -> Result<Self> {
+        if !(per_second.is_finite() && per_second > 0.0) {
+            return Err(UserManagerBuilder::invalid(
+                "per_second",
+                format!("must be a positive number of requests, got {}", per_second),
+            ));
+        }
+        Ok(Self {
+            per_second,
+            burst: per_second.ceil().min(f64::from(u32::MAX)) as u32,
+        })
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+// At most `max_requests` in each fixed `window`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub max_requests: u64,
+    pub window: Duration,
+}
+
+impl Quota {
+    /// The window must be longer than zero
+    pub fn new(max_requests: u64, window: Duration) -> Result<Self> {
+        if window.is_zero() {
+            return Err(UserManagerBuilder::invalid(
+                "window",
+                "must be longer than zero",
+            ));
+        }
+        Ok(Self {
+            max_requests,
+            window,
+        })
+    }
+}
+
+// Rate and quota applying to one tenant; either may be absent
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TenantLimits {
+    pub rate: Option<RateLimit>,
+    pub quota: Option<Quota>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    tokens: f64,
+    refilled_at: Instant,
+    window_started: Instant,
+    window_requests: u64,
+}
+
+impl LimiterState {
+    /// Whether the bucket is back to what a fresh one would hold: full, with its quota
+    /// window over
+    fn is_idle(&self, limits: TenantLimits, now: Instant) -> bool {
+        let refilled = limits.rate.is_none_or(|rate| {
+            let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+            self.tokens + elapsed * rate.per_second >= f64::from(rate.burst)
+        });
+        let window_over = limits
+            .quota
+            .is_none_or(|quota| now.duration_since(self.window_started) >= quota.window);
+        refilled && window_over
+    }
+}
+
+#[derive(Debug, Default)]
+struct LimiterStates {
+    buckets: HashMap<Option<TenantId>, LimiterState>,
+    /// Bucket count at which idle buckets are next swept
+    sweep_at: usize,
+}
+
+// Per-tenant rate limits and quotas, so one busy tenant cannot starve the others.
+// Tenants without their own limits share the defaults but get separate budgets; idle
+// budgets are dropped once the number held doubles, since a fresh one behaves the same.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    default: TenantLimits,
+    per_tenant: HashMap<TenantId, TenantLimits>,
+    state: Mutex<LimiterStates>,
+}
+
+impl RateLimiter {
+    /// Fewest budgets held before idle ones are swept
+    const MIN_SWEEP: usize = 64;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_default_rate(mut self, rate: RateLimit) -> Self {
+        self.default.rate = Some(rate);
+        self
+    }
+
+    pub fn with_default_quota(mut self, quota: Quota) -> Self {
+        self.default.quota = Some(quota);
+        self
+    }
+
+    /// Replace the defaults for one tenant
+    pub fn with_tenant_limits(mut self, tenant: TenantId, limits: TenantLimits) -> Self {
+        self.per_tenant.insert(tenant, limits);
+        self
+    }
+
+    pub fn limits_for(&self, tenant: Option<&TenantId>) -> TenantLimits {
+        tenant
+            .and_then(|tenant| self.per_tenant.get(tenant))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Take one request from the tenant's budget, or say when to come back
+    pub fn acquire(&self, tenant: Option<&TenantId>) -> Result<()> {
+        self.acquire_at(tenant, Instant::now())
+    }
+
+    pub(crate) fn acquire_at(&self, tenant: Option<&TenantId>, now: Instant) -> Result<()> {
+        let limits = self.limits_for(tenant);
+        if limits == TenantLimits::default() {
+            return Ok(());
+        }
+        let label = tenant.map_or("default", TenantId::as_str);
+        let mut states = self.state.lock().expect("rate limiter poisoned");
+        if states.buckets.len() >= states.sweep_at {
+            states
+                .buckets
+                .retain(|tenant, state| !state.is_idle(self.limits_for(tenant.as_ref()), now));
+            states.sweep_at = (states.buckets.len() * 2).max(Self::MIN_SWEEP);
+        }
+        let state = states
+            .buckets
+            .entry(tenant.cloned())
+            .or_insert_with(|| LimiterState {
+                tokens: limits.rate.map_or(0.0, |rate| f64::from(rate.burst)),
+                refilled_at: now,
+                window_started: now,
+                window_requests: 0,
+            });
+
+        if let Some(quota) = limits.quota {
+            if now.duration_since(state.window_started) >= quota.window {
+                state.window_started = now;
+                state.window_requests = 0;
+            }
+            if state.window_requests >= quota.max_requests {
+                metrics::counter!(metric_names::RATE_LIMITED, "tenant" => label.to_string(), "reason" => "quota")
+                    .increment(1);
+                return Err(UserError::QuotaExceeded {
+                    tenant: label.to_string(),
+                    resets_in: quota.window - now.duration_since(state.window_started),
+                }
+                .into());
+            }
+        }
+        if let Some(rate) = limits.rate {
+            let elapsed = now.duration_since(state.refilled_at).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * rate.per_second).min(f64::from(rate.burst));
+            state.refilled_at = now;
+            if state.tokens < 1.0 {
+                metrics::counter!(metric_names::RATE_LIMITED, "tenant" => label.to_string(), "reason" => "rate")
+                    .increment(1);
+                return Err(UserError::RateLimited {
+                    tenant: label.to_string(),
+                    retry_after: Duration::try_from_secs_f64(
+                        (1.0 - state.tokens) / rate.per_second,
+                    )
+                    .unwrap_or(Duration::MAX),
+                }
+                .into());
+            }
+            state.tokens -= 1.0;
+        }
+        state.window_requests += 1;
+        Ok(())
+    }
+
+    /// Every budget in use, sorted by tenant, for another limiter to `restore`
+    pub fn snapshot(&self) -> Vec<BucketSnapshot> {
+        let now = Instant::now();
+        let states = self.state.lock().expect("rate limiter poisoned");
+        let mut buckets: Vec<BucketSnapshot> = states
+            .buckets
+            .iter()
+            .map(|(tenant, state)| BucketSnapshot {
+                tenant: tenant.clone(),
+                tokens: state.tokens,
+                since_refill: now.duration_since(state.refilled_at),
+                window_requests: state.window_requests,
+                window_age: now.duration_since(state.window_started),
+            })
+            .collect();
+        buckets.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        buckets
+    }
+
+    /// Adopt budgets from a snapshot taken `elapsed` ago, replacing those of the same
+    /// tenants; tokens keep refilling and quota windows keep running over the gap
+    pub fn restore(&self, buckets: &[BucketSnapshot], elapsed: Duration) {
+        let now = Instant::now();
+        let ago = |age: Duration| now.checked_sub(age + elapsed).unwrap_or(now);
+        let mut states = self.state.lock().expect("rate limiter poisoned");
+        for bucket in buckets {
+            states.buckets.insert(
+                bucket.tenant.clone(),
+                LimiterState {
+                    tokens: bucket.tokens,
+                    refilled_at: ago(bucket.since_refill),
+                    window_started: ago(bucket.window_age),
+                    window_requests: bucket.window_requests,
+                },
+            );
+        }
+    }
+}
+
+// One tenant's rate-limit budget, with times relative to when the snapshot was taken
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BucketSnapshot {
+    pub tenant: Option<TenantId>,
+    pub tokens: f64,
+    pub since_refill: Duration,
+    pub window_requests: u64,
+    pub window_age: Duration,
+}
+
+type BatchWaiter = (
+    String,
+    tokio::sync::oneshot::Sender<Result<Option<User>, Arc<anyhow::Error>>>,
+);
+
+// A batch request's failure, handed to every `get` that was waiting on it with the
+// original error as its source
+#[derive(Debug, Clone)]
+struct BatchFailure(Arc<anyhow::Error>);
+
+impl fmt::Display for BatchFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Batched fetch failed")
+    }
+}
+
+impl std::error::Error for BatchFailure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&**self.0)
+    }
+}
+
+// `get`s waiting to go out together in one `POST /users:batchGet`
+#[derive(Debug)]
+struct GetBatcher {
+    window: Duration,
+    pending: Mutex<PendingGets>,
+    /// Set once the backend turns out not to have the endpoint, so `get`s go out singly
+    unsupported: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct PendingGets {
+    /// Bumped whenever the waiters are taken, so a stale timer leaves the next batch alone
+    generation: u64,
+    waiters: Vec<BatchWaiter>,
+}
+
+impl PendingGets {
+    fn take(&mut self) -> Vec<BatchWaiter> {
+        self.generation += 1;
+        std::mem::take(&mut self.waiters)
+    }
+}
+
+// HTTP backend talking to the user API
+#[derive(Debug, Clone)]
+pub struct HttpUserRepository {
+    base_url: String,
+    client: reqwest::Client,
+    transport: Arc<dyn HttpTransport>,
+    tenant: Option<TenantId>,
+    health_path: Option<String>,
+    retry_policy: RetryPolicy,
+    signer: Option<RequestSigner>,
+    batcher: Option<Arc<GetBatcher>>,
+}
+
+impl HttpUserRepository {
+    pub const TENANT_HEADER: &'static str = "X-Tenant-ID";
+    /// Most ids sent in one coalesced batch request
+    pub const BATCH_GET_MAX: usize = 100;
+
+    pub fn new(base_url: String, client: reqwest::Client) -> Self {
+        Self {
+            base_url,
+            transport: Arc::new(client.clone()),
+            client,
+            tenant: None,
+            health_path: None,
+            retry_policy: RetryPolicy::none(),
+            signer: None,
+            batcher: None,
+        }
+    }
+
+    /// Retry idempotent requests (and those carrying an idempotency key) under `policy`
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Send requests through `transport` instead of the client directly
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Sign every request, each retry with a fresh timestamp
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Probe `GET {base_url}{path}` for health instead of `HEAD` on the users collection
+    pub fn with_health_path(mut self, path: impl Into<String>) -> Self {
+        self.health_path = Some(path.into());
+        self
+    }
+
+    /// Coalesce `get`s made within `window` of each other into one `POST /users:batchGet`
+    /// of up to `BATCH_GET_MAX` ids, for backends exposing that endpoint
+    pub fn with_batch_get(mut self, window: Duration) -> Self {
+        self.batcher = Some(Arc::new(GetBatcher {
+            window,
+            pending: Mutex::new(PendingGets::default()),
+            unsupported: AtomicBool::new(false),
+        }));
+        self
+    }
+
+    /// Same backend with paths under `/tenants/{tenant}` and the tenant header set
+    pub fn for_tenant(&self, tenant: &TenantId) -> Self {
+        let repository = Self {
+            tenant: Some(tenant.clone()),
+            batcher: None,
+            ..self.clone()
+        };
+        // Batches go to one tenant's URL, so each tenant gets its own
+        match &self.batcher {
+            Some(batcher) => repository.with_batch_get(batcher.window),
+            None => repository,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn tenant(&self) -> Option<&TenantId> {
+        self.tenant.as_ref()
+    }
+
+    /// `{base_url}[/tenants/{tenant}]/{collection}/{segments..}`, each segment percent-encoded
+    /// so a tenant or user id cannot change the path or add a query
+    fn endpoint(&self, collection: &str, segments: &[&str]) -> Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&self.base_url)
+            .with_context(|| format!("Invalid base URL {}", redact(&self.base_url)))?;
+        let tenant = self.tenant.as_ref().map(TenantId::as_str);
+        let dynamic = tenant.into_iter().chain(segments.iter().copied());
+        // The url crate drops dot segments rather than encoding them
+        if let Some(segment) = dynamic.clone().find(|s| matches!(*s, "" | "." | "..")) {
+            return Err(UserError::InvalidUpdate {
+                field: "id".to_string(),
+                message: format!("{:?} cannot be used as a URL path segment", segment),
+            }
+            .into());
+        }
+        url.path_segments_mut()
+            .map_err(|()| {
+                anyhow::anyhow!("Base URL {} cannot have a path", redact(&self.base_url))
+            })?
+            .pop_if_empty()
+            .extend(
+                tenant
+                    .map(|tenant| ["tenants", tenant])
+                    .into_iter()
+                    .flatten(),
+            )
+            .push(collection)
+            .extend(segments);
+        Ok(url)
+    }
+
+    fn users_url(&self) -> Result<reqwest::Url> {
+        self.endpoint("users", &[])
+    }
+
+    pub(crate) fn user_url(&self, user_id: &str) -> Result<reqwest::Url> {
+        self.endpoint("users", &[user_id])
+    }
+
+    fn request(&self, method: reqwest::Method, url: reqwest::Url) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.tenant {
+            Some(tenant) => request.header(Self::TENANT_HEADER, tenant.as_str()),
+            None => request,
+        }
+    }
+
+    /// Send under the retry policy; only requests that are safe to repeat are retried
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        let request = request.build()?;
+        let retryable =
+            request.method().is_idempotent() || request.headers().contains_key("Idempotency-Key");
+        self.send_retrying(request, retryable).await
+    }
+
+    async fn send_retrying(
+        &self,
+        mut request: reqwest::Request,
+        retryable: bool,
+    ) -> Result<reqwest::Response> {
+        let mut retry = 0;
+        loop {
+            let next = if retryable && retry < self.retry_policy.max_retries {
+                request.try_clone()
+            } else {
+                None
+            };
+            let response = self.send_once(request).await;
+            let Some(next) = next else {
+                return response;
+            };
+            let delay = match &response {
+                Ok(sent)
+                    if sent.status().is_server_error()
+                        || sent.status() == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    match retry_after(sent.headers()) {
+                        Some(wait) if wait > self.retry_policy.max_backoff => return response,
+                        Some(wait) => wait,
+                        None => self.retry_policy.backoff(retry),
+                    }
+                }
+                Ok(_) => return response,
+                Err(_) => self.retry_policy.backoff(retry),
+            };
+            retry += 1;
+            metrics::counter!(metric_names::RETRIES, "reason" => "http").increment(1);
+            tracing::debug!(retry, ?delay, "Retrying HTTP request");
+            tokio::time::sleep(delay).await;
+            request = next;
+        }
+    }
+
+    /// Send inside a client span, propagating its trace context as W3C headers
+    async fn send_once(&self, mut request: reqwest::Request) -> Result<reqwest::Response> {
+        let span = tracing::info_span!(
+            "http_request",
+            otel.kind = "client",
+            http.request.method = %request.method(),
+            url.full = %redact(request.url().as_str()),
+            http.response.status_code = tracing::field::Empty
+        );
+        inject_trace_context(&span, request.headers_mut());
+        if let Some(signer) = &self.signer {
+            signer.sign(&mut request, Utc::now())?;
+        }
+        count_attempt();
+
+        let response = self
+            .transport
+            .execute(request)
+            .instrument(span.clone())
+            .await;
+        if let Ok(response) = &response {
+            span.record("http.response.status_code", response.status().as_u16());
+        }
+        response
+    }
+
+    fn with_idempotency_key(
+        request: reqwest::RequestBuilder,
+        idempotency_key: Option<&str>,
+    ) -> reqwest::RequestBuilder {
+        match idempotency_key {
+            Some(key) => request.header("Idempotency-Key", key),
+            None => request,
+        }
+    }
+
+    async fn send_create(&self, user: &User, idempotency_key: Option<&str>) -> Result<User> {
+        let request = self
+            .request(reqwest::Method::POST, self.users_url()?)
+            .json(user);
+        let response = self
+            .send(Self::with_idempotency_key(request, idempotency_key))
+            .await
+            .context("Failed to send create request")?;
+
+        if !response.status().is_success() {
+            return Err(UserError::ApiError {
+                message: format!(
+                    "Failed to create user {}: {}",
+                    redact_id(&user.id),
+                    response.status()
+                ),
+            }
+            .into());
+        }
+
+        let api_response: ApiResponse<User, ApiErrorBody> = response
+            .json()
+            .await
+            .context("Failed to parse JSON response")?;
+        Ok(Self::into_data(api_response)?.unwrap_or_else(|| user.clone()))
+    }
+
+    async fn send_update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+        idempotency_key: Option<&str>,
+    ) -> Result<bool> {
+        let request = self
+            .request(reqwest::Method::PUT, self.user_url(user_id)?)
+            .json(updates);
+        let response = self
+            .send(Self::with_idempotency_key(request, idempotency_key))
+            .await
+            .context("Failed to send update request")?;
+
+        if response.status().is_success() {
+            Ok(true)
+        } else {
+            tracing::error!(user_id = %redact_id(user_id), status = %response.status(), "Failed to update user");
+            Ok(false)
+        }
+    }
+
+    async fn send_delete(&self, user_id: &str, idempotency_key: Option<&str>) -> Result<bool> {
+        let request = self.request(reqwest::Method::DELETE, self.user_url(user_id)?);
+        let response = self
+            .send(Self::with_idempotency_key(request, idempotency_key))
+            .await
+            .context("Failed to send delete request")?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            reqwest::StatusCode::NOT_FOUND => Ok(false),
+            status => Err(UserError::ApiError {
+                message: format!("Failed to delete user {}: {}", redact_id(user_id), status),
+            }
+            .into()),
+        }
+    }
+
+    /// Unwrap a response envelope, mapping a reported failure to its `UserError`
+    pub(crate) fn into_data<T, E>(api_response: ApiResponse<T, E>) -> Result<Option<T>>
+    where
+        E: From<String>,
+        ApiErrorBody: From<E>,
+    {
+        let request_id = api_response
+            .meta
+            .as_ref()
+            .and_then(|meta| meta.request_id.clone());
+        match api_response.into_result() {
+            Ok(data) => Ok(Some(data)),
+            Err(ApiError::MissingData) => Ok(None),
+            Err(ApiError::Failed { message }) => Err(match (
+                UserError::from(ApiErrorBody::from(message)),
+                request_id,
+            ) {
+                (UserError::ApiError { message }, Some(request_id)) => UserError::ApiError {
+                    message: format!("{} (request {})", message, request_id),
+                },
+                (error, _) => error,
+            }
+            .into()),
+        }
+    }
+
+    /// Fetch several users in one `POST /users:batchGet`; ids the backend does not have
+    /// are left out. A backend answering 404 or 405 lacks the endpoint, so the users are
+    /// fetched one by one instead.
+    pub async fn batch_get(&self, user_ids: &[String]) -> Result<HashMap<String, User>> {
+        let request = self
+            .request(reqwest::Method::POST, self.endpoint("users:batchGet", &[])?)
+            .json(&serde_json::json!({ "ids": user_ids }))
+            .build()?;
+        // A read, so safe to repeat despite the POST
+        let response = self
+            .send_retrying(request, true)
+            .await
+            .context("Failed to send batch get request")?;
+
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::METHOD_NOT_ALLOWED
+        ) {
+            tracing::warn!(
+                status = %response.status(),
+                "Batch get endpoint unavailable, fetching users one by one"
+            );
+            if let Some(batcher) = &self.batcher {
+                batcher.unsupported.store(true, Ordering::Relaxed);
+            }
+            let mut users = HashMap::new();
+            for user_id in user_ids {
+                if let Some(user) = self.get_one(user_id).await? {
+                    users.insert(user.id.clone(), user);
+                }
+            }
+            return Ok(users);
+        }
+        if !response.status().is_success() {
+            return Err(UserError::ApiError {
+                message: format!(
+                    "Failed to fetch {} users: {}",
+                    user_ids.len(),
+                    response.status()
+                ),
+            }
+            .into());
+        }
+
+        let api_response: ApiResponse<Vec<User>, ApiErrorBody> = response
+            .json()
+            .await
+            .context("Failed to parse JSON response")?;
+        Ok(Self::into_data(api_response)?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|user| (user.id.clone(), user))
+            .collect())
+    }
+
+    /// Join the pending batch, starting its timer when first or sending it when full
+    async fn batched_get(&self, batcher: &Arc<GetBatcher>, user_id: &str) -> Result<Option<User>> {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        {
+            let mut pending = batcher.pending.lock().expect("batch get poisoned");
+            pending.waiters.push((user_id.to_string(), sender));
+            if pending.waiters.len() >= Self::BATCH_GET_MAX {
+                tokio::spawn(self.clone().flush_batch(pending.take()));
+            } else if pending.waiters.len() == 1 {
+                let (repository, batcher) = (self.clone(), batcher.clone());
+                let generation = pending.generation;
+                tokio::spawn(async move {
+                    tokio::time::sleep(batcher.window).await;
+                    let waiters = {
+                        let mut pending = batcher.pending.lock().expect("batch get poisoned");
+                        if pending.generation != generation {
+                            return;
+                        }
+                        pending.take()
+                    };
+                    repository.flush_batch(waiters).await;
+                });
+            }
+        }
+        match receiver.await {
+            Ok(result) => result.map_err(|failure| BatchFailure(failure).into()),
+            // The flushing task died, e.g. with its runtime; ask on our own
+            Err(_) => self.get_one(user_id).await,
+        }
+    }
+
+    /// Send one batch request for `waiters` and hand each its user
+    async fn flush_batch(self, waiters: Vec<BatchWaiter>) {
+        let mut user_ids: Vec<String> = waiters.iter().map(|(id, _)| id.clone()).collect();
+        user_ids.sort();
+        user_ids.dedup();
+        metrics::histogram!(metric_names::BATCH_GET_SIZE).record(user_ids.len() as f64);
+        let result = self.batch_get(&user_ids).await.map_err(Arc::new);
+        if let Err(e) = &result {
+            tracing::warn!(
+                users = user_ids.len(),
+                error = redact_error(e),
+                "Batched fetch failed"
+            );
+        }
+        for (user_id, sender) in waiters {
+            // The caller may have given up waiting
+            let _ = sender.send(match &result {
+                Ok(users) => Ok(users.get(&user_id).cloned()),
+                Err(e) => Err(e.clone()),
+            });
+        }
+    }
+
+    async fn get_one(&self, user_id: &str) -> Result<Option<User>> {
+        let request = self.request(reqwest::Method::GET, self.user_url(user_id)?);
+        let response = self.send(request).await.context("Failed to send request")?;
+
+        if !response.status().is_success() {
+            tracing::warn!(user_id = %redact_id(user_id), status = %response.status(), "Failed to fetch user");
+            return Ok(None);
+        }
+
+        let api_response: ApiResponse<User, ApiErrorBody> = response
+            .json()
+            .await
+            .context("Failed to parse JSON response")?;
+        Self::into_data(api_response)
+    }
+
+    /// A page of users with the pagination details the API returned, if any
+    pub async fn list_with_meta(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<User>, Option<ResponseMeta>)> {
+        self.list_page(None, offset, limit).await
+    }
+
+    async fn list_page(
+        &self,
+        filter: Option<&Filter>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<User>, Option<ResponseMeta>)> {
+        let mut request = self
+            .request(reqwest::Method::GET, self.users_url()?)
+            .query(&[("offset", offset), ("limit", limit)]);
+        if let Some(filter) = filter {
+            request = request.query(&filter.to_query_params());
+        }
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to send list request")?;
+
+        if !response.status().is_success() {
+            return Err(UserError::ApiError {
+                message: format!("Failed to list users: {}", response.status()),
+            }
+            .into());
+        }
+
+        let mut api_response: ApiResponse<Vec<User>, ApiErrorBody> = response
+            .json()
+            .await
+            .context("Failed to parse JSON response")?;
+        let meta = api_response.meta.take();
+        Ok((Self::into_data(api_response)?.unwrap_or_default(), meta))
+    }
+}
+
+/// Write the span's `traceparent`/`tracestate` using the global propagator
+#[cfg(feature = "otel")]
+fn inject_trace_context(span: &tracing::Span, headers: &mut reqwest::header::HeaderMap) {
+    use opentelemetry::propagation::Injector;
+    use reqwest::header::{HeaderName, HeaderValue};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    struct HeaderInjector<'a>(&'a mut reqwest::header::HeaderMap);
+
+    impl Injector for HeaderInjector<'_> {
+        fn set(&mut self, key: &str, value: String) {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(key.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                self.0.insert(name, value);
+            }
+        }
+    }
+
+    let context = span.context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+#[cfg(not(feature = "otel"))]
+fn inject_trace_context(_span: &tracing::Span, _headers: &mut reqwest::header::HeaderMap) {}
+
+#[async_trait]
+impl UserRepository for HttpUserRepository {
+    async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        match &self.batcher {
+            Some(batcher) if !batcher.unsupported.load(Ordering::Relaxed) => {
+                self.batched_get(batcher, user_id).await
+            }
+            _ => self.get_one(user_id).await,
+        }
+    }
+
+    async fn create(&self, user: &User) -> Result<User> {
+        self.send_create(user, None).await
+    }
+
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        self.send_update(user_id, updates, None).await
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool> {
+        self.send_delete(user_id, None).await
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        Ok(self.list_page(None, offset, limit).await?.0)
+    }
+
+    async fn list_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        Ok(self.list_page(Some(filter), offset, limit).await?.0)
+    }
+
+    async fn changes_since(&self, cursor: Option<&str>, limit: usize) -> Result<ChangeSet> {
+        let mut request = self
+            .request(reqwest::Method::GET, self.endpoint("users", &["changes"])?)
+            .query(&[("limit", limit)]);
+        if let Some(cursor) = cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to send changes request")?;
+
+        if !response.status().is_success() {
+            return Err(UserError::ApiError {
+                message: format!("Failed to fetch changes: {}", response.status()),
+            }
+            .into());
+        }
+
+        let api_response: ApiResponse<ChangeSet, ApiErrorBody> = response
+            .json()
+            .await
+            .context("Failed to parse JSON response")?;
+        Ok(Self::into_data(api_response)?.unwrap_or_default())
+    }
+
+    async fn notify_erasure(&self, user_id: &str) -> Result<bool> {
+        let request = self.request(
+            reqwest::Method::POST,
+            self.endpoint("users", &[user_id, "erasure"])?,
+        );
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to send erasure notice")?;
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            reqwest::StatusCode::NOT_FOUND => Ok(false),
+            status => Err(UserError::ApiError {
+                message: format!("Failed to notify erasure: {}", status),
+            }
+            .into()),
+        }
+    }
+
+    async fn apply_idempotent(&self, mutation: &Mutation, idempotency_key: &str) -> Result<bool> {
+        let key = Some(idempotency_key);
+        match mutation {
+            Mutation::Create(user) => self.send_create(user, key).await.map(|_| true),
+            Mutation::Update { user_id, updates } => self.send_update(user_id, updates, key).await,
+            Mutation::Delete { user_id } => self.send_delete(user_id, key).await,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let base_url = match reqwest::Url::parse(&self.base_url) {
+            Ok(mut url) => {
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                url.set_query(None);
+                url.to_string()
+            }
+            Err(_) => "<invalid url>".to_string(),
+        };
+        match &self.tenant {
+            Some(tenant) => format!("http {} (tenant {})", base_url, tenant),
+            None => format!("http {}", base_url),
+        }
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let request = match &self.health_path {
+            Some(path) => self.request(
+                reqwest::Method::GET,
+                reqwest::Url::parse(&format!("{}{}", self.base_url, path))
+                    .context("Invalid health check URL")?,
+            ),
+            None => self.request(reqwest::Method::HEAD, self.users_url()?),
+        };
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to send health check")?;
+        if !response.status().is_success() {
+            return Err(UserError::ApiError {
+                message: format!("Health check failed: {}", response.status()),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// Routes reads to a replica and writes to the primary, failing over to the primary on replica errors
+#[derive(Debug)]
+pub struct ReplicatedUserRepository {
+    primary: Arc<dyn UserRepository>,
+    replica: Arc<dyn UserRepository>,
+    primary_on_miss: bool,
+    failovers: std::sync::atomic::AtomicU64,
+}
+
+impl ReplicatedUserRepository {
+    pub fn new(primary: Arc<dyn UserRepository>, replica: Arc<dyn UserRepository>) -> Self {
+        Self {
+            primary,
+            replica,
+            primary_on_miss: false,
+            failovers: Default::default(),
+        }
+    }
+
+    /// Also ask the primary when the replica has not seen a user yet (replication lag)
+    pub fn with_primary_on_miss(mut self, enabled: bool) -> Self {
+        self.primary_on_miss = enabled;
+        self
+    }
+
+    /// Number of reads that fell back to the primary
+    pub fn failovers(&self) -> u64 {
+        self.failovers.load(Ordering::Relaxed)
+    }
+
+    fn failover(&self, operation: &str, error: &anyhow::Error) {
+        self.failovers.fetch_add(1, Ordering::Relaxed);
+        metrics::counter!(metric_names::RETRIES, "reason" => "replica_failover").increment(1);
+        tracing::warn!(
+            operation,
+            error = redact_error(error),
+            "Read replica failed, retrying on primary"
+        );
+    }
+}
+
+#[async_trait]
+impl UserRepository for ReplicatedUserRepository {
+    async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        match self.replica.get(user_id).await {
+            Ok(Some(user)) => Ok(Some(user)),
+            Ok(None) if self.primary_on_miss => self.primary.get(user_id).await,
+            Ok(None) => Ok(None),
+            Err(e) => {
+                self.failover("get", &e);
+                self.primary.get(user_id).await
+            }
+        }
+    }
+
+    async fn create(&self, user: &User) -> Result<User> {
+        self.primary.create(user).await
+    }
+
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        self.primary.update(user_id, updates).await
+    }
+
+    async fn swap_two_factor(
+        &self,
+        user_id: &str,
+        expected: Option<&TwoFactor>,
+        replacement: Option<&TwoFactor>,
+    ) -> Result<bool> {
+        self.primary
+            .swap_two_factor(user_id, expected, replacement)
+            .await
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool> {
+        self.primary.delete(user_id).await
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        match self.replica.list(offset, limit).await {
+            Ok(users) => Ok(users),
+            Err(e) => {
+                self.failover("list", &e);
+                self.primary.list(offset, limit).await
+            }
+        }
+    }
+
+    async fn list_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        match self.replica.list_filtered(filter, offset, limit).await {
+            Ok(users) => Ok(users),
+            Err(e) => {
+                self.failover("list_filtered", &e);
+                self.primary.list_filtered(filter, offset, limit).await
+            }
+        }
+    }
+
+    async fn changes_since(&self, cursor: Option<&str>, limit: usize) -> Result<ChangeSet> {
+        match self.replica.changes_since(cursor, limit).await {
+            Ok(changes) => Ok(changes),
+            Err(e) => {
+                self.failover("changes_since", &e);
+                self.primary.changes_since(cursor, limit).await
+            }
+        }
+    }
+
+    async fn apply_batch(&self, mutations: &[Mutation]) -> Result<Vec<bool>> {
+        self.primary.apply_batch(mutations).await
+    }
+
+    fn supports_transactions(&self) -> bool {
+        self.primary.supports_transactions()
+    }
+
+    fn writes_outbox(&self) -> bool {
+        self.primary.writes_outbox()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "replicated(primary: {}, replica: {}, failovers: {})",
+            self.primary.describe(),
+            self.replica.describe(),
+            self.failovers()
+        )
+    }
+
+    /// Healthy while the primary is; a failing replica only degrades reads to the primary
+    async fn ping(&self) -> Result<()> {
+        if let Err(e) = self.replica.ping().await {
+            tracing::warn!(error = redact_error(&e), "Read replica health check failed");
+        }
+        self.primary.ping().await
+    }
+
+    async fn apply_idempotent(&self, mutation: &Mutation, idempotency_key: &str) -> Result<bool> {
+        self.primary
+            .apply_idempotent(mutation, idempotency_key)
+            .await
+    }
+
+    async fn notify_erasure(&self, user_id: &str) -> Result<bool> {
+        self.primary.notify_erasure(user_id).await
+    }
+}
+
+// Thread-safe in-memory backend for tests and prototyping
+#[derive(Debug, Default)]
+pub struct InMemoryUserRepository {
+    users: RwLock<HashMap<String, User>>,
+    idempotency_keys: Mutex<std::collections::HashSet<String>>,
+}
+
+impl InMemoryUserRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seed the store with existing users
+    pub fn with_users(users: impl IntoIterator<Item = User>) -> Self {
+        Self {
+            users: RwLock::new(users.into_iter().map(|u| (u.id.clone(), u)).collect()),
+            idempotency_keys: Mutex::default(),
+        }
+    }
+
+    pub async fn len(&self) -> usize {
+        self.users.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.users.read().await.is_empty()
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        Ok(self.users.read().await.get(user_id).cloned())
+    }
+
+    async fn create(&self, user: &User) -> Result<User> {
+        let mut users = self.users.write().await;
+        if users.contains_key(&user.id) {
+            return Err(UserError::ApiError {
+                message: format!("User {} already exists", redact_id(&user.id)),
+            }
+            .into());
+        }
+        users.insert(user.id.clone(), user.clone());
+        Ok(user.clone())
+    }
+
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        let mut users = self.users.write().await;
+        match users.get_mut(user_id) {
+            Some(user) => {
+                user.apply_updates(updates)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn swap_two_factor(
+        &self,
+        user_id: &str,
+        expected: Option<&TwoFactor>,
+        replacement: Option<&TwoFactor>,
+    ) -> Result<bool> {
+        let mut users = self.users.write().await;
+        match users.get_mut(user_id) {
+            Some(user) if user.two_factor.as_deref() == expected => {
+                user.two_factor = replacement.cloned().map(Box::new);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool> {
+        Ok(self.users.write().await.remove(user_id).is_some())
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        let users = self.users.read().await;
+        let mut sorted: Vec<&User> = users.values().collect();
+        sorted.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        Ok(sorted
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn apply_batch(&self, mutations: &[Mutation]) -> Result<Vec<bool>> {
+        let mut users = self.users.write().await;
+        // Work on a copy so a failing mutation leaves the store untouched
+        let mut working = users.clone();
+        let mut outcomes = Vec::with_capacity(mutations.len());
+        for mutation in mutations {
+            let applied = match mutation {
+                Mutation::Create(user) => {
+                    if working.contains_key(&user.id) {
+                        return Err(UserError::ApiError {
+                            message: format!("User {} already exists", redact_id(&user.id)),
+                        }
+                        .into());
+                    }
+                    working.insert(user.id.clone(), user.clone());
+                    true
+                }
+                Mutation::Update { user_id, updates } => match working.get_mut(user_id) {
+                    Some(user) => {
+                        user.apply_updates(updates)?;
+                        true
+                    }
+                    None => false,
+                },
+                Mutation::Delete { user_id } => working.remove(user_id).is_some(),
+            };
+            outcomes.push(applied);
+        }
+        *users = working;
+        Ok(outcomes)
+    }
+
+    fn supports_transactions(&self) -> bool {
+        true
+    }
+
+    async fn apply_idempotent(&self, mutation: &Mutation, idempotency_key: &str) -> Result<bool> {
+        if self
+            .idempotency_keys
+            .lock()
+            .expect("idempotency keys poisoned")
+            .contains(idempotency_key)
+        {
+            return Ok(true);
+        }
+        let outcomes = self.apply_batch(std::slice::from_ref(mutation)).await?;
+        self.idempotency_keys
+            .lock()
+            .expect("idempotency keys poisoned")
+            .insert(idempotency_key.to_string());
+        Ok(outcomes.first().copied().unwrap_or(false))
+    }
+}
+
+// SQLite backend for single-binary deployments and offline tools
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone)]
+pub struct SqliteUserRepository {
+    pool: sqlx::SqlitePool,
+    outbox: bool,
+    tenant: Option<TenantId>,
+    upcaster: Arc<EventUpcaster>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteUserRepository {
+    const SCHEMA: &'static str = "
+        CREATE TABLE IF NOT EXISTS users (
+            id TEXT PRIMARY KEY NOT NULL,
+            name TEXT NOT NULL,
+            email TEXT NOT NULL,
+            status TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            metadata TEXT NOT NULL DEFAULT '{}',
+            consents TEXT NOT NULL DEFAULT '[]',
+            two_factor TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_users_email ON users (email);
+        CREATE INDEX IF NOT EXISTS idx_users_created_at ON users (created_at);
+        CREATE TABLE IF NOT EXISTS user_outbox (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            event TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            claimed_until TEXT,
+            published_at TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_user_outbox_pending ON user_outbox (published_at, id);
+    ";
+
+    pub fn new(pool: sqlx::SqlitePool) -> Self {
+        Self {
+            pool,
+            outbox: false,
+            tenant: None,
+            upcaster: Arc::new(EventUpcaster::new()),
+        }
+    }
+
+    /// Write an event to the `user_outbox` table in the same transaction as each mutation
+    pub fn with_outbox(mut self) -> Self {
+        self.outbox = true;
+        self
+    }
+
+    /// Tag outbox events with the tenant this repository stores, for repositories built
+    /// by a tenant factory
+    pub fn with_tenant(mut self, tenant: TenantId) -> Self {
+        self.tenant = Some(tenant);
+        self
+    }
+
+    /// Read outbox events written under older schema versions with `upcaster`
+    pub fn with_event_upcaster(mut self, upcaster: Arc<EventUpcaster>) -> Self {
+        self.upcaster = upcaster;
+        self
+    }
+
+    /// Open (creating if missing) a database file and ensure the schema exists
+    pub async fn connect(url: &str) -> Result<Self> {
+        let options = url
+            .parse::<sqlx::sqlite::SqliteConnectOptions>()
+            .map_err(UserError::from)?
+            .create_if_missing(true);
+        let pool = sqlx::SqlitePool::connect_with(options)
+            .await
+            .map_err(UserError::from)?;
+        let repository = Self::new(pool);
+        repository.create_schema().await?;
+        Ok(repository)
+    }
+
+    /// Private in-memory database, mostly useful for tests
+    pub async fn in_memory() -> Result<Self> {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .map_err(UserError::from)?;
+        let repository = Self::new(pool);
+        repository.create_schema().await?;
+        Ok(repository)
+    }
+
+    /// Create the users table and indexes if they do not exist, adding columns newer
+    /// than the table
+    pub async fn create_schema(&self) -> Result<()> {
+        sqlx::raw_sql(Self::SCHEMA)
+            .execute(&self.pool)
+            .await
+            .map_err(UserError::from)?;
+        const ADDED_COLUMNS: [(&str, &str, &str); 3] = [
+            (
+                "users",
+                "consents",
+                "ALTER TABLE users ADD COLUMN consents TEXT NOT NULL DEFAULT '[]'",
+            ),
+            (
+                "users",
+                "two_factor",
+                "ALTER TABLE users ADD COLUMN two_factor TEXT",
+            ),
+            (
+                "user_outbox",
+                "claimed_until",
+                "ALTER TABLE user_outbox ADD COLUMN claimed_until TEXT",
+            ),
+        ];
+        for (table, column, alter) in ADDED_COLUMNS {
+            let (exists,): (bool,) =
+                sqlx::query_as("SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?")
+                    .bind(table)
+                    .bind(column)
+                    .fetch_one(&self.pool)
+                    .await
+                    .map_err(UserError::from)?;
+            if !exists {
+                sqlx::query(alter)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(UserError::from)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn pool(&self) -> &sqlx::SqlitePool {
+        &self.pool
+    }
+
+    fn user_from_row(row: &sqlx::sqlite::SqliteRow) -> Result<User> {
+        use sqlx::Row;
+
+        let status: String = row.try_get("status").map_err(UserError::from)?;
+        let metadata: String = row.try_get("metadata").map_err(UserError::from)?;
+        let consents: String = row.try_get("consents").map_err(UserError::from)?;
+        let two_factor: Option<String> = row.try_get("two_factor").map_err(UserError::from)?;
+        Ok(User {
+            id: row.try_get("id").map_err(UserError::from)?,
+            name: row.try_get("name").map_err(UserError::from)?,
+            email: row.try_get("email").map_err(UserError::from)?,
+            status: status.parse()?,
+            created_at: row.try_get("created_at").map_err(UserError::from)?,
+            metadata: serde_json::from_str(&metadata).context("Failed to parse stored metadata")?,
+            consents: serde_json::from_str(&consents).context("Failed to parse stored consents")?,
+            two_factor: two_factor
+                .map(|two_factor| serde_json::from_str(&two_factor))
+                .transpose()
+                .context("Failed to parse stored two-factor enrollment")?,
+        })
+    }
+
+    fn two_factor_json(two_factor: Option<&TwoFactor>) -> Result<Option<String>> {
+        two_factor
+            .map(serde_json::to_string)
+            .transpose()
+            .context("Failed to serialize two-factor enrollment")
+    }
+
+    async fn fetch(conn: &mut sqlx::SqliteConnection, user_id: &str) -> Result<Option<User>> {
+        let row = sqlx::query("SELECT * FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_optional(conn)
+            .await
+            .map_err(UserError::from)?;
+        row.as_ref().map(Self::user_from_row).transpose()
+    }
+
+    async fn insert(conn: &mut sqlx::SqliteConnection, user: &User) -> Result<User> {
+        let result = sqlx::query(
+            "INSERT INTO users (id, name, email, status, created_at, metadata, consents, two_factor)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&user.id)
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(user.status.as_str())
+        .bind(user.created_at)
+        .bind(serde_json::to_string(&user.metadata).context("Failed to serialize metadata")?)
+        .bind(serde_json::to_string(&user.consents).context("Failed to serialize consents")?)
+        .bind(Self::two_factor_json(user.two_factor.as_deref())?)
+        .execute(conn)
+        .await;
+
+        match result {
+            Ok(_) => Ok(user.clone()),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(UserError::ApiError {
+                message: format!("User {} already exists", redact_id(&user.id)),
+            }
+            .into()),
+            Err(e) => Err(UserError::from(e).into()),
+        }
+    }
+
+    async fn modify(
+        conn: &mut sqlx::SqliteConnection,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        let Some(mut user) = Self::fetch(&mut *conn, user_id).await? else {
+            return Ok(false);
+        };
+        user.apply_updates(updates)?;
+        sqlx::query(
+            "UPDATE users
+             SET name = ?, email = ?, status = ?, created_at = ?, metadata = ?, consents = ?
+             WHERE id = ?",
+        )
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(user.status.as_str())
+        .bind(user.created_at)
+        .bind(serde_json::to_string(&user.metadata).context("Failed to serialize metadata")?)
+        .bind(serde_json::to_string(&user.consents).context("Failed to serialize consents")?)
+        .bind(&user.id)
+        .execute(conn)
+        .await
+        .map_err(UserError::from)?;
+        Ok(true)
+    }
+
+    /// Queue the event for an applied mutation inside the caller's transaction
+    async fn record(
+        &self,
+        conn: &mut sqlx::SqliteConnection,
+        mutation: &Mutation,
+        applied: bool,
+    ) -> Result<()> {
+        if !self.outbox || !applied {
+            return Ok(());
+        }
+        let event = UserEvent::applied(self.tenant.as_ref(), mutation).to_stored()?;
+        sqlx::query("INSERT INTO user_outbox (event, created_at) VALUES (?, ?)")
+            .bind(event.to_string())
+            .bind(Utc::now())
+            .execute(conn)
+            .await
+            .map_err(UserError::from)?;
+        Ok(())
+    }
+
+    async fn remove(conn: &mut sqlx::SqliteConnection, user_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(user_id)
+            .execute(conn)
+            .await
+            .map_err(UserError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl UserRepository for SqliteUserRepository {
+    async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        let mut conn = self.pool.acquire().await.map_err(UserError::from)?;
+        Self::fetch(&mut conn, user_id).await
+    }
+
+    async fn create(&self, user: &User) -> Result<User> {
+        if !self.outbox {
+            let mut conn = self.pool.acquire().await.map_err(UserError::from)?;
+            return Self::insert(&mut conn, user).await;
+        }
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let created = Self::insert(&mut tx, user).await?;
+        self.record(&mut tx, &Mutation::Create(created.clone()), true)
+            .await?;
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(created)
+    }
+
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let updated = Self::modify(&mut tx, user_id, updates).await?;
+        if self.outbox {
+            let mutation = Mutation::Update {
+                user_id: user_id.to_string(),
+                updates: updates.clone(),
+            };
+            self.record(&mut tx, &mutation, updated).await?;
+        }
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(updated)
+    }
+
+    async fn swap_two_factor(
+        &self,
+        user_id: &str,
+        expected: Option<&TwoFactor>,
+        replacement: Option<&TwoFactor>,
+    ) -> Result<bool> {
+        // Stored enrollments are written by `two_factor_json` alone, so equal values
+        // compare equal as text
+        let result =
+            sqlx::query("UPDATE users SET two_factor = ? WHERE id = ? AND two_factor IS ?")
+                .bind(Self::two_factor_json(replacement)?)
+                .bind(user_id)
+                .bind(Self::two_factor_json(expected)?)
+                .execute(&self.pool)
+                .await
+                .map_err(UserError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let deleted = Self::remove(&mut tx, user_id).await?;
+        let mutation = Mutation::Delete {
+            user_id: user_id.to_string(),
+        };
+        self.record(&mut tx, &mutation, deleted).await?;
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(deleted)
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        let rows = sqlx::query("SELECT * FROM users ORDER BY created_at, id LIMIT ? OFFSET ?")
+            .bind(limit as i64)
+            .bind(offset as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(UserError::from)?;
+        rows.iter().map(Self::user_from_row).collect()
+    }
+
+    async fn apply_batch(&self, mutations: &[Mutation]) -> Result<Vec<bool>> {
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let mut outcomes = Vec::with_capacity(mutations.len());
+        for mutation in mutations {
+            let applied = match mutation {
+                Mutation::Create(user) => Self::insert(&mut tx, user).await.map(|_| true)?,
+                Mutation::Update { user_id, updates } => {
+                    Self::modify(&mut tx, user_id, updates).await?
+                }
+                Mutation::Delete { user_id } => Self::remove(&mut tx, user_id).await?,
+            };
+            self.record(&mut tx, mutation, applied).await?;
+            outcomes.push(applied);
+        }
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(outcomes)
+    }
+
+    fn supports_transactions(&self) -> bool {
+        true
+    }
+
+    fn writes_outbox(&self) -> bool {
+        self.outbox
+    }
+}
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl Outbox for SqliteUserRepository {
+    async fn pending(&self, limit: usize) -> Result<Vec<OutboxEntry>> {
+        let rows = sqlx::query(
+            "SELECT id, event, created_at FROM user_outbox
+             WHERE published_at IS NULL ORDER BY id LIMIT ?",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(UserError::from)?;
+        rows.iter().map(|row| self.entry_from_row(row)).collect()
+    }
+
+    async fn claim(&self, limit: usize, lease: Duration) -> Result<Vec<OutboxEntry>> {
+        let now = Utc::now();
+        // SQLite runs one write at a time, so two relays never claim the same rows
+        let rows = sqlx::query(
+            "UPDATE user_outbox SET claimed_until = ?
+             WHERE id IN (
+                 SELECT id FROM user_outbox
+                 WHERE published_at IS NULL AND (claimed_until IS NULL OR claimed_until < ?)
+                 ORDER BY id LIMIT ?
+             )
+             RETURNING id, event, created_at",
+        )
+        .bind(outbox_lease_end(now, lease))
+        .bind(now)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(UserError::from)?;
+        let mut entries = rows
+            .iter()
+            .map(|row| self.entry_from_row(row))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.id);
+        Ok(entries)
+    }
+
+    async fn mark_published(&self, ids: &[i64]) -> Result<()> {
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let now = Utc::now();
+        for id in ids {
+            sqlx::query("UPDATE user_outbox SET published_at = ? WHERE id = ?")
+                .bind(now)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(UserError::from)?;
+        }
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(())
+    }
+
+    async fn purge_published(&self, before: DateTime<Utc>) -> Result<usize> {
+        let result = sqlx::query("DELETE FROM user_outbox WHERE published_at < ?")
+            .bind(before)
+            .execute(&self.pool)
+            .await
+            .map_err(UserError::from)?;
+        Ok(result.rows_affected() as usize)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteUserRepository {
+    fn entry_from_row(&self, row: &sqlx::sqlite::SqliteRow) -> Result<OutboxEntry> {
+        use sqlx::Row;
+
+        let event: String = row.try_get("event").map_err(UserError::from)?;
+        let event = serde_json::from_str(&event).context("Failed to parse outbox event")?;
+        Ok(OutboxEntry {
+            id: row.try_get("id").map_err(UserError::from)?,
+            event: self.upcaster.upcast(event)?,
+            created_at: row.try_get("created_at").map_err(UserError::from)?,
+        })
+    }
+}
+
+/// When a claim taken at `now` for `lease` runs out
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn outbox_lease_end(now: DateTime<Utc>, lease: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(lease)
+        .ok()
+        .and_then(|lease| now.checked_add_signed(lease))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+// Postgres backend for server applications
+#[cfg(feature = "postgres")]
+#[derive(Debug, Clone)]
+pub struct PostgresUserRepository {
+    pool: sqlx::PgPool,
+    outbox: bool,
+    tenant: Option<TenantId>,
+    upcaster: Arc<EventUpcaster>,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresUserRepository {
+    const SCHEMA: &'static str = "
+        CREATE TABLE IF NOT EXISTS users (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            email TEXT NOT NULL,
+            status TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL,
+            metadata JSONB NOT NULL DEFAULT '{}'::jsonb,
+            consents JSONB NOT NULL DEFAULT '[]'::jsonb,
+            two_factor JSONB
+        );
+        ALTER TABLE users ADD COLUMN IF NOT EXISTS consents JSONB NOT NULL DEFAULT '[]'::jsonb;
+        ALTER TABLE users ADD COLUMN IF NOT EXISTS two_factor JSONB;
+        CREATE INDEX IF NOT EXISTS idx_users_email ON users (email);
+        CREATE INDEX IF NOT EXISTS idx_users_status ON users (status);
+        CREATE INDEX IF NOT EXISTS idx_users_created_at ON users (created_at);
+        CREATE TABLE IF NOT EXISTS user_outbox (
+            id BIGSERIAL PRIMARY KEY,
+            event JSONB NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL,
+            claimed_until TIMESTAMPTZ,
+            published_at TIMESTAMPTZ
+        );
+        ALTER TABLE user_outbox ADD COLUMN IF NOT EXISTS claimed_until TIMESTAMPTZ;
+        CREATE INDEX IF NOT EXISTS idx_user_outbox_pending ON user_outbox (id)
+            WHERE published_at IS NULL;
+    ";
+
+    pub fn new(pool: sqlx::PgPool) -> Self {
+        Self {
+            pool,
+            outbox: false,
+            tenant: None,
+            upcaster: Arc::new(EventUpcaster::new()),
+        }
+    }
+
+    /// Write an event to the `user_outbox` table in the same transaction as each mutation
+    pub fn with_outbox(mut self) -> Self {
+        self.outbox = true;
+        self
+    }
+
+    /// Tag outbox events with the tenant this repository stores, for repositories built
+    /// by a tenant factory
+    pub fn with_tenant(mut self, tenant: TenantId) -> Self {
+        self.tenant = Some(tenant);
+        self
+    }
+
+    /// Read outbox events written under older schema versions with `upcaster`
+    pub fn with_event_upcaster(mut self, upcaster: Arc<EventUpcaster>) -> Self {
+        self.upcaster = upcaster;
+        self
+    }
+
+    /// Connect to a database and ensure the schema exists
+    pub async fn connect(url: &str) -> Result<Self> {
+        let pool = sqlx::PgPool::connect(url).await.map_err(UserError::from)?;
+        let repository = Self::new(pool);
+        repository.create_schema().await?;
+        Ok(repository)
+    }
+
+    /// Create the users table and indexes if they do not exist
+    pub async fn create_schema(&self) -> Result<()> {
+        sqlx::raw_sql(Self::SCHEMA)
+            .execute(&self.pool)
+            .await
+            .map_err(UserError::from)?;
+        Ok(())
+    }
+
+    pub fn pool(&self) -> &sqlx::PgPool {
+        &self.pool
+    }
+
+    /// A limit or offset as the BIGINT Postgres takes, refusing ones it cannot hold
+    fn bind_count(name: &str, value: usize) -> Result<i64> {
+        i64::try_from(value).map_err(|_| {
+            UserError::InvalidFilter {
+                message: format!("{} {} is too large", name, value),
+            }
+            .into()
+        })
+    }
+
+    fn user_from_row(row: &sqlx::postgres::PgRow) -> Result<User> {
+        use sqlx::Row;
+
+        let status: String = row.try_get("status").map_err(UserError::from)?;
+        let metadata: sqlx::types::Json<HashMap<MetadataKey, serde_json::Value>> =
+            row.try_get("metadata").map_err(UserError::from)?;
+        let consents: sqlx::types::Json<Vec<Consent>> =
+            row.try_get("consents").map_err(UserError::from)?;
+        let two_factor: Option<sqlx::types::Json<TwoFactor>> =
+            row.try_get("two_factor").map_err(UserError::from)?;
+        Ok(User {
+            id: row.try_get("id").map_err(UserError::from)?,
+            name: row.try_get("name").map_err(UserError::from)?,
+            email: row.try_get("email").map_err(UserError::from)?,
+            status: status.parse()?,
+            created_at: row.try_get("created_at").map_err(UserError::from)?,
+            metadata: metadata.0,
+            consents: consents.0,
+            two_factor: two_factor.map(|two_factor| Box::new(two_factor.0)),
+        })
+    }
+
+    async fn fetch(
+        conn: &mut sqlx::PgConnection,
+        user_id: &str,
+        for_update: bool,
+    ) -> Result<Option<User>> {
+        let sql = if for_update {
+            "SELECT * FROM users WHERE id = $1 FOR UPDATE"
+        } else {
+            "SELECT * FROM users WHERE id = $1"
+        };
+        let row = sqlx::query(sql)
+            .bind(user_id)
+            .fetch_optional(conn)
+            .await
+            .map_err(UserError::from)?;
+        row.as_ref().map(Self::user_from_row).transpose()
+    }
+
+    async fn insert(conn: &mut sqlx::PgConnection, user: &User) -> Result<User> {
+        let result = sqlx::query(
+            "INSERT INTO users (id, name, email, status, created_at, metadata, consents, two_factor)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             RETURNING *",
+        )
+        .bind(&user.id)
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(user.status.as_str())
+        .bind(user.created_at)
+        .bind(sqlx::types::Json(&user.metadata))
+        .bind(sqlx::types::Json(&user.consents))
+        .bind(user.two_factor.as_deref().map(sqlx::types::Json))
+        .fetch_one(conn)
+        .await;
+
+        match result {
+            Ok(row) => Self::user_from_row(&row),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(UserError::ApiError {
+                message: format!("User {} already exists", redact_id(&user.id)),
+            }
+            .into()),
+            Err(e) => Err(UserError::from(e).into()),
+        }
+    }
+
+    async fn modify(
+        conn: &mut sqlx::PgConnection,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        let Some(mut user) = Self::fetch(&mut *conn, user_id, true).await? else {
+            return Ok(false);
+        };
+        user.apply_updates(updates)?;
+        sqlx::query(
+            "UPDATE users
+             SET name = $2, email = $3, status = $4, created_at = $5, metadata = $6, consents = $7
+             WHERE id = $1",
+        )
+        .bind(&user.id)
+        .bind(&user.name)
+        .bind(&user.email)
+        .bind(user.status.as_str())
+        .bind(user.created_at)
+        .bind(sqlx::types::Json(&user.metadata))
+        .bind(sqlx::types::Json(&user.consents))
+        .execute(conn)
+        .await
+        .map_err(UserError::from)?;
+        Ok(true)
+    }
+
+    /// Queue the event for an applied mutation inside the caller's transaction
+    async fn record(
+        &self,
+        conn: &mut sqlx::PgConnection,
+        mutation: &Mutation,
+        applied: bool,
+    ) -> Result<()> {
+        if !self.outbox || !applied {
+            return Ok(());
+        }
+        sqlx::query("INSERT INTO user_outbox (event, created_at) VALUES ($1, $2)")
+            .bind(UserEvent::applied(self.tenant.as_ref(), mutation).to_stored()?)
+            .bind(Utc::now())
+            .execute(conn)
+            .await
+            .map_err(UserError::from)?;
+        Ok(())
+    }
+
+    async fn remove(conn: &mut sqlx::PgConnection, user_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(conn)
+            .await
+            .map_err(UserError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl UserRepository for PostgresUserRepository {
+    async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        let mut conn = self.pool.acquire().await.map_err(UserError::from)?;
+        Self::fetch(&mut conn, user_id, false).await
+    }
+
+    async fn create(&self, user: &User) -> Result<User> {
+        if !self.outbox {
+            let mut conn = self.pool.acquire().await.map_err(UserError::from)?;
+            return Self::insert(&mut conn, user).await;
+        }
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let created = Self::insert(&mut tx, user).await?;
+        self.record(&mut tx, &Mutation::Create(created.clone()), true)
+            .await?;
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(created)
+    }
+
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let updated = Self::modify(&mut tx, user_id, updates).await?;
+        if self.outbox {
+            let mutation = Mutation::Update {
+                user_id: user_id.to_string(),
+                updates: updates.clone(),
+            };
+            self.record(&mut tx, &mutation, updated).await?;
+        }
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(updated)
+    }
+
+    async fn swap_two_factor(
+        &self,
+        user_id: &str,
+        expected: Option<&TwoFactor>,
+        replacement: Option<&TwoFactor>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE users SET two_factor = $2 WHERE id = $1 AND two_factor IS NOT DISTINCT FROM $3",
+        )
+        .bind(user_id)
+        .bind(replacement.map(sqlx::types::Json))
+        .bind(expected.map(sqlx::types::Json))
+        .execute(&self.pool)
+        .await
+        .map_err(UserError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let deleted = Self::remove(&mut tx, user_id).await?;
+        let mutation = Mutation::Delete {
+            user_id: user_id.to_string(),
+        };
+        self.record(&mut tx, &mutation, deleted).await?;
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(deleted)
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        let rows = sqlx::query("SELECT * FROM users ORDER BY created_at, id LIMIT $1 OFFSET $2")
+            .bind(Self::bind_count("limit", limit)?)
+            .bind(Self::bind_count("offset", offset)?)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(UserError::from)?;
+        rows.iter().map(Self::user_from_row).collect()
+    }
+
+    async fn apply_batch(&self, mutations: &[Mutation]) -> Result<Vec<bool>> {
+        let mut tx = self.pool.begin().await.map_err(UserError::from)?;
+        let mut outcomes = Vec::with_capacity(mutations.len());
+        for mutation in mutations {
+            let applied = match mutation {
+                Mutation::Create(user) => Self::insert(&mut tx, user).await.map(|_| true)?,
+                Mutation::Update { user_id, updates } => {
+                    Self::modify(&mut tx, user_id, updates).await?
+                }
+                Mutation::Delete { user_id } => Self::remove(&mut tx, user_id).await?,
+            };
+            self.record(&mut tx, mutation, applied).await?;
+            outcomes.push(applied);
+        }
+        tx.commit().await.map_err(UserError::from)?;
+        Ok(outcomes)
+    }
+
+    fn supports_transactions(&self) -> bool {
+        true
+    }
+
+    fn writes_outbox(&self) -> bool {
+        self.outbox
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl Outbox for PostgresUserRepository {
+    async fn pending(&self, limit: usize) -> Result<Vec<OutboxEntry>> {
+        let rows = sqlx::query(
+            "SELECT id, event, created_at FROM user_outbox
+             WHERE published_at IS NULL ORDER BY id LIMIT $1",
+        )
+        .bind(Self::bind_count("limit", limit)?)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(UserError::from)?;
+        rows.iter().map(|row| self.entry_from_row(row)).collect()
+    }
+
+    async fn claim(&self, limit: usize, lease: Duration) -> Result<Vec<OutboxEntry>> {
+        let now = Utc::now();
+        // Rows another relay is claiming right now are skipped rather than waited for
+        let rows = sqlx::query(
+            "UPDATE user_outbox SET claimed_until = $1
+             WHERE id IN (
+                 SELECT id FROM user_outbox
+                 WHERE published_at IS NULL AND (claimed_until IS NULL OR claimed_until < $2)
+                 ORDER BY id LIMIT $3
+                 FOR UPDATE SKIP LOCKED
+             )
+             RETURNING id, event, created_at",
+        )
+        .bind(outbox_lease_end(now, lease))
+        .bind(now)
+        .bind(Self::bind_count("limit", limit)?)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(UserError::from)?;
+        let mut entries = rows
+            .iter()
+            .map(|row| self.entry_from_row(row))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.id);
+        Ok(entries)
+    }
+
+    async fn mark_published(&self, ids: &[i64]) -> Result<()> {
+        sqlx::query("UPDATE user_outbox SET published_at = $1 WHERE id = ANY($2)")
+            .bind(Utc::now())
+            .bind(ids)
+            .execute(&self.pool)
+            .await
+            .map_err(UserError::from)?;
+        Ok(())
+    }
+
+    async fn purge_published(&self, before: DateTime<Utc>) -> Result<usize> {
+        let result = sqlx::query("DELETE FROM user_outbox WHERE published_at < $1")
+            .bind(before)
+            .execute(&self.pool)
+            .await
+            .map_err(UserError::from)?;
+        Ok(result.rows_affected() as usize)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresUserRepository {
+    fn entry_from_row(&self, row: &sqlx::postgres::PgRow) -> Result<OutboxEntry> {
+        use sqlx::Row;
+
+        let event: serde_json::Value = row.try_get("event").map_err(UserError::from)?;
+        Ok(OutboxEntry {
+            id: row.try_get("id").map_err(UserError::from)?,
+            event: self.upcaster.upcast(event)?,
+            created_at: row.try_get("created_at").map_err(UserError::from)?,
+        })
+    }
+}
+
+// Mutation recorded while the backend was unreachable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedMutation {
+    pub idempotency_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    pub mutation: Mutation,
+    pub queued_at: DateTime<Utc>,
+}
+
+// Outcome of replaying the offline queue
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    /// Mutations the backend accepted but did not apply, e.g. updates of users deleted
+    /// while they were queued
+    pub not_applied: usize,
+    pub remaining: usize,
+    pub failed: Vec<(QueuedMutation, String)>,
+}
+
+// Durable, ordered queue of mutations awaiting replay
+#[derive(Debug, Default)]
+pub struct OfflineQueue {
+    path: Option<PathBuf>,
+    entries: Mutex<std::collections::VecDeque<QueuedMutation>>,
+}
+
+impl OfflineQueue {
+    /// Queue that lives only as long as the process
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Queue persisted as JSON lines at `path`, reloading anything left from a previous run
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).context("Corrupt offline queue entry"))
+                .collect::<Result<_>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read offline queue {}", path.display()))
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    fn persist(&self, entries: &std::collections::VecDeque<QueuedMutation>) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut contents = String::new();
+        for entry in entries {
+            contents.push_str(
+                &serde_json::to_string(entry).context("Failed to serialize queue entry")?,
+            );
+            contents.push('\n');
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, contents)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .with_context(|| format!("Failed to persist offline queue {}", path.display()))
+    }
+
+    /// Append a mutation, returning its idempotency key
+    pub fn push(&self, tenant: Option<&TenantId>, mutation: Mutation) -> Result<String> {
+        let entry = QueuedMutation {
+            idempotency_key: uuid::Uuid::new_v4().to_string(),
+            tenant: tenant.cloned(),
+            mutation,
+            queued_at: Utc::now(),
+        };
+        let key = entry.idempotency_key.clone();
+        let mut entries = self.entries.lock().expect("offline queue poisoned");
+        entries.push_back(entry);
+        self.persist(&entries)?;
+        Ok(key)
+    }
+
+    pub fn peek(&self) -> Option<QueuedMutation> {
+        self.entries
+            .lock()
+            .expect("offline queue poisoned")
+            .front()
+            .cloned()
+    }
+
+    fn pop(&self, idempotency_key: &str) -> Result<()> {
+        let mut entries = self.entries.lock().expect("offline queue poisoned");
+        if entries
+            .front()
+            .is_some_and(|entry| entry.idempotency_key == idempotency_key)
+        {
+            entries.pop_front();
+            self.persist(&entries)?;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("offline queue poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn entries(&self) -> Vec<QueuedMutation> {
+        self.entries
+            .lock()
+            .expect("offline queue poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Drop every queued mutation of one user, returning how many were removed
+    pub fn remove_user(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<usize> {
+        let mut entries = self.entries.lock().expect("offline queue poisoned");
+        let before = entries.len();
+        entries
+            .retain(|entry| entry.tenant.as_ref() != tenant || entry.mutation.user_id() != user_id);
+        let removed = before - entries.len();
+        if removed > 0 {
+            self.persist(&entries)?;
+        }
+        Ok(removed)
+    }
+}
+
+// Persistent state of a sync relationship between two repositories
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// Remote change cursor from the last completed sync
+    pub cursor: Option<String>,
+    /// Users as both sides agreed on them after the last sync
+    pub base: HashMap<String, User>,
+    pub last_synced_at: Option<DateTime<Utc>>,
+}
+
+// Both sides changed the same user since the last sync
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncConflict {
+    pub user_id: String,
+    pub base: Option<User>,
+    pub local: Option<User>,
+    pub remote: Option<User>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    KeepLocal,
+    KeepRemote,
+    Merged(User),
+    /// Leave both sides untouched and report the conflict again next time
+    Defer,
+}
+
+impl Resolution {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Resolution::KeepLocal => "keep_local",
+            Resolution::KeepRemote => "keep_remote",
+            Resolution::Merged(_) => "merged",
+            Resolution::Defer => "defer",
+        }
+    }
+}
+
+// Strategy for settling sync conflicts
+pub trait ConflictResolver: Send + Sync {
+    fn resolve(&self, conflict: &SyncConflict) -> Resolution;
+}
+
+impl<F> ConflictResolver for F
+where
+    F: Fn(&SyncConflict) -> Resolution + Send + Sync,
+{
+    fn resolve(&self, conflict: &SyncConflict) -> Resolution {
+        self(conflict)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalWins;
+
+impl ConflictResolver for LocalWins {
+    fn resolve(&self, _: &SyncConflict) -> Resolution {
+        Resolution::KeepLocal
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RemoteWins;
+
+impl ConflictResolver for RemoteWins {
+    fn resolve(&self, _: &SyncConflict) -> Resolution {
+        Resolution::KeepRemote
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncPhase {
+    Pull,
+    Scan,
+    Reconcile,
+    Push,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncProgress {
+    pub phase: SyncPhase,
+    pub processed: usize,
+    pub total: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    pub pulled: usize,
+    pub pushed: usize,
+    pub deleted_local: usize,
+    pub deleted_remote: usize,
+    pub conflicts: Vec<SyncConflictRecord>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncConflictRecord {
+    pub user_id: String,
+    pub resolution: String,
+}
+
+type ProgressFn = Box<dyn Fn(&SyncProgress) + Send + Sync>;
+
+// Reconciles a local repository with a remote one
+pub struct SyncEngine {
+    local: Arc<dyn UserRepository>,
+    remote: Arc<dyn UserRepository>,
+    resolver: Box<dyn ConflictResolver>,
+    on_progress: Option<ProgressFn>,
+    page_size: usize,
+}
+
+impl fmt::Debug for SyncEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncEngine")
+            .field("local", &self.local)
+            .field("remote", &self.remote)
+            .field("page_size", &self.page_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SyncEngine {
+    pub fn new(local: Arc<dyn UserRepository>, remote: Arc<dyn UserRepository>) -> Self {
+        Self {
+            local,
+            remote,
+            resolver: Box::new(RemoteWins),
+            on_progress: None,
+            page_size: 500,
+        }
+    }
+
+    pub fn with_resolver(mut self, resolver: impl ConflictResolver + 'static) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    pub fn with_progress(
+        mut self,
+        on_progress: impl Fn(&SyncProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    fn progress(&self, phase: SyncPhase, processed: usize, total: Option<usize>) {
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(&SyncProgress {
+                phase,
+                processed,
+                total,
+            });
+        }
+    }
+
+    /// Pull remote changes, push local modifications and settle conflicts.
+    /// `state` is only updated when the sync completes.
+    pub async fn sync(&self, state: &mut SyncState) -> Result<SyncReport> {
+        let mut report = SyncReport::default();
+
+        // Pull every remote change since the cursor
+        let mut remote_changes: HashMap<String, Option<User>> = HashMap::new();
+        let mut remote_seen = std::collections::HashSet::new();
+        let mut complete_listing = true;
+        let mut cursor = state.cursor.clone();
+        loop {
+            let page = self
+                .remote
+                .changes_since(cursor.as_deref(), self.page_size)
+                .await
+                .context("Failed to pull remote changes")?;
+            complete_listing &= page.complete;
+            for user in page.changed {
+                remote_seen.insert(user.id.clone());
+                if state.base.get(&user.id) != Some(&user) {
+                    remote_changes.insert(user.id.clone(), Some(user));
+                }
+            }
+            for user_id in page.deleted {
+                remote_changes.insert(user_id, None);
+            }
+            self.progress(SyncPhase::Pull, remote_seen.len(), None);
+            if let Some(next) = page.next_cursor {
+                cursor = Some(next);
+            }
+            if !page.has_more {
+                break;
+            }
+        }
+        if complete_listing {
+            for user_id in state.base.keys() {
+                if !remote_seen.contains(user_id) {
+                    remote_changes.insert(user_id.clone(), None);
+                }
+            }
+        }
+        report.cursor = if complete_listing { None } else { cursor };
+
+        // Scan local state for modifications relative to the base
+        let mut local_users = HashMap::new();
+        let mut offset = 0;
+        loop {
+            let page = self.local.list(offset, self.page_size).await?;
+            let fetched = page.len();
+            offset += fetched;
+            local_users.extend(page.into_iter().map(|u| (u.id.clone(), u)));
+            self.progress(SyncPhase::Scan, local_users.len(), None);
+            if fetched < self.page_size {
+                break;
+            }
+        }
+        let mut local_changes: HashMap<String, Option<User>> = HashMap::new();
+        for (user_id, user) in &local_users {
+            if state.base.get(user_id) != Some(user) {
+                local_changes.insert(user_id.clone(), Some(user.clone()));
+            }
+        }
+        for user_id in state.base.keys() {
+            if !local_users.contains_key(user_id) {
+                local_changes.insert(user_id.clone(), None);
+            }
+        }
+
+        // Apply remote changes locally, resolving conflicts along the way
+        let mut new_base = state.base.clone();
+        let mut to_push: Vec<(String, Option<User>)> = Vec::new();
+        let total = remote_changes.len();
+        for (processed, (user_id, remote)) in remote_changes.into_iter().enumerate() {
+            self.progress(SyncPhase::Reconcile, processed, Some(total));
+            let winner = match local_changes.remove(&user_id) {
+                Some(local) if local == remote => Some((None, local)),
+                Some(local) => {
+                    let conflict = SyncConflict {
+                        user_id: user_id.clone(),
+                        base: state.base.get(&user_id).cloned(),
+                        local: local.clone(),
+                        remote: remote.clone(),
+                    };
+                    let resolution = self.resolver.resolve(&conflict);
+                    report.conflicts.push(SyncConflictRecord {
+                        user_id: user_id.clone(),
+                        resolution: resolution.label().to_string(),
+                    });
+                    match resolution {
+                        Resolution::KeepRemote => Some((Some(remote.clone()), remote)),
+                        Resolution::KeepLocal => {
+                            to_push.push((user_id.clone(), local.clone()));
+                            Some((None, local))
+                        }
+                        Resolution::Merged(user) => {
+                            to_push.push((user_id.clone(), Some(user.clone())));
+                            Some((Some(Some(user.clone())), Some(user)))
+                        }
+                        Resolution::Defer => None,
+                    }
+                }
+                None => Some((Some(remote.clone()), remote)),
+            };
+
+            let Some((apply_locally, agreed)) = winner else {
+                continue;
+            };
+            if let Some(value) = apply_locally {
+                match &value {
+                    Some(user) => {
+                        Self::put(&*self.local, user).await?;
+                        report.pulled += 1;
+                    }
+                    None => {
+                        if self.local.delete(&user_id).await? {
+                            report.deleted_local += 1;
+                        }
+                    }
+                }
+            }
+            match agreed {
+                Some(user) => new_base.insert(user_id, user),
+                None => new_base.remove(&user_id),
+            };
+        }
+
+        // Push the remaining local modifications
+        to_push.extend(local_changes);
+        let total = to_push.len();
+        for (processed, (user_id, local)) in to_push.into_iter().enumerate() {
+            self.progress(SyncPhase::Push, processed, Some(total));
+            match local {
+                Some(user) => {
+                    Self::put(&*self.remote, &user).await?;
+                    report.pushed += 1;
+                    new_base.insert(user_id, user);
+                }
+                None => {
+                    if self.remote.delete(&user_id).await? {
+                        report.deleted_remote += 1;
+                    }
+                    new_base.remove(&user_id);
+                }
+            }
+        }
+
+        state.base = new_base;
+        state.cursor = report.cursor.clone();
+        state.last_synced_at = Some(Utc::now());
+        tracing::info!(
+            pulled = report.pulled,
+            pushed = report.pushed,
+            conflicts = report.conflicts.len(),
+            "Sync finished"
+        );
+        Ok(report)
+    }
+
+    /// Create or fully overwrite a user in a repository
+    async fn put(repository: &dyn UserRepository, user: &User) -> Result<()> {
+        if repository.get(&user.id).await?.is_none() {
+            repository.create(user).await?;
+            return Ok(());
+        }
+        repository
+            .update(&user.id, &user.to_update_fields())
+            .await?;
+        Ok(())
+    }
+}
+
+// Kind of mutation captured in the change log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Create,
+    Update,
+    Delete,
+    /// Anonymized in place by `UserManager::erase_user`
+    Erase,
+}
+
+// One captured mutation with before/after snapshots
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    pub sequence: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    pub kind: ChangeKind,
+    pub user_id: String,
+    pub before: Option<User>,
+    pub after: Option<User>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+impl ChangeRecord {
+    /// Mutation that reproduces this change against another repository
+    pub fn to_mutation(&self) -> Option<Mutation> {
+        match self.kind {
+            ChangeKind::Create => self.after.clone().map(Mutation::Create),
+            ChangeKind::Update | ChangeKind::Erase => {
+                self.after.as_ref().map(|after| Mutation::Update {
+                    user_id: self.user_id.clone(),
+                    updates: after.to_update_fields(),
+                })
+            }
+            ChangeKind::Delete => Some(Mutation::Delete {
+                user_id: self.user_id.clone(),
+            }),
+        }
+    }
+
+    /// Lifecycle event this change published when it happened
+    pub fn to_event(&self) -> Option<UserEvent> {
+        let tenant = self.tenant.clone();
+        let user_id = self.user_id.clone();
+        match self.kind {
+            ChangeKind::Create => self
+                .after
+                .clone()
+                .map(|user| UserEvent::UserCreated { tenant, user }),
+            ChangeKind::Update => {
+                let before = self.before.as_ref().map(User::to_update_fields);
+                let after = self.after.as_ref()?.to_update_fields();
+                let fields: std::collections::BTreeSet<String> = after
+                    .iter()
+                    .filter(|(field, value)| {
+                        before.as_ref().and_then(|before| before.get(*field)) != Some(*value)
+                    })
+                    .map(|(field, _)| field.clone())
+                    .collect();
+                Some(UserEvent::UserUpdated {
+                    tenant,
+                    user_id,
+                    fields: fields.into_iter().collect(),
+                })
+            }
+            ChangeKind::Delete => Some(UserEvent::UserDeleted { tenant, user_id }),
+            ChangeKind::Erase => Some(UserEvent::UserErased { tenant, user_id }),
+        }
+    }
+}
+
+// How long change records are kept
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionPolicy {
+    pub max_entries: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+// Filter for reading the change log
+#[derive(Debug, Clone, Default)]
+pub struct ChangeQuery {
+    pub tenant: Option<TenantId>,
+    pub user_id: Option<String>,
+    pub kinds: Option<Vec<ChangeKind>>,
+    pub after_sequence: Option<u64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl ChangeQuery {
+    pub fn for_user(user_id: &str) -> Self {
+        Self {
+            user_id: Some(user_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn matches(&self, record: &ChangeRecord) -> bool {
+        self.tenant
+            .as_ref()
+            .is_none_or(|tenant| record.tenant.as_ref() == Some(tenant))
+            && self.user_id.as_ref().is_none_or(|id| *id == record.user_id)
+            && self
+                .kinds
+                .as_ref()
+                .is_none_or(|kinds| kinds.contains(&record.kind))
+            && self.after_sequence.is_none_or(|seq| record.sequence > seq)
+            && self.since.is_none_or(|since| record.recorded_at >= since)
+            && self.until.is_none_or(|until| record.recorded_at < until)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ChangeLogState {
+    records: std::collections::VecDeque<ChangeRecord>,
+    next_sequence: u64,
+    subscribers: Vec<tokio::sync::mpsc::Sender<ChangeRecord>>,
+}
+
+// Append-only log of every mutation made through a `UserManager`
+#[derive(Debug, Default)]
+pub struct ChangeLog {
+    path: Option<PathBuf>,
+    retention: RetentionPolicy,
+    state: Mutex<ChangeLogState>,
+}
+
+impl ChangeLog {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Log persisted as JSON lines at `path`, reloading earlier records
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let records: std::collections::VecDeque<ChangeRecord> = match std::fs::read_to_string(&path)
+        {
+            Ok(contents) => contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).context("Corrupt change log entry"))
+                .collect::<Result<_>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read change log {}", path.display()))
+            }
+        };
+        let next_sequence = records.back().map_or(1, |record| record.sequence + 1);
+        Ok(Self {
+            path: Some(path),
+            retention: RetentionPolicy::default(),
+            state: Mutex::new(ChangeLogState {
+                records,
+                next_sequence,
+                ..Default::default()
+            }),
+        })
+    }
+
+    pub fn with_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Append a change, assigning the next sequence number
+    pub fn append(
+        &self,
+        tenant: Option<&TenantId>,
+        kind: ChangeKind,
+        user_id: &str,
+        before: Option<User>,
+        after: Option<User>,
+    ) -> Result<ChangeRecord> {
+        let mut state = self.state.lock().expect("change log poisoned");
+        let record = ChangeRecord {
+            sequence: state.next_sequence.max(1),
+            tenant: tenant.cloned(),
+            kind,
+            user_id: user_id.to_string(),
+            before,
+            after,
+            recorded_at: Utc::now(),
+        };
+        state.next_sequence = record.sequence + 1;
+        state.records.push_back(record.clone());
+        // A full subscriber misses this record and reads it back from the log later
+        state.subscribers.retain(|subscriber| {
+            !matches!(
+                subscriber.try_send(record.clone()),
+                Err(tokio::sync::mpsc::error::TrySendError::Closed(_))
+            )
+        });
+
+        let pruned = self.prune(&mut state);
+        if let Some(path) = &self.path {
+            if pruned > 0 {
+                Self::rewrite(path, &state.records)?;
+            } else {
+                use std::io::Write;
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open change log {}", path.display()))?;
+                let line = serde_json::to_string(&record).context("Failed to serialize change")?;
+                writeln!(file, "{}", line).context("Failed to append to change log")?;
+            }
+        }
+        Ok(record)
+    }
+
+    fn prune(&self, state: &mut ChangeLogState) -> usize {
+        let before = state.records.len();
+        if let Some(max_age) = self.retention.max_age {
+            let cutoff =
+                Utc::now() - chrono::Duration::from_std(max_age).unwrap_or(chrono::Duration::MAX);
+            while state
+                .records
+                .front()
+                .is_some_and(|r| r.recorded_at < cutoff)
+            {
+                state.records.pop_front();
+            }
+        }
+        if let Some(max_entries) = self.retention.max_entries {
+            while state.records.len() > max_entries {
+                state.records.pop_front();
+            }
+        }
+        before - state.records.len()
+    }
+
+    fn rewrite(
+        path: &std::path::Path,
+        records: &std::collections::VecDeque<ChangeRecord>,
+    ) -> Result<()> {
+        let mut contents = String::new();
+        for record in records {
+            contents
+                .push_str(&serde_json::to_string(record).context("Failed to serialize change")?);
+            contents.push('\n');
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, contents)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .with_context(|| format!("Failed to compact change log {}", path.display()))
+    }
+
+    /// Anonymize the snapshots in one user's records, returning how many records were
+    /// rewritten; the log keeps its sequence but no longer holds the old values
+    pub fn redact_user(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        erased_at: DateTime<Utc>,
+    ) -> Result<usize> {
+        let mut state = self.state.lock().expect("change log poisoned");
+        let mut redacted = 0;
+        for record in state.records.iter_mut() {
+            if record.tenant.as_ref() != tenant || record.user_id != user_id {
+                continue;
+            }
+            for user in [&mut record.before, &mut record.after]
+                .into_iter()
+                .flatten()
+            {
+                *user = user.anonymized(erased_at);
+            }
+            redacted += 1;
+        }
+        if let (Some(path), true) = (&self.path, redacted > 0) {
+            Self::rewrite(path, &state.records)?;
+        }
+        Ok(redacted)
+    }
+
+    /// Apply the retention policy now, returning how many records were dropped
+    pub fn enforce_retention(&self) -> Result<usize> {
+        let mut state = self.state.lock().expect("change log poisoned");
+        let pruned = self.prune(&mut state);
+        if let (Some(path), true) = (&self.path, pruned > 0) {
+            Self::rewrite(path, &state.records)?;
+        }
+        Ok(pruned)
+    }
+
+    pub fn query(&self, query: &ChangeQuery) -> Vec<ChangeRecord> {
+        let state = self.state.lock().expect("change log poisoned");
+        state
+            .records
+            .iter()
+            .filter(|record| query.matches(record))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    /// Every change after `after_sequence` (0 for the whole log) followed by changes as
+    /// they are appended, with no gap or repeat between the two
+    pub fn subscribe(
+        self: &Arc<Self>,
+        after_sequence: u64,
+        capacity: usize,
+    ) -> Result<ChangeSubscription> {
+        let (sender, live) = tokio::sync::mpsc::channel(capacity.max(1));
+        let mut state = self.state.lock().expect("change log poisoned");
+        let backlog = Self::since(&state, after_sequence)?;
+        state.subscribers.push(sender);
+        Ok(ChangeSubscription {
+            log: self.clone(),
+            cursor: after_sequence,
+            backlog,
+            live,
+        })
+    }
+
+    /// Retained records after `sequence`, failing if retention already dropped some
+    fn since(
+        state: &ChangeLogState,
+        sequence: u64,
+    ) -> Result<std::collections::VecDeque<ChangeRecord>> {
+        let oldest = state
+            .records
+            .front()
+            .map_or(state.next_sequence.max(1), |record| record.sequence);
+        if sequence + 1 < oldest {
+            return Err(UserError::CursorExpired {
+                cursor: sequence,
+                oldest,
+            }
+            .into());
+        }
+        let start = state
+            .records
+            .partition_point(|record| record.sequence <= sequence);
+        Ok(state.records.range(start..).cloned().collect())
+    }
+
+    pub fn last_sequence(&self) -> Option<u64> {
+        let state = self.state.lock().expect("change log poisoned");
+        state.records.back().map(|record| record.sequence)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state
+            .lock()
+            .expect("change log poisoned")
+            .records
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Re-apply matching changes to another repository in sequence order
+    pub async fn replay_into(
+        &self,
+        repository: &dyn UserRepository,
+        query: &ChangeQuery,
+    ) -> Result<usize> {
+        let mut replayed = 0;
+        for record in self.query(query) {
+            if let Some(mutation) = record.to_mutation() {
+                repository
+                    .apply_batch(std::slice::from_ref(&mutation))
+                    .await
+                    .with_context(|| format!("Replay failed at change {}", record.sequence))?;
+                replayed += 1;
+            }
+        }
+        Ok(replayed)
+    }
+}
+
+// Change log records from a starting cursor, historical ones first and then live ones.
+// Records a slow subscriber missed are read back from the log.
+#[derive(Debug)]
+pub struct ChangeSubscription {
+    log: Arc<ChangeLog>,
+    cursor: u64,
+    backlog: std::collections::VecDeque<ChangeRecord>,
+    live: tokio::sync::mpsc::Receiver<ChangeRecord>,
+}
+
+impl ChangeSubscription {
+    /// Sequence of the last record returned; store it to resume from there
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    /// Next record in sequence order, waiting for one to be appended if caught up
+    pub async fn next(&mut self) -> Result<Option<ChangeRecord>> {
+        use tokio::sync::mpsc::error::TryRecvError;
+
+        loop {
+            if let Some(record) = self.backlog.pop_front() {
+                self.cursor = record.sequence;
+                return Ok(Some(record));
+            }
+            let record = match self.live.try_recv() {
+                Ok(record) => record,
+                Err(TryRecvError::Disconnected) => return Ok(None),
+                Err(TryRecvError::Empty) => {
+                    // Anything appended while the channel was full is only in the log
+                    self.read_back()?;
+                    if !self.backlog.is_empty() {
+                        continue;
+                    }
+                    let Some(record) = self.live.recv().await else {
+                        return Ok(None);
+                    };
+                    record
+                }
+            };
+            if record.sequence <= self.cursor {
+                continue;
+            }
+            if record.sequence == self.cursor + 1 {
+                self.cursor = record.sequence;
+                return Ok(Some(record));
+            }
+            tracing::debug!(
+                cursor = self.cursor,
+                "Change subscriber lagged; reading back"
+            );
+            self.read_back()?;
+        }
+    }
+
+    fn read_back(&mut self) -> Result<()> {
+        let state = self.log.state.lock().expect("change log poisoned");
+        self.backlog = ChangeLog::since(&state, self.cursor)?;
+        Ok(())
+    }
+}
+
+// Shutdown configuration
+#[derive(Debug, Clone)]
+pub struct ShutdownOptions {
+    pub drain_timeout: Duration,
+    pub flush_cache_to: Option<PathBuf>,
+}
+
+impl Default for ShutdownOptions {
+    fn default() -> Self {
+        Self {
+            drain_timeout: Duration::from_secs(10),
+            flush_cache_to: None,
+        }
+    }
+}
+
+impl ShutdownOptions {
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    pub fn flush_cache_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.flush_cache_to = Some(path.into());
+        self
+    }
+}
+
+// Outcome of a shutdown
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ShutdownReport {
+    pub drained: bool,
+    pub abandoned_requests: usize,
+    pub background_tasks_stopped: usize,
+    pub background_tasks_aborted: usize,
+    pub flushed_entries: Option<usize>,
+}
+
+// Tracks in-flight requests so shutdown can wait for them
+#[derive(Debug, Default)]
+struct InFlight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+struct InFlightGuard<'a> {
+    in_flight: &'a InFlight,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.in_flight.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.in_flight.idle.notify_waiters();
+        }
+    }
+}
+
+// Cache entries are keyed per tenant so tenants never see each other's users
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    tenant: Option<TenantId>,
+    user_id: String,
+}
+
+impl CacheKey {
+    fn new(tenant: Option<&TenantId>, user_id: &str) -> Self {
+        Self {
+            tenant: tenant.cloned(),
+            user_id: user_id.to_string(),
+        }
+    }
+}
+
+// Cached users with a running estimate of the memory they hold. Reads go through `Deref`;
+// every write goes through a method so the estimate stays in step.
+#[derive(Debug, Default)]
+struct UserCache {
+    entries: HashMap<CacheKey, User>,
+    bytes: usize,
+    /// Write order, for evicting the entry written longest ago
+    written: std::collections::BTreeMap<u64, CacheKey>,
+    stamps: HashMap<CacheKey, u64>,
+    next_stamp: u64,
+}
+
+impl Deref for UserCache {
+    type Target = HashMap<CacheKey, User>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl UserCache {
+    /// Estimated bytes of one entry, key included
+    fn entry_size(key: &CacheKey, user: &User) -> usize {
+        std::mem::size_of::<CacheKey>()
+            + key.user_id.capacity()
+            + key
+                .tenant
+                .as_ref()
+                .map_or(0, |tenant| tenant.as_str().len())
+            + user.estimated_size()
+    }
+
+    /// Bytes the cache would hold after putting `user` under `key`
+    fn bytes_with(&self, key: &CacheKey, user: &User) -> usize {
+        let replaced = self
+            .entries
+            .get_key_value(key)
+            .map_or(0, |(key, user)| Self::entry_size(key, user));
+        self.bytes - replaced + Self::entry_size(key, user)
+    }
+
+    fn insert(&mut self, key: CacheKey, user: User) -> Option<User> {
+        self.bytes = self.bytes_with(&key, &user);
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        if let Some(previous) = self.stamps.insert(key.clone(), stamp) {
+            self.written.remove(&previous);
+        }
+        self.written.insert(stamp, key.clone());
+        self.entries.insert(key, user)
+    }
+
+    /// Changes whenever the entry is written, for spotting writes made meanwhile
+    fn version(&self, key: &CacheKey) -> Option<u64> {
+        self.stamps.get(key).copied()
+    }
+
+    /// Entry written longest ago, the first to go when the cache is full
+    fn oldest(&self) -> Option<&CacheKey> {
+        self.written.values().next()
+    }
+
+    /// Overwrite an entry that is already cached, returning whether there was one
+    fn replace(&mut self, key: &CacheKey, user: User) -> bool {
+        if !self.entries.contains_key(key) {
+            return false;
+        }
+        self.insert(key.clone(), user);
+        true
+    }
+
+    fn remove_entry(&mut self, key: &CacheKey) -> Option<(CacheKey, User)> {
+        let (key, user) = self.entries.remove_entry(key)?;
+        self.bytes -= Self::entry_size(&key, &user);
+        if let Some(stamp) = self.stamps.remove(&key) {
+            self.written.remove(&stamp);
+        }
+        Some((key, user))
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> Option<User> {
+        self.remove_entry(key).map(|(_, user)| user)
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = (CacheKey, User)> + '_ {
+        self.bytes = 0;
+        self.written.clear();
+        self.stamps.clear();
+        self.entries.drain()
+    }
+}
+
+// Striped locks serializing read-modify-write updates of the same user, so two metadata
+// updates through one manager cannot both build on the same stored metadata
+struct UpdateLocks(Vec<tokio::sync::Mutex<()>>);
+
+impl UpdateLocks {
+    const STRIPES: usize = 64;
+
+    async fn lock(&self, key: &CacheKey) -> tokio::sync::MutexGuard<'_, ()> {
+        self.0[self.stripe(key)].lock().await
+    }
+
+    /// Lock every key, taking each stripe once and in order so concurrent callers cannot
+    /// deadlock
+    async fn lock_all(&self, keys: &[CacheKey]) -> Vec<tokio::sync::MutexGuard<'_, ()>> {
+        let mut stripes: Vec<usize> = keys.iter().map(|key| self.stripe(key)).collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        let mut guards = Vec::with_capacity(stripes.len());
+        for stripe in stripes {
+            guards.push(self.0[stripe].lock().await);
+        }
+        guards
+    }
+
+    fn stripe(&self, key: &CacheKey) -> usize {
+        use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher};
+        let hash = BuildHasherDefault::<DefaultHasher>::default().hash_one(key);
+        hash as usize % self.0.len()
+    }
+}
+
+impl Default for UpdateLocks {
+    fn default() -> Self {
+        Self((0..Self::STRIPES).map(|_| Default::default()).collect())
+    }
+}
+
+// Estimated memory held by the cache, from `UserManager::memory_usage`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CacheMemoryUsage {
+    pub entries: usize,
+    /// Approximate; allocator overhead and hash table slack are not counted
+    pub estimated_bytes: usize,
+    pub largest_entry_bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget_bytes: Option<usize>,
+}
+
+/// A user within a tenant, for per-user state kept beside the repository
+pub(crate) type UserKey = (Option<TenantId>, String);
+
+type TenantRepositoryFactory = Box<dyn Fn(&TenantId) -> Arc<dyn UserRepository> + Send + Sync>;
+
+// What an operation reads or writes, for picking the region that serves it
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    Tenant,
+    User(&'a str),
+    NewUser(&'a User),
+}
+
+impl<'a> Target<'a> {
+    fn of(mutation: &'a Mutation) -> Self {
+        match mutation {
+            Mutation::Create(user) => Target::NewUser(user),
+            Mutation::Update { user_id, .. } | Mutation::Delete { user_id } => {
+                Target::User(user_id)
+            }
+        }
+    }
+}
+
+// Reason a hook gave for denying an operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Veto(pub String);
+
+// Context for `UserHook::on_fetch`, after the lookup and before the result is returned
+#[derive(Debug)]
+pub struct FetchContext<'a> {
+    pub tenant: Option<&'a TenantId>,
+    pub user_id: &'a str,
+    pub cache_hit: bool,
+    pub user: Option<&'a User>,
+}
+
+// Context for `UserHook::on_update`, before the update reaches the backend
+#[derive(Debug)]
+pub struct UpdateContext<'a> {
+    pub tenant: Option<&'a TenantId>,
+    pub user_id: &'a str,
+    /// The stored user as last seen by this manager, if it exists
+    pub current: Option<&'a User>,
+    pub updates: &'a HashMap<String, serde_json::Value>,
+}
+
+// Why a cache entry was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictReason {
+    /// Written through this manager
+    Invalidated,
+    /// Changed by another instance, via the invalidation bus
+    Remote,
+    Cleared,
+    /// Dropped to stay within the configured cache capacity
+    Capacity,
+}
+
+// Context for `UserHook::on_cache_evict`
+#[derive(Debug)]
+pub struct EvictContext<'a> {
+    pub tenant: Option<&'a TenantId>,
+    pub user_id: &'a str,
+    pub user: &'a User,
+    pub reason: EvictReason,
+}
+
+// Application callbacks run synchronously by `UserManager`; returning a `Veto` denies the operation
+pub trait UserHook: Send + Sync {
+    fn on_fetch(&self, ctx: &FetchContext<'_>) -> Result<(), Veto> {
+        let _ = ctx;
+        Ok(())
+    }
+
+    fn on_update(&self, ctx: &UpdateContext<'_>) -> Result<(), Veto> {
+        let _ = ctx;
+        Ok(())
+    }
+
+    fn on_cache_evict(&self, ctx: &EvictContext<'_>) {
+        let _ = ctx;
+    }
+}
+
+tokio::task_local! {
+    static AUDIT_ACTOR: String;
+}
+
+/// Run `operation` with mutations attributed to `actor` in the audit log
+pub async fn with_audit_actor<F: std::future::Future>(
+    actor: impl Into<String>,
+    operation: F,
+) -> F::Output {
+    AUDIT_ACTOR.scope(actor.into(), operation).await
+}
+
+// Before/after values of one changed field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+}
+
+// Who changed which user record, when, and how
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub id: String,
+    pub at: DateTime<Utc>,
+    pub actor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    pub action: ChangeKind,
+    pub user_id: String,
+    /// Only the fields that differ, keyed by wire name
+    pub changes: std::collections::BTreeMap<String, FieldChange>,
+    /// `hash` of the previous entry in a chained log; `None` for the first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_hash: Option<String>,
+    /// Random per-entry salt for `content_hash`, dropped when the entry is scrubbed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+    /// Salted SHA-256 over `user_id` and `changes`; the chain covers this instead of the
+    /// personal data itself, so scrubbing an entry leaves the chain intact
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// SHA-256 over this entry's canonical JSON without `hash` or personal data, set by
+    /// chaining sinks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl AuditEvent {
+    pub const SYSTEM_ACTOR: &'static str = "system";
+    /// `user_id` of a scrubbed entry
+    pub const ERASED_SUBJECT: &'static str = "erased";
+
+    /// Event for the current actor, diffing the two snapshots field by field
+    pub fn new(
+        tenant: Option<&TenantId>,
+        action: ChangeKind,
+        user_id: &str,
+        before: Option<&User>,
+        after: Option<&User>,
+    ) -> Self {
+        let mut before = before.map(User::to_update_fields).unwrap_or_default();
+        let mut after = after.map(User::to_update_fields).unwrap_or_default();
+        let fields: std::collections::BTreeSet<String> =
+            before.keys().chain(after.keys()).cloned().collect();
+        let changes = fields
+            .into_iter()
+            .filter_map(|field| {
+                let change = FieldChange {
+                    before: before.remove(&field),
+                    after: after.remove(&field),
+                };
+                (change.before != change.after).then_some((field, change))
+            })
+            .collect();
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            at: Utc::now(),
+            actor: AUDIT_ACTOR
+                .try_with(Clone::clone)
+                .unwrap_or_else(|_| Self::SYSTEM_ACTOR.to_string()),
+            tenant: tenant.cloned(),
+            action,
+            user_id: user_id.to_string(),
+            changes,
+            prev_hash: None,
+            salt: None,
+            content_hash: None,
+            hash: None,
+        }
+    }
+
+    /// Hash this entry would carry after `prev_hash`, over everything but the personal
+    /// data, which it covers through `content_hash`
+    pub fn chain_hash(&self) -> String {
+        use sha2::{Digest, Sha256};
+        let sealed = AuditEvent {
+            user_id: String::new(),
+            changes: Default::default(),
+            salt: None,
+            hash: None,
+            ..self.clone()
+        };
+        let value = serde_json::to_value(&sealed).expect("audit events always serialize");
+        let canonical = canonical_value(value).to_string();
+        encode_hex(&Sha256::digest(canonical.as_bytes()))
+    }
+
+    /// `content_hash` for the current `user_id` and `changes`, or `None` without a salt
+    pub fn compute_content_hash(&self) -> Option<String> {
+        use sha2::{Digest, Sha256};
+        let salt = self.salt.as_ref()?;
+        let content = serde_json::json!({ "user_id": self.user_id, "changes": self.changes });
+        let canonical = canonical_value(content).to_string();
+        let mut digest = Sha256::new();
+        digest.update(salt.as_bytes());
+        digest.update(canonical.as_bytes());
+        Some(encode_hex(&digest.finalize()))
+    }
+
+    /// Whether `scrub` removed this entry's personal data
+    pub fn is_scrubbed(&self) -> bool {
+        self.salt.is_none() && self.content_hash.is_some()
+    }
+
+    /// Drop the personal data, keeping the entry's place and hash in the chain
+    pub fn scrub(&mut self) {
+        self.user_id = Self::ERASED_SUBJECT.to_string();
+        self.changes.clear();
+        self.salt = None;
+    }
+
+    /// Link this entry after `prev_hash` and seal it
+    pub fn chained(mut self, prev_hash: Option<String>) -> Self {
+        self.prev_hash = prev_hash;
+        self.salt = Some(uuid::Uuid::new_v4().simple().to_string());
+        self.content_hash = self.compute_content_hash();
+        self.hash = Some(self.chain_hash());
+        self
+    }
+}
+
+// Destination for audit events; called synchronously after each successful mutation
+pub trait AuditSink: fmt::Debug + Send + Sync {
+    fn record(&self, event: &AuditEvent) -> Result<()>;
+
+    /// Entries recorded about a user, oldest first, for subject access exports. Sinks
+    /// that cannot read back what they wrote return none.
+    fn entries_for(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<Vec<AuditEvent>> {
+        let _ = (tenant, user_id);
+        Ok(Vec::new())
+    }
+
+    /// Remove the personal data from a user's entries on erasure, returning how many were
+    /// scrubbed. Sinks that cannot rewrite what they wrote scrub none.
+    fn scrub_user(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<usize> {
+        let _ = (tenant, user_id);
+        Ok(0)
+    }
+}
+
+// Appends audit events to a file, one JSON object per line, each chained to the one before
+// by hash so `verify` can detect edited, reordered or removed entries
+#[derive(Debug)]
+pub struct JsonLinesAuditSink {
+    path: PathBuf,
+    state: Mutex<AuditLogState>,
+}
+
+#[derive(Debug)]
+struct AuditLogState {
+    file: std::fs::File,
+    head: Option<String>,
+}
+
+// Outcome of verifying a chained audit log
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditChainReport {
+    pub entries: usize,
+    /// Hash of the last entry; keep it outside the log to detect truncation later
+    pub head: Option<String>,
+}
+
+impl JsonLinesAuditSink {
+    /// Open for appending, continuing the chain after verifying the entries already in the
+    /// file; a broken chain fails with `AuditChainBroken`
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let head = match Self::verify(&path, None) {
+            Ok(report) => report.head,
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+            {
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        Ok(Self {
+            path,
+            state: Mutex::new(AuditLogState { file, head }),
+        })
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Hash of the last entry written
+    pub fn head(&self) -> Option<String> {
+        self.state.lock().expect("audit log poisoned").head.clone()
+    }
+
+    /// Check every link of the chain at `path`. Removing entries from the end leaves a
+    /// valid chain, so pass a head saved earlier to also detect truncation.
+    pub fn verify(
+        path: impl AsRef<std::path::Path>,
+        expected_head: Option<&str>,
+    ) -> Result<AuditChainReport> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read audit log {}", path.display()))?;
+        let broken = |entry: usize, message: &str| UserError::AuditChainBroken {
+            entry,
+            message: message.to_string(),
+        };
+
+        let mut head: Option<String> = None;
+        let mut entries = 0;
+        let mut seen_expected = expected_head.is_none();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            entries += 1;
+            let event: AuditEvent =
+                serde_json::from_str(line).map_err(|_| broken(entries, "not an audit event"))?;
+            let Some(hash) = &event.hash else {
+                return Err(broken(entries, "entry is not chained").into());
+            };
+            if event.prev_hash != head {
+                return Err(broken(entries, "previous hash does not match").into());
+            }
+            if *hash != event.chain_hash() {
+                return Err(broken(entries, "contents do not match the hash").into());
+            }
+            if event.is_scrubbed() {
+                if event.user_id != AuditEvent::ERASED_SUBJECT || !event.changes.is_empty() {
+                    return Err(broken(entries, "scrubbed entry still holds data").into());
+                }
+            } else if event.content_hash.is_none()
+                || event.compute_content_hash() != event.content_hash
+            {
+                return Err(broken(entries, "personal data does not match its hash").into());
+            }
+            seen_expected |= expected_head == Some(hash.as_str());
+            head = event.hash;
+        }
+        if !seen_expected {
+            return Err(broken(entries, "expected head not found; entries were removed").into());
+        }
+        Ok(AuditChainReport { entries, head })
+    }
+}
+
+impl AuditSink for JsonLinesAuditSink {
+    fn record(&self, event: &AuditEvent) -> Result<()> {
+        use std::io::Write;
+        let mut state = self.state.lock().expect("audit log poisoned");
+        let event = event.clone().chained(state.head.clone());
+        let line = serde_json::to_string(&event).context("Failed to serialize audit event")?;
+        writeln!(state.file, "{}", line).context("Failed to append to audit log")?;
+        state.file.flush().context("Failed to flush audit log")?;
+        state.head = event.hash;
+        Ok(())
+    }
+
+    fn entries_for(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<Vec<AuditEvent>> {
+        // Held so a concurrent append is not read half-written
+        let _state = self.state.lock().expect("audit log poisoned");
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read audit log {}", self.path.display()))?;
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str::<AuditEvent>(line).context("Corrupt audit log entry"))
+            .filter(|event| {
+                event.as_ref().map_or(true, |event| {
+                    event.user_id == user_id && event.tenant.as_ref() == tenant
+                })
+            })
+            .collect()
+    }
+
+    fn scrub_user(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<usize> {
+        let mut state = self.state.lock().expect("audit log poisoned");
+        let contents = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read audit log {}", self.path.display()))?;
+        let mut scrubbed = 0;
+        let mut rewritten = String::new();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let mut event: AuditEvent =
+                serde_json::from_str(line).context("Corrupt audit log entry")?;
+            if event.user_id == user_id && event.tenant.as_ref() == tenant {
+                event.scrub();
+                scrubbed += 1;
+            }
+            rewritten.push_str(
+                &serde_json::to_string(&event).context("Failed to serialize audit event")?,
+            );
+            rewritten.push('\n');
+        }
+        if scrubbed == 0 {
+            return Ok(0);
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, rewritten)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .with_context(|| format!("Failed to rewrite audit log {}", self.path.display()))?;
+        // The rename replaced the file the append handle points at
+        state.file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open audit log {}", self.path.display()))?;
+        Ok(scrubbed)
+    }
+}
+
+// A status transition recovered from the change log; `None` means the user did not exist
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatusChange {
+    pub at: DateTime<Utc>,
+    pub from: Option<UserStatus>,
+    pub to: Option<UserStatus>,
+}
+
+// Everything held about one user, for data-subject access requests
+#[derive(Debug, Clone, Serialize)]
+pub struct SubjectAccessExport {
+    pub generated_at: DateTime<Utc>,
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    /// Current record, metadata included; `None` once the user is deleted
+    pub profile: Option<User>,
+    pub status_history: Vec<StatusChange>,
+    /// Change log entries for this user, oldest first
+    pub changes: Vec<ChangeRecord>,
+    /// Audit sink entries for this user, oldest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub audit: Vec<AuditEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    /// One `section,key,value,at` row per fact
+    Csv,
+}
+
+impl SubjectAccessExport {
+    pub fn render(&self, format: ExportFormat) -> Result<String> {
+        match format {
+            ExportFormat::Json => {
+                let value = canonical_value(serde_json::to_value(self)?);
+                Ok(serde_json::to_string_pretty(&value)?)
+            }
+            ExportFormat::Csv => Ok(self.to_csv()),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let mut rows = vec![["section", "key", "value", "at"].map(String::from)];
+        let mut row = |section: &str, key: &str, value: String, at: Option<DateTime<Utc>>| {
+            rows.push([
+                section.to_string(),
+                key.to_string(),
+                value,
+                at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+            ]);
+        };
+        if let Some(profile) = &self.profile {
+            row("profile", "id", profile.id.clone(), None);
+            row("profile", "name", profile.name.clone(), None);
+            row("profile", "email", profile.email.clone(), None);
+            row(
+                "profile",
+                "status",
+                profile.status.as_str().to_string(),
+                None,
+            );
+            row(
+                "profile",
+                "created_at",
+                profile.created_at.to_rfc3339(),
+                None,
+            );
+            let mut metadata: Vec<_> = profile.metadata.iter().collect();
+            metadata.sort_by_key(|(key, _)| *key);
+            for (key, value) in metadata {
+                row("metadata", key, value.to_string(), None);
+            }
+            for consent in &profile.consents {
+                row(
+                    "consent",
+                    &consent.purpose,
+                    consent.terms_version.clone(),
+                    Some(consent.granted_at),
+                );
+                if let Some(withdrawn_at) = consent.withdrawn_at {
+                    row(
+                        "consent",
+                        &consent.purpose,
+                        "withdrawn".to_string(),
+                        Some(withdrawn_at),
+                    );
+                }
+            }
+        }
+        let status = |status: Option<UserStatus>| status.map_or("", |status| status.as_str());
+        for change in &self.status_history {
+            let transition = format!("{} -> {}", status(change.from), status(change.to));
+            row("status", "transition", transition, Some(change.at));
+        }
+        for record in &self.changes {
+            let kind = serde_json::to_value(record.kind)
+                .ok()
+                .and_then(|kind| kind.as_str().map(str::to_string))
+                .unwrap_or_default();
+            row(
+                "change",
+                &record.sequence.to_string(),
+                kind,
+                Some(record.recorded_at),
+            );
+        }
+        for event in &self.audit {
+            let action = serde_json::to_value(event.action)
+                .ok()
+                .and_then(|action| action.as_str().map(str::to_string))
+                .unwrap_or_default();
+            let fields: Vec<&str> = event.changes.keys().map(String::as_str).collect();
+            let value = format!("{} by {}: {}", action, event.actor, fields.join(" "));
+            row("audit", &event.id, value, Some(event.at));
+        }
+
+        let mut csv = String::new();
+        for fields in rows {
+            let escaped: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&escaped.join(","));
+            csv.push_str("\r\n");
+        }
+        csv
+    }
+}
+
+/// Quoted where needed, and prefixed with `'` where a spreadsheet would read a formula
+pub(crate) fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+// What `UserManager::erase_user` should reach beyond the backend and in-process state
+#[derive(Debug, Clone, Default)]
+pub struct ErasureOptions {
+    /// Cache snapshots written by `shutdown` to scrub of the user's entries
+    pub cache_snapshots: Vec<PathBuf>,
+    /// Ask the backend to purge copies it keeps elsewhere, such as backups or exports
+    pub notify_backend: bool,
+}
+
+impl ErasureOptions {
+    pub fn with_cache_snapshot(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cache_snapshots.push(path.into());
+        self
+    }
+
+    pub fn with_backend_notification(mut self) -> Self {
+        self.notify_backend = true;
+        self
+    }
+}
+
+// Outcome of `UserManager::refresh_cache`
+#[derive(Debug, Default)]
+pub struct CacheRefresh {
+    pub refreshed: usize,
+    /// Users gone from the backend, or grown past the cache budget
+    pub evicted: usize,
+    pub failed: Vec<RefreshFailure>,
+}
+
+// A cached user `refresh_cache` could not re-read; its cached copy is kept
+#[derive(Debug)]
+pub struct RefreshFailure {
+    pub tenant: Option<TenantId>,
+    pub user_id: String,
+    pub error: anyhow::Error,
+}
+
+// Completion report of an erasure request
+#[derive(Debug, Clone, Serialize)]
+pub struct ErasureReport {
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    pub erased_at: DateTime<Utc>,
+    /// Whether a stored profile was replaced with the anonymized placeholder
+    pub profile_anonymized: bool,
+    pub cache_entry_evicted: bool,
+    pub snapshot_entries_removed: usize,
+    pub change_records_redacted: usize,
+    pub queued_mutations_dropped: usize,
+    /// Whether the activity log held anything for the user
+    pub activity_cleared: bool,
+    /// Sessions of the user dropped from the session store
+    pub sessions_removed: usize,
+    /// Audit sink entries whose personal data was scrubbed
+    pub audit_entries_scrubbed: usize,
+    /// `None` when not requested; `Some(false)` when the backend did not acknowledge
+    pub backend_notified: Option<bool>,
+}
+
+/// Remove one user's entries from a cache snapshot, keeping the rest in order.
+/// Untagged entries match any tenant, since `load_cache_snapshot` may assign them one.
+async fn scrub_cache_snapshot(
+    path: &std::path::Path,
+    tenant: Option<&TenantId>,
+    user_id: &str,
+) -> Result<usize> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read cache snapshot {}", path.display()))
+        }
+    };
+    let mut records: Vec<serde_json::Value> =
+        serde_json::from_slice(&bytes).context("Failed to parse cache snapshot")?;
+    let before = records.len();
+    records.retain(|record| {
+        let tagged = record
+            .get("tenant")
+            .and_then(|tenant| serde_json::from_value::<TenantId>(tenant.clone()).ok());
+        UserEnvelope::record_id(record) != Some(user_id)
+            || tagged.is_some_and(|tagged| Some(&tagged) != tenant)
+    });
+    let removed = before - records.len();
+    if removed > 0 {
+        let json = serde_json::to_vec(&records).context("Failed to serialize cache")?;
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json)
+            .await
+            .with_context(|| format!("Failed to rewrite cache snapshot {}", path.display()))?;
+        tokio::fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("Failed to rewrite cache snapshot {}", path.display()))?;
+    }
+    Ok(removed)
+}
+
+// Cache invalidation broadcast between manager instances
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InvalidationMessage {
+    pub origin: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    /// `None` invalidates every entry of the tenant
+    pub user_id: Option<String>,
+    /// Invalidate every entry of every tenant, e.g. after a listener missed messages
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub flush_all: bool,
+}
+
+impl InvalidationMessage {
+    /// Message a bus yields in place of the ones a lagging listener missed
+    pub fn flush_all(origin: impl Into<String>) -> Self {
+        Self {
+            origin: origin.into(),
+            tenant: None,
+            user_id: None,
+            flush_all: true,
+        }
+    }
+
+    fn covers(&self, key: &CacheKey) -> bool {
+        self.flush_all
+            || (key.tenant == self.tenant
+                && self
+                    .user_id
+                    .as_ref()
+                    .is_none_or(|user_id| *user_id == key.user_id))
+    }
+}
+
+// Transport for invalidation messages
+#[async_trait]
+pub trait InvalidationBus: fmt::Debug + Send + Sync {
+    async fn publish(&self, message: &InvalidationMessage) -> Result<()>;
+
+    async fn subscribe(&self) -> Result<futures::stream::BoxStream<'static, InvalidationMessage>>;
+}
+
+// In-process bus, for managers sharing one process and for tests
+#[derive(Debug, Clone)]
+pub struct LocalInvalidationBus {
+    sender: tokio::sync::broadcast::Sender<InvalidationMessage>,
+}
+
+impl Default for LocalInvalidationBus {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl LocalInvalidationBus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: tokio::sync::broadcast::channel(capacity).0,
+        }
+    }
+}
+
+#[async_trait]
+impl InvalidationBus for LocalInvalidationBus {
+    async fn publish(&self, message: &InvalidationMessage) -> Result<()> {
+        // No subscribers is not an error
+        let _ = self.sender.send(message.clone());
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<futures::stream::BoxStream<'static, InvalidationMessage>> {
+        use futures::StreamExt;
+        let receiver = self.sender.subscribe();
+        let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+            let message = match receiver.recv().await {
+                Ok(message) => message,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    // Which entries the missed messages covered is unknown, so drop them all
+                    tracing::warn!(skipped, "Invalidation listener lagged; flushing the cache");
+                    InvalidationMessage::flush_all("lagged")
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            };
+            Some((message, receiver))
+        });
+        Ok(stream.boxed())
+    }
+}
+
+// Redis pub/sub bus
+#[cfg(feature = "redis")]
+#[derive(Debug, Clone)]
+pub struct RedisInvalidationBus {
+    client: redis::Client,
+    channel: String,
+    /// Shared by every publish; dropped after a failure so the next one reconnects
+    connection: Arc<tokio::sync::Mutex<Option<redis::aio::MultiplexedConnection>>>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisInvalidationBus {
+    pub const DEFAULT_CHANNEL: &'static str = "users:invalidate";
+
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            client: redis::Client::open(url).context("Invalid Redis URL")?,
+            channel: Self::DEFAULT_CHANNEL.to_string(),
+            connection: Arc::default(),
+        })
+    }
+
+    pub fn with_channel(mut self, channel: &str) -> Self {
+        self.channel = channel.to_string();
+        self
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl InvalidationBus for RedisInvalidationBus {
+    async fn publish(&self, message: &InvalidationMessage) -> Result<()> {
+        use redis::AsyncCommands;
+        let payload = serde_json::to_string(message).context("Failed to serialize invalidation")?;
+        let mut conn = {
+            let mut connection = self.connection.lock().await;
+            match &*connection {
+                Some(conn) => conn.clone(),
+                None => connection
+                    .insert(
+                        self.client
+                            .get_multiplexed_async_connection()
+                            .await
+                            .context("Failed to connect to Redis")?,
+                    )
+                    .clone(),
+            }
+        };
+        if let Err(e) = conn.publish::<_, _, ()>(&self.channel, payload).await {
+            *self.connection.lock().await = None;
+            return Err(anyhow::Error::new(e).context("Failed to publish invalidation"));
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<futures::stream::BoxStream<'static, InvalidationMessage>> {
+        use futures::StreamExt;
+        let mut pubsub = self
+            .client
+            .get_async_pubsub()
+            .await
+            .context("Failed to connect to Redis")?;
+        pubsub
+            .subscribe(&self.channel)
+            .await
+            .context("Failed to subscribe to invalidation channel")?;
+        let stream = pubsub.into_on_message().filter_map(|msg| async move {
+            let payload: String = msg.get_payload().ok()?;
+            match serde_json::from_str(&payload) {
+                Ok(message) => Some(message),
+                Err(e) => {
+                    tracing::warn!(error = %redact(&e.to_string()), "Ignoring malformed invalidation message");
+                    None
+                }
+            }
+        });
+        Ok(stream.boxed())
+    }
+}
+
+// Span for a manager operation; `traced` fills in status and duration
+macro_rules! operation_span {
+    ($name:literal, $tenant:expr $(, $($fields:tt)+)?) => {{
+        let span = tracing::info_span!(
+            $name,
+            tenant = tracing::field::Empty,
+            status = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+            $($($fields)+)?
+        );
+        if let Some(tenant) = $tenant {
+            span.record("tenant", tenant.as_str());
+        }
+        span
+    }};
+}
+
+// Latency limits above which an operation is logged as slow
+#[derive(Debug, Clone, Default)]
+pub struct SlowOperationThresholds {
+    default: Option<Duration>,
+    per_operation: HashMap<&'static str, Duration>,
+}
+
+impl SlowOperationThresholds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Threshold for operations without their own
+    pub fn with_default(mut self, threshold: Duration) -> Self {
+        self.default = Some(threshold);
+        self
+    }
+
+    /// Threshold for one operation, named as in its span (`fetch_user`, `batch_fetch_users`, ...)
+    pub fn with_operation(mut self, operation: &'static str, threshold: Duration) -> Self {
+        self.per_operation.insert(operation, threshold);
+        self
+    }
+
+    pub fn threshold_for(&self, operation: &str) -> Option<Duration> {
+        self.per_operation.get(operation).copied().or(self.default)
+    }
+}
+
+tokio::task_local! {
+    static ATTEMPTS: std::cell::Cell<u32>;
+}
+
+/// Count one backend request against the operation currently being traced
+fn count_attempt() {
+    let _ = ATTEMPTS.try_with(|attempts| attempts.set(attempts.get() + 1));
+}
+
+// Result of probing the backend
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub latency: Duration,
+    pub checked_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// A failed operation kept for diagnostics
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordedError {
+    pub at: DateTime<Utc>,
+    pub operation: String,
+    pub kind: &'static str,
+    pub message: String,
+}
+
+// A finished operation, kept for live dashboards
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecordedOperation {
+    pub at: DateTime<Utc>,
+    pub operation: String,
+    pub status: &'static str,
+    pub duration: Duration,
+    pub attempts: u32,
+}
+
+// Sanitized manager configuration; backends describe themselves without credentials
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigSummary {
+    pub instance_id: String,
+    pub backend: String,
+    pub tenant: Option<TenantId>,
+    pub tenant_repositories: bool,
+    pub offline_queue: bool,
+    pub change_log: bool,
+    pub audit_sink: bool,
+    pub invalidation_bus: bool,
+}
+
+// Cache contents by tenant and status, without user data
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheSummary {
+    pub entries: usize,
+    pub by_tenant: std::collections::BTreeMap<String, usize>,
+    pub by_status: std::collections::BTreeMap<String, usize>,
+}
+
+// Warm state handed from a manager to its replacement, e.g. across a blue/green deploy,
+// so the new instance starts with a full cache and the old rate-limit budgets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagerSnapshot {
+    pub taken_at: DateTime<Utc>,
+    pub cache: Vec<CachedUserSnapshot>,
+    pub rate_limits: Vec<BucketSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedUserSnapshot {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    pub user: UserEnvelope,
+}
+
+impl ManagerSnapshot {
+    /// Write as JSON, replacing `path` atomically
+    pub async fn save(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec(self).context("Failed to serialize manager snapshot")?;
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, json)
+            .await
+            .with_context(|| format!("Failed to write manager snapshot {}", path.display()))?;
+        tokio::fs::rename(&tmp, path)
+            .await
+            .with_context(|| format!("Failed to write manager snapshot {}", path.display()))
+    }
+
+    pub async fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read manager snapshot {}", path.display()))?;
+        serde_json::from_slice(&bytes).context("Failed to parse manager snapshot")
+    }
+}
+
+// What `UserManager::restore` took from a snapshot
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestoreReport {
+    pub cache_entries: usize,
+    /// Zero when the manager has no rate limiter
+    pub rate_limit_buckets: usize,
+    /// Cached users that could not be migrated to the current schema
+    pub failed: Vec<MigrationFailure>,
+}
+
+// Point-in-time diagnostic report for attaching to bug reports
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugSnapshot {
+    pub taken_at: DateTime<Utc>,
+    pub config: ConfigSummary,
+    pub cache: CacheSummary,
+    pub in_flight_requests: usize,
+    pub shutting_down: bool,
+    pub background_tasks: usize,
+    pub queued_mutations: usize,
+    /// Last health check; the backend's availability as seen by this manager
+    pub health: Option<HealthReport>,
+    pub recent_errors: Vec<RecordedError>,
+    pub latencies: std::collections::BTreeMap<String, LatencySummary>,
+}
+
+// Configures an HTTP-backed manager; `build` reports bad settings instead of panicking
+#[derive(Clone)]
+pub struct UserManagerBuilder {
+    pub(crate) base_url: String,
+    pub(crate) replica_url: Option<String>,
+    pub(crate) timeout: Duration,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) cache_capacity: Option<usize>,
+    pub(crate) cache_budget: Option<usize>,
+    pub(crate) bearer_token: Option<Secret<String>>,
+    pub(crate) request_signer: Option<RequestSigner>,
+    pub(crate) batch_get: Option<Duration>,
+    pub(crate) headers: Vec<(String, Secret<String>)>,
+    pub(crate) proxy: Option<String>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) regions: Vec<(String, String)>,
+    pub(crate) region_rule: Option<RegionRule>,
+    pub(crate) layers: Vec<Arc<dyn Layer>>,
+    pub(crate) scim: bool,
+}
+
+impl fmt::Debug for UserManagerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserManagerBuilder")
+            .field("base_url", &redact(&self.base_url))
+            .field("replica_url", &self.replica_url.as_deref().map(redact))
+            .field("timeout", &self.timeout)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("retry_policy", &self.retry_policy)
+            .field("cache_capacity", &self.cache_capacity)
+            .field("cache_budget", &self.cache_budget)
+            .field("bearer_token", &self.bearer_token)
+            .field("request_signer", &self.request_signer)
+            .field("batch_get", &self.batch_get)
+            .field("headers", &self.headers)
+            .field("proxy", &self.proxy.as_deref().map(redact))
+            .field("user_agent", &self.user_agent)
+            .field(
+                "regions",
+                &self
+                    .regions
+                    .iter()
+                    .map(|(region, url)| (region, redact(url)))
+                    .collect::<Vec<_>>(),
+            )
+            .field("region_rule", &self.region_rule)
+            .field("layers", &self.layers)
+            .field("scim", &self.scim)
+            .finish()
+    }
+}
+
+impl UserManagerBuilder {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            replica_url: None,
+            timeout: Duration::from_secs(UserManager::TIMEOUT_SECS),
+            connect_timeout: None,
+            retry_policy: RetryPolicy::none(),
+            cache_capacity: None,
+            cache_budget: None,
+            bearer_token: None,
+            request_signer: None,
+            batch_get: None,
+            headers: Vec::new(),
+            proxy: None,
+            user_agent: None,
+            regions: Vec::new(),
+            region_rule: None,
+            layers: Vec::new(),
+            scim: false,
+        }
+    }
+
+    /// Builder configured from the variables listed in `env_vars`
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let get = |name: &str| lookup(name).filter(|value| !value.trim().is_empty());
+        let parse = |name: &str, expected: &str| -> Result<Option<u64>> {
+            get(name)
+                .map(|value| {
+                    value.trim().parse::<u64>().map_err(|_| {
+                        Self::invalid(name, format!("expected {}, got {:?}", expected, value))
+                    })
+                })
+                .transpose()
+        };
+
+        let base_url = get(env_vars::BASE_URL)
+            .ok_or_else(|| Self::invalid(env_vars::BASE_URL, "required but not set"))?;
+        let mut builder = Self::new(base_url);
+        if let Some(replica_url) = get(env_vars::REPLICA_URL) {
+            builder = builder.with_read_replica(replica_url);
+        }
+        if let Some(token) = get(env_vars::TOKEN) {
+            builder = builder.with_bearer_token(token);
+        }
+        let timeout = |name: &str| -> Result<Option<Duration>> {
+            match parse(name, "a whole number of seconds")? {
+                Some(0) => Err(Self::invalid(name, "must be at least 1 second")),
+                secs => Ok(secs.map(Duration::from_secs)),
+            }
+        };
+        if let Some(timeout) = timeout(env_vars::TIMEOUT_SECS)? {
+            builder = builder.with_timeout(timeout);
+        }
+        if let Some(timeout) = timeout(env_vars::CONNECT_TIMEOUT_SECS)? {
+            builder = builder.with_connect_timeout(timeout);
+        }
+        if let Some(retries) = parse(env_vars::MAX_RETRIES, "a retry count")? {
+            let retries = u32::try_from(retries)
+                .map_err(|_| Self::invalid(env_vars::MAX_RETRIES, "retry count too large"))?;
+            builder = builder.with_retry_policy(RetryPolicy::new(retries));
+        }
+        if let Some(proxy) = get(env_vars::PROXY) {
+            builder = builder.with_proxy(proxy);
+        }
+        if let Some(user_agent) = get(env_vars::USER_AGENT) {
+            builder = builder.with_user_agent(user_agent);
+        }
+        if let Some(capacity) = parse(env_vars::CACHE_CAPACITY, "a number of users")? {
+            builder = builder.with_cache_capacity(capacity as usize);
+        }
+        if let Some(bytes) = parse(env_vars::CACHE_BUDGET_BYTES, "a number of bytes")? {
+            builder = builder.with_cache_budget(bytes as usize);
+        }
+        let cache_enabled =
+            match get(env_vars::CACHE_ENABLED).map(|v| v.trim().to_ascii_lowercase()) {
+                None => true,
+                Some(value) => match value.as_str() {
+                    "1" | "true" | "yes" | "on" => true,
+                    "0" | "false" | "no" | "off" => false,
+                    _ => {
+                        return Err(Self::invalid(
+                            env_vars::CACHE_ENABLED,
+                            format!("expected true or false, got {:?}", value),
+                        ))
+                    }
+                },
+            };
+        if !cache_enabled {
+            builder = builder.with_cache_capacity(0);
+        }
+        Ok(builder)
+    }
+
+    /// Read from this replica, writing to the base URL
+    pub fn with_read_replica(mut self, replica_url: impl Into<String>) -> Self {
+        self.replica_url = Some(replica_url.into());
+        self
+    }
+
+    /// Whole-request timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Most users kept in the cache; 0 disables caching
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Most bytes the cache may hold, by estimate; see `UserManager::with_cache_budget`
+    pub fn with_cache_budget(mut self, bytes: usize) -> Self {
+        self.cache_budget = Some(bytes);
+        self
+    }
+
+    /// Send `Authorization: Bearer {token}` on every request
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(Secret::new(token.into()));
+        self
+    }
+
+    /// Sign every request with HMAC-SHA256, alongside or instead of a bearer token
+    pub fn with_request_signer(mut self, signer: RequestSigner) -> Self {
+        self.request_signer = Some(signer);
+        self
+    }
+
+    /// Coalesce fetches made within `window` into `POST /users:batchGet` requests; see
+    /// `HttpUserRepository::with_batch_get`
+    pub fn with_batch_get(mut self, window: Duration) -> Self {
+        self.batch_get = Some(window);
+        self
+    }
+
+    /// Send this header on every request; the value is treated as a credential
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), Secret::new(value.into())));
+        self
+    }
+
+    /// Route all traffic through this proxy URL
+    pub fn with_proxy(mut self, proxy_url: impl Into<String>) -> Self {
+        self.proxy = Some(proxy_url.into());
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Serve `region` from another base URL; the base URL itself serves the rule's home region
+    pub fn with_region(mut self, region: impl Into<String>, base_url: impl Into<String>) -> Self {
+        self.regions.push((region.into(), base_url.into()));
+        self
+    }
+
+    /// Which region each tenant's or user's records belong in
+    pub fn with_region_rule(mut self, rule: RegionRule) -> Self {
+        self.region_rule = Some(rule);
+        self
+    }
+
+    /// Put `layer` around every repository call, inside the layers added before it. Layers
+    /// add to the manager's own cache, retries, rate limiter and access policy.
+    pub fn layer(mut self, layer: impl Layer + 'static) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// Speak SCIM 2.0 to `base_url` (e.g. `https://idp.example.com/scim/v2`) instead of the
+    /// user API. Read replicas, regions, tenants and request signing are not available.
+    pub fn with_scim(mut self) -> Self {
+        self.scim = true;
+        self
+    }
+
+    fn invalid(field: &str, message: impl Into<String>) -> anyhow::Error {
+        UserError::InvalidConfig {
+            field: field.to_string(),
+            message: message.into(),
+        }
+        .into()
+    }
+
+    fn client(&self) -> Result<reqwest::Client> {
+        use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Self::invalid("headers", format!("{:?}: {}", name, e)))?;
+            let mut header_value = HeaderValue::from_str(value.expose())
+                .map_err(|e| Self::invalid("headers", format!("value of {:?}: {}", name, e)))?;
+            header_value.set_sensitive(true);
+            headers.insert(header_name, header_value);
+        }
+        if let Some(token) = &self.bearer_token {
+            let bearer = format!("Bearer {}", token.expose());
+            let mut value = HeaderValue::from_str(&bearer).map_err(|_| {
+                Self::invalid(
+                    "bearer_token",
+                    "contains characters not allowed in a header",
+                )
+            })?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+
+        // A zero timeout would fail every request instead of disabling the limit
+        if self.timeout.is_zero() {
+            return Err(Self::invalid("timeout", "must be greater than zero"));
+        }
+        if self
+            .connect_timeout
+            .is_some_and(|timeout| timeout.is_zero())
+        {
+            return Err(Self::invalid(
+                "connect_timeout",
+                "must be greater than zero",
+            ));
+        }
+        let mut builder = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .default_headers(headers);
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| Self::invalid("proxy", redact_error(&e.into())))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        builder
+            .build()
+            .map_err(|e| Self::invalid("client", e.to_string()))
+    }
+
+    fn repository(
+        &self,
+        url: &str,
+        field: &str,
+        client: &reqwest::Client,
+    ) -> Result<HttpUserRepository> {
+        reqwest::Url::parse(url)
+            .map_err(|e| Self::invalid(field, format!("{}: {}", redact(url), e)))?;
+        let mut repository =
+            HttpUserRepository::new(url.trim_end_matches('/').to_string(), client.clone())
+                .with_retry_policy(self.retry_policy.clone());
+        if let Some(window) = self.batch_get {
+            repository = repository.with_batch_get(window);
+        }
+        Ok(match &self.request_signer {
+            Some(signer) => repository.with_signer(signer.clone()),
+            None => repository,
+        })
+    }
+
+    pub fn build(self) -> Result<UserManager> {
+        if let Some(signer) = &self.request_signer {
+            signer.header_names()?;
+        }
+        let client = self.client()?;
+        if self.scim {
+            return self.build_scim(client);
+        }
+        let primary = self.repository(&self.base_url, "base_url", &client)?;
+        let layers = self.layers.clone();
+        let manager = match &self.replica_url {
+            None => {
+                let scoped = primary.clone();
+                UserManager::with_repository(apply_layers(Arc::new(primary), &layers, None))
+                    .with_tenant_repositories(move |tenant| {
+                        apply_layers(Arc::new(scoped.for_tenant(tenant)), &layers, Some(tenant))
+                    })
+            }
+            Some(replica_url) => {
+                let replica = self.repository(replica_url, "replica_url", &client)?;
+                let repository = ReplicatedUserRepository::new(
+                    Arc::new(primary.clone()),
+                    Arc::new(replica.clone()),
+                );
+                UserManager::with_repository(apply_layers(Arc::new(repository), &layers, None))
+                    .with_tenant_repositories(move |tenant| {
+                        let repository = ReplicatedUserRepository::new(
+                            Arc::new(primary.for_tenant(tenant)),
+                            Arc::new(replica.for_tenant(tenant)),
+                        );
+                        apply_layers(Arc::new(repository), &layers, Some(tenant))
+                    })
+            }
+        };
+        let manager = match self.region_routing(&client)? {
+            Some(routing) => manager.with_region_routing(routing),
+            None => manager,
+        };
+        Ok(self.configure_cache(manager))
+    }
+
+    fn build_scim(self, client: reqwest::Client) -> Result<UserManager> {
+        if self.replica_url.is_some() || !self.regions.is_empty() || self.region_rule.is_some() {
+            return Err(Self::invalid(
+                "scim",
+                "read replicas and regions are not supported over SCIM",
+            ));
+        }
+        if self.request_signer.is_some() {
+            return Err(Self::invalid(
+                "scim",
+                "request signing is not supported over SCIM",
+            ));
+        }
+        if self.batch_get.is_some() {
+            return Err(Self::invalid(
+                "scim",
+                "batch gets are not supported over SCIM",
+            ));
+        }
+        reqwest::Url::parse(&self.base_url)
+            .map_err(|e| Self::invalid("base_url", format!("{}: {}", redact(&self.base_url), e)))?;
+        let repository = ScimUserRepository::new(self.base_url.clone(), client);
+        let manager =
+            UserManager::with_repository(apply_layers(Arc::new(repository), &self.layers, None));
+        Ok(self.configure_cache(manager))
+    }
+
+    fn configure_cache(&self, mut manager: UserManager) -> UserManager {
+        if let Some(capacity) = self.cache_capacity {
+            manager = manager.with_cache_capacity(capacity);
+        }
+        if let Some(bytes) = self.cache_budget {
+            manager = manager.with_cache_budget(bytes);
+        }
+        manager
+    }
+
+    fn region_routing(&self, client: &reqwest::Client) -> Result<Option<RegionRouting>> {
+        let Some(rule) = &self.region_rule else {
+            if self.regions.is_empty() {
+                return Ok(None);
+            }
+            return Err(Self::invalid(
+                "regions",
+                "regional base URLs need a region rule",
+            ));
+        };
+        let mut routing = RegionRouting::new(rule.clone());
+        for (region, url) in &self.regions {
+            let repository = self.repository(url, "regions", client)?;
+            let layers = self.layers.clone();
+            routing = routing.with_region(region.clone(), move |tenant| {
+                let scoped = match tenant {
+                    Some(tenant) => repository.for_tenant(tenant),
+                    None => repository.clone(),
+                };
+                apply_layers(Arc::new(scoped), &layers, tenant)
+            });
+        }
+        routing.validate()?;
+        Ok(Some(routing))
+    }
+}
+
+// Client settings as kept in a service's TOML or YAML config file
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ManagerConfig {
+    pub base_url: String,
+    pub replica_url: Option<String>,
+    pub token: Option<Secret<String>>,
+    pub timeout_secs: u64,
+    pub connect_timeout_secs: Option<u64>,
+    pub max_retries: u32,
+    pub proxy: Option<String>,
+    pub user_agent: Option<String>,
+    pub headers: std::collections::BTreeMap<String, Secret<String>>,
+    pub cache: CacheConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Most users kept cached; unbounded when unset
+    pub capacity: Option<usize>,
+    /// Most bytes the cache may hold, by estimate; unbounded when unset
+    pub budget_bytes: Option<usize>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            capacity: None,
+            budget_bytes: None,
+        }
+    }
+}
+
+impl Default for ManagerConfig {
+    fn default() -> Self {
+        Self {
+            base_url: String::new(),
+            replica_url: None,
+            token: None,
+            timeout_secs: UserManager::TIMEOUT_SECS,
+            connect_timeout_secs: None,
+            max_retries: 0,
+            proxy: None,
+            user_agent: None,
+            headers: Default::default(),
+            cache: CacheConfig::default(),
+        }
+    }
+}
+
+impl fmt::Debug for ManagerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManagerConfig")
+            .field("base_url", &redact(&self.base_url))
+            .field("replica_url", &self.replica_url.as_deref().map(redact))
+            .field("token", &self.token)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("connect_timeout_secs", &self.connect_timeout_secs)
+            .field("max_retries", &self.max_retries)
+            .field("proxy", &self.proxy.as_deref().map(redact))
+            .field("user_agent", &self.user_agent)
+            .field("headers", &self.headers)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+impl ManagerConfig {
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(input: &str) -> Result<Self> {
+        let config: Self = toml::from_str(input).context("Failed to parse TOML config")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_str(input: &str) -> Result<Self> {
+        let config: Self = serde_yaml::from_str(input).context("Failed to parse YAML config")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load a `.toml`, `.yaml` or `.yml` file, by extension
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let input = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        match extension {
+            #[cfg(feature = "toml")]
+            "toml" => Self::from_toml_str(&input),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Self::from_yaml_str(&input),
+            _ => {
+                let _ = input;
+                Err(UserManagerBuilder::invalid(
+                    "path",
+                    format!("unsupported config format {:?}", path.display().to_string()),
+                ))
+            }
+        }
+    }
+
+    /// Check values that deserialize fine but cannot configure a client
+    pub fn validate(&self) -> Result<()> {
+        let invalid = UserManagerBuilder::invalid;
+        if self.base_url.trim().is_empty() {
+            return Err(invalid("base_url", "required but not set"));
+        }
+        if self.timeout_secs == 0 {
+            return Err(invalid("timeout_secs", "must be at least 1"));
+        }
+        if self.connect_timeout_secs == Some(0) {
+            return Err(invalid("connect_timeout_secs", "must be at least 1"));
+        }
+        if self.cache.capacity.is_some() && !self.cache.enabled {
+            return Err(invalid("cache.capacity", "set while the cache is disabled"));
+        }
+        Ok(())
+    }
+
+    /// Builder carrying every setting; `build` still checks URLs, headers and the proxy
+    pub fn into_builder(self) -> UserManagerBuilder {
+        let mut builder = UserManagerBuilder::new(self.base_url)
+            .with_timeout(Duration::from_secs(self.timeout_secs))
+            .with_retry_policy(RetryPolicy::new(self.max_retries));
+        if let Some(replica_url) = self.replica_url {
+            builder = builder.with_read_replica(replica_url);
+        }
+        if let Some(token) = &self.token {
+            builder = builder.with_bearer_token(token.expose());
+        }
+        if let Some(secs) = self.connect_timeout_secs {
+            builder = builder.with_connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(proxy) = self.proxy {
+            builder = builder.with_proxy(proxy);
+        }
+        if let Some(user_agent) = self.user_agent {
+            builder = builder.with_user_agent(user_agent);
+        }
+        for (name, value) in &self.headers {
+            builder = builder.with_header(name, value.expose());
+        }
+        if let Some(bytes) = self.cache.budget_bytes {
+            builder = builder.with_cache_budget(bytes);
+        }
+        match (self.cache.enabled, self.cache.capacity) {
+            (false, _) => builder.with_cache_capacity(0),
+            (true, Some(capacity)) => builder.with_cache_capacity(capacity),
+            (true, None) => builder,
+        }
+    }
+}
+
+// User manager with async operations
+pub struct UserManager {
+    cache: Arc<RwLock<UserCache>>,
+    repository: Arc<dyn UserRepository>,
+    tenant: Option<TenantId>,
+    tenant_repositories: Option<TenantRepositoryFactory>,
+    scoped_repositories: Mutex<HashMap<TenantId, Arc<dyn UserRepository>>>,
+    in_flight: InFlight,
+    shutting_down: AtomicBool,
+    shutdown_tx: watch::Sender<bool>,
+    background_tasks: Mutex<Vec<JoinHandle<()>>>,
+    offline_queue: Option<Arc<OfflineQueue>>,
+    health: Mutex<Option<HealthReport>>,
+    statistics: Mutex<std::collections::BTreeMap<String, UserStatistics>>,
+    recent_errors: Mutex<std::collections::VecDeque<RecordedError>>,
+    recent_operations: Mutex<std::collections::VecDeque<RecordedOperation>>,
+    latencies: Mutex<HashMap<&'static str, LatencyHistogram>>,
+    change_log: Option<Arc<ChangeLog>>,
+    slow_thresholds: SlowOperationThresholds,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    instance_id: String,
+    invalidation_bus: Option<Arc<dyn InvalidationBus>>,
+    invalidation_debounce: Option<Duration>,
+    hooks: Vec<Arc<dyn UserHook>>,
+    cache_capacity: Option<usize>,
+    cache_budget: Option<usize>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    access_policy: Option<Arc<AccessPolicy>>,
+    caller: Option<Caller>,
+    region_routing: Option<RegionRouting>,
+    events: Option<Arc<EventBus>>,
+    outbox: Option<Arc<dyn Outbox>>,
+    watchers: Mutex<HashMap<CacheKey, watch::Sender<Option<User>>>>,
+    update_locks: UpdateLocks,
+    cursors: CursorCodec,
+    activity_log: Option<Arc<ActivityLog>>,
+    session_store: Option<Arc<SessionStore>>,
+    password_reset: Option<Arc<PasswordReset>>,
+    invitations: Option<Arc<Invitations>>,
+}
+
+impl fmt::Debug for UserManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserManager")
+            .field("repository", &self.repository)
+            .field("tenant", &self.tenant)
+            .field("in_flight", &self.in_flight.count.load(Ordering::Relaxed))
+            .field("shutting_down", &self.shutting_down.load(Ordering::Relaxed))
+            .field("offline_queue", &self.offline_queue)
+            .field("change_log", &self.change_log)
+            .field("audit_sink", &self.audit_sink)
+            .field("hooks", &self.hooks.len())
+            .field("rate_limiter", &self.rate_limiter)
+            .field("access_policy", &self.access_policy)
+            .field("caller", &self.caller)
+            .field("region_routing", &self.region_routing)
+            .field("events", &self.events)
+            .field("outbox", &self.outbox)
+            .finish_non_exhaustive()
+    }
+}
+
+// Tenant-scoped view of a manager, sharing its cache, backends and lifecycle
+#[derive(Debug, Clone)]
+pub struct TenantScope<'a> {
+    manager: &'a UserManager,
+    tenant: TenantId,
+}
+
+impl TenantScope<'_> {
+    pub fn tenant(&self) -> &TenantId {
+        &self.tenant
+    }
+
+    pub async fn fetch_user(&self, user_id: &str) -> Result<Option<User>> {
+        self.manager
+            .fetch_user_in(Some(&self.tenant), user_id)
+            .await
+    }
+
+    pub async fn batch_fetch_users(&self, user_ids: &[String]) -> HashMap<String, Option<User>> {
+        self.manager
+            .batch_fetch_users_in(Some(&self.tenant), user_ids)
+            .await
+    }
+
+    pub async fn create_user(&self, user: &User) -> Result<User> {
+        self.manager.create_user_in(Some(&self.tenant), user).await
+    }
+
+    pub async fn update_user(&self, user_id: &str, update: UserUpdate) -> Result<bool> {
+        self.manager
+            .update_user_in(Some(&self.tenant), user_id, update)
+            .await
+    }
+
+    pub async fn delete_user(&self, user_id: &str) -> Result<bool> {
+        self.manager
+            .delete_user_in(Some(&self.tenant), user_id)
+            .await
+    }
+
+    pub async fn list_users(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        self.manager
+            .list_users_in(Some(&self.tenant), offset, limit)
+            .await
+    }
+
+    pub async fn list_users_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        self.manager
+            .list_users_filtered_in(Some(&self.tenant), filter, offset, limit)
+            .await
+    }
+
+    pub async fn watch_user(&self, user_id: &str) -> Result<watch::Receiver<Option<User>>> {
+        self.manager
+            .watch_user_in(Some(&self.tenant), user_id)
+            .await
+    }
+
+    /// Statistics over this tenant's cached users
+    pub async fn cached_statistics(&self) -> UserStatistics {
+        self.manager.cached_statistics(Some(&self.tenant)).await
+    }
+}
+
+// The manager's user operations, for substituting fakes in application tests
+#[cfg_attr(feature = "mockall", mockall::automock)]
+#[async_trait]
+pub trait UserService: Send + Sync {
+    async fn fetch_user(&self, user_id: &str) -> Result<Option<User>>;
+
+    async fn batch_fetch_users(&self, user_ids: &[String]) -> HashMap<String, Option<User>>;
+
+    async fn create_user(&self, user: &User) -> Result<User>;
+
+    async fn update_user(&self, user_id: &str, update: UserUpdate) -> Result<bool>;
+
+    async fn delete_user(&self, user_id: &str) -> Result<bool>;
+
+    async fn list_users(&self, offset: usize, limit: usize) -> Result<Vec<User>>;
+}
+
+#[async_trait]
+impl UserService for UserManager {
+    async fn fetch_user(&self, user_id: &str) -> Result<Option<User>> {
+        UserManager::fetch_user(self, user_id).await
+    }
+
+    async fn batch_fetch_users(&self, user_ids: &[String]) -> HashMap<String, Option<User>> {
+        UserManager::batch_fetch_users(self, user_ids).await
+    }
+
+    async fn create_user(&self, user: &User) -> Result<User> {
+        UserManager::create_user(self, user).await
+    }
+
+    async fn update_user(&self, user_id: &str, update: UserUpdate) -> Result<bool> {
+        UserManager::update_user(self, user_id, update).await
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<bool> {
+        UserManager::delete_user(self, user_id).await
+    }
+
+    async fn list_users(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        UserManager::list_users(self, offset, limit).await
+    }
+}
+
+#[async_trait]
+impl UserService for TenantScope<'_> {
+    async fn fetch_user(&self, user_id: &str) -> Result<Option<User>> {
+        TenantScope::fetch_user(self, user_id).await
+    }
+
+    async fn batch_fetch_users(&self, user_ids: &[String]) -> HashMap<String, Option<User>> {
+        TenantScope::batch_fetch_users(self, user_ids).await
+    }
+
+    async fn create_user(&self, user: &User) -> Result<User> {
+        TenantScope::create_user(self, user).await
+    }
+
+    async fn update_user(&self, user_id: &str, update: UserUpdate) -> Result<bool> {
+        TenantScope::update_user(self, user_id, update).await
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<bool> {
+        TenantScope::delete_user(self, user_id).await
+    }
+
+    async fn list_users(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        TenantScope::list_users(self, offset, limit).await
+    }
+}
+
+/// Restricted handle to a manager for handing to components that should not hold all of
+/// its powers. Mutations outside the scope fail with `Forbidden` before reaching the
+/// backend, and every call runs in the scope's tenant; the manager's own access policy
+/// still applies on top.
+#[derive(Debug, Clone)]
+pub struct ScopedManager {
+    manager: Arc<UserManager>,
+    scope: ManagerScope,
+}
+
+impl ScopedManager {
+    pub fn scope(&self) -> &ManagerScope {
+        &self.scope
+    }
+
+    fn tenant(&self) -> Option<&TenantId> {
+        self.scope.tenant.as_ref().or(self.manager.tenant())
+    }
+
+    fn check(&self, permission: Permission) -> Result<()> {
+        if self.scope.allows(permission) {
+            return Ok(());
+        }
+        tracing::warn!(scope = %self.scope.name, %permission, "Operation outside manager scope");
+        Err(UserError::Forbidden {
+            caller: format!("scope {}", self.scope.name),
+            permission: permission.to_string(),
+        }
+        .into())
+    }
+
+    pub async fn fetch_user(&self, user_id: &str) -> Result<Option<User>> {
+        self.manager.fetch_user_in(self.tenant(), user_id).await
+    }
+
+    pub async fn batch_fetch_users(&self, user_ids: &[String]) -> HashMap<String, Option<User>> {
+        self.manager
+            .batch_fetch_users_in(self.tenant(), user_ids)
+            .await
+    }
+
+    pub async fn create_user(&self, user: &User) -> Result<User> {
+        self.check(Permission::Create)?;
+        self.manager.create_user_in(self.tenant(), user).await
+    }
+
+    pub async fn update_user(&self, user_id: &str, update: UserUpdate) -> Result<bool> {
+        self.check(Permission::Update)?;
+        self.manager
+            .update_user_in(self.tenant(), user_id, update)
+            .await
+    }
+
+    pub async fn delete_user(&self, user_id: &str) -> Result<bool> {
+        self.check(Permission::Delete)?;
+        self.manager.delete_user_in(self.tenant(), user_id).await
+    }
+
+    pub async fn list_users(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        self.manager
+            .list_users_in(self.tenant(), offset, limit)
+            .await
+    }
+
+    pub async fn list_users_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        self.manager
+            .list_users_filtered_in(self.tenant(), filter, offset, limit)
+            .await
+    }
+
+    pub async fn watch_user(&self, user_id: &str) -> Result<watch::Receiver<Option<User>>> {
+        self.manager.watch_user_in(self.tenant(), user_id).await
+    }
+}
+
+#[async_trait]
+impl UserService for ScopedManager {
+    async fn fetch_user(&self, user_id: &str) -> Result<Option<User>> {
+        ScopedManager::fetch_user(self, user_id).await
+    }
+
+    async fn batch_fetch_users(&self, user_ids: &[String]) -> HashMap<String, Option<User>> {
+        ScopedManager::batch_fetch_users(self, user_ids).await
+    }
+
+    async fn create_user(&self, user: &User) -> Result<User> {
+        ScopedManager::create_user(self, user).await
+    }
+
+    async fn update_user(&self, user_id: &str, update: UserUpdate) -> Result<bool> {
+        ScopedManager::update_user(self, user_id, update).await
+    }
+
+    async fn delete_user(&self, user_id: &str) -> Result<bool> {
+        ScopedManager::delete_user(self, user_id).await
+    }
+
+    async fn list_users(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        ScopedManager::list_users(self, offset, limit).await
+    }
+}
+
+impl UserManager {
+    const MAX_RETRIES: u32 = 3;
+    const RECENT_ERRORS: usize = 32;
+    const RECENT_OPERATIONS: usize = 64;
+    pub(crate) const TIMEOUT_SECS: u64 = 5;
+
+    /// Configure an HTTP-backed manager for `base_url`
+    pub fn builder(base_url: impl Into<String>) -> UserManagerBuilder {
+        UserManagerBuilder::new(base_url)
+    }
+
+    /// HTTP-backed manager configured from the variables listed in `env_vars`
+    pub fn from_env() -> Result<Self> {
+        UserManagerBuilder::from_env()?.build()
+    }
+
+    /// HTTP-backed manager configured from a config file section
+    pub fn from_config(config: ManagerConfig) -> Result<Self> {
+        config.validate()?;
+        config.into_builder().build()
+    }
+
+    /// Create a manager backed by any storage implementation
+    pub fn with_repository(repository: Arc<dyn UserRepository>) -> Self {
+        Self {
+            cache: Arc::new(RwLock::new(UserCache::default())),
+            repository,
+            tenant: None,
+            tenant_repositories: None,
+            scoped_repositories: Mutex::new(HashMap::new()),
+            in_flight: InFlight::default(),
+            shutting_down: AtomicBool::new(false),
+            shutdown_tx: watch::channel(false).0,
+            background_tasks: Mutex::new(Vec::new()),
+            offline_queue: None,
+            health: Mutex::new(None),
+            statistics: Mutex::new(Default::default()),
+            recent_errors: Mutex::new(Default::default()),
+            recent_operations: Mutex::new(Default::default()),
+            latencies: Mutex::new(HashMap::new()),
+            change_log: None,
+            slow_thresholds: SlowOperationThresholds::default(),
+            audit_sink: None,
+            instance_id: uuid::Uuid::new_v4().to_string(),
+            invalidation_bus: None,
+            invalidation_debounce: None,
+            hooks: Vec::new(),
+            cache_capacity: None,
+            cache_budget: None,
+            rate_limiter: None,
+            access_policy: None,
+            caller: None,
+            region_routing: None,
+            events: None,
+            outbox: None,
+            watchers: Mutex::new(HashMap::new()),
+            update_locks: UpdateLocks::default(),
+            cursors: CursorCodec::ephemeral(),
+            activity_log: None,
+            session_store: None,
+            password_reset: None,
+            invitations: None,
+        }
+    }
+
+    /// Keep at most `capacity` users cached, evicting the least recently written beyond it; 0 disables caching
+    pub fn with_cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Cap the estimated bytes held by the cache; inserts that would exceed it are refused
+    /// with `UserError::CacheBudgetExceeded` rather than evicting other entries
+    pub fn with_cache_budget(mut self, bytes: usize) -> Self {
+        self.cache_budget = Some(bytes);
+        self
+    }
+
+    /// Refuse to put `user` under `key` when the cache would end up over budget, counting
+    /// `freed` bytes evicted to make room
+    fn check_cache_budget(
+        &self,
+        cache: &UserCache,
+        key: &CacheKey,
+        user: &User,
+        freed: usize,
+    ) -> Result<()> {
+        let Some(budget) = self.cache_budget else {
+            return Ok(());
+        };
+        let needed = cache.bytes_with(key, user) - freed;
+        if needed > budget {
+            return Err(UserError::CacheBudgetExceeded { needed, budget }.into());
+        }
+        Ok(())
+    }
+
+    /// Insert into a locked cache, returning the entry evicted to make room
+    fn cache_insert(
+        &self,
+        cache: &mut UserCache,
+        key: CacheKey,
+        user: User,
+    ) -> Result<Option<(CacheKey, User)>> {
+        let mut victim = None;
+        if let Some(capacity) = self.cache_capacity {
+            if capacity == 0 {
+                return Ok(None);
+            }
+            if cache.len() >= capacity && !cache.contains_key(&key) {
+                victim = cache.oldest().cloned();
+            }
+        }
+        let freed = victim
+            .as_ref()
+            .and_then(|victim| cache.get_key_value(victim))
+            .map_or(0, |(victim, user)| UserCache::entry_size(victim, user));
+        self.check_cache_budget(cache, &key, &user, freed)?;
+        let evicted = victim.and_then(|victim| cache.remove_entry(&victim));
+        cache.insert(key, user);
+        Ok(evicted)
+    }
+
+    /// `cache_insert`, leaving a user that does not fit the budget uncached
+    fn cache_insert_or_skip(
+        &self,
+        cache: &mut UserCache,
+        key: CacheKey,
+        user: User,
+    ) -> Option<(CacheKey, User)> {
+        match self.cache_insert(cache, key, user) {
+            Ok(evicted) => evicted,
+            Err(e) => {
+                metrics::counter!(metric_names::CACHE_REJECTED).increment(1);
+                tracing::debug!(error = %e, "User not cached");
+                None
+            }
+        }
+    }
+
+    /// Sign pagination cursors with `codec` instead of a per-process secret, so they stay
+    /// valid across restarts and between instances sharing the secret
+    pub fn with_cursor_codec(mut self, codec: CursorCodec) -> Self {
+        self.cursors = codec;
+        self
+    }
+
+    pub fn cursor_codec(&self) -> &CursorCodec {
+        &self.cursors
+    }
+
+    /// Keep users' recent activity and last-seen times in `log`
+    pub fn with_activity_log(mut self, log: Arc<ActivityLog>) -> Self {
+        self.activity_log = Some(log);
+        self
+    }
+
+    pub fn activity_log(&self) -> Option<&Arc<ActivityLog>> {
+        self.activity_log.as_ref()
+    }
+
+    /// Track users' sessions in `store`
+    pub fn with_session_store(mut self, store: Arc<SessionStore>) -> Self {
+        self.session_store = Some(store);
+        self
+    }
+
+    pub fn session_store(&self) -> Option<&Arc<SessionStore>> {
+        self.session_store.as_ref()
+    }
+
+    /// Issue and redeem password-reset tokens through `reset`
+    pub fn with_password_reset(mut self, reset: Arc<PasswordReset>) -> Self {
+        self.password_reset = Some(reset);
+        self
+    }
+
+    pub fn password_reset(&self) -> Option<&Arc<PasswordReset>> {
+        self.password_reset.as_ref()
+    }
+
+    /// Issue and check invite tokens through `invitations`
+    pub fn with_invitations(mut self, invitations: Arc<Invitations>) -> Self {
+        self.invitations = Some(invitations);
+        self
+    }
+
+    pub fn invitations(&self) -> Option<&Arc<Invitations>> {
+        self.invitations.as_ref()
+    }
+
+    /// Scope every call on this manager to a tenant
+    pub fn with_tenant(mut self, tenant: impl Into<TenantId>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// How to build the storage view for a tenant; required for tenant-scoped calls
+    pub fn with_tenant_repositories<F>(mut self, factory: F) -> Self
+    where
+        F: Fn(&TenantId) -> Arc<dyn UserRepository> + Send + Sync + 'static,
+    {
+        self.tenant_repositories = Some(Box::new(factory));
+        self.scoped_repositories
+            .lock()
+            .expect("tenant repositories poisoned")
+            .clear();
+        self
+    }
+
+    /// Default tenant of this manager
+    pub fn tenant(&self) -> Option<&TenantId> {
+        self.tenant.as_ref()
+    }
+
+    /// Handle running calls against a specific tenant
+    pub fn for_tenant(&self, tenant: impl Into<TenantId>) -> TenantScope<'_> {
+        TenantScope {
+            manager: self,
+            tenant: tenant.into(),
+        }
+    }
+
+    /// Restricted handle sharing this manager, e.g. read-only or pinned to one tenant
+    pub fn scoped(self: &Arc<Self>, scope: ManagerScope) -> ScopedManager {
+        ScopedManager {
+            manager: self.clone(),
+            scope,
+        }
+    }
+
+    /// Storage view for a tenant, or the shared repository when there is none
+    pub(crate) fn repository_for(
+        &self,
+        tenant: Option<&TenantId>,
+    ) -> Result<Arc<dyn UserRepository>> {
+        let Some(tenant) = tenant else {
+            return Ok(self.repository.clone());
+        };
+        let Some(factory) = &self.tenant_repositories else {
+            return Err(UserError::UnsupportedTenant {
+                tenant: tenant.to_string(),
+                message: "no tenant repositories configured".to_string(),
+            }
+            .into());
+        };
+        let mut scoped = self
+            .scoped_repositories
+            .lock()
+            .expect("tenant repositories poisoned");
+        Ok(scoped
+            .entry(tenant.clone())
+            .or_insert_with(|| factory(tenant))
+            .clone())
+    }
+
+    /// Check mutations against `policy` before they reach the backend
+    pub fn with_access_policy(mut self, policy: Arc<AccessPolicy>) -> Self {
+        self.access_policy = Some(policy);
+        self
+    }
+
+    /// Identity used when no `as_caller` scope is active
+    pub fn with_caller(mut self, caller: Caller) -> Self {
+        self.caller = Some(caller);
+        self
+    }
+
+    /// Refuse backend calls beyond each tenant's rate limit or quota
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Backend for a caller's operation, charged to the tenant's rate limit and quota
+    async fn limited_repository(
+        &self,
+        tenant: Option<&TenantId>,
+        target: Target<'_>,
+    ) -> Result<Arc<dyn UserRepository>> {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire(tenant)?;
+        }
+        self.routed_repository(tenant, target).await
+    }
+
+    /// Send each tenant's or user's records to the backend of the region they belong in
+    pub fn with_region_routing(mut self, routing: RegionRouting) -> Self {
+        self.region_routing = Some(routing);
+        self
+    }
+
+    pub fn region_routing(&self) -> Option<&RegionRouting> {
+        self.region_routing.as_ref()
+    }
+
+    /// Backend of the region `target` lives in; without routing, the tenant's repository
+    async fn routed_repository(
+        &self,
+        tenant: Option<&TenantId>,
+        target: Target<'_>,
+    ) -> Result<Arc<dyn UserRepository>> {
+        let Some(routing) = &self.region_routing else {
+            return self.repository_for(tenant);
+        };
+        let region = match target {
+            Target::Tenant => routing.rule().region_for_tenant(tenant).to_string(),
+            Target::NewUser(user) => routing.rule().region_for_user(tenant, &user.metadata)?,
+            Target::User(user_id) => self.locate(routing, tenant, user_id).await?,
+        };
+        self.regional_repository(routing, tenant, &region)
+    }
+
+    /// The backend holding one user, by region routing when configured
+    pub(crate) async fn user_repository(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+    ) -> Result<Arc<dyn UserRepository>> {
+        self.routed_repository(tenant, Target::User(user_id)).await
+    }
+
+    /// Backends a tenant's listing spans, charged once to its rate limit: every region
+    /// when users pick their own by metadata, otherwise the tenant's region alone
+    pub(crate) async fn listing_repositories(
+        &self,
+        tenant: Option<&TenantId>,
+    ) -> Result<Vec<Arc<dyn UserRepository>>> {
+        match &self.region_routing {
+            Some(routing) if routing.rule().metadata_key.is_some() => {
+                if let Some(limiter) = &self.rate_limiter {
+                    limiter.acquire(tenant)?;
+                }
+                routing
+                    .regions()
+                    .map(|region| self.regional_repository(routing, tenant, region))
+                    .collect()
+            }
+            _ => Ok(vec![self.limited_repository(tenant, Target::Tenant).await?]),
+        }
+    }
+
+    fn regional_repository(
+        &self,
+        routing: &RegionRouting,
+        tenant: Option<&TenantId>,
+        region: &str,
+    ) -> Result<Arc<dyn UserRepository>> {
+        if region == routing.rule().home {
+            self.repository_for(tenant)
+        } else {
+            routing.repository(tenant, region)
+        }
+    }
+
+    /// Region holding an existing user: remembered from earlier calls, read off the cached
+    /// copy, or found by asking the tenant's region and then the others in turn
+    async fn locate(
+        &self,
+        routing: &RegionRouting,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+    ) -> Result<String> {
+        let fallback = routing.rule().region_for_tenant(tenant).to_string();
+        if routing.rule().metadata_key.is_none() {
+            return Ok(fallback);
+        }
+        if let Some(region) = routing.located(tenant, user_id) {
+            return Ok(region);
+        }
+        let cached = self
+            .cache
+            .read()
+            .await
+            .get(&CacheKey::new(tenant, user_id))
+            .cloned();
+        if let Some(user) = cached {
+            return routing.rule().region_for_user(tenant, &user.metadata);
+        }
+        let others = routing.regions().filter(|region| *region != fallback);
+        for region in std::iter::once(fallback.as_str()).chain(others) {
+            let repository = self.regional_repository(routing, tenant, region)?;
+            if repository.get(user_id).await?.is_some() {
+                routing.remember(tenant, user_id, Some(region));
+                return Ok(region.to_string());
+            }
+        }
+        Ok(fallback)
+    }
+
+    /// Refuse updates that would move a user's records to another region
+    async fn check_residency(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<()> {
+        let Some(routing) = &self.region_routing else {
+            return Ok(());
+        };
+        let Some(metadata) = updates.get("metadata") else {
+            return Ok(());
+        };
+        if routing.rule().metadata_key.is_none() {
+            return Ok(());
+        }
+        let metadata: HashMap<MetadataKey, serde_json::Value> =
+            serde_json::from_value(metadata.clone()).map_err(|e| UserError::InvalidUpdate {
+                field: "metadata".to_string(),
+                message: e.to_string(),
+            })?;
+        let region = routing.rule().region_for_user(tenant, &metadata)?;
+        let current = self.locate(routing, tenant, user_id).await?;
+        if region != current {
+            return Err(UserError::ResidencyViolation {
+                region: current,
+                message: format!(
+                    "user {} cannot move to region {}",
+                    redact_id(user_id),
+                    region
+                ),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Capture every mutation, with before/after snapshots, into a change log
+    pub fn with_change_log(mut self, change_log: Arc<ChangeLog>) -> Self {
+        self.change_log = Some(change_log);
+        self
+    }
+
+    pub fn change_log(&self) -> Option<&Arc<ChangeLog>> {
+        self.change_log.as_ref()
+    }
+
+    /// Warn when operations take longer than these thresholds
+    pub fn with_slow_operation_thresholds(mut self, thresholds: SlowOperationThresholds) -> Self {
+        self.slow_thresholds = thresholds;
+        self
+    }
+
+    /// Run an operation inside its span and record how it ended
+    async fn traced<T>(
+        &self,
+        span: tracing::Span,
+        operation: impl std::future::Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let started = Instant::now();
+        let attempts = std::cell::Cell::new(0);
+        let (result, attempts) = ATTEMPTS
+            .scope(attempts, async {
+                let result = operation.instrument(span.clone()).await;
+                (result, ATTEMPTS.with(|attempts| attempts.get()))
+            })
+            .await;
+        // Nested operations (a batch's fetches) roll up into the enclosing one
+        let _ = ATTEMPTS.try_with(|outer| outer.set(outer.get() + attempts));
+        let elapsed = started.elapsed();
+        let status = if result.is_ok() { "ok" } else { "error" };
+        span.record("status", status);
+        span.record("duration_ms", elapsed.as_millis() as u64);
+
+        let operation = span
+            .metadata()
+            .map_or("unknown", |metadata| metadata.name());
+        metrics::counter!(metric_names::REQUESTS, "operation" => operation, "status" => status)
+            .increment(1);
+        metrics::histogram!(metric_names::REQUEST_DURATION, "operation" => operation)
+            .record(elapsed.as_secs_f64());
+        self.latencies
+            .lock()
+            .expect("latency histograms poisoned")
+            .entry(operation)
+            .or_default()
+            .record(elapsed);
+        let mut recent = self
+            .recent_operations
+            .lock()
+            .expect("operation history poisoned");
+        if recent.len() == Self::RECENT_OPERATIONS {
+            recent.pop_front();
+        }
+        recent.push_back(RecordedOperation {
+            at: Utc::now(),
+            operation: operation.to_string(),
+            status,
+            duration: elapsed,
+            attempts,
+        });
+        drop(recent);
+        if let Err(e) = &result {
+            let kind = UserError::kind_of(e);
+            metrics::counter!(metric_names::ERRORS, "operation" => operation, "kind" => kind)
+                .increment(1);
+            let mut recent = self.recent_errors.lock().expect("error history poisoned");
+            if recent.len() == Self::RECENT_ERRORS {
+                recent.pop_front();
+            }
+            recent.push_back(RecordedError {
+                at: Utc::now(),
+                operation: operation.to_string(),
+                kind,
+                message: redact_error(e),
+            });
+            drop(recent);
+            span.in_scope(|| tracing::warn!(error = redact_error(e), kind, "Operation failed"));
+        }
+        if let Some(threshold) = self.slow_thresholds.threshold_for(operation) {
+            if elapsed > threshold {
+                metrics::counter!(metric_names::SLOW_OPERATIONS, "operation" => operation)
+                    .increment(1);
+                span.in_scope(|| {
+                    tracing::warn!(
+                        operation,
+                        duration_ms = elapsed.as_millis() as u64,
+                        threshold_ms = threshold.as_millis() as u64,
+                        attempts,
+                        "Slow operation"
+                    )
+                });
+            }
+        }
+        result
+    }
+
+    /// The most recent operations, oldest first
+    pub fn recent_operations(&self) -> Vec<RecordedOperation> {
+        self.recent_operations
+            .lock()
+            .expect("operation history poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Broadcast cache invalidations to other instances sharing the bus
+    pub fn with_invalidation_bus(mut self, bus: Arc<dyn InvalidationBus>) -> Self {
+        self.invalidation_bus = Some(bus);
+        self
+    }
+
+    /// Hold remote invalidations for `window` after the first one for a user (or tenant),
+    /// so a burst of them costs one eviction and watcher refresh
+    pub fn with_invalidation_debounce(mut self, window: Duration) -> Self {
+        self.invalidation_debounce = Some(window).filter(|window| !window.is_zero());
+        self
+    }
+
+    /// Identifier used to ignore this instance's own invalidation messages
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Drop a cached user locally and tell other instances to do the same
+    pub(crate) async fn invalidate_cached(&self, tenant: Option<&TenantId>, user_id: &str) {
+        let key = CacheKey::new(tenant, user_id);
+        let evicted = self.cache.write().await.remove(&key);
+        if let Some(user) = evicted {
+            self.notify_evicted([(key, user)], EvictReason::Invalidated);
+        }
+        if let Some(bus) = &self.invalidation_bus {
+            let message = InvalidationMessage {
+                origin: self.instance_id.clone(),
+                tenant: tenant.cloned(),
+                user_id: Some(user_id.to_string()),
+                flush_all: false,
+            };
+            if let Err(e) = bus.publish(&message).await {
+                tracing::warn!(
+                    user_id = %redact_id(user_id),
+                    error = redact_error(&e),
+                    "Failed to publish invalidation"
+                );
+            }
+        }
+    }
+
+    /// Apply an invalidation received from another instance, returning whether anything was evicted
+    pub async fn apply_invalidation(&self, message: &InvalidationMessage) -> bool {
+        if message.origin == self.instance_id {
+            return false;
+        }
+        let mut cache = self.cache.write().await;
+        let keys: Vec<CacheKey> = match &message.user_id {
+            Some(user_id) if !message.flush_all => {
+                vec![CacheKey::new(message.tenant.as_ref(), user_id)]
+            }
+            _ => cache
+                .keys()
+                .filter(|key| message.covers(key))
+                .cloned()
+                .collect(),
+        };
+        let evicted: Vec<(CacheKey, User)> = keys
+            .into_iter()
+            .filter_map(|key| cache.remove(&key).map(|user| (key, user)))
+            .collect();
+        drop(cache);
+        let any = !evicted.is_empty();
+        self.notify_evicted(evicted, EvictReason::Remote);
+
+        let watched: Vec<CacheKey> = {
+            let mut watchers = self.watchers.lock().expect("user watchers poisoned");
+            watchers.retain(|_, sender| sender.receiver_count() > 0);
+            watchers
+                .keys()
+                .filter(|key| message.covers(key))
+                .cloned()
+                .collect()
+        };
+        for key in watched {
+            self.refresh_watched(key.tenant.as_ref(), &key.user_id)
+                .await;
+        }
+        any
+    }
+
+    /// Deliver this manager's events to webhooks in the background until shutdown;
+    /// needs an event bus. Events wait in memory rather than being dropped while
+    /// deliveries are slow; those still waiting at shutdown or a crash are lost, and
+    /// deliveries that exhaust their retries go to the dead letters.
+    pub fn start_webhook_dispatcher(
+        self: &Arc<Self>,
+        dispatcher: Arc<WebhookDispatcher>,
+    ) -> Result<()> {
+        self.spawn_event_consumer("webhooks", move |event| {
+            let dispatcher = dispatcher.clone();
+            async move {
+                dispatcher.dispatch(&event).await;
+            }
+        })
+    }
+
+    /// Send this manager's events to `sink` in the background until shutdown, in the
+    /// order they were published; needs an event bus. No event is dropped while the sink
+    /// falls behind, but delivery is best-effort: events still queued in memory at
+    /// shutdown or a crash are lost, and so are those the sink rejects.
+    pub fn start_event_sink(self: &Arc<Self>, sink: Arc<dyn EventSink>) -> Result<()> {
+        self.spawn_event_consumer(sink.name(), move |event| {
+            let sink = sink.clone();
+            async move {
+                if !sink.wants(&event) {
+                    return;
+                }
+                let outcome = sink.send(&event).await;
+                let status = if outcome.is_ok() { "sent" } else { "failed" };
+                metrics::counter!(
+                    metric_names::SINK_EVENTS,
+                    "sink" => sink.name(),
+                    "event" => event.kind(),
+                    "outcome" => status
+                )
+                .increment(1);
+                if let Err(e) = outcome {
+                    tracing::warn!(
+                        sink = sink.name(),
+                        event = event.kind(),
+                        user_id = %redact_id(event.user_id()),
+                        error = redact_error(&e),
+                        "Dropping event the sink did not accept"
+                    );
+                }
+            }
+        })
+    }
+
+    /// Run `handle` on each published event, one at a time, until shutdown. Events wait
+    /// in an unbounded queue rather than being dropped when `handle` is slow.
+    fn spawn_event_consumer<F, Fut>(self: &Arc<Self>, field: &str, mut handle: F) -> Result<()>
+    where
+        F: FnMut(UserEvent) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = ()> + Send,
+    {
+        let Some(bus) = &self.events else {
+            return Err(UserError::InvalidConfig {
+                field: field.to_string(),
+                message: "publishing events needs an event bus".to_string(),
+            }
+            .into());
+        };
+        let mut events = bus.subscribe_unbounded();
+        self.spawn_background(move |mut shutdown| async move {
+            loop {
+                let event = tokio::select! {
+                    event = events.recv() => event,
+                    _ = shutdown.changed() => break,
+                };
+                let Some(event) = event else {
+                    break;
+                };
+                handle(event).await;
+            }
+        });
+        Ok(())
+    }
+
+    /// Follow a user's latest known state. The receiver starts from the cache or the
+    /// backend and changes whenever this manager caches, writes or is told about a new
+    /// version; `None` once the user is gone.
+    pub async fn watch_user(&self, user_id: &str) -> Result<watch::Receiver<Option<User>>> {
+        self.watch_user_in(self.tenant.as_ref(), user_id).await
+    }
+
+    async fn watch_user_in(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+    ) -> Result<watch::Receiver<Option<User>>> {
+        let key = CacheKey::new(tenant, user_id);
+        if let Some(sender) = self
+            .watchers
+            .lock()
+            .expect("user watchers poisoned")
+            .get(&key)
+        {
+            return Ok(sender.subscribe());
+        }
+        let current = self.fetch_user_in(tenant, user_id).await?;
+        let mut watchers = self.watchers.lock().expect("user watchers poisoned");
+        // Watches on users that never change again are not forgotten by `notify_watchers`
+        watchers.retain(|_, sender| sender.receiver_count() > 0);
+        Ok(watchers
+            .entry(key)
+            .or_insert_with(|| watch::channel(current).0)
+            .subscribe())
+    }
+
+    /// Pass a user's new state to its watchers, forgetting watches nobody holds any more
+    fn notify_watchers(&self, tenant: Option<&TenantId>, user_id: &str, user: Option<&User>) {
+        let mut watchers = self.watchers.lock().expect("user watchers poisoned");
+        let key = CacheKey::new(tenant, user_id);
+        let Some(sender) = watchers.get(&key) else {
+            return;
+        };
+        if sender.receiver_count() == 0 {
+            watchers.remove(&key);
+            return;
+        }
+        sender.send_if_modified(|current| {
+            if current.as_ref() == user {
+                return false;
+            }
+            *current = user.cloned();
+            true
+        });
+    }
+
+    fn is_watched(&self, tenant: Option<&TenantId>, user_id: &str) -> bool {
+        self.watchers
+            .lock()
+            .expect("user watchers poisoned")
+            .get(&CacheKey::new(tenant, user_id))
+            .is_some_and(|sender| sender.receiver_count() > 0)
+    }
+
+    /// Re-read a watched user from the backend after it changed without a new copy in hand
+    async fn refresh_watched(&self, tenant: Option<&TenantId>, user_id: &str) {
+        if !self.is_watched(tenant, user_id) {
+            return;
+        }
+        let user = match self.routed_repository(tenant, Target::User(user_id)).await {
+            Ok(repository) => repository.get(user_id).await,
+            Err(e) => Err(e),
+        };
+        match user {
+            Ok(user) => self.notify_watchers(tenant, user_id, user.as_ref()),
+            Err(e) => tracing::warn!(
+                user_id = %redact_id(user_id),
+                error = redact_error(&e),
+                "Failed to refresh watched user"
+            ),
+        }
+    }
+
+    /// Observe or veto operations; hooks run in registration order
+    pub fn with_hook(mut self, hook: Arc<dyn UserHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Publish lifecycle events to `bus` after each operation completes
+    pub fn with_event_bus(mut self, bus: Arc<EventBus>) -> Self {
+        self.events = Some(bus);
+        self
+    }
+
+    pub fn event_bus(&self) -> Option<&Arc<EventBus>> {
+        self.events.as_ref()
+    }
+
+    /// Build and publish an event, only when someone could be listening
+    fn publish(&self, event: impl FnOnce() -> UserEvent) {
+        let Some(bus) = &self.events else {
+            return;
+        };
+        bus.publish(&event());
+    }
+
+    /// Publish the event of a mutation `repository` applied, unless the repository wrote it
+    /// to an outbox the relay publishes from
+    fn publish_applied(&self, repository: &dyn UserRepository, event: impl FnOnce() -> UserEvent) {
+        if self.outbox.is_some() && repository.writes_outbox() {
+            return;
+        }
+        self.publish(event);
+    }
+
+    /// Take create, update and delete events from the outbox of repositories that write
+    /// one rather than publishing them directly; start the relay with
+    /// `start_outbox_relay`. Events other backends apply are still published directly.
+    pub fn with_outbox(mut self, outbox: Arc<dyn Outbox>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Move committed outbox entries onto the event bus every `poll_interval` until shutdown
+    pub fn start_outbox_relay(self: &Arc<Self>, poll_interval: Duration) -> Result<()> {
+        let (Some(outbox), Some(bus)) = (&self.outbox, &self.events) else {
+            return Err(UserError::InvalidConfig {
+                field: "outbox".to_string(),
+                message: "the outbox relay needs an outbox and an event bus".to_string(),
+            }
+            .into());
+        };
+        let relay = OutboxRelay::new(outbox.clone(), bus.clone());
+        self.spawn_background(move |mut shutdown| async move {
+            let mut interval = tokio::time::interval(poll_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.changed() => break,
+                }
+                if let Err(e) = relay.relay_once().await {
+                    tracing::warn!(error = redact_error(&e), "Outbox relay failed; retrying");
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Reject a mutation the current caller's roles do not permit; allowed when no policy is set
+    pub(crate) fn authorize(&self, permission: Permission) -> Result<()> {
+        let Some(policy) = &self.access_policy else {
+            return Ok(());
+        };
+        let caller = current_caller().or_else(|| self.caller.clone());
+        if caller
+            .as_ref()
+            .is_some_and(|caller| policy.allows(caller, permission))
+        {
+            return Ok(());
+        }
+        let caller = caller.map_or_else(|| "anonymous caller".to_string(), |caller| caller.id);
+        tracing::warn!(caller = %caller, %permission, "Operation forbidden by access policy");
+        Err(UserError::Forbidden {
+            caller,
+            permission: permission.to_string(),
+        }
+        .into())
+    }
+
+    /// Let hooks veto an update, showing them the cached user, else `stored`, else the
+    /// backend's copy
+    async fn run_update_hooks(
+        &self,
+        tenant: Option<&TenantId>,
+        repository: &dyn UserRepository,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+        stored: Option<User>,
+    ) -> Result<()> {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        let cached = self
+            .cache
+            .read()
+            .await
+            .get(&CacheKey::new(tenant, user_id))
+            .cloned();
+        let current = match cached.or(stored) {
+            Some(user) => Some(user),
+            None => repository.get(user_id).await?,
+        };
+        let ctx = UpdateContext {
+            tenant,
+            user_id,
+            current: current.as_ref(),
+            updates,
+        };
+        self.run_hooks("update", |hook| hook.on_update(&ctx))
+    }
+
+    fn run_hooks(
+        &self,
+        operation: &str,
+        check: impl Fn(&dyn UserHook) -> Result<(), Veto>,
+    ) -> Result<()> {
+        for hook in &self.hooks {
+            if let Err(Veto(reason)) = check(hook.as_ref()) {
+                return Err(UserError::Vetoed {
+                    operation: operation.to_string(),
+                    reason,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn notify_evicted(
+        &self,
+        evicted: impl IntoIterator<Item = (CacheKey, User)>,
+        reason: EvictReason,
+    ) {
+        if self.hooks.is_empty() && self.events.is_none() {
+            return;
+        }
+        for (key, user) in evicted {
+            let ctx = EvictContext {
+                tenant: key.tenant.as_ref(),
+                user_id: &key.user_id,
+                user: &user,
+                reason,
+            };
+            for hook in &self.hooks {
+                hook.on_cache_evict(&ctx);
+            }
+            self.publish(|| UserEvent::CacheEvicted {
+                tenant: key.tenant.clone(),
+                user_id: key.user_id.clone(),
+                reason,
+            });
+        }
+    }
+
+    /// Listen on the invalidation bus until shutdown, evicting entries changed elsewhere.
+    /// When the subscription ends, e.g. with its connection, the whole cache is flushed, as
+    /// invalidations may be missed, and the listener subscribes again with backoff.
+    pub async fn start_invalidation_listener(self: &Arc<Self>) -> Result<()> {
+        let Some(bus) = self.invalidation_bus.clone() else {
+            return Ok(());
+        };
+        let mut messages = bus.subscribe().await?;
+        let manager = Arc::downgrade(self);
+        let instance_id = self.instance_id.clone();
+        let debounce = self.invalidation_debounce;
+        self.spawn_background(move |mut shutdown| async move {
+            use futures::StreamExt;
+            use tokio::time::Instant;
+
+            // Debounced messages by tenant and user, each due a window after its first arrival
+            let mut held: HashMap<(Option<TenantId>, Option<String>), (InvalidationMessage, Instant)> =
+                HashMap::new();
+            loop {
+                let next_due = held.values().map(|(_, due)| *due).min();
+                let message = tokio::select! {
+                    message = messages.next() => message,
+                    _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                        let Some(manager) = manager.upgrade() else {
+                            break;
+                        };
+                        let now = Instant::now();
+                        let due: Vec<_> = held
+                            .iter()
+                            .filter(|(_, (_, due))| *due <= now)
+                            .map(|(key, _)| key.clone())
+                            .collect();
+                        for key in due {
+                            if let Some((message, _)) = held.remove(&key) {
+                                manager.apply_remote_invalidation(&message).await;
+                            }
+                        }
+                        continue;
+                    }
+                    _ = shutdown.changed() => {
+                        if let Some(manager) = manager.upgrade() {
+                            for (message, _) in held.into_values() {
+                                manager.apply_remote_invalidation(&message).await;
+                            }
+                        }
+                        break;
+                    }
+                };
+                let Some(message) = message else {
+                    held.clear();
+                    if let Some(manager) = manager.upgrade() {
+                        manager.clear_cache().await;
+                    }
+                    match Self::resubscribe_invalidations(&*bus, &mut shutdown).await {
+                        Some(stream) => messages = stream,
+                        None => break,
+                    }
+                    // Whatever changed while the subscription was down went unheard
+                    if let Some(manager) = manager.upgrade() {
+                        manager.clear_cache().await;
+                    }
+                    continue;
+                };
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                match debounce {
+                    None => manager.apply_remote_invalidation(&message).await,
+                    Some(_) if message.flush_all => {
+                        held.clear();
+                        manager.apply_remote_invalidation(&message).await;
+                    }
+                    Some(_) if message.origin == instance_id => {}
+                    Some(window) => {
+                        let key = (message.tenant.clone(), message.user_id.clone());
+                        held.entry(key)
+                            .or_insert_with(|| (message, Instant::now() + window));
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Subscribe to `bus` again after its stream ended, backing off between failed attempts;
+    /// `None` once shutdown begins
+    async fn resubscribe_invalidations(
+        bus: &dyn InvalidationBus,
+        shutdown: &mut watch::Receiver<bool>,
+    ) -> Option<futures::stream::BoxStream<'static, InvalidationMessage>> {
+        const MAX_BACKOFF: Duration = Duration::from_secs(30);
+        let mut backoff = Duration::from_millis(100);
+        loop {
+            tracing::warn!(
+                retry_in = ?backoff,
+                "Invalidation subscription ended; cache flushed until it is back"
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = shutdown.changed() => return None,
+            }
+            match bus.subscribe().await {
+                Ok(stream) => {
+                    tracing::info!("Invalidation subscription restored");
+                    return Some(stream);
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        error = redact_error(&e),
+                        "Failed to resubscribe to invalidations"
+                    );
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    async fn apply_remote_invalidation(&self, message: &InvalidationMessage) {
+        if self.apply_invalidation(message).await {
+            tracing::info!(
+                origin = %message.origin,
+                user_id = ?message.user_id.as_deref().map(redact_id),
+                "Cache entry invalidated remotely"
+            );
+        }
+    }
+
+    /// Current backend state of a user, read only when changes are being captured
+    async fn snapshot_for_log(
+        &self,
+        repository: &dyn UserRepository,
+        user_id: &str,
+    ) -> Option<User> {
+        if !self.tracks_changes() {
+            return None;
+        }
+        match repository.get(user_id).await {
+            Ok(user) => user,
+            Err(e) => {
+                tracing::warn!(
+                    user_id = %redact_id(user_id),
+                    error = redact_error(&e),
+                    "Failed to snapshot user for change log"
+                );
+                None
+            }
+        }
+    }
+
+    fn record_change(
+        &self,
+        tenant: Option<&TenantId>,
+        kind: ChangeKind,
+        user_id: &str,
+        before: Option<User>,
+        after: Option<User>,
+    ) {
+        if let Some(audit_sink) = &self.audit_sink {
+            let event = AuditEvent::new(tenant, kind, user_id, before.as_ref(), after.as_ref());
+            if let Err(e) = audit_sink.record(&event) {
+                tracing::error!(
+                    user_id = %redact_id(user_id),
+                    error = redact_error(&e),
+                    "Failed to write audit event"
+                );
+            }
+        }
+        if let Some(change_log) = &self.change_log {
+            if let Err(e) = change_log.append(tenant, kind, user_id, before, after) {
+                tracing::error!(
+                    user_id = %redact_id(user_id),
+                    error = redact_error(&e),
+                    "Failed to record change"
+                );
+            }
+        }
+    }
+
+    /// Whether mutations need before/after snapshots
+    fn tracks_changes(&self) -> bool {
+        self.change_log.is_some() || self.audit_sink.is_some()
+    }
+
+    /// Report every mutation to `sink`; see [`with_audit_actor`] for attributing them
+    pub fn with_audit_sink(mut self, sink: Arc<dyn AuditSink>) -> Self {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// Queue creates and updates for later replay when the backend is unreachable
+    pub fn with_offline_queue(mut self, queue: Arc<OfflineQueue>) -> Self {
+        self.offline_queue = Some(queue);
+        self
+    }
+
+    pub fn offline_queue(&self) -> Option<&Arc<OfflineQueue>> {
+        self.offline_queue.as_ref()
+    }
+
+    /// Queue a mutation if the error means the backend is unreachable, otherwise pass it through
+    fn queue_if_unreachable(
+        &self,
+        tenant: Option<&TenantId>,
+        error: anyhow::Error,
+        mutation: Mutation,
+    ) -> anyhow::Error {
+        let Some(queue) = &self.offline_queue else {
+            return error;
+        };
+        if !UserError::is_unreachable(&error) {
+            return error;
+        }
+        match queue.push(tenant, mutation) {
+            Ok(idempotency_key) => {
+                tracing::warn!(
+                    idempotency_key,
+                    error = redact_error(&error),
+                    "Backend unreachable, mutation queued"
+                );
+                UserError::Queued { idempotency_key }.into()
+            }
+            Err(queue_error) => queue_error.context(error.to_string()),
+        }
+    }
+
+    /// Replay queued mutations in order, stopping while the backend is still unreachable
+    pub async fn replay_offline_queue(&self) -> Result<ReplayReport> {
+        let mut report = ReplayReport::default();
+        let Some(queue) = &self.offline_queue else {
+            return Ok(report);
+        };
+        let _guard = self.begin_request()?;
+
+        while let Some(entry) = queue.peek() {
+            let tenant = entry.tenant.as_ref();
+            let user_id = entry.mutation.user_id();
+            let result = match self
+                .routed_repository(tenant, Target::of(&entry.mutation))
+                .await
+            {
+                Ok(repository) => {
+                    let before = self.snapshot_for_log(&*repository, user_id).await;
+                    repository
+                        .apply_idempotent(&entry.mutation, &entry.idempotency_key)
+                        .await
+                        .map(|applied| (repository, before, applied))
+                }
+                Err(e) => Err(e),
+            };
+            self.invalidate_cached(tenant, user_id).await;
+            match result {
+                Ok((repository, before, true)) => {
+                    report.replayed += 1;
+                    metrics::counter!(metric_names::RETRIES, "reason" => "offline_replay")
+                        .increment(1);
+                    self.mutation_applied(tenant, &*repository, &entry.mutation, before)
+                        .await;
+                }
+                Ok((_, _, false)) => {
+                    tracing::warn!(
+                        idempotency_key = %entry.idempotency_key,
+                        "Queued mutation no longer applies"
+                    );
+                    report.not_applied += 1;
+                }
+                Err(e) if UserError::is_unreachable(&e) => break,
+                Err(e) => {
+                    tracing::error!(
+                        idempotency_key = %entry.idempotency_key,
+                        error = redact_error(&e),
+                        "Dropping queued mutation"
+                    );
+                    report.failed.push((entry.clone(), redact_error(&e)));
+                }
+            }
+            queue.pop(&entry.idempotency_key)?;
+        }
+
+        report.remaining = queue.len();
+        if report.replayed > 0 || report.not_applied > 0 || !report.failed.is_empty() {
+            tracing::info!(
+                replayed = report.replayed,
+                not_applied = report.not_applied,
+                failed = report.failed.len(),
+                remaining = report.remaining,
+                "Offline queue replayed"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Periodically replay the offline queue until shutdown
+    pub fn spawn_offline_replay(self: &Arc<Self>, interval: Duration) {
+        let manager = Arc::downgrade(self);
+        self.spawn_background(move |mut shutdown| async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown.changed() => break,
+                }
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                if manager
+                    .offline_queue
+                    .as_ref()
+                    .is_some_and(|q| !q.is_empty())
+                {
+                    if let Err(e) = manager.replay_offline_queue().await {
+                        tracing::warn!(error = redact_error(&e), "Offline queue replay failed");
+                    }
+                }
+            }
+        });
+    }
+
+    /// Probe the backend once, remembering the result for [`Self::current_health`]
+    pub async fn health_check(&self) -> HealthReport {
+        let started = Instant::now();
+        let result = match self.repository_for(self.tenant.as_ref()) {
+            Ok(repository) => repository.ping().await,
+            Err(e) => Err(e),
+        };
+        let report = HealthReport {
+            healthy: result.is_ok(),
+            latency: started.elapsed(),
+            checked_at: Utc::now(),
+            error: result.err().map(|e| redact_error(&e)),
+        };
+        if !report.healthy {
+            tracing::warn!(
+                error = report.error.as_deref(),
+                latency_ms = report.latency.as_millis() as u64,
+                "Backend health check failed"
+            );
+        }
+        *self.health.lock().expect("health state poisoned") = Some(report.clone());
+        report
+    }
+
+    /// Most recent health check, if any has run
+    pub fn current_health(&self) -> Option<HealthReport> {
+        self.health.lock().expect("health state poisoned").clone()
+    }
+
+    /// Readiness: the last health check passed and shutdown has not begun
+    pub fn is_ready(&self) -> bool {
+        !self.shutting_down.load(Ordering::Acquire)
+            && self.current_health().is_some_and(|report| report.healthy)
+    }
+
+    /// Run a health check every `interval` until shutdown
+    pub fn spawn_health_checker(self: &Arc<Self>, interval: Duration) {
+        let manager = Arc::downgrade(self);
+        self.spawn_background(move |mut shutdown| async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown.changed() => break,
+                }
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                manager.health_check().await;
+            }
+        });
+    }
+
+    /// Re-read every cached user from the backend holding it, evicting users deleted
+    /// since. Entries written while their fresh copy was being read are left alone, and a
+    /// user that cannot be read keeps its cached copy and is reported in `failed`.
+    pub async fn refresh_cache(&self) -> CacheRefresh {
+        let keys: Vec<(CacheKey, u64)> = {
+            let cache = self.cache.read().await;
+            cache
+                .keys()
+                .filter_map(|key| Some((key.clone(), cache.version(key)?)))
+                .collect()
+        };
+        let mut report = CacheRefresh::default();
+        let mut evicted = Vec::new();
+        for (key, version) in keys {
+            let tenant = key.tenant.as_ref();
+            let current = match self
+                .routed_repository(tenant, Target::User(&key.user_id))
+                .await
+            {
+                Ok(repository) => repository.get(&key.user_id).await,
+                Err(e) => Err(e),
+            };
+            let current = match current {
+                Ok(current) => current,
+                Err(error) => {
+                    report.failed.push(RefreshFailure {
+                        tenant: key.tenant.clone(),
+                        user_id: key.user_id.clone(),
+                        error,
+                    });
+                    continue;
+                }
+            };
+            let mut cache = self.cache.write().await;
+            if cache.version(&key) != Some(version) {
+                continue;
+            }
+            match current {
+                // A copy grown past the budget is dropped rather than left stale
+                Some(user) if self.check_cache_budget(&cache, &key, &user, 0).is_ok() => {
+                    cache.replace(&key, user.clone());
+                    drop(cache);
+                    report.refreshed += 1;
+                    self.notify_watchers(tenant, &key.user_id, Some(&user));
+                }
+                Some(_) => evicted.extend(cache.remove_entry(&key)),
+                None => {
+                    evicted.extend(cache.remove_entry(&key));
+                    drop(cache);
+                    self.notify_watchers(tenant, &key.user_id, None);
+                }
+            }
+        }
+        report.evicted = evicted.len();
+        self.notify_evicted(evicted, EvictReason::Invalidated);
+        report
+    }
+
+    /// Cached copy of a user and the version it is at, without going to the backend or
+    /// counting a hit or miss
+    pub(crate) async fn peek_cached(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+    ) -> Option<(User, u64)> {
+        let key = CacheKey::new(tenant, user_id);
+        let cache = self.cache.read().await;
+        Some((cache.get(&key)?.clone(), cache.version(&key)?))
+    }
+
+    /// Ids of the tenant's cached users
+    pub(crate) async fn cached_ids(&self, tenant: Option<&TenantId>) -> Vec<String> {
+        self.cache
+            .read()
+            .await
+            .keys()
+            .filter(|key| key.tenant.as_ref() == tenant)
+            .map(|key| key.user_id.clone())
+            .collect()
+    }
+
+    /// Bring a cached user in line with the backend's `current` copy, read after the entry
+    /// was at `version`, evicting it when the backend has none. An entry written since is
+    /// newer than `current` and left alone. Returns whether an entry changed.
+    pub(crate) async fn settle_cached(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        version: u64,
+        current: Option<&User>,
+    ) -> bool {
+        let key = CacheKey::new(tenant, user_id);
+        let mut cache = self.cache.write().await;
+        if cache.version(&key) != Some(version) {
+            return false;
+        }
+        let evicted = match current {
+            Some(user) if cache.get(&key).is_some_and(|cached| cached == user) => return false,
+            Some(user) if self.check_cache_budget(&cache, &key, user, 0).is_ok() => {
+                cache.replace(&key, user.clone());
+                None
+            }
+            // Gone from the backend, or too big to keep
+            _ => cache.remove_entry(&key),
+        };
+        drop(cache);
+        self.notify_evicted(evicted, EvictReason::Invalidated);
+        self.notify_watchers(tenant, user_id, current);
+        true
+    }
+
+    /// Recompute statistics over the cache for every tenant in it, keyed by tenant (`""`
+    /// for untenanted users), keeping them for [`Self::latest_statistics`]
+    pub async fn recompute_statistics(&self) -> std::collections::BTreeMap<String, UserStatistics> {
+        let mut by_tenant: HashMap<String, Vec<User>> = HashMap::new();
+        for (key, user) in self.cache.read().await.iter() {
+            let tenant = key.tenant.as_ref().map_or("", TenantId::as_str);
+            by_tenant
+                .entry(tenant.to_string())
+                .or_default()
+                .push(user.clone());
+        }
+        let statistics: std::collections::BTreeMap<String, UserStatistics> = by_tenant
+            .into_iter()
+            .map(|(tenant, users)| (tenant, Self::get_user_statistics(&users)))
+            .collect();
+        *self.statistics.lock().expect("statistics poisoned") = statistics.clone();
+        statistics
+    }
+
+    /// Statistics from the last [`Self::recompute_statistics`], empty until it has run
+    pub fn latest_statistics(&self) -> std::collections::BTreeMap<String, UserStatistics> {
+        self.statistics.lock().expect("statistics poisoned").clone()
+    }
+
+    /// Register the chosen maintenance jobs with `scheduler` under their field names; the
+    /// jobs hold the manager weakly and fail once it is dropped
+    pub fn register_maintenance(
+        self: &Arc<Self>,
+        scheduler: &Scheduler,
+        jobs: MaintenanceJobs,
+    ) -> Result<()> {
+        fn job<F, Fut>(
+            manager: &Arc<UserManager>,
+            work: F,
+        ) -> impl Fn() -> futures::future::BoxFuture<'static, Result<()>> + Send + Sync + 'static
+        where
+            F: Fn(Arc<UserManager>) -> Fut + Send + Sync + 'static,
+            Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+        {
+            let manager = Arc::downgrade(manager);
+            let work = Arc::new(work);
+            move || {
+                let manager = manager.upgrade();
+                let work = work.clone();
+                Box::pin(async move {
+                    let manager = manager.context("User manager was dropped")?;
+                    work(manager).await
+                })
+            }
+        }
+
+        if let Some(schedule) = jobs.outbox_relay {
+            let (Some(outbox), Some(bus)) = (&self.outbox, &self.events) else {
+                return Err(UserError::InvalidConfig {
+                    field: "outbox".to_string(),
+                    message: "the outbox relay needs an outbox and an event bus".to_string(),
+                }
+                .into());
+            };
+            let relay = Arc::new(OutboxRelay::new(outbox.clone(), bus.clone()));
+            scheduler.register("outbox_relay", schedule, move || {
+                let relay = relay.clone();
+                async move { relay.relay_once().await.map(|_| ()) }
+            })?;
+        }
+        if let Some(schedule) = jobs.cache_refresh {
+            scheduler.register(
+                "cache_refresh",
+                schedule,
+                job(self, |manager| async move {
+                    let report = manager.refresh_cache().await;
+                    match report.failed.first() {
+                        Some(first) => Err(anyhow::anyhow!(
+                            "{} cached users could not be refreshed; user {}: {}",
+                            report.failed.len(),
+                            redact_id(&first.user_id),
+                            redact_error(&first.error)
+                        )),
+                        None => Ok(()),
+                    }
+                }),
+            )?;
+        }
+        if let Some(schedule) = jobs.statistics {
+            scheduler.register(
+                "statistics",
+                schedule,
+                job(self, |manager| async move {
+                    manager.recompute_statistics().await;
+                    Ok(())
+                }),
+            )?;
+        }
+        if let Some(schedule) = jobs.health_check {
+            scheduler.register(
+                "health_check",
+                schedule,
+                job(self, |manager| async move {
+                    match manager.health_check().await.error {
+                        Some(error) => anyhow::bail!("Backend unhealthy: {}", error),
+                        None => Ok(()),
+                    }
+                }),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Start every job in `scheduler` and stop them all when the manager shuts down
+    pub fn start_scheduler(&self, scheduler: Arc<Scheduler>) {
+        scheduler.start_all();
+        self.spawn_background(move |mut shutdown| async move {
+            let _ = shutdown.changed().await;
+            scheduler.stop_all().await;
+        });
+    }
+
+    /// Diagnostic report of configuration, cache, in-flight work and recent failures
+    pub async fn debug_snapshot(&self) -> DebugSnapshot {
+        let mut cache_summary = CacheSummary::default();
+        for (key, user) in self.cache.read().await.iter() {
+            cache_summary.entries += 1;
+            let tenant = key.tenant.as_ref().map_or("", TenantId::as_str);
+            *cache_summary
+                .by_tenant
+                .entry(tenant.to_string())
+                .or_default() += 1;
+            *cache_summary
+                .by_status
+                .entry(user.status.as_str().to_string())
+                .or_default() += 1;
+        }
+
+        DebugSnapshot {
+            taken_at: Utc::now(),
+            config: ConfigSummary {
+                instance_id: self.instance_id.clone(),
+                backend: self.repository.describe(),
+                tenant: self.tenant.clone(),
+                tenant_repositories: self.tenant_repositories.is_some(),
+                offline_queue: self.offline_queue.is_some(),
+                change_log: self.change_log.is_some(),
+                audit_sink: self.audit_sink.is_some(),
+                invalidation_bus: self.invalidation_bus.is_some(),
+            },
+            cache: cache_summary,
+            in_flight_requests: self.in_flight.count.load(Ordering::Acquire),
+            shutting_down: self.shutting_down.load(Ordering::Acquire),
+            background_tasks: self
+                .background_tasks
+                .lock()
+                .expect("background task registry poisoned")
+                .iter()
+                .filter(|task| !task.is_finished())
+                .count(),
+            queued_mutations: self.offline_queue.as_ref().map_or(0, |queue| queue.len()),
+            health: self.current_health(),
+            recent_errors: self
+                .recent_errors
+                .lock()
+                .expect("error history poisoned")
+                .iter()
+                .cloned()
+                .collect(),
+            latencies: self.latency_stats(),
+        }
+    }
+
+    /// Latency percentiles per operation (`fetch_user`, `batch_fetch_users`, `update_user`, ...)
+    pub fn latency_stats(&self) -> std::collections::BTreeMap<String, LatencySummary> {
+        self.latencies
+            .lock()
+            .expect("latency histograms poisoned")
+            .iter()
+            .map(|(operation, histogram)| (operation.to_string(), histogram.summary()))
+            .collect()
+    }
+
+    /// Register an in-flight request, refusing new work once shutdown has begun
+    fn begin_request(&self) -> Result<InFlightGuard<'_>> {
+        self.in_flight.count.fetch_add(1, Ordering::AcqRel);
+        let guard = InFlightGuard {
+            in_flight: &self.in_flight,
+        };
+        if self.shutting_down.load(Ordering::Acquire) {
+            return Err(UserError::ShuttingDown.into());
+        }
+        Ok(guard)
+    }
+
+    /// Receiver that flips to `true` when shutdown starts
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown_tx.subscribe()
+    }
+
+    /// Spawn a background task that is stopped on shutdown
+    pub fn spawn_background<F, Fut>(&self, task: F)
+    where
+        F: FnOnce(watch::Receiver<bool>) -> Fut,
+        Fut: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(task(self.shutdown_signal()));
+        self.background_tasks
+            .lock()
+            .expect("background task registry poisoned")
+            .push(handle);
+    }
+
+    /// Stop background tasks, drain in-flight requests and optionally flush the cache
+    pub async fn shutdown(&self, options: ShutdownOptions) -> Result<ShutdownReport> {
+        self.shutting_down.store(true, Ordering::Release);
+        self.shutdown_tx.send_replace(true);
+        let deadline = tokio::time::Instant::now() + options.drain_timeout;
+        let mut report = ShutdownReport::default();
+
+        let handles = std::mem::take(
+            &mut *self
+                .background_tasks
+                .lock()
+                .expect("background task registry poisoned"),
+        );
+        for mut handle in handles {
+            match tokio::time::timeout_at(deadline, &mut handle).await {
+                Ok(_) => report.background_tasks_stopped += 1,
+                Err(_) => {
+                    handle.abort();
+                    report.background_tasks_aborted += 1;
+                }
+            }
+        }
+
+        loop {
+            let idle = self.in_flight.idle.notified();
+            if self.in_flight.count.load(Ordering::Acquire) == 0 {
+                report.drained = true;
+                break;
+            }
+            if tokio::time::timeout_at(deadline, idle).await.is_err() {
+                report.abandoned_requests = self.in_flight.count.load(Ordering::Acquire);
+                report.drained = report.abandoned_requests == 0;
+                break;
+            }
+        }
+
+        if let Some(path) = &options.flush_cache_to {
+            let cache = self.cache.read().await;
+            let mut records = Vec::with_capacity(cache.len());
+            for (key, user) in cache.iter() {
+                let mut record = serde_json::to_value(UserEnvelope::wrap(user)?)
+                    .context("Failed to serialize cache")?;
+                if let (Some(tenant), Some(fields)) = (&key.tenant, record.as_object_mut()) {
+                    fields.insert("tenant".to_string(), serde_json::json!(tenant));
+                }
+                records.push(record);
+            }
+            let json = serde_json::to_vec(&records).context("Failed to serialize cache")?;
+            tokio::fs::write(path, json)
+                .await
+                .with_context(|| format!("Failed to flush cache to {}", path.display()))?;
+            report.flushed_entries = Some(records.len());
+        }
+
+        tracing::info!(
+            drained = report.drained,
+            abandoned = report.abandoned_requests,
+            tasks_stopped = report.background_tasks_stopped,
+            tasks_aborted = report.background_tasks_aborted,
+            "User manager shut down"
+        );
+        Ok(report)
+    }
+
+    /// Fetch user by ID with caching
+    pub async fn fetch_user(&self, user_id: &str) -> Result<Option<User>> {
+        self.fetch_user_in(self.tenant.as_ref(), user_id).await
+    }
+
+    async fn fetch_user_in(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+    ) -> Result<Option<User>> {
+        self.traced(
+            operation_span!(
+                "fetch_user",
+                tenant,
+                user_id = %redact_id(user_id),
+                cache_hit = tracing::field::Empty
+            ),
+            async move {
+                if user_id.is_empty() {
+                    return Err(UserError::NotFound {
+                        id: user_id.to_string(),
+                    }
+                    .into());
+                }
+                let _guard = self.begin_request()?;
+                let key = CacheKey::new(tenant, user_id);
+                tracing::Span::current().record("cache_hit", false);
+
+                // Check cache first
+                let cached = self.cache.read().await.get(&key).cloned();
+                let cache_hit = cached.is_some();
+                let user = match cached {
+                    Some(user) => {
+                        tracing::Span::current().record("cache_hit", true);
+                        metrics::counter!(metric_names::CACHE_HITS).increment(1);
+                        tracing::debug!("User found in cache");
+                        Some(user)
+                    }
+                    None => {
+                        // Fetch from the backend
+                        metrics::counter!(metric_names::CACHE_MISSES).increment(1);
+                        let user = self
+                            .limited_repository(tenant, Target::User(user_id))
+                            .await?
+                            .get(user_id)
+                            .await?;
+                        self.notify_watchers(tenant, user_id, user.as_ref());
+                        if let Some(user) = &user {
+                            // Cache the result
+                            let mut cache = self.cache.write().await;
+                            let evicted = self.cache_insert_or_skip(&mut cache, key, user.clone());
+                            drop(cache);
+                            self.notify_evicted(evicted, EvictReason::Capacity);
+                            tracing::debug!("User fetched and cached");
+                        }
+                        user
+                    }
+                };
+
+                let ctx = FetchContext {
+                    tenant,
+                    user_id,
+                    cache_hit,
+                    user: user.as_ref(),
+                };
+                self.run_hooks("fetch", |hook| hook.on_fetch(&ctx))?;
+                self.publish(|| UserEvent::UserFetched {
+                    tenant: tenant.cloned(),
+                    user_id: user_id.to_string(),
+                    cache_hit,
+                    found: user.is_some(),
+                });
+                Ok(user)
+            },
+        )
+        .await
+    }
+
+    /// Batch fetch multiple users concurrently
+    pub async fn batch_fetch_users(&self, user_ids: &[String]) -> HashMap<String, Option<User>> {
+        self.batch_fetch_users_in(self.tenant.as_ref(), user_ids)
+            .await
+    }
+
+    async fn batch_fetch_users_in(
+        &self,
+        tenant: Option<&TenantId>,
+        user_ids: &[String],
+    ) -> HashMap<String, Option<User>> {
+        let span = operation_span!(
+            "batch_fetch_users",
+            tenant,
+            requested = user_ids.len(),
+            found = tracing::field::Empty
+        );
+        let futures = user_ids.iter().map(|id| async move {
+            let result = self.fetch_user_in(tenant, id).await.unwrap_or(None);
+            (id.clone(), result)
+        });
+
+        let results = self.traced(span, async {
+            let results: HashMap<_, _> = futures::future::join_all(futures)
+                .await
+                .into_iter()
+                .collect();
+            let found = results.values().filter(|user| user.is_some()).count();
+            tracing::Span::current().record("found", found);
+            Ok(results)
+        });
+        results.await.unwrap_or_default()
+    }
+
+    /// Update user information; metadata ops that build on stored metadata read it first,
+    /// serialized with other such updates of the user through this manager
+    pub async fn update_user(&self, user_id: &str, update: UserUpdate) -> Result<bool> {
+        self.update_user_in(self.tenant.as_ref(), user_id, update)
+            .await
+    }
+
+    async fn update_user_in(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        update: UserUpdate,
+    ) -> Result<bool> {
+        self.traced(
+            operation_span!(
+                "update_user",
+                tenant,
+                user_id = %redact_id(user_id),
+                fields = update.len()
+            ),
+            async move {
+                let _guard = self.begin_request()?;
+                self.authorize(Permission::Update)?;
+                let repository = self
+                    .limited_repository(tenant, Target::User(user_id))
+                    .await?;
+                // Held from the read of the stored metadata until the write built on it
+                let _locked = if update.needs_current() {
+                    Some(
+                        self.update_locks
+                            .lock(&CacheKey::new(tenant, user_id))
+                            .await,
+                    )
+                } else {
+                    None
+                };
+                let before = self.snapshot_for_log(&*repository, user_id).await;
+                let updates = if update.needs_current() {
+                    let current = match before.clone() {
+                        Some(user) => Some(user),
+                        None => repository.get(user_id).await?,
+                    };
+                    let Some(current) = current else {
+                        return Ok(false);
+                    };
+                    update.to_fields(Some(&current))
+                } else {
+                    update.to_fields(None)
+                };
+                self.check_residency(tenant, user_id, &updates).await?;
+                self.run_update_hooks(tenant, &*repository, user_id, &updates, before.clone())
+                    .await?;
+
+                let updated = match repository.update(user_id, &updates).await {
+                    Ok(updated) => updated,
+                    Err(e) => {
+                        let mutation = Mutation::Update {
+                            user_id: user_id.to_string(),
+                            updates,
+                        };
+                        return Err(self.queue_if_unreachable(tenant, e, mutation));
+                    }
+                };
+                if updated {
+                    self.invalidate_cached(tenant, user_id).await;
+                    tracing::info!("User updated");
+                    let after = self.snapshot_for_log(&*repository, user_id).await;
+                    self.record_change(tenant, ChangeKind::Update, user_id, before, after);
+                    self.publish_applied(&*repository, || {
+                        UserEvent::updated(tenant, user_id, &updates)
+                    });
+                    self.refresh_watched(tenant, user_id).await;
+                    self.after_update(tenant, user_id, &updates);
+                }
+                Ok(updated)
+            },
+        )
+        .await
+    }
+
+    /// Create a new user and cache the stored record
+    pub async fn create_user(&self, user: &User) -> Result<User> {
+        self.create_user_in(self.tenant.as_ref(), user).await
+    }
+
+    async fn create_user_in(&self, tenant: Option<&TenantId>, user: &User) -> Result<User> {
+        self.traced(
+            operation_span!("create_user", tenant, user_id = tracing::field::Empty),
+            async move {
+                let _guard = self.begin_request()?;
+                self.authorize(Permission::Create)?;
+
+                let repository = self
+                    .limited_repository(tenant, Target::NewUser(user))
+                    .await?;
+                let created = match repository.create(user).await {
+                    Ok(created) => created,
+                    Err(e) => {
+                        return Err(self.queue_if_unreachable(
+                            tenant,
+                            e,
+                            Mutation::Create(user.clone()),
+                        ))
+                    }
+                };
+                self.invalidate_cached(tenant, &created.id).await;
+                let mut cache = self.cache.write().await;
+                let evicted = self.cache_insert_or_skip(
+                    &mut cache,
+                    CacheKey::new(tenant, &created.id),
+                    created.clone(),
+                );
+                drop(cache);
+                self.notify_evicted(evicted, EvictReason::Capacity);
+                if let Some(routing) = &self.region_routing {
+                    let region = routing.rule().region_for_user(tenant, &created.metadata)?;
+                    routing.remember(tenant, &created.id, Some(&region));
+                }
+                tracing::Span::current().record("user_id", redact_id(&created.id).as_str());
+                tracing::info!("User created");
+                self.record_change(
+                    tenant,
+                    ChangeKind::Create,
+                    &created.id,
+                    None,
+                    Some(created.clone()),
+                );
+                self.publish_applied(&*repository, || UserEvent::UserCreated {
+                    tenant: tenant.cloned(),
+                    user: created.clone(),
+                });
+                self.notify_watchers(tenant, &created.id, Some(&created));
+                Ok(created)
+            },
+        )
+        .await
+    }
+
+    /// Delete a user and drop it from the cache
+    pub async fn delete_user(&self, user_id: &str) -> Result<bool> {
+        self.delete_user_in(self.tenant.as_ref(), user_id).await
+    }
+
+    async fn delete_user_in(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<bool> {
+        self.traced(
+            operation_span!("delete_user", tenant, user_id = %redact_id(user_id)),
+            async move {
+                let _guard = self.begin_request()?;
+                self.authorize(Permission::Delete)?;
+                let repository = self
+                    .limited_repository(tenant, Target::User(user_id))
+                    .await?;
+                let before = self.snapshot_for_log(&*repository, user_id).await;
+
+                let deleted = repository.delete(user_id).await?;
+                self.invalidate_cached(tenant, user_id).await;
+                if let Some(routing) = &self.region_routing {
+                    routing.remember(tenant, user_id, None);
+                }
+                if deleted {
+                    tracing::info!("User deleted");
+                    self.record_change(tenant, ChangeKind::Delete, user_id, before, None);
+                    self.publish_applied(&*repository, || UserEvent::UserDeleted {
+                        tenant: tenant.cloned(),
+                        user_id: user_id.to_string(),
+                    });
+                    self.notify_watchers(tenant, user_id, None);
+                    self.forget_deleted(tenant, user_id);
+                }
+                Ok(deleted)
+            },
+        )
+        .await
+    }
+
+    /// Drop what this manager keeps alongside a user once the user is deleted
+    fn forget_deleted(&self, tenant: Option<&TenantId>, user_id: &str) {
+        if let Some(log) = &self.activity_log {
+            log.remove(tenant, user_id);
+        }
+        self.end_sessions(tenant, user_id);
+        if let Some(invitations) = &self.invitations {
+            if let Err(e) = invitations.remove(tenant, user_id) {
+                tracing::warn!(
+                    user_id = %redact_id(user_id),
+                    error = redact_error(&e),
+                    "Failed to drop the invitation of a deleted user"
+                );
+            }
+        }
+    }
+
+    /// Follow up an applied update: a user it suspends loses their sessions
+    fn after_update(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) {
+        if updates.get("status").and_then(|status| status.as_str())
+            == Some(UserStatus::Suspended.as_str())
+        {
+            self.end_sessions(tenant, user_id);
+        }
+    }
+
+    /// Follow up a mutation `repository` applied in a batch or replay: publish its event,
+    /// refresh watchers, run the follow-ups of its kind and record the change from
+    /// `before`. Returns the user's state after it when changes are tracked.
+    async fn mutation_applied(
+        &self,
+        tenant: Option<&TenantId>,
+        repository: &dyn UserRepository,
+        mutation: &Mutation,
+        before: Option<User>,
+    ) -> Option<User> {
+        let user_id = mutation.user_id();
+        self.publish_applied(repository, || UserEvent::applied(tenant, mutation));
+        self.refresh_watched(tenant, user_id).await;
+        let (kind, after) = match mutation {
+            Mutation::Create(user) => (ChangeKind::Create, Some(user.clone())),
+            Mutation::Update { updates, .. } => {
+                self.after_update(tenant, user_id, updates);
+                let after = self.snapshot_for_log(repository, user_id).await;
+                (ChangeKind::Update, after)
+            }
+            Mutation::Delete { .. } => {
+                self.forget_deleted(tenant, user_id);
+                (ChangeKind::Delete, None)
+            }
+        };
+        if !self.tracks_changes() {
+            return None;
+        }
+        self.record_change(tenant, kind, user_id, before, after.clone());
+        after
+    }
+
+    /// List a page of users from the backend
+    pub async fn list_users(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        self.list_users_in(self.tenant.as_ref(), offset, limit)
+            .await
+    }
+
+    async fn list_users_in(
+        &self,
+        tenant: Option<&TenantId>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        self.traced(
+            operation_span!("list_users", tenant, offset = offset, limit = limit),
+            async move {
+                let _guard = self.begin_request()?;
+                let repositories = self.listing_repositories(tenant).await?;
+                if let [repository] = repositories.as_slice() {
+                    return repository.list(offset, limit).await;
+                }
+                let end = offset.saturating_add(limit);
+                let pages = repositories
+                    .iter()
+                    .map(|repository| repository.list(0, end));
+                Ok(region::merge_pages(
+                    futures::future::try_join_all(pages).await?,
+                    offset,
+                    limit,
+                ))
+            },
+        )
+        .await
+    }
+
+    /// Collect the profile, status history, change log and audit entries of one user for a
+    /// data-subject access request; history needs a change log on the manager
+    pub async fn export_user_data(&self, user_id: &str) -> Result<SubjectAccessExport> {
+        let tenant = self.tenant.as_ref();
+        self.traced(
+            operation_span!("export_user_data", tenant, user_id = %redact_id(user_id)),
+            async move {
+                let _guard = self.begin_request()?;
+                let profile = self
+                    .limited_repository(tenant, Target::User(user_id))
+                    .await?
+                    .get(user_id)
+                    .await?;
+                let changes = self
+                    .change_log
+                    .as_ref()
+                    .map(|change_log| {
+                        change_log.query(&ChangeQuery {
+                            tenant: tenant.cloned(),
+                            ..ChangeQuery::for_user(user_id)
+                        })
+                    })
+                    .unwrap_or_default();
+                let audit = match &self.audit_sink {
+                    Some(sink) => sink.entries_for(tenant, user_id)?,
+                    None => Vec::new(),
+                };
+                if profile.is_none() && changes.is_empty() && audit.is_empty() {
+                    return Err(UserError::NotFound {
+                        id: user_id.to_string(),
+                    }
+                    .into());
+                }
+                let status_history = changes
+                    .iter()
+                    .filter_map(|record| {
+                        let from = record.before.as_ref().map(|user| user.status);
+                        let to = record.after.as_ref().map(|user| user.status);
+                        (from != to).then_some(StatusChange {
+                            at: record.recorded_at,
+                            from,
+                            to,
+                        })
+                    })
+                    .collect();
+                Ok(SubjectAccessExport {
+                    generated_at: Utc::now(),
+                    user_id: user_id.to_string(),
+                    tenant: tenant.cloned(),
+                    profile,
+                    status_history,
+                    changes,
+                    audit,
+                })
+            },
+        )
+        .await
+    }
+
+    /// Honour a right-to-erasure request: replace the stored profile with an inactive,
+    /// anonymized placeholder, drop any second-factor enrollment, evict it from the cache, scrub snapshots, the change log,
+    /// the audit sink, the offline queue, the activity log and sessions, record an `Erase`
+    /// event and optionally notify the backend.
+    /// Every step is idempotent, so a failed erasure can simply be retried.
+    pub async fn erase_user(
+        &self,
+        user_id: &str,
+        options: &ErasureOptions,
+    ) -> Result<ErasureReport> {
+        let tenant = self.tenant.as_ref();
+        self.traced(
+            operation_span!("erase_user", tenant, user_id = %redact_id(user_id)),
+            async move {
+                let _guard = self.begin_request()?;
+                self.authorize(Permission::Erase)?;
+                let repository = self
+                    .limited_repository(tenant, Target::User(user_id))
+                    .await?;
+                let erased_at = Utc::now();
+                let stored = repository.get(user_id).await?;
+                let placeholder = stored.as_ref().map(|user| user.anonymized(erased_at));
+                let profile_anonymized = match &placeholder {
+                    Some(placeholder) => {
+                        repository
+                            .update(user_id, &placeholder.to_update_fields())
+                            .await?
+                    }
+                    None => false,
+                };
+                if let Some(two_factor) = stored.and_then(|user| user.two_factor) {
+                    repository
+                        .swap_two_factor(user_id, Some(&two_factor), None)
+                        .await?;
+                }
+
+                let cache_entry_evicted = self
+                    .cache
+                    .read()
+                    .await
+                    .contains_key(&CacheKey::new(tenant, user_id));
+                self.invalidate_cached(tenant, user_id).await;
+
+                let mut snapshot_entries_removed = 0;
+                for path in &options.cache_snapshots {
+                    snapshot_entries_removed += scrub_cache_snapshot(path, tenant, user_id).await?;
+                }
+                let change_records_redacted = match &self.change_log {
+                    Some(change_log) => change_log.redact_user(tenant, user_id, erased_at)?,
+                    None => 0,
+                };
+                let queued_mutations_dropped = match &self.offline_queue {
+                    Some(queue) => queue.remove_user(tenant, user_id)?,
+                    None => 0,
+                };
+                let activity_cleared = self
+                    .activity_log
+                    .as_ref()
+                    .is_some_and(|log| log.remove(tenant, user_id));
+                let sessions_removed = match &self.session_store {
+                    Some(store) => store.remove_user(tenant, user_id),
+                    None => 0,
+                };
+                let audit_entries_scrubbed = match &self.audit_sink {
+                    Some(sink) => sink.scrub_user(tenant, user_id)?,
+                    None => 0,
+                };
+
+                if placeholder.is_none()
+                    && !cache_entry_evicted
+                    && !activity_cleared
+                    && snapshot_entries_removed
+                        + change_records_redacted
+                        + queued_mutations_dropped
+                        + sessions_removed
+                        + audit_entries_scrubbed
+                        == 0
+                {
+                    return Err(UserError::NotFound {
+                        id: user_id.to_string(),
+                    }
+                    .into());
+                }
+                self.notify_watchers(tenant, user_id, placeholder.as_ref());
+                self.record_change(tenant, ChangeKind::Erase, user_id, None, placeholder);
+                self.publish(|| UserEvent::UserErased {
+                    tenant: tenant.cloned(),
+                    user_id: user_id.to_string(),
+                });
+
+                let backend_notified = if options.notify_backend {
+                    Some(
+                        repository
+                            .notify_erasure(user_id)
+                            .await
+                            .unwrap_or_else(|e| {
+                                tracing::warn!(
+                                    user_id = %redact_id(user_id),
+                                    error = redact_error(&e),
+                                    "Failed to notify backend of erasure"
+                                );
+                                false
+                            }),
+                    )
+                } else {
+                    None
+                };
+
+                tracing::info!(
+                    profile_anonymized,
+                    snapshot_entries_removed,
+                    change_records_redacted,
+                    queued_mutations_dropped,
+                    activity_cleared,
+                    sessions_removed,
+                    audit_entries_scrubbed,
+                    "User erased"
+                );
+                Ok(ErasureReport {
+                    user_id: user_id.to_string(),
+                    tenant: tenant.cloned(),
+                    erased_at,
+                    profile_anonymized,
+                    cache_entry_evicted,
+                    snapshot_entries_removed,
+                    change_records_redacted,
+                    queued_mutations_dropped,
+                    activity_cleared,
+                    sessions_removed,
+                    audit_entries_scrubbed,
+                    backend_notified,
+                })
+            },
+        )
+        .await
+    }
+
+    /// List users matching a filter, filtered by the backend when it can
+    pub async fn list_users_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        self.list_users_filtered_in(self.tenant.as_ref(), filter, offset, limit)
+            .await
+    }
+
+    async fn list_users_filtered_in(
+        &self,
+        tenant: Option<&TenantId>,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        self.traced(
+            operation_span!(
+                "list_users_filtered",
+                tenant,
+                offset = offset,
+                limit = limit
+            ),
+            async move {
+                let _guard = self.begin_request()?;
+                let repositories = self.listing_repositories(tenant).await?;
+                if let [repository] = repositories.as_slice() {
+                    return repository.list_filtered(filter, offset, limit).await;
+                }
+                let end = offset.saturating_add(limit);
+                let pages = repositories
+                    .iter()
+                    .map(|repository| repository.list_filtered(filter, 0, end));
+                Ok(region::merge_pages(
+                    futures::future::try_join_all(pages).await?,
+                    offset,
+                    limit,
+                ))
+            },
+        )
+        .await
+    }
+
+    /// Run a closure that stages writes, then commit them together.
+    /// Atomic on transactional backends, best-effort ordered batching otherwise.
+    /// Nothing is written if the closure returns an error or a hook vetoes a staged update.
+    pub async fn transaction<F, Fut, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(Transaction) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        self.traced(
+            operation_span!(
+                "transaction",
+                self.tenant.as_ref(),
+                mutations = tracing::field::Empty
+            ),
+            async move {
+                let _guard = self.begin_request()?;
+                let tenant = self.tenant.as_ref();
+                let repository = self.limited_repository(tenant, Target::Tenant).await?;
+                let tx = Transaction {
+                    repository: repository.clone(),
+                    staged: Arc::new(Mutex::new(Vec::new())),
+                };
+                let value = f(tx.clone()).await?;
+
+                let staged =
+                    std::mem::take(&mut *tx.staged.lock().expect("transaction state poisoned"));
+                if staged.is_empty() {
+                    return Ok(value);
+                }
+                for write in &staged {
+                    self.authorize(write.permission())?;
+                }
+                // Held from the reads of stored metadata until the batch built on them is written
+                let keys: Vec<CacheKey> = staged
+                    .iter()
+                    .filter(|write| write.needs_current())
+                    .map(|write| CacheKey::new(tenant, write.user_id()))
+                    .collect();
+                let _locked = self.update_locks.lock_all(&keys).await;
+                let mutations = tx.resolve(staged).await?;
+                for mutation in &mutations {
+                    // Hooks see committed state, as `Transaction::get` does
+                    if let Mutation::Update { user_id, updates } = mutation {
+                        self.check_residency(tenant, user_id, updates).await?;
+                        self.run_update_hooks(tenant, &*repository, user_id, updates, None)
+                            .await?;
+                    }
+                    if let Some(routing) = &self.region_routing {
+                        let staged = self.routed_repository(tenant, Target::of(mutation)).await?;
+                        if !Arc::ptr_eq(&staged, &repository) {
+                            return Err(UserError::ResidencyViolation {
+                                region: routing.rule().region_for_tenant(tenant).to_string(),
+                                message: format!(
+                                    "transaction touches user {} in another region",
+                                    mutation.user_id()
+                                ),
+                            }
+                            .into());
+                        }
+                    }
+                }
+                if !repository.supports_transactions() {
+                    tracing::warn!(
+                        mutations = mutations.len(),
+                        "Backend is not transactional; applying best-effort"
+                    );
+                }
+
+                let mut befores = HashMap::new();
+                if self.tracks_changes() {
+                    for mutation in &mutations {
+                        if !befores.contains_key(mutation.user_id()) {
+                            let before = self
+                                .snapshot_for_log(&*repository, mutation.user_id())
+                                .await;
+                            befores.insert(mutation.user_id().to_string(), before);
+                        }
+                    }
+                }
+
+                let result = repository.apply_batch(&mutations).await;
+                // Invalidate everything touched, even on partial failure
+                for mutation in &mutations {
+                    self.invalidate_cached(tenant, mutation.user_id()).await;
+                }
+
+                let outcomes = result?;
+                tracing::Span::current().record("mutations", outcomes.len());
+                for (mutation, _) in mutations
+                    .iter()
+                    .zip(&outcomes)
+                    .filter(|(_, applied)| **applied)
+                {
+                    let user_id = mutation.user_id();
+                    let before = befores.get(user_id).cloned().flatten();
+                    let after = self
+                        .mutation_applied(tenant, &*repository, mutation, before)
+                        .await;
+                    // Later mutations of the same user start from this one's result
+                    befores.insert(user_id.to_string(), after);
+                }
+                tracing::info!(mutations = outcomes.len(), "Transaction committed");
+                Ok(value)
+            },
+        )
+        .await
+    }
+
+    /// Capture the cache and rate-limit budgets for `restore` on another instance
+    pub async fn snapshot(&self) -> Result<ManagerSnapshot> {
+        let taken_at = Utc::now();
+        let mut cache = self
+            .cache
+            .read()
+            .await
+            .iter()
+            .map(|(key, user)| {
+                Ok(CachedUserSnapshot {
+                    tenant: key.tenant.clone(),
+                    user: UserEnvelope::wrap(user)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        cache.sort_by(|a, b| {
+            a.tenant.cmp(&b.tenant).then_with(|| {
+                UserEnvelope::record_id(&a.user.payload)
+                    .cmp(&UserEnvelope::record_id(&b.user.payload))
+            })
+        });
+        let rate_limits = self
+            .rate_limiter
+            .as_ref()
+            .map(|limiter| limiter.snapshot())
+            .unwrap_or_default();
+        Ok(ManagerSnapshot {
+            taken_at,
+            cache,
+            rate_limits,
+        })
+    }
+
+    /// Warm this manager from another instance's `snapshot`, migrating cached users to
+    /// the current schema and ageing rate-limit budgets by the time since it was taken
+    pub async fn restore(&self, snapshot: ManagerSnapshot) -> Result<RestoreReport> {
+        let elapsed = (Utc::now() - snapshot.taken_at)
+            .to_std()
+            .unwrap_or_default();
+        let mut tenants = Vec::with_capacity(snapshot.cache.len());
+        let mut records = Vec::with_capacity(snapshot.cache.len());
+        for entry in snapshot.cache {
+            tenants.push(entry.tenant);
+            records
+                .push(serde_json::to_value(entry.user).context("Failed to read manager snapshot")?);
+        }
+        let (users, migration) = UserMigrator::for_users().migrate_all(records);
+        let failed: std::collections::HashSet<usize> = migration
+            .failed
+            .iter()
+            .map(|failure| failure.index)
+            .collect();
+        let tenants = tenants
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| !failed.contains(index))
+            .map(|(_, tenant)| tenant);
+
+        let mut report = RestoreReport {
+            cache_entries: users.len(),
+            failed: migration.failed,
+            ..Default::default()
+        };
+        let mut cache = self.cache.write().await;
+        let mut evicted = Vec::new();
+        for (user, tenant) in users.into_iter().zip(tenants) {
+            match self.cache_insert(&mut cache, CacheKey::new(tenant.as_ref(), &user.id), user) {
+                Ok(victim) => evicted.extend(victim),
+                Err(_) => report.cache_entries -= 1,
+            }
+        }
+        drop(cache);
+        self.notify_evicted(evicted, EvictReason::Capacity);
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.restore(&snapshot.rate_limits, elapsed);
+            report.rate_limit_buckets = snapshot.rate_limits.len();
+        }
+        tracing::info!(
+            cache_entries = report.cache_entries,
+            rate_limit_buckets = report.rate_limit_buckets,
+            failed = report.failed.len(),
+            "Manager state restored"
+        );
+        Ok(report)
+    }
+
+    /// Warm the cache from a snapshot written by `shutdown`, upgrading old records
+    pub async fn load_cache_snapshot(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        migrator: &UserMigrator,
+    ) -> Result<MigrationReport> {
+        let path = path.as_ref();
+        let bytes = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read cache snapshot {}", path.display()))?;
+        let records: Vec<serde_json::Value> =
+            serde_json::from_slice(&bytes).context("Failed to parse cache snapshot")?;
+
+        // Snapshots written by `shutdown` tag tenant-scoped entries
+        let mut tenants = Vec::with_capacity(records.len());
+        let records = records
+            .into_iter()
+            .map(|mut record| {
+                let tenant = record
+                    .as_object_mut()
+                    .and_then(|fields| fields.remove("tenant"))
+                    .and_then(|tenant| serde_json::from_value::<TenantId>(tenant).ok());
+                tenants.push(tenant);
+                record
+            })
+            .collect();
+
+        let (users, report) = migrator.migrate_all(records);
+        let failed: std::collections::HashSet<usize> =
+            report.failed.iter().map(|failure| failure.index).collect();
+        let loaded_tenants = tenants
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| !failed.contains(index))
+            .map(|(_, tenant)| tenant);
+        let mut cache = self.cache.write().await;
+        let mut evicted = Vec::new();
+        for (user, tenant) in users.into_iter().zip(loaded_tenants) {
+            let tenant = tenant.as_ref().or(self.tenant.as_ref());
+            evicted.extend(self.cache_insert_or_skip(
+                &mut cache,
+                CacheKey::new(tenant, &user.id),
+                user,
+            ));
+        }
+        drop(cache);
+        self.notify_evicted(evicted, EvictReason::Capacity);
+        tracing::info!(
+            migrated = report.migrated,
+            up_to_date = report.up_to_date,
+            failed = report.failed.len(),
+            "Cache snapshot loaded"
+        );
+        Ok(report)
+    }
+
+    /// Statistics over the cached users of one tenant (`None` for untenanted entries)
+    pub async fn cached_statistics(&self, tenant: Option<&TenantId>) -> UserStatistics {
+        let cache = self.cache.read().await;
+        let users: Vec<User> = cache
+            .iter()
+            .filter(|(key, _)| key.tenant.as_ref() == tenant)
+            .map(|(_, user)| user.clone())
+            .collect();
+        Self::get_user_statistics(&users)
+    }
+
+    /// Clear cache and return number of entries cleared
+    pub async fn clear_cache(&self) -> usize {
+        let evicted: Vec<(CacheKey, User)> = self.cache.write().await.drain().collect();
+        let count = evicted.len();
+        self.notify_evicted(evicted, EvictReason::Cleared);
+        tracing::info!(removed = count, "Cache cleared");
+        count
+    }
+
+    /// Cache `users` without going to the backend, e.g. after a bulk listing. Stops with
+    /// `UserError::CacheBudgetExceeded` at the first user over the memory budget, keeping
+    /// those cached before it.
+    pub async fn prime_cache(&self, users: &[User]) -> Result<()> {
+        let mut cache = self.cache.write().await;
+        let mut evicted = Vec::new();
+        let mut outcome = Ok(());
+        for user in users {
+            match self.cache_insert(
+                &mut cache,
+                CacheKey::new(self.tenant(), &user.id),
+                user.clone(),
+            ) {
+                Ok(victim) => evicted.extend(victim),
+                Err(e) => {
+                    outcome = Err(e);
+                    break;
+                }
+            }
+        }
+        drop(cache);
+        self.notify_evicted(evicted, EvictReason::Capacity);
+        outcome
+    }
+
+    /// Estimated memory held by the cache
+    pub async fn memory_usage(&self) -> CacheMemoryUsage {
+        let cache = self.cache.read().await;
+        CacheMemoryUsage {
+            entries: cache.len(),
+            estimated_bytes: cache.bytes,
+            largest_entry_bytes: cache
+                .iter()
+                .map(|(key, user)| UserCache::entry_size(key, user))
+                .max()
+                .unwrap_or(0),
+            budget_bytes: self.cache_budget,
+        }
+    }
+
+    /// Export users to JSON in canonical order
+    pub fn export_users_json(users: &[User]) -> Result<String> {
+        Self::export_users_json_with(users, ExportOrder::default())
+    }
+
+    /// Export users to JSON with an explicit ordering
+    pub fn export_users_json_with(users: &[User], order: ExportOrder) -> Result<String> {
+        match order {
+            ExportOrder::Canonical => {
+                let mut sorted: Vec<&User> = users.iter().collect();
+                sorted.sort_by(|a, b| a.id.cmp(&b.id));
+                let value =
+                    serde_json::to_value(&sorted).context("Failed to serialize users to JSON")?;
+                serde_json::to_string_pretty(&canonical_value(value))
+                    .context("Failed to serialize users to JSON")
+            }
+            ExportOrder::Input => {
+                serde_json::to_string_pretty(users).context("Failed to serialize users to JSON")
+            }
+        }
+    }
+
+    /// Export users as a JSON array of `UserEnvelope`s sorted by id, readable after later
+    /// schema changes with `UserMigrator::load_all_json`
+    pub fn export_users_versioned(users: &[User]) -> Result<String> {
+        let mut sorted: Vec<&User> = users.iter().collect();
+        sorted.sort_by(|a, b| a.id.cmp(&b.id));
+        let envelopes = sorted
+            .into_iter()
+            .map(UserEnvelope::wrap)
+            .collect::<Result<Vec<_>>>()?;
+        let value = serde_json::to_value(envelopes).context("Failed to serialize users to JSON")?;
+        serde_json::to_string_pretty(&canonical_value(value))
+            .context("Failed to serialize users to JSON")
+    }
+
+    /// Create user from JSON
+    pub fn create_user_from_json(json: &str) -> Result<User> {
+        let user: User =
+            serde_json::from_str(json).context("Failed to deserialize user from JSON")?;
+        user.check_metadata_depth()?;
+        Ok(user)
+    }
+
+    /// Parse newline-delimited JSON users, collecting per-line failures instead of stopping
+    pub fn import_users_ndjson(input: &str) -> ImportReport {
+        Self::import_users_ndjson_with(input, &ImportPipeline::default())
+    }
+
+    /// Parse newline-delimited JSON users, cleaning each record through `pipeline` first
+    pub fn import_users_ndjson_with(input: &str, pipeline: &ImportPipeline) -> ImportReport {
+        pipeline.import_ndjson(input)
+    }
+}
+
+// Versioned upgrades for stored user records
+type MigrationFn =
+    Box<dyn Fn(&mut serde_json::Map<String, serde_json::Value>) -> Result<()> + Send + Sync>;
+
+pub struct MigrationStep {
+    pub from_version: u32,
+    pub description: String,
+    apply: MigrationFn,
+}
+
+impl fmt::Debug for MigrationStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MigrationStep")
+            .field("from_version", &self.from_version)
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+// Result of migrating (or dry-running) a set of records
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrationReport {
+    pub total: usize,
+    pub up_to_date: usize,
+    pub migrated: usize,
+    pub failed: Vec<MigrationFailure>,
+    pub steps_applied: std::collections::BTreeMap<u32, usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationFailure {
+    pub index: usize,
+    pub id: Option<String>,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+pub struct UserMigrator {
+    current_version: u32,
+    legacy_version: u32,
+    steps: Vec<MigrationStep>,
+}
+
+impl UserMigrator {
+    /// Field holding the record version; records without it are treated as legacy
+    pub const VERSION_FIELD: &'static str = "schema_version";
+
+    pub fn new(current_version: u32) -> Self {
+        Self {
+            current_version,
+            legacy_version: 0,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Migrator up to `User::SCHEMA_VERSION`, reading an envelope's `user_version` as the
+    /// record version. Bare records written before envelopes have the version 1 shape;
+    /// register a step for each later version.
+    pub fn for_users() -> Self {
+        Self::new(User::SCHEMA_VERSION).with_legacy_version(1)
+    }
+
+    /// Version assumed for records that carry no version field
+    pub fn with_legacy_version(mut self, version: u32) -> Self {
+        self.legacy_version = version;
+        self
+    }
+
+    /// Register a step upgrading records from `from_version` to `from_version + 1`
+    pub fn with_step<F>(mut self, from_version: u32, description: &str, apply: F) -> Self
+    where
+        F: Fn(&mut serde_json::Map<String, serde_json::Value>) -> Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.steps.push(MigrationStep {
+            from_version,
+            description: description.to_string(),
+            apply: Box::new(apply),
+        });
+        self.steps.sort_by_key(|step| step.from_version);
+        self
+    }
+
+    /// Register a step renaming a field, if present
+    pub fn rename_field(self, from_version: u32, from: &str, to: &str) -> Self {
+        let (from, to) = (from.to_string(), to.to_string());
+        let description = format!("rename {} to {}", from, to);
+        self.with_step(from_version, &description, move |record| {
+            if let Some(value) = record.remove(&from) {
+                record.insert(to.clone(), value);
+            }
+            Ok(())
+        })
+    }
+
+    /// Register a step filling a field with a default when it is missing
+    pub fn default_field(self, from_version: u32, field: &str, value: serde_json::Value) -> Self {
+        let field = field.to_string();
+        let description = format!("default {} to {}", field, value);
+        self.with_step(from_version, &description, move |record| {
+            record.entry(field.clone()).or_insert_with(|| value.clone());
+            Ok(())
+        })
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.current_version
+    }
+
+    /// Upgrade a raw record to the current shape, returning the versions it was migrated from.
+    /// A `UserEnvelope` is replaced by its upgraded payload.
+    pub fn upgrade(&self, record: &mut serde_json::Value) -> Result<Vec<u32>> {
+        if UserEnvelope::is_envelope(record) {
+            let envelope: UserEnvelope = serde_json::from_value(record.take())
+                .with_context(|| format!("Invalid {}", UserEnvelope::VERSION_FIELD))?;
+            *record = envelope.payload;
+            if let Some(fields) = record.as_object_mut() {
+                fields.insert(
+                    Self::VERSION_FIELD.to_string(),
+                    envelope.user_version.into(),
+                );
+            }
+        }
+        let object = record
+            .as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("Stored user record is not a JSON object"))?;
+        let mut version = match object.remove(Self::VERSION_FIELD) {
+            Some(value) => value
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid {}: {}", Self::VERSION_FIELD, value))?,
+            None => self.legacy_version,
+        };
+        if version > self.current_version {
+            return Err(anyhow::anyhow!(
+                "Record version {} is newer than supported version {}",
+                version,
+                self.current_version
+            ));
+        }
+
+        let mut applied = Vec::new();
+        while version < self.current_version {
+            let step = self
+                .steps
+                .iter()
+                .find(|step| step.from_version == version)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("No migration registered from version {}", version)
+                })?;
+            (step.apply)(object)
+                .with_context(|| format!("Migration from version {} failed", version))?;
+            applied.push(version);
+            version += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Upgrade and deserialize a single record
+    pub fn load(&self, mut record: serde_json::Value) -> Result<User> {
+        self.upgrade(&mut record)?;
+        serde_json::from_value(record).context("Failed to deserialize migrated user")
+    }
+
+    pub fn load_json(&self, json: &str) -> Result<User> {
+        self.load(serde_json::from_str(json).context("Failed to parse stored user")?)
+    }
+
+    /// Migrate a JSON array of enveloped or bare records, such as a versioned export
+    pub fn load_all_json(&self, json: &str) -> Result<(Vec<User>, MigrationReport)> {
+        let records: Vec<serde_json::Value> =
+            serde_json::from_str(json).context("Failed to parse stored users")?;
+        Ok(self.migrate_all(records))
+    }
+
+    /// Migrate a batch, keeping the users that succeeded
+    pub fn migrate_all(&self, records: Vec<serde_json::Value>) -> (Vec<User>, MigrationReport) {
+        let mut report = MigrationReport {
+            total: records.len(),
+            ..Default::default()
+        };
+        let mut users = Vec::with_capacity(records.len());
+        for (index, mut record) in records.into_iter().enumerate() {
+            let id = UserEnvelope::record_id(&record).map(str::to_string);
+            let result = self.upgrade(&mut record).and_then(|applied| {
+                let user: User = serde_json::from_value(record)
+                    .context("Failed to deserialize migrated user")?;
+                Ok((user, applied))
+            });
+            match result {
+                Ok((user, applied)) => {
+                    if applied.is_empty() {
+                        report.up_to_date += 1;
+                    } else {
+                        report.migrated += 1;
+                    }
+                    for version in applied {
+                        *report.steps_applied.entry(version).or_default() += 1;
+                    }
+                    users.push(user);
+                }
+                Err(e) => report.failed.push(MigrationFailure {
+                    index,
+                    id,
+                    error: redact_error(&e),
+                }),
+            }
+        }
+        (users, report)
+    }
+
+    /// Report what `migrate_all` would do without keeping the results
+    pub fn dry_run(&self, records: &[serde_json::Value]) -> MigrationReport {
+        self.migrate_all(records.to_vec()).1
+    }
+}
@@ -0,0 +1,167 @@
+use super::*;
+
+/// Users indexed by id and email, with O(1) lookup, insertion and removal
+///
+/// Ids are unique: inserting an existing id replaces that user in place. Emails are not
+/// unique in every backend, so `get_by_email` returns the first match in iteration order.
+/// Removal moves the last user into the freed slot, so iteration order is insertion
+/// order only until the first removal.
+#[derive(Debug, Clone, Default)]
+pub struct UserCollection {
+    users: Vec<User>,
+    by_id: HashMap<String, usize>,
+    by_email: HashMap<String, Vec<usize>>,
+}
+
+impl UserCollection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.by_id.contains_key(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&User> {
+        self.by_id.get(id).map(|&index| &self.users[index])
+    }
+
+    pub fn get_by_email(&self, email: &str) -> Option<&User> {
+        self.by_email
+            .get(email)
+            .and_then(|indexes| indexes.first())
+            .map(|&index| &self.users[index])
+    }
+
+    /// Insert a user, returning the one it replaced if the id was already present
+    pub fn insert(&mut self, user: User) -> Option<User> {
+        match self.by_id.get(&user.id) {
+            Some(&index) => {
+                self.unindex_email(index);
+                self.index_email(&user.email, index);
+                Some(std::mem::replace(&mut self.users[index], user))
+            }
+            None => {
+                let index = self.users.len();
+                self.by_id.insert(user.id.clone(), index);
+                self.index_email(&user.email, index);
+                self.users.push(user);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<User> {
+        let index = *self.by_id.get(id)?;
+        self.unindex_email(index);
+        self.by_id.remove(id);
+
+        let last = self.users.len() - 1;
+        if index != last {
+            let (moved_id, moved_email) =
+                (self.users[last].id.clone(), self.users[last].email.clone());
+            self.unindex_email(last);
+            self.index_email(&moved_email, index);
+            self.by_id.insert(moved_id, index);
+        }
+        Some(self.users.swap_remove(index))
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, User> {
+        self.users.iter()
+    }
+
+    pub fn as_slice(&self) -> &[User] {
+        &self.users
+    }
+
+    pub fn into_vec(self) -> Vec<User> {
+        self.users
+    }
+
+    /// Reorder users in place, keeping the indexes in step
+    pub fn sort_by(&mut self, comparator: &UserComparator) {
+        comparator.sort(&mut self.users);
+        *self = std::mem::take(&mut self.users).into_iter().collect();
+    }
+
+    pub fn filter(&self, mut predicate: impl FnMut(&User) -> bool) -> Vec<&User> {
+        self.users.iter().filter(|user| predicate(user)).collect()
+    }
+
+    pub fn with_status(&self, status: UserStatus) -> Vec<&User> {
+        self.filter(|user| user.status == status)
+    }
+
+    pub fn statistics(&self) -> UserStatistics {
+        self.statistics_at(Utc::now())
+    }
+
+    pub fn statistics_at(&self, now: DateTime<Utc>) -> UserStatistics {
+        UserStatistics::from_users_at(&self.users, now)
+    }
+
+    fn index_email(&mut self, email: &str, index: usize) {
+        let indexes = self.by_email.entry(email.to_string()).or_default();
+        let position = indexes.partition_point(|&existing| existing < index);
+        indexes.insert(position, index);
+    }
+
+    fn unindex_email(&mut self, index: usize) {
+        let email = &self.users[index].email;
+        if let Some(indexes) = self.by_email.get_mut(email) {
+            indexes.retain(|&existing| existing != index);
+            if indexes.is_empty() {
+                self.by_email.remove(email);
+            }
+        }
+    }
+}
+
+impl FromIterator<User> for UserCollection {
+    fn from_iter<I: IntoIterator<Item = User>>(users: I) -> Self {
+        let mut collection = Self::new();
+        collection.extend(users);
+        collection
+    }
+}
+
+impl Extend<User> for UserCollection {
+    fn extend<I: IntoIterator<Item = User>>(&mut self, users: I) {
+        for user in users {
+            self.insert(user);
+        }
+    }
+}
+
+impl From<Vec<User>> for UserCollection {
+    fn from(users: Vec<User>) -> Self {
+        users.into_iter().collect()
+    }
+}
+
+impl IntoIterator for UserCollection {
+    type Item = User;
+    type IntoIter = std::vec::IntoIter<User>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.users.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a UserCollection {
+    type Item = &'a User;
+    type IntoIter = std::slice::Iter<'a, User>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.users.iter()
+    }
+}
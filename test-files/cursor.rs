@@ -0,0 +1,179 @@
+use super::*;
+use crate::signing::{open_token, seal_token};
+
+// Where a listing resumes. Signed into an opaque token by `CursorCodec`, so clients can
+// hand it back but not edit it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageCursor {
+    /// Ordering the position belongs to; a cursor is only valid for the same ordering
+    pub sort: String,
+    /// Offset of the next item
+    pub position: usize,
+    /// Sort key of the last item served, for backends that page by key rather than offset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    /// Unix seconds
+    pub issued_at: i64,
+}
+
+impl PageCursor {
+    pub fn new(sort: impl Into<String>, position: usize) -> Self {
+        Self {
+            sort: sort.into(),
+            position,
+            last_key: None,
+            tenant: None,
+            issued_at: Utc::now().timestamp(),
+        }
+    }
+
+    pub fn with_last_key(mut self, key: impl Into<String>) -> Self {
+        self.last_key = Some(key.into());
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: Option<&TenantId>) -> Self {
+        self.tenant = tenant.cloned();
+        self
+    }
+
+    /// Check the cursor belongs to this listing, so one from another ordering or tenant
+    /// is rejected rather than misread
+    pub fn expect(&self, sort: &str, tenant: Option<&TenantId>) -> Result<()> {
+        if self.sort != sort {
+            return Err(invalid(format!(
+                "cursor is for ordering {:?}, not {:?}",
+                self.sort, sort
+            )));
+        }
+        if self.tenant.as_ref() != tenant {
+            return Err(invalid("cursor belongs to another tenant"));
+        }
+        Ok(())
+    }
+}
+
+/// Encodes `PageCursor`s as `<payload>.<signature>`, both base64url without padding: the
+/// payload is the cursor's JSON and the signature its HMAC-SHA256. Any edit to the token
+/// fails `decode` with `UserError::InvalidCursor`.
+#[derive(Debug, Clone)]
+pub struct CursorCodec {
+    secret: Secret<Vec<u8>>,
+    max_age: Option<chrono::Duration>,
+}
+
+impl CursorCodec {
+    /// The `purpose` claim of its tokens
+    const TOKEN_PURPOSE: &'static str = "cursor";
+    /// How far in the future a cursor's `issued_at` may be, for clocks that disagree
+    pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: Secret::new(secret.into()),
+            max_age: None,
+        }
+    }
+
+    /// Codec with a random secret, so its cursors only work in this process
+    pub fn ephemeral() -> Self {
+        let mut secret = uuid::Uuid::new_v4().as_bytes().to_vec();
+        secret.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+        Self::new(secret)
+    }
+
+    /// Reject cursors issued longer ago than `max_age`
+    pub fn with_max_age(mut self, max_age: chrono::Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn encode(&self, cursor: &PageCursor) -> String {
+        seal_token(self.secret.expose(), Self::TOKEN_PURPOSE, cursor)
+    }
+
+    pub fn decode(&self, token: &str) -> Result<PageCursor> {
+        self.decode_at(token, Utc::now())
+    }
+
+    /// Verify and parse a token, judging its age relative to `now`. A cursor issued more
+    /// than `MAX_CLOCK_SKEW_SECS` after `now` is rejected, so it cannot outlive `max_age`.
+    pub fn decode_at(&self, token: &str, now: DateTime<Utc>) -> Result<PageCursor> {
+        let cursor: PageCursor = open_token(self.secret.expose(), Self::TOKEN_PURPOSE, token)
+            .map_err(|fault| invalid(format!("cursor {}", fault)))?;
+        if cursor.issued_at.saturating_sub(now.timestamp()) > Self::MAX_CLOCK_SKEW_SECS {
+            return Err(invalid("cursor is issued in the future"));
+        }
+        if let Some(max_age) = self.max_age {
+            if now.timestamp() - cursor.issued_at > max_age.num_seconds() {
+                return Err(invalid("cursor has expired"));
+            }
+        }
+        Ok(cursor)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> anyhow::Error {
+    UserError::InvalidCursor {
+        message: message.into(),
+    }
+    .into()
+}
+
+// One page of a cursor-paginated listing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    /// Token for the following page; `None` on the last one
+    pub next_cursor: Option<String>,
+}
+
+impl UserManager {
+    /// Ordering name in cursors from `list_users_page`: the repository's own list order
+    pub const LIST_ORDER: &'static str = "repository";
+
+    /// A page of users starting at `cursor` (`None` for the first page)
+    pub async fn list_users_page(&self, cursor: Option<&str>, limit: usize) -> Result<UserPage> {
+        let position = match cursor {
+            Some(token) => {
+                let cursor = self.cursor_codec().decode(token)?;
+                cursor.expect(Self::LIST_ORDER, self.tenant())?;
+                cursor.position
+            }
+            None => 0,
+        };
+        let users = self.list_users(position, limit).await?;
+        let next_cursor = (limit > 0 && users.len() == limit).then(|| {
+            let last = users.last().map(|user| user.id.clone()).unwrap_or_default();
+            self.cursor_codec().encode(
+                &PageCursor::new(Self::LIST_ORDER, position + users.len())
+                    .with_last_key(last)
+                    .with_tenant(self.tenant()),
+            )
+        });
+        Ok(UserPage { users, next_cursor })
+    }
+
+    /// Every user, fetched `page_size` at a time through signed cursors
+    pub fn stream_users(&self, page_size: usize) -> futures::stream::BoxStream<'_, Result<User>> {
+        use futures::{StreamExt, TryStreamExt};
+
+        futures::stream::try_unfold(Some(None::<String>), move |next| async move {
+            let Some(cursor) = next else {
+                return Ok(None);
+            };
+            let page = self
+                .list_users_page(cursor.as_deref(), page_size.max(1))
+                .await?;
+            let next = page.next_cursor.map(Some);
+            Ok::<_, anyhow::Error>(Some((
+                futures::stream::iter(page.users.into_iter().map(Ok)),
+                next,
+            )))
+        })
+        .try_flatten()
+        .boxed()
+    }
+}
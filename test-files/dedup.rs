@@ -0,0 +1,448 @@
+use super::*;
+
+// String similarity used for names, from 0.0 (nothing alike) to 1.0 (equal)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StringSimilarity {
+    /// Favours strings sharing a prefix, which suits typos in short names
+    #[default]
+    JaroWinkler,
+    /// Edit distance scaled by the longer string's length
+    Levenshtein,
+}
+
+impl StringSimilarity {
+    pub fn similarity(self, a: &str, b: &str) -> f64 {
+        match self {
+            StringSimilarity::JaroWinkler => jaro_winkler(a, b),
+            StringSimilarity::Levenshtein => {
+                let longest = a.chars().count().max(b.chars().count());
+                if longest == 0 {
+                    return 1.0;
+                }
+                1.0 - levenshtein(a, b) as f64 / longest as f64
+            }
+        }
+    }
+}
+
+/// Jaro-Winkler similarity, counting up to four shared leading characters
+pub fn jaro_winkler(a: &str, b: &str) -> f64 {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.is_empty() || b.is_empty() {
+        return if a.len() == b.len() { 1.0 } else { 0.0 };
+    }
+    let window = (a.len().max(b.len()) / 2).saturating_sub(1);
+    let mut a_matched = vec![false; a.len()];
+    let mut b_matched = vec![false; b.len()];
+    let mut matches = 0;
+    for (i, ca) in a.iter().enumerate() {
+        let end = (i + window + 1).min(b.len());
+        for j in i.saturating_sub(window)..end {
+            if !b_matched[j] && b[j] == *ca {
+                a_matched[i] = true;
+                b_matched[j] = true;
+                matches += 1;
+                break;
+            }
+        }
+    }
+    if matches == 0 {
+        return 0.0;
+    }
+    let mut transpositions = 0;
+    let mut matched_b = b.iter().zip(&b_matched).filter(|(_, m)| **m);
+    for (ca, _) in a.iter().zip(&a_matched).filter(|(_, m)| **m) {
+        if matched_b.next().is_some_and(|(cb, _)| cb != ca) {
+            transpositions += 1;
+        }
+    }
+    let m = matches as f64;
+    let jaro =
+        (m / a.len() as f64 + m / b.len() as f64 + (m - transpositions as f64 / 2.0) / m) / 3.0;
+    let prefix = a.iter().zip(&b).take(4).take_while(|(x, y)| x == y).count();
+    jaro + prefix as f64 * 0.1 * (1.0 - jaro)
+}
+
+/// Number of single-character insertions, deletions and substitutions between `a` and `b`
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+// Scores how likely two users are the same person, from 0.0 to 1.0; `None` abstains
+pub trait Matcher: Send + Sync {
+    fn score(&self, a: &User, b: &User) -> Option<f64>;
+}
+
+impl<F> Matcher for F
+where
+    F: Fn(&User, &User) -> Option<f64> + Send + Sync,
+{
+    fn score(&self, a: &User, b: &User) -> Option<f64> {
+        self(a, b)
+    }
+}
+
+// Equal emails once trimmed and lowercased score 1.0; equal once a `+tag` (and, for
+// Gmail, dots) are dropped from the local part score 0.9. Other pairs abstain.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmailMatcher;
+
+impl EmailMatcher {
+    /// The address with case, a `+tag` and Gmail's ignored dots removed
+    pub fn normalize(email: &str) -> String {
+        let email = QualityAnalyzer::canonical_email(&email.trim().to_lowercase());
+        match email.split_once('@') {
+            Some((local, domain @ ("gmail.com" | "googlemail.com"))) => {
+                format!("{}@{}", local.replace('.', ""), domain)
+            }
+            _ => email,
+        }
+    }
+}
+
+impl Matcher for EmailMatcher {
+    fn score(&self, a: &User, b: &User) -> Option<f64> {
+        if a.email.trim().eq_ignore_ascii_case(b.email.trim()) {
+            Some(1.0)
+        } else if Self::normalize(&a.email) == Self::normalize(&b.email) {
+            Some(0.9)
+        } else {
+            None
+        }
+    }
+}
+
+// Similarity of names with case and spacing ignored, also compared with words sorted so
+// "Lovelace, Ada" matches "Ada Lovelace"; abstains when either name is blank
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NameMatcher {
+    pub similarity: StringSimilarity,
+}
+
+impl NameMatcher {
+    pub fn new(similarity: StringSimilarity) -> Self {
+        Self { similarity }
+    }
+
+    fn words(name: &str) -> Vec<String> {
+        name.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+}
+
+impl Matcher for NameMatcher {
+    fn score(&self, a: &User, b: &User) -> Option<f64> {
+        let (mut a, mut b) = (Self::words(&a.name), Self::words(&b.name));
+        if a.is_empty() || b.is_empty() {
+            return None;
+        }
+        let direct = self.similarity.similarity(&a.join(" "), &b.join(" "));
+        a.sort_unstable();
+        b.sort_unstable();
+        let sorted = self.similarity.similarity(&a.join(" "), &b.join(" "));
+        Some(direct.max(sorted))
+    }
+}
+
+// One matcher's opinion of a pair
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MatchReason {
+    pub matcher: String,
+    pub score: f64,
+}
+
+// Two users scored as possibly the same person
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicatePair {
+    pub first: String,
+    pub second: String,
+    /// Highest weighted matcher score
+    pub confidence: f64,
+    pub reasons: Vec<MatchReason>,
+}
+
+// Users linked by pairs at or above the detector's threshold
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateCluster {
+    /// In input order; the first is a reasonable merge target
+    pub user_ids: Vec<String>,
+    /// Confidence of the cluster's strongest pair
+    pub confidence: f64,
+    pub pairs: Vec<DuplicatePair>,
+}
+
+struct WeightedMatcher {
+    name: String,
+    weight: f64,
+    matcher: Box<dyn Matcher>,
+}
+
+// Finds likely duplicate users. Each pair's confidence is its best matcher score times
+// that matcher's weight, so a strong signal is not diluted by weak ones. Compares every
+// pair, so run it over one tenant or a filtered set rather than a whole large backend.
+pub struct DuplicateDetector {
+    matchers: Vec<WeightedMatcher>,
+    threshold: f64,
+}
+
+impl fmt::Debug for DuplicateDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DuplicateDetector")
+            .field(
+                "matchers",
+                &self
+                    .matchers
+                    .iter()
+                    .map(|m| (m.name.as_str(), m.weight))
+                    .collect::<Vec<_>>(),
+            )
+            .field("threshold", &self.threshold)
+            .finish()
+    }
+}
+
+impl Default for DuplicateDetector {
+    /// Email matching at full weight, Jaro-Winkler names at 0.85, threshold 0.8
+    fn default() -> Self {
+        Self::empty()
+            .with_matcher("email", 1.0, EmailMatcher)
+            .with_matcher("name", 0.85, NameMatcher::default())
+    }
+}
+
+impl DuplicateDetector {
+    pub const DEFAULT_THRESHOLD: f64 = 0.8;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A detector without matchers, to be given its own with `with_matcher`
+    pub fn empty() -> Self {
+        Self {
+            matchers: Vec::new(),
+            threshold: Self::DEFAULT_THRESHOLD,
+        }
+    }
+
+    /// Add a matcher whose scores are scaled by `weight` (0.0..=1.0)
+    pub fn with_matcher(
+        mut self,
+        name: impl Into<String>,
+        weight: f64,
+        matcher: impl Matcher + 'static,
+    ) -> Self {
+        self.matchers.push(WeightedMatcher {
+            name: name.into(),
+            weight: weight.clamp(0.0, 1.0),
+            matcher: Box::new(matcher),
+        });
+        self
+    }
+
+    /// Lowest confidence at which a pair is reported
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Score one pair; `None` when every matcher abstains
+    pub fn compare(&self, first: &User, second: &User) -> Option<DuplicatePair> {
+        let reasons: Vec<MatchReason> = self
+            .matchers
+            .iter()
+            .filter_map(|m| {
+                m.matcher.score(first, second).map(|score| MatchReason {
+                    matcher: m.name.clone(),
+                    score: score.clamp(0.0, 1.0) * m.weight,
+                })
+            })
+            .collect();
+        let confidence = reasons.iter().map(|reason| reason.score).reduce(f64::max)?;
+        Some(DuplicatePair {
+            first: first.id.clone(),
+            second: second.id.clone(),
+            confidence,
+            reasons,
+        })
+    }
+
+    /// Clusters of likely duplicates, most confident first
+    pub fn find(&self, users: &[User]) -> Vec<DuplicateCluster> {
+        // Union-find over user indexes, joined by every pair over the threshold
+        let mut parent: Vec<usize> = (0..users.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+
+        let mut pairs = Vec::new();
+        for i in 0..users.len() {
+            for j in i + 1..users.len() {
+                let Some(pair) = self.compare(&users[i], &users[j]) else {
+                    continue;
+                };
+                if pair.confidence >= self.threshold {
+                    let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                    parent[a.max(b)] = a.min(b);
+                    pairs.push((i, pair));
+                }
+            }
+        }
+
+        let mut clusters: std::collections::BTreeMap<usize, DuplicateCluster> = Default::default();
+        for (i, pair) in pairs {
+            let cluster =
+                clusters
+                    .entry(root(&mut parent, i))
+                    .or_insert_with(|| DuplicateCluster {
+                        user_ids: Vec::new(),
+                        confidence: 0.0,
+                        pairs: Vec::new(),
+                    });
+            cluster.confidence = cluster.confidence.max(pair.confidence);
+            cluster.pairs.push(pair);
+        }
+        for (i, user) in users.iter().enumerate() {
+            if let Some(cluster) = clusters.get_mut(&root(&mut parent, i)) {
+                cluster.user_ids.push(user.id.clone());
+            }
+        }
+        let mut clusters: Vec<DuplicateCluster> = clusters.into_values().collect();
+        clusters.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        clusters
+    }
+}
+
+impl User {
+    pub const MERGED_FROM_KEY: &'static str = "merged_from";
+
+    /// This user with `duplicates` folded in: identity, email, status and creation time are
+    /// kept, a blank name is filled, metadata this user lacks is added, consents are
+    /// combined, and the duplicates' ids are appended to `merged_from`
+    pub fn merged_with(&self, duplicates: &[User]) -> User {
+        let mut merged = self.clone();
+        for duplicate in duplicates {
+            if merged.name.trim().is_empty() {
+                merged.name = duplicate.name.clone();
+            }
+            for (key, value) in &duplicate.metadata {
+                merged
+                    .metadata
+                    .entry(key.clone())
+                    .or_insert_with(|| value.clone());
+            }
+            for consent in &duplicate.consents {
+                if !merged.consents.contains(consent) {
+                    merged.consents.push(consent.clone());
+                }
+            }
+        }
+        merged.consents.sort_by_key(|consent| consent.granted_at);
+
+        if !duplicates.is_empty() {
+            let mut merged_from = match merged.metadata.get(Self::MERGED_FROM_KEY) {
+                Some(serde_json::Value::Array(ids)) => ids.clone(),
+                _ => Vec::new(),
+            };
+            merged_from.extend(duplicates.iter().map(|d| serde_json::json!(d.id)));
+            merged.add_metadata(Self::MERGED_FROM_KEY, serde_json::Value::Array(merged_from));
+        }
+        merged
+    }
+}
+
+#[cfg(feature = "client")]
+impl UserManager {
+    /// Fold `duplicate_ids` into `primary_id` with [`User::merged_with`], save the result
+    /// and delete the duplicates. On a transactional backend this is one all-or-nothing
+    /// [`transaction`](Self::transaction); otherwise it goes through `update_user` and
+    /// `delete_user`, and a failure part way can leave some duplicates in place.
+    pub async fn merge_duplicates(
+        &self,
+        primary_id: &str,
+        duplicate_ids: &[String],
+    ) -> Result<User> {
+        let fetch = |id: &str| {
+            let id = id.to_string();
+            async move {
+                self.fetch_user(&id)
+                    .await?
+                    .ok_or_else(|| anyhow::Error::from(UserError::NotFound { id }))
+            }
+        };
+        let primary = fetch(primary_id).await?;
+        let mut duplicates = Vec::with_capacity(duplicate_ids.len());
+        for id in duplicate_ids.iter().filter(|id| *id != primary_id) {
+            duplicates.push(fetch(id).await?);
+        }
+        let merged = primary.merged_with(&duplicates);
+
+        let mut update = UserUpdate::new();
+        if merged.name != primary.name {
+            update = update.name(merged.name.clone());
+        }
+        for (key, value) in &merged.metadata {
+            if primary.metadata.get(key) != Some(value) {
+                update = update.set_metadata(key.as_str(), value.clone());
+            }
+        }
+        if merged.consents != primary.consents {
+            update = update.consents(merged.consents.clone());
+        }
+        let repository = self.user_repository(self.tenant(), primary_id).await?;
+        if repository.supports_transactions() {
+            let duplicate_ids: Vec<&str> = duplicates.iter().map(|d| d.id.as_str()).collect();
+            self.transaction(|tx| async move {
+                if tx.get(primary_id).await?.is_none() {
+                    return Err(UserError::NotFound {
+                        id: primary_id.to_string(),
+                    }
+                    .into());
+                }
+                if !update.is_empty() {
+                    tx.update(primary_id, update);
+                }
+                for id in duplicate_ids {
+                    tx.delete(id);
+                }
+                Ok(())
+            })
+            .await?;
+        } else {
+            if !update.is_empty() && !self.update_user(primary_id, update).await? {
+                return Err(UserError::NotFound {
+                    id: primary_id.to_string(),
+                }
+                .into());
+            }
+            for duplicate in &duplicates {
+                self.delete_user(&duplicate.id).await?;
+            }
+        }
+        tracing::info!(
+            user_id = %redact_id(primary_id),
+            merged = duplicates.len(),
+            "Merged duplicate users"
+        );
+        Ok(merged)
+    }
+}
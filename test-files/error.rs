@@ -0,0 +1,399 @@
+use super::*;
+
+// Custom error types
+#[derive(Error, Debug)]
+pub enum UserError {
+    #[error("User not found: {}", redact_id(id))]
+    NotFound { id: String },
+    #[error("Invalid email format: {}", redact(email))]
+    InvalidEmail { email: String },
+    #[error("API request failed: {message}")]
+    ApiError { message: String },
+    #[cfg(feature = "sqlx")]
+    #[error("Database error")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("User manager is shutting down")]
+    ShuttingDown,
+    #[error("Invalid update for {field}: {message}")]
+    InvalidUpdate { field: String, message: String },
+    #[error("Backend unavailable: {message}")]
+    Unavailable { message: String },
+    #[error("Backend unreachable; mutation queued for replay (idempotency key {idempotency_key})")]
+    Queued { idempotency_key: String },
+    #[error("Tenant {tenant} is not supported: {message}")]
+    UnsupportedTenant { tenant: String, message: String },
+    #[error("{operation} denied by hook: {reason}")]
+    Vetoed { operation: String, reason: String },
+    #[error("Invalid configuration for {field}: {message}")]
+    InvalidConfig { field: String, message: String },
+    #[error("Invalid filter: {message}")]
+    InvalidFilter { message: String },
+    #[error("User {field} cannot be empty")]
+    EmptyField { field: String },
+    #[error("Audit log tampered at entry {entry}: {message}")]
+    AuditChainBroken { entry: usize, message: String },
+    #[error("Rate limit exceeded for {tenant}; retry in {retry_after:?}")]
+    RateLimited {
+        tenant: String,
+        retry_after: std::time::Duration,
+    },
+    #[error("Request quota exhausted for {tenant}; resets in {resets_in:?}")]
+    QuotaExceeded {
+        tenant: String,
+        resets_in: std::time::Duration,
+    },
+    #[error("{caller} may not {permission} users")]
+    Forbidden { caller: String, permission: String },
+    #[error("Data residency violation for region {region}: {message}")]
+    ResidencyViolation { region: String, message: String },
+    #[error("Change log no longer holds changes after {cursor}; oldest kept is {oldest}")]
+    CursorExpired { cursor: u64, oldest: u64 },
+    #[error("Invalid pagination cursor: {message}")]
+    InvalidCursor { message: String },
+    #[error("Invalid password reset token: {message}")]
+    InvalidResetToken { message: String },
+    #[error("Invalid invitation: {message}")]
+    InvalidInvitation { message: String },
+    #[error("Cache memory budget of {budget} bytes exceeded; the insert needs {needed}")]
+    CacheBudgetExceeded { needed: usize, budget: usize },
+    #[error("Session not found: {id}")]
+    SessionNotFound { id: String },
+}
+
+// Why an `ApiResponse` carried no usable data
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ApiError<E = String> {
+    #[error("API reported failure: {message}")]
+    Failed { message: E },
+    #[error("API reported success without data")]
+    MissingData,
+}
+
+// Error object returned by backends, read from either a plain message or
+// `{"code": ..., "message": ..., "details": ...}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ApiErrorBodyWire")]
+pub struct ApiErrorBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ApiErrorBodyWire {
+    Message(String),
+    Structured {
+        #[serde(default)]
+        code: Option<String>,
+        message: String,
+        #[serde(default)]
+        details: Option<serde_json::Value>,
+    },
+}
+
+impl From<ApiErrorBodyWire> for ApiErrorBody {
+    fn from(wire: ApiErrorBodyWire) -> Self {
+        match wire {
+            ApiErrorBodyWire::Message(message) => message.into(),
+            ApiErrorBodyWire::Structured {
+                code,
+                message,
+                details,
+            } => ApiErrorBody {
+                code,
+                message,
+                details,
+            },
+        }
+    }
+}
+
+impl From<String> for ApiErrorBody {
+    fn from(message: String) -> Self {
+        ApiErrorBody {
+            code: None,
+            message,
+            details: None,
+        }
+    }
+}
+
+impl fmt::Display for ApiErrorBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{} ({})", self.message, code),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl ApiErrorBody {
+    fn detail(&self, key: &str) -> Option<String> {
+        self.details
+            .as_ref()?
+            .get(key)?
+            .as_str()
+            .map(str::to_string)
+    }
+}
+
+impl From<ApiErrorBody> for UserError {
+    /// Known codes map to their `UserError`; anything else stays an API error
+    fn from(body: ApiErrorBody) -> Self {
+        match body.code.as_deref() {
+            Some("not_found") => match body.detail("id") {
+                Some(id) => UserError::NotFound { id },
+                None => UserError::ApiError {
+                    message: body.to_string(),
+                },
+            },
+            Some("invalid_email") => match body.detail("email") {
+                Some(email) => UserError::InvalidEmail { email },
+                None => UserError::ApiError {
+                    message: body.to_string(),
+                },
+            },
+            Some("unavailable") => UserError::Unavailable {
+                message: body.message,
+            },
+            _ => UserError::ApiError {
+                message: body.to_string(),
+            },
+        }
+    }
+}
+
+impl UserError {
+    /// Whether an error means the backend could not be reached at all
+    pub fn is_unreachable(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| {
+            if let Some(UserError::Unavailable { .. }) = cause.downcast_ref::<UserError>() {
+                return true;
+            }
+            Self::is_http_unreachable(cause)
+        })
+    }
+
+    #[cfg(feature = "client")]
+    fn is_http_unreachable(cause: &(dyn std::error::Error + 'static)) -> bool {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+    }
+
+    #[cfg(not(feature = "client"))]
+    fn is_http_unreachable(_cause: &(dyn std::error::Error + 'static)) -> bool {
+        false
+    }
+
+    #[cfg(feature = "client")]
+    fn is_http(cause: &(dyn std::error::Error + 'static)) -> bool {
+        cause.is::<reqwest::Error>()
+    }
+
+    #[cfg(not(feature = "client"))]
+    fn is_http(_cause: &(dyn std::error::Error + 'static)) -> bool {
+        false
+    }
+
+    /// Short, stable label for metrics and logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UserError::NotFound { .. } => "not_found",
+            UserError::InvalidEmail { .. } => "invalid_email",
+            UserError::ApiError { .. } => "api",
+            #[cfg(feature = "sqlx")]
+            UserError::DatabaseError(_) => "database",
+            UserError::ShuttingDown => "shutting_down",
+            UserError::InvalidUpdate { .. } => "invalid_update",
+            UserError::Unavailable { .. } => "unavailable",
+            UserError::Queued { .. } => "queued",
+            UserError::UnsupportedTenant { .. } => "unsupported_tenant",
+            UserError::Vetoed { .. } => "vetoed",
+            UserError::InvalidConfig { .. } => "invalid_config",
+            UserError::InvalidFilter { .. } => "invalid_filter",
+            UserError::EmptyField { .. } => "empty_field",
+            UserError::AuditChainBroken { .. } => "audit_chain_broken",
+            UserError::RateLimited { .. } => "rate_limited",
+            UserError::QuotaExceeded { .. } => "quota_exceeded",
+            UserError::Forbidden { .. } => "forbidden",
+            UserError::ResidencyViolation { .. } => "residency_violation",
+            UserError::CursorExpired { .. } => "cursor_expired",
+            UserError::InvalidCursor { .. } => "invalid_cursor",
+            UserError::InvalidResetToken { .. } => "invalid_reset_token",
+            UserError::InvalidInvitation { .. } => "invalid_invitation",
+            UserError::CacheBudgetExceeded { .. } => "cache_budget_exceeded",
+            UserError::SessionNotFound { .. } => "session_not_found",
+        }
+    }
+
+    /// Label for any error: the first `UserError` in the chain, else a transport class
+    pub fn kind_of(error: &anyhow::Error) -> &'static str {
+        if let Some(user_error) = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<UserError>())
+        {
+            return user_error.kind();
+        }
+        if Self::is_unreachable(error) {
+            "unavailable"
+        } else if error.chain().any(Self::is_http) {
+            "http"
+        } else if error.chain().any(|cause| cause.is::<serde_json::Error>()) {
+            "serialization"
+        } else {
+            "other"
+        }
+    }
+}
+
+/// A credential that stays out of `Debug` output and error messages; read it with `expose`
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+// Masks PII and credentials in log fields and error messages; emails, URL credentials,
+// secret query parameters and bearer tokens are masked by default
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    patterns: Vec<(regex::Regex, String)>,
+    mask_user_ids: bool,
+    id_key: Secret<Vec<u8>>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::new()
+            .with_pattern(Self::URL_CREDENTIALS_PATTERN, "$1<redacted>@")
+            .and_then(|r| r.with_pattern(Self::SECRET_PARAM_PATTERN, "$1<redacted>"))
+            .and_then(|r| r.with_pattern(Self::BEARER_PATTERN, "$1 <redacted>"))
+            .and_then(|r| r.with_pattern(Self::EMAIL_PATTERN, "<email>"))
+            .expect("default patterns are valid")
+    }
+}
+
+impl Redactor {
+    pub const EMAIL_PATTERN: &'static str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)*";
+    /// `user:password@` in URLs
+    pub const URL_CREDENTIALS_PATTERN: &'static str = r"(://)[^/@\s:]+:[^/@\s]+@";
+    /// Query parameters that carry credentials, such as `?token=...`
+    pub const SECRET_PARAM_PATTERN: &'static str =
+        r"(?i)([?&](?:access_token|token|api_key|apikey|key|secret|signature)=)[^&\s]+";
+    /// `Bearer ...` and `Basic ...` credentials, as found in echoed auth headers
+    pub const BEARER_PATTERN: &'static str = r"(?i)\b(bearer|basic)\s+[A-Za-z0-9._~+/=-]+";
+
+    /// Redactor that leaves everything untouched
+    pub fn new() -> Self {
+        Self {
+            patterns: Vec::new(),
+            mask_user_ids: false,
+            id_key: Secret::new(uuid::Uuid::new_v4().as_bytes().to_vec()),
+        }
+    }
+
+    /// Replace matches of `pattern`; `replacement` may use `$1`-style groups
+    pub fn with_pattern(mut self, pattern: &str, replacement: &str) -> Result<Self> {
+        let regex = regex::Regex::new(pattern)
+            .with_context(|| format!("Invalid redaction pattern {}", pattern))?;
+        self.patterns.push((regex, replacement.to_string()));
+        Ok(self)
+    }
+
+    /// Log user IDs as keyed hashes instead of their values. The key is random per process
+    /// unless set with `with_user_id_key`.
+    pub fn with_user_ids_masked(mut self, mask: bool) -> Self {
+        self.mask_user_ids = mask;
+        self
+    }
+
+    /// HMAC key for masked user IDs; share it between instances so their logs correlate
+    pub fn with_user_id_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.id_key = Secret::new(key.into());
+        self
+    }
+
+    pub fn redact<'t>(&self, text: &'t str) -> std::borrow::Cow<'t, str> {
+        let mut text = std::borrow::Cow::Borrowed(text);
+        for (pattern, replacement) in &self.patterns {
+            let replaced = match pattern.replace_all(&text, replacement.as_str()) {
+                std::borrow::Cow::Owned(replaced) => Some(replaced),
+                std::borrow::Cow::Borrowed(_) => None,
+            };
+            if let Some(replaced) = replaced {
+                text = std::borrow::Cow::Owned(replaced);
+            }
+        }
+        text
+    }
+
+    pub fn redact_id(&self, user_id: &str) -> String {
+        if self.mask_user_ids {
+            use hmac::{Hmac, Mac};
+            let mut mac = Hmac::<sha2::Sha256>::new_from_slice(self.id_key.expose())
+                .expect("HMAC accepts keys of any length");
+            mac.update(user_id.as_bytes());
+            let digest = mac.finalize().into_bytes();
+            let mut prefix = [0; 8];
+            prefix.copy_from_slice(&digest[..8]);
+            format!("id:{:016x}", u64::from_be_bytes(prefix))
+        } else {
+            self.redact(user_id).into_owned()
+        }
+    }
+}
+
+fn redactor_slot() -> &'static std::sync::RwLock<Arc<Redactor>> {
+    static REDACTOR: std::sync::OnceLock<std::sync::RwLock<Arc<Redactor>>> =
+        std::sync::OnceLock::new();
+    REDACTOR.get_or_init(Default::default)
+}
+
+/// Replace the process-wide redactor used for logs and error messages
+pub fn set_redactor(redactor: Redactor) {
+    *redactor_slot().write().expect("redactor poisoned") = Arc::new(redactor);
+}
+
+pub fn redactor() -> Arc<Redactor> {
+    redactor_slot().read().expect("redactor poisoned").clone()
+}
+
+/// Apply the process-wide redactor to free text
+pub fn redact(text: &str) -> String {
+    redactor().redact(text).into_owned()
+}
+
+/// User ID as it may appear in logs
+pub fn redact_id(user_id: &str) -> String {
+    redactor().redact_id(user_id)
+}
+
+/// Full error chain, redacted, for logs and diagnostics. Masking user IDs relies on errors
+/// formatting them with `redact_id`, as `UserError` does.
+pub fn redact_error(error: &anyhow::Error) -> String {
+    redact(&format!("{:#}", error))
+}
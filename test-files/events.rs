@@ -0,0 +1,520 @@
+use super::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU64;
+
+type UpcastStep = Box<dyn Fn(serde_json::Value) -> Result<serde_json::Value> + Send + Sync>;
+
+// Something that happened to a user, published once the operation has completed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UserEvent {
+    UserFetched {
+        tenant: Option<TenantId>,
+        user_id: String,
+        cache_hit: bool,
+        found: bool,
+    },
+    UserCreated {
+        tenant: Option<TenantId>,
+        user: User,
+    },
+    UserUpdated {
+        tenant: Option<TenantId>,
+        user_id: String,
+        /// Wire names of the fields written, sorted
+        fields: Vec<String>,
+    },
+    UserDeleted {
+        tenant: Option<TenantId>,
+        user_id: String,
+    },
+    UserErased {
+        tenant: Option<TenantId>,
+        user_id: String,
+    },
+    CacheEvicted {
+        tenant: Option<TenantId>,
+        user_id: String,
+        reason: EvictReason,
+    },
+}
+
+impl UserEvent {
+    /// Payload shape written by `to_stored`; a bump needs an `EventUpcaster` step
+    pub const SCHEMA_VERSION: u32 = 2;
+
+    /// JSON for long-lived storage, tagged with `SCHEMA_VERSION` in a `version` field
+    pub fn to_stored(&self) -> Result<serde_json::Value> {
+        let mut stored = serde_json::to_value(self).context("Failed to serialize event")?;
+        if let Some(fields) = stored.as_object_mut() {
+            fields.insert("version".to_string(), Self::SCHEMA_VERSION.into());
+        }
+        Ok(stored)
+    }
+
+    /// Stable name, also the `type` field of the serialized event
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UserEvent::UserFetched { .. } => "user_fetched",
+            UserEvent::UserCreated { .. } => "user_created",
+            UserEvent::UserUpdated { .. } => "user_updated",
+            UserEvent::UserDeleted { .. } => "user_deleted",
+            UserEvent::UserErased { .. } => "user_erased",
+            UserEvent::CacheEvicted { .. } => "cache_evicted",
+        }
+    }
+
+    /// Whether the event records a change to stored data rather than a read or cache churn
+    pub fn is_mutation(&self) -> bool {
+        !matches!(
+            self,
+            UserEvent::UserFetched { .. } | UserEvent::CacheEvicted { .. }
+        )
+    }
+
+    /// Kinds that transactional repositories write to their outbox
+    pub fn is_outboxed(&self) -> bool {
+        matches!(
+            self,
+            UserEvent::UserCreated { .. }
+                | UserEvent::UserUpdated { .. }
+                | UserEvent::UserDeleted { .. }
+        )
+    }
+
+    pub fn tenant(&self) -> Option<&TenantId> {
+        match self {
+            UserEvent::UserFetched { tenant, .. }
+            | UserEvent::UserCreated { tenant, .. }
+            | UserEvent::UserUpdated { tenant, .. }
+            | UserEvent::UserDeleted { tenant, .. }
+            | UserEvent::UserErased { tenant, .. }
+            | UserEvent::CacheEvicted { tenant, .. } => tenant.as_ref(),
+        }
+    }
+
+    pub fn user_id(&self) -> &str {
+        match self {
+            UserEvent::UserCreated { user, .. } => &user.id,
+            UserEvent::UserFetched { user_id, .. }
+            | UserEvent::UserUpdated { user_id, .. }
+            | UserEvent::UserDeleted { user_id, .. }
+            | UserEvent::UserErased { user_id, .. }
+            | UserEvent::CacheEvicted { user_id, .. } => user_id,
+        }
+    }
+
+    pub(crate) fn updated(
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Self {
+        let mut fields: Vec<String> = updates.keys().cloned().collect();
+        fields.sort();
+        UserEvent::UserUpdated {
+            tenant: tenant.cloned(),
+            user_id: user_id.to_string(),
+            fields,
+        }
+    }
+
+    /// Event for a mutation the backend applied
+    pub(crate) fn applied(tenant: Option<&TenantId>, mutation: &Mutation) -> Self {
+        match mutation {
+            Mutation::Create(user) => UserEvent::UserCreated {
+                tenant: tenant.cloned(),
+                user: user.clone(),
+            },
+            Mutation::Update { user_id, updates } => Self::updated(tenant, user_id, updates),
+            Mutation::Delete { user_id } => UserEvent::UserDeleted {
+                tenant: tenant.cloned(),
+                user_id: user_id.clone(),
+            },
+        }
+    }
+}
+
+// Reads stored events written under any earlier schema version, upgrading them one
+// version at a time. Payloads without a `version` are version 1, the shape stored before
+// events were versioned; version 2 only adds the tag.
+pub struct EventUpcaster {
+    steps: BTreeMap<u32, UpcastStep>,
+}
+
+impl Default for EventUpcaster {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for EventUpcaster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventUpcaster")
+            .field("steps", &self.steps.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl EventUpcaster {
+    pub fn new() -> Self {
+        Self {
+            steps: BTreeMap::new(),
+        }
+        .with_step(1, Ok)
+    }
+
+    /// Convert payloads of version `from` to version `from + 1`, replacing any step
+    /// already registered for it
+    pub fn with_step<F>(mut self, from: u32, step: F) -> Self
+    where
+        F: Fn(serde_json::Value) -> Result<serde_json::Value> + Send + Sync + 'static,
+    {
+        self.steps.insert(from, Box::new(step));
+        self
+    }
+
+    /// Current-shape event for a stored payload
+    pub fn upcast(&self, mut stored: serde_json::Value) -> Result<UserEvent> {
+        let version = match stored.get("version") {
+            None => 1,
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .with_context(|| format!("Invalid event schema version {}", version))?,
+        };
+        if version > UserEvent::SCHEMA_VERSION {
+            anyhow::bail!(
+                "Event schema version {} is newer than supported version {}",
+                version,
+                UserEvent::SCHEMA_VERSION
+            );
+        }
+        for from in version..UserEvent::SCHEMA_VERSION {
+            let step = self
+                .steps
+                .get(&from)
+                .with_context(|| format!("No upcaster from event schema version {}", from))?;
+            stored = step(stored)
+                .with_context(|| format!("Failed to upcast event from version {}", from))?;
+        }
+        if let Some(fields) = stored.as_object_mut() {
+            fields.remove("version");
+        }
+        serde_json::from_value(stored).context("Failed to parse stored event")
+    }
+}
+
+/// Handle for removing a subscriber with `EventBus::unsubscribe`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type EventCallback = Arc<dyn Fn(&UserEvent) + Send + Sync>;
+
+#[derive(Clone)]
+enum Subscriber {
+    Callback(EventCallback),
+    Channel(tokio::sync::mpsc::Sender<UserEvent>),
+    Unbounded(tokio::sync::mpsc::UnboundedSender<UserEvent>),
+}
+
+// Fans lifecycle events out to subscribers in registration order. Callbacks run
+// synchronously on the publishing task; bounded channel subscribers that fall behind lose
+// events rather than slowing the manager down, unbounded ones queue them in memory.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<(SubscriptionId, Subscriber)>>,
+    next_id: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribers", &self.subscriber_count())
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `callback` with every event; keep it short, it runs inside the operation
+    pub fn subscribe(
+        &self,
+        callback: impl Fn(&UserEvent) + Send + Sync + 'static,
+    ) -> SubscriptionId {
+        self.add(Subscriber::Callback(Arc::new(callback)))
+    }
+
+    /// Receive events on a channel holding up to `capacity` of them; the subscription
+    /// ends when the receiver is dropped
+    pub fn subscribe_channel(&self, capacity: usize) -> tokio::sync::mpsc::Receiver<UserEvent> {
+        let (sender, receiver) = tokio::sync::mpsc::channel(capacity.max(1));
+        self.add(Subscriber::Channel(sender));
+        receiver
+    }
+
+    /// Receive every event, queued in memory however far the receiver falls behind; the
+    /// subscription ends when the receiver is dropped
+    pub fn subscribe_unbounded(&self) -> tokio::sync::mpsc::UnboundedReceiver<UserEvent> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.add(Subscriber::Unbounded(sender));
+        receiver
+    }
+
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut subscribers = self.subscribers.lock().expect("event subscribers poisoned");
+        let before = subscribers.len();
+        subscribers.retain(|(subscriber, _)| *subscriber != id);
+        subscribers.len() != before
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers
+            .lock()
+            .expect("event subscribers poisoned")
+            .len()
+    }
+
+    /// Events lost because a channel subscriber was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Hand `event` to every subscriber, returning how many channel subscribers were full
+    /// and missed it
+    pub fn publish(&self, event: &UserEvent) -> usize {
+        // Snapshot first, so callbacks may subscribe or unsubscribe
+        let subscribers = self
+            .subscribers
+            .lock()
+            .expect("event subscribers poisoned")
+            .clone();
+        let mut closed = Vec::new();
+        let mut missed = 0;
+        for (id, subscriber) in subscribers {
+            match subscriber {
+                Subscriber::Callback(callback) => callback(event),
+                Subscriber::Channel(sender) => match sender.try_send(event.clone()) {
+                    Ok(()) => {}
+                    Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        missed += 1;
+                        tracing::warn!("Event subscriber lagged; dropping event");
+                    }
+                    Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => closed.push(id),
+                },
+                Subscriber::Unbounded(sender) => {
+                    if sender.send(event.clone()).is_err() {
+                        closed.push(id);
+                    }
+                }
+            }
+        }
+        if !closed.is_empty() {
+            self.subscribers
+                .lock()
+                .expect("event subscribers poisoned")
+                .retain(|(id, _)| !closed.contains(id));
+        }
+        missed
+    }
+
+    fn add(&self, subscriber: Subscriber) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        self.subscribers
+            .lock()
+            .expect("event subscribers poisoned")
+            .push((id, subscriber));
+        id
+    }
+}
+
+// Destination outside the process for user events, fed from the event bus by
+// `UserManager::start_event_sink`
+#[async_trait]
+pub trait EventSink: fmt::Debug + Send + Sync {
+    /// Short name for logs and metrics, e.g. `kafka`
+    fn name(&self) -> &'static str;
+
+    /// By default only changes to stored users, not reads or cache churn
+    fn wants(&self, event: &UserEvent) -> bool {
+        event.is_mutation()
+    }
+
+    /// Send one event, returning once the destination has accepted it
+    async fn send(&self, event: &UserEvent) -> Result<()>;
+}
+
+// NATS subject or AMQP routing key for an event, with `{event}`, `{tenant}` and
+// `{user_id}` filled in. Tenantless events use `default`; dots, wildcards and whitespace
+// in values become `_`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubjectTemplate(String);
+
+impl SubjectTemplate {
+    const PLACEHOLDERS: [&'static str; 3] = ["{event}", "{tenant}", "{user_id}"];
+
+    pub fn new(template: impl Into<String>) -> Result<Self> {
+        let template = template.into();
+        let mut rest = template.clone();
+        for placeholder in Self::PLACEHOLDERS {
+            rest = rest.replace(placeholder, "x");
+        }
+        let valid = !rest.is_empty()
+            && !rest.contains(['{', '}', '*', '>', '#'])
+            && !rest.contains(char::is_whitespace)
+            && rest.split('.').all(|token| !token.is_empty());
+        if !valid {
+            return Err(UserError::InvalidConfig {
+                field: "subject".to_string(),
+                message: format!("{:?} is not a valid subject template", template),
+            }
+            .into());
+        }
+        Ok(Self(template))
+    }
+
+    pub fn render(&self, event: &UserEvent) -> String {
+        let tenant = event.tenant().map_or("default", TenantId::as_str);
+        self.0
+            .replace("{event}", event.kind())
+            .replace("{tenant}", &Self::token(tenant))
+            .replace("{user_id}", &Self::token(event.user_id()))
+    }
+
+    fn token(value: &str) -> String {
+        let token: String = value
+            .chars()
+            .map(|c| match c {
+                '.' | '*' | '>' | '#' => '_',
+                c if c.is_whitespace() => '_',
+                c => c,
+            })
+            .collect();
+        if token.is_empty() {
+            "_".to_string()
+        } else {
+            token
+        }
+    }
+}
+
+impl Default for SubjectTemplate {
+    /// `users.{tenant}.{event}`
+    fn default() -> Self {
+        Self("users.{tenant}.{event}".to_string())
+    }
+}
+
+// An event committed to an outbox, waiting to be published
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub event: UserEvent,
+    pub created_at: DateTime<Utc>,
+}
+
+// Events stored in the same transaction as the mutations that caused them
+#[async_trait]
+pub trait Outbox: fmt::Debug + Send + Sync {
+    /// Unpublished entries, oldest first, claimed or not
+    async fn pending(&self, limit: usize) -> Result<Vec<OutboxEntry>>;
+
+    /// Take up to `limit` unpublished entries, oldest first, that no other relay holds, and
+    /// hold them for `lease`; entries still unpublished when it runs out can be claimed again
+    async fn claim(&self, limit: usize, lease: Duration) -> Result<Vec<OutboxEntry>>;
+
+    async fn mark_published(&self, ids: &[i64]) -> Result<()>;
+
+    /// Delete entries published before `before`, returning how many went
+    async fn purge_published(&self, before: DateTime<Utc>) -> Result<usize>;
+}
+
+// Moves outbox entries onto an event bus. Only committed mutations ever reach the outbox
+// and entries stay pending until published, so no event is lost or invented. Relays claim
+// each batch before publishing it, so concurrent relays publish different entries, and
+// order holds within a relay rather than across them. An entry published just before a
+// crash is published again once its claim runs out, so delivery is at least once. So is
+// one a full subscriber missed: it and the rest of its batch stay unpublished, to be
+// published again to every subscriber once the claim runs out.
+#[derive(Debug, Clone)]
+pub struct OutboxRelay {
+    outbox: Arc<dyn Outbox>,
+    bus: Arc<EventBus>,
+    batch_size: usize,
+    lease: Duration,
+    retention: Option<Duration>,
+}
+
+impl OutboxRelay {
+    pub fn new(outbox: Arc<dyn Outbox>, bus: Arc<EventBus>) -> Self {
+        Self {
+            outbox,
+            bus,
+            batch_size: 100,
+            lease: Duration::from_secs(30),
+            retention: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// How long a claimed batch is held before another relay may take it over; 30 seconds
+    /// by default
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Keep published entries this long before purging them, or forever with `None`;
+    /// a week by default
+    pub fn with_retention(mut self, retention: Option<Duration>) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Publish everything pending, in order, returning how many entries went out, then
+    /// purge entries published longer ago than the retention. Stops at the first entry a
+    /// subscriber missed, leaving it for a later run.
+    pub async fn relay_once(&self) -> Result<usize> {
+        let mut relayed = 0;
+        loop {
+            let entries = self.outbox.claim(self.batch_size, self.lease).await?;
+            let delivered = entries
+                .iter()
+                .take_while(|entry| self.bus.publish(&entry.event) == 0)
+                .count();
+            let ids: Vec<i64> = entries[..delivered].iter().map(|entry| entry.id).collect();
+            if !ids.is_empty() {
+                self.outbox.mark_published(&ids).await?;
+            }
+            relayed += delivered;
+            if delivered < entries.len() {
+                tracing::warn!(
+                    undelivered = entries.len() - delivered,
+                    "Event subscriber full; leaving outbox entries for the next relay"
+                );
+                break;
+            }
+            if entries.len() < self.batch_size {
+                break;
+            }
+        }
+        if let Some(retention) = self.retention {
+            let cutoff = chrono::Duration::from_std(retention)
+                .ok()
+                .and_then(|retention| Utc::now().checked_sub_signed(retention));
+            if let Some(cutoff) = cutoff {
+                self.outbox.purge_published(cutoff).await?;
+            }
+        }
+        Ok(relayed)
+    }
+}
@@ -0,0 +1,397 @@
+use super::*;
+
+/// A predicate over users, evaluated locally with `matches` or sent to a backend as the
+/// `filter` query parameter. `Display` writes a text form that `FromStr` parses back:
+/// `status eq "active" and (created_at gt "2024-01-01T00:00:00Z" or metadata.team pr)`,
+/// or `quality_score lt 60`.
+/// Metadata keys outside `[A-Za-z0-9_.-]` are written quoted, as in `metadata."cost center" pr`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Status(UserStatus),
+    CreatedAfter(DateTime<Utc>),
+    CreatedBefore(DateTime<Utc>),
+    MetadataExists(String),
+    /// Only scalar values survive the text form
+    MetadataEquals(String, serde_json::Value),
+    /// Users whose default `QualityScorer` score, without activity, is below the bound
+    QualityBelow(u8),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn status(status: UserStatus) -> Self {
+        Filter::Status(status)
+    }
+
+    pub fn created_after(at: DateTime<Utc>) -> Self {
+        Filter::CreatedAfter(at)
+    }
+
+    pub fn created_before(at: DateTime<Utc>) -> Self {
+        Filter::CreatedBefore(at)
+    }
+
+    pub fn has_metadata(key: impl Into<String>) -> Self {
+        Filter::MetadataExists(key.into())
+    }
+
+    pub fn metadata_eq(key: impl Into<String>, value: serde_json::Value) -> Self {
+        Filter::MetadataEquals(key.into(), value)
+    }
+
+    pub fn quality_below(score: u8) -> Self {
+        Filter::QualityBelow(score)
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Filter::And(mut all) => {
+                all.push(other);
+                Filter::And(all)
+            }
+            first => Filter::And(vec![first, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Filter::Or(mut any) => {
+                any.push(other);
+                Filter::Or(any)
+            }
+            first => Filter::Or(vec![first, other]),
+        }
+    }
+
+    pub fn matches(&self, user: &User) -> bool {
+        match self {
+            Filter::Status(status) => user.status == *status,
+            Filter::CreatedAfter(at) => user.created_at > *at,
+            Filter::CreatedBefore(at) => user.created_at < *at,
+            Filter::MetadataExists(key) => user.metadata.contains_key(key.as_str()),
+            Filter::MetadataEquals(key, value) => user.metadata.get(key.as_str()) == Some(value),
+            // A user record carries no activity, so only its own fields are scored
+            Filter::QualityBelow(bound) => {
+                QualityScorer::default()
+                    .with_weight(ScoreComponent::Activity, 0)
+                    .score_at(user, None, Utc::now())
+                    .score
+                    < *bound
+            }
+            Filter::And(all) => all.iter().all(|filter| filter.matches(user)),
+            Filter::Or(any) => any.iter().any(|filter| filter.matches(user)),
+            Filter::Not(filter) => !filter.matches(user),
+        }
+    }
+
+    /// Query parameters that ask a backend to apply this filter
+    pub fn to_query_params(&self) -> Vec<(String, String)> {
+        vec![("filter".to_string(), self.to_string())]
+    }
+
+    fn is_compound(&self) -> bool {
+        matches!(self, Filter::And(_) | Filter::Or(_))
+    }
+}
+
+impl std::ops::Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Status(status) => write!(f, "status eq {}", json(status)),
+            Filter::CreatedAfter(at) => write!(f, "created_at gt {}", json(at)),
+            Filter::CreatedBefore(at) => write!(f, "created_at lt {}", json(at)),
+            Filter::MetadataExists(key) => write!(f, "metadata.{} pr", MetadataKey(key)),
+            Filter::MetadataEquals(key, value) => {
+                write!(f, "metadata.{} eq {}", MetadataKey(key), value)
+            }
+            Filter::QualityBelow(bound) => write!(f, "quality_score lt {}", bound),
+            Filter::And(all) => write_joined(f, all, " and "),
+            Filter::Or(any) => write_joined(f, any, " or "),
+            Filter::Not(filter) if filter.is_compound() => write!(f, "not ({})", filter),
+            Filter::Not(filter) => write!(f, "not {}", filter),
+        }
+    }
+}
+
+// A metadata key as written after `metadata.`: bare when it is a plain identifier, else a
+// JSON string, so no key can end the field early or smuggle in operators
+struct MetadataKey<'a>(&'a str);
+
+impl MetadataKey<'_> {
+    fn is_bare(key: &str) -> bool {
+        !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    }
+}
+
+impl fmt::Display for MetadataKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if Self::is_bare(self.0) {
+            f.write_str(self.0)
+        } else {
+            f.write_str(&json(&self.0))
+        }
+    }
+}
+
+fn write_joined(f: &mut fmt::Formatter<'_>, filters: &[Filter], separator: &str) -> fmt::Result {
+    for (index, filter) in filters.iter().enumerate() {
+        if index > 0 {
+            f.write_str(separator)?;
+        }
+        if filter.is_compound() {
+            write!(f, "({})", filter)?;
+        } else {
+            write!(f, "{}", filter)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).expect("filter values always serialize")
+}
+
+impl std::str::FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+        };
+        let filter = parser.expression()?;
+        match parser.advance() {
+            None => Ok(filter),
+            Some(token) => Err(invalid(format!("unexpected {}", token))),
+        }
+    }
+}
+
+fn invalid(message: String) -> anyhow::Error {
+    UserError::InvalidFilter { message }.into()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Open => f.write_str("`(`"),
+            Token::Close => f.write_str("`)`"),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut escaped = false;
+                let end = loop {
+                    match chars.next() {
+                        Some((index, '"')) if !escaped => break index,
+                        Some((_, c)) => escaped = c == '\\' && !escaped,
+                        None => return Err(invalid("unterminated string".to_string())),
+                    }
+                };
+                let text = serde_json::from_str(&input[start..=end])
+                    .map_err(|e| invalid(format!("bad string literal: {}", e)))?;
+                tokens.push(Token::Text(text));
+            }
+            _ => {
+                let mut end = start;
+                while let Some(&(index, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+                        break;
+                    }
+                    end = index + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Word(input[start..end].to_string()));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let found = matches!(self.tokens.get(self.position), Some(Token::Word(w)) if w == word);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expression(&mut self) -> Result<Filter> {
+        let mut filter = self.conjunction()?;
+        while self.eat_word("or") {
+            filter = filter.or(self.conjunction()?);
+        }
+        Ok(filter)
+    }
+
+    fn conjunction(&mut self) -> Result<Filter> {
+        let mut filter = self.unary()?;
+        while self.eat_word("and") {
+            filter = filter.and(self.unary()?);
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter> {
+        if self.eat_word("not") {
+            return Ok(!self.unary()?);
+        }
+        match self.advance() {
+            Some(Token::Open) => {
+                let filter = self.expression()?;
+                match self.advance() {
+                    Some(Token::Close) => Ok(filter),
+                    _ => Err(invalid("expected `)`".to_string())),
+                }
+            }
+            Some(Token::Word(field)) if field == "metadata." => match self.advance() {
+                Some(Token::Text(key)) => self.metadata_comparison(&key),
+                _ => Err(invalid("expected a metadata key".to_string())),
+            },
+            Some(Token::Word(field)) => self.comparison(&field),
+            Some(token) => Err(invalid(format!("unexpected {}", token))),
+            None => Err(invalid("unexpected end of filter".to_string())),
+        }
+    }
+
+    fn comparison(&mut self, field: &str) -> Result<Filter> {
+        let operator = match self.advance() {
+            Some(Token::Word(operator)) => operator,
+            _ => return Err(invalid(format!("expected an operator after `{}`", field))),
+        };
+        if let Some(key) = field.strip_prefix("metadata.") {
+            if !MetadataKey::is_bare(key) {
+                return Err(invalid(format!("metadata key `{}` must be quoted", key)));
+            }
+            return self.metadata_operation(key, &operator);
+        }
+        match (field, operator.as_str()) {
+            ("status", "eq") => {
+                let status = self.text()?;
+                let status = status
+                    .parse()
+                    .map_err(|e: anyhow::Error| invalid(e.to_string()))?;
+                Ok(Filter::status(status))
+            }
+            ("created_at", "gt") => Ok(Filter::created_after(self.timestamp()?)),
+            ("created_at", "lt") => Ok(Filter::created_before(self.timestamp()?)),
+            ("quality_score", "lt") => Ok(Filter::quality_below(self.score()?)),
+            ("status" | "created_at" | "quality_score", _) => Err(invalid(format!(
+                "`{}` does not support `{}`",
+                field, operator
+            ))),
+            _ => Err(invalid(format!("unknown field `{}`", field))),
+        }
+    }
+
+    fn metadata_comparison(&mut self, key: &str) -> Result<Filter> {
+        match self.advance() {
+            Some(Token::Word(operator)) => self.metadata_operation(key, &operator),
+            _ => Err(invalid(format!(
+                "expected an operator after metadata key {:?}",
+                key
+            ))),
+        }
+    }
+
+    fn metadata_operation(&mut self, key: &str, operator: &str) -> Result<Filter> {
+        if key.is_empty() {
+            return Err(invalid("empty metadata key".to_string()));
+        }
+        match operator {
+            "pr" => Ok(Filter::has_metadata(key)),
+            "eq" => Ok(Filter::metadata_eq(key, self.value()?)),
+            _ => Err(invalid(format!(
+                "metadata key {:?} does not support `{}`",
+                key, operator
+            ))),
+        }
+    }
+
+    fn text(&mut self) -> Result<String> {
+        match self.advance() {
+            Some(Token::Text(text)) => Ok(text),
+            _ => Err(invalid("expected a quoted string".to_string())),
+        }
+    }
+
+    fn timestamp(&mut self) -> Result<DateTime<Utc>> {
+        let text = self.text()?;
+        text.parse()
+            .map_err(|e| invalid(format!("bad timestamp {:?}: {}", text, e)))
+    }
+
+    fn score(&mut self) -> Result<u8> {
+        match self.advance() {
+            Some(Token::Word(word)) => word
+                .parse()
+                .ok()
+                .filter(|score| *score <= 100)
+                .ok_or_else(|| invalid(format!("bad score `{}`: expected 0 to 100", word))),
+            _ => Err(invalid("expected a score".to_string())),
+        }
+    }
+
+    fn value(&mut self) -> Result<serde_json::Value> {
+        match self.advance() {
+            Some(Token::Text(text)) => Ok(serde_json::Value::String(text)),
+            Some(Token::Word(word)) => {
+                serde_json::from_str(&word).map_err(|_| invalid(format!("bad value `{}`", word)))
+            }
+            _ => Err(invalid("expected a value".to_string())),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use super::*;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const FIRST_NAMES: &[&str] = &[
+    "Ada", "Alan", "Barbara", "Claude", "Dennis", "Edsger", "Frances", "Grace", "Hedy", "Ivan",
+    "Joan", "Ken", "Linus", "Margaret", "Niklaus", "Radia", "Sophie", "Tim",
+];
+const LAST_NAMES: &[&str] = &[
+    "Allen",
+    "Backus",
+    "Cerf",
+    "Dijkstra",
+    "Engelbart",
+    "Floyd",
+    "Goldberg",
+    "Hopper",
+    "Kay",
+    "Lamport",
+    "Liskov",
+    "Perlman",
+    "Ritchie",
+    "Shannon",
+    "Thompson",
+    "Wirth",
+];
+const DEPARTMENTS: &[&str] = &["engineering", "sales", "support", "finance", "research"];
+const PLANS: &[&str] = &["free", "pro", "enterprise"];
+
+// Configurable generator; the same seed and settings always yield the same users.
+// The default creation window is the year before the generator was built.
+#[derive(Debug, Clone)]
+pub struct UserFixtures {
+    rng: StdRng,
+    statuses: Vec<(UserStatus, u32)>,
+    created_between: (DateTime<Utc>, DateTime<Utc>),
+    domains: Vec<String>,
+    with_metadata: bool,
+    next_id: usize,
+}
+
+impl UserFixtures {
+    pub fn new(seed: u64) -> Self {
+        let now = Utc::now();
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            statuses: vec![
+                (UserStatus::Active, 70),
+                (UserStatus::Inactive, 15),
+                (UserStatus::Pending, 10),
+                (UserStatus::Suspended, 5),
+            ],
+            created_between: (now - chrono::Duration::days(365), now),
+            domains: vec!["example.com".to_string(), "example.org".to_string()],
+            with_metadata: true,
+            next_id: 1,
+        }
+    }
+
+    /// Relative weights per status; statuses left out are never generated.
+    /// Fails unless at least one weight is non-zero.
+    pub fn with_status_weights(mut self, weights: &[(UserStatus, u32)]) -> Result<Self> {
+        if weights.iter().all(|(_, weight)| *weight == 0) {
+            return Err(UserError::InvalidConfig {
+                field: "status_weights".to_string(),
+                message: "at least one status needs a non-zero weight".to_string(),
+            }
+            .into());
+        }
+        self.statuses = weights.to_vec();
+        Ok(self)
+    }
+
+    pub fn with_created_between(mut self, from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        self.created_between = (from.min(to), from.max(to));
+        self
+    }
+
+    pub fn with_domains(mut self, domains: &[&str]) -> Self {
+        self.domains = domains.iter().map(|domain| domain.to_string()).collect();
+        self
+    }
+
+    pub fn with_metadata(mut self, enabled: bool) -> Self {
+        self.with_metadata = enabled;
+        self
+    }
+
+    /// Next user, with a sequential id and an email unique within this generator
+    pub fn user(&mut self) -> User {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let first = FIRST_NAMES[self.rng.gen_range(0..FIRST_NAMES.len())];
+        let last = LAST_NAMES[self.rng.gen_range(0..LAST_NAMES.len())];
+        let domain = match self.domains.len() {
+            0 => "example.com",
+            len => &self.domains[self.rng.gen_range(0..len)],
+        };
+        let email = format!(
+            "{}.{}{}@{}",
+            first.to_lowercase(),
+            last.to_lowercase(),
+            id,
+            domain
+        );
+        // Summed as u64 so large weights cannot overflow the total
+        let status =
+            match WeightedIndex::new(self.statuses.iter().map(|(_, weight)| u64::from(*weight))) {
+                Ok(index) => self.statuses[index.sample(&mut self.rng)].0,
+                Err(_) => UserStatus::Active,
+            };
+        let (from, to) = self.created_between;
+        let span = (to - from).num_seconds().max(0);
+        let created_at = from + chrono::Duration::seconds(self.rng.gen_range(0..=span));
+
+        let mut user = User {
+            id: format!("user-{:06}", id),
+            name: format!("{} {}", first, last),
+            email,
+            status,
+            created_at,
+            metadata: HashMap::new(),
+            consents: Vec::new(),
+            two_factor: None,
+        };
+        if self.with_metadata {
+            let department = DEPARTMENTS[self.rng.gen_range(0..DEPARTMENTS.len())];
+            let plan = PLANS[self.rng.gen_range(0..PLANS.len())];
+            user.add_metadata("department", serde_json::json!(department));
+            user.add_metadata("plan", serde_json::json!(plan));
+            user.add_metadata("logins", serde_json::json!(self.rng.gen_range(0..500)));
+        }
+        user
+    }
+
+    pub fn users(&mut self, count: usize) -> Vec<User> {
+        (0..count).map(|_| self.user()).collect()
+    }
+}
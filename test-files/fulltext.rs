@@ -0,0 +1,345 @@
+use super::*;
+use tantivy::collector::TopDocs;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Schema, Value, STORED, STRING, TEXT};
+use tantivy::{doc, Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    key: Field,
+    id: Field,
+    tenant: Field,
+    name: Field,
+    email: Field,
+    metadata: Field,
+}
+
+// A user matching a full-text query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FullTextHit {
+    pub user_id: String,
+    pub tenant: Option<TenantId>,
+    /// BM25 relevance; only comparable between hits of the same query
+    pub score: f32,
+}
+
+// Tantivy index over users' names, emails and metadata values, for datasets too large to
+// scan. Queries use tantivy's syntax (`ada lovelace`, `email:example`, `"exact phrase"`)
+// and rank by relevance, with name matches boosted. Writes are visible after `commit`.
+pub struct FullTextIndex {
+    index: Index,
+    fields: Fields,
+    writer: Mutex<IndexWriter>,
+    reader: IndexReader,
+}
+
+impl fmt::Debug for FullTextIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FullTextIndex")
+            .field("documents", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FullTextIndex {
+    const WRITER_MEMORY: usize = 32 * 1024 * 1024;
+    const NAME_BOOST: f32 = 2.0;
+
+    /// Index held in memory and rebuilt on every start
+    pub fn in_memory() -> Result<Self> {
+        let (schema, fields) = Self::schema();
+        Self::with_index(Index::create_in_ram(schema), fields)
+    }
+
+    /// Index persisted in `dir`, created there if missing
+    pub fn open(dir: impl AsRef<std::path::Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create index directory {}", dir.display()))?;
+        let (schema, fields) = Self::schema();
+        let directory = tantivy::directory::MmapDirectory::open(dir)
+            .with_context(|| format!("Failed to open index directory {}", dir.display()))?;
+        let index = Index::open_or_create(directory, schema)
+            .context("Index directory holds an incompatible index")?;
+        Self::with_index(index, fields)
+    }
+
+    fn schema() -> (Schema, Fields) {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            key: builder.add_text_field("key", STRING),
+            id: builder.add_text_field("id", STRING | STORED),
+            tenant: builder.add_text_field("tenant", STRING | STORED),
+            name: builder.add_text_field("name", TEXT),
+            email: builder.add_text_field("email", TEXT),
+            metadata: builder.add_text_field("metadata", TEXT),
+        };
+        (builder.build(), fields)
+    }
+
+    fn with_index(index: Index, fields: Fields) -> Result<Self> {
+        let writer = index
+            .writer_with_num_threads(1, Self::WRITER_MEMORY)
+            .context("Failed to open index writer")?;
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .context("Failed to open index reader")?;
+        Ok(Self {
+            index,
+            fields,
+            writer: Mutex::new(writer),
+            reader,
+        })
+    }
+
+    /// Documents visible to searches, i.e. as of the last commit
+    pub fn len(&self) -> usize {
+        self.reader.searcher().num_docs() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add or replace `user`; call `commit` to make it searchable
+    pub fn upsert(&self, tenant: Option<&TenantId>, user: &User) -> Result<()> {
+        let fields = self.fields;
+        let mut metadata = String::new();
+        let mut pending: Vec<&serde_json::Value> = user.metadata.values().collect();
+        while let Some(value) = pending.pop() {
+            match value {
+                serde_json::Value::String(text) => metadata.push_str(text),
+                serde_json::Value::Number(number) => metadata.push_str(&number.to_string()),
+                serde_json::Value::Bool(flag) => metadata.push_str(&flag.to_string()),
+                serde_json::Value::Array(items) => pending.extend(items),
+                serde_json::Value::Object(entries) => pending.extend(entries.values()),
+                serde_json::Value::Null => continue,
+            }
+            metadata.push('\n');
+        }
+
+        let key = Self::key(tenant, &user.id);
+        let writer = self.writer.lock().expect("index writer poisoned");
+        writer.delete_term(Term::from_field_text(fields.key, &key));
+        writer
+            .add_document(doc!(
+                fields.key => key,
+                fields.id => user.id.as_str(),
+                fields.tenant => tenant.map_or("", TenantId::as_str),
+                fields.name => user.name.as_str(),
+                fields.email => user.email.as_str(),
+                fields.metadata => metadata,
+            ))
+            .context("Failed to index user")?;
+        Ok(())
+    }
+
+    /// Drop a user; call `commit` to make the removal visible
+    pub fn remove(&self, tenant: Option<&TenantId>, user_id: &str) {
+        let key = Self::key(tenant, user_id);
+        self.writer
+            .lock()
+            .expect("index writer poisoned")
+            .delete_term(Term::from_field_text(self.fields.key, &key));
+    }
+
+    /// Persist pending writes and make them visible to searches
+    pub fn commit(&self) -> Result<()> {
+        self.writer
+            .lock()
+            .expect("index writer poisoned")
+            .commit()
+            .context("Failed to commit index")?;
+        self.reader.reload().context("Failed to reload index")?;
+        Ok(())
+    }
+
+    /// Best `limit` matches for `query` within one tenant (`None` for untenanted users).
+    /// Malformed query syntax is read as leniently as possible rather than rejected.
+    pub fn search(
+        &self,
+        tenant: Option<&TenantId>,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<FullTextHit>> {
+        let fields = self.fields;
+        let mut parser = QueryParser::for_index(
+            &self.index,
+            vec![fields.name, fields.email, fields.metadata],
+        );
+        parser.set_field_boost(fields.name, Self::NAME_BOOST);
+        let (text, _) = parser.parse_query_lenient(query);
+        let scope = TermQuery::new(
+            Term::from_field_text(fields.tenant, tenant.map_or("", TenantId::as_str)),
+            IndexRecordOption::Basic,
+        );
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, text),
+            (Occur::Must, Box::new(scope) as Box<dyn Query>),
+        ]);
+
+        let searcher = self.reader.searcher();
+        let top = searcher
+            .search(&query, &TopDocs::with_limit(limit.max(1)))
+            .context("Full-text search failed")?;
+        top.into_iter()
+            .take(limit)
+            .map(|(score, address)| {
+                let document: TantivyDocument =
+                    searcher.doc(address).context("Failed to read index")?;
+                let text = |field| {
+                    document
+                        .get_first(field)
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let tenant = text(fields.tenant);
+                Ok(FullTextHit {
+                    user_id: text(fields.id),
+                    tenant: (!tenant.is_empty()).then(|| TenantId::from(tenant)),
+                    score,
+                })
+            })
+            .collect()
+    }
+
+    fn key(tenant: Option<&TenantId>, user_id: &str) -> String {
+        format!("{}\u{1f}{}", tenant.map_or("", TenantId::as_str), user_id)
+    }
+}
+
+// Keeps a `FullTextIndex` in step with a manager's writes, re-reading updated users
+// through the manager so the index sees the same state as its cache. Writes are committed
+// in batches, off the async runtime, so searches trail them by up to `COMMIT_INTERVAL`.
+#[derive(Debug)]
+struct FullTextIndexer {
+    index: Arc<FullTextIndex>,
+    manager: std::sync::Weak<UserManager>,
+    uncommitted: Arc<AtomicUsize>,
+}
+
+impl FullTextIndexer {
+    const COMMIT_INTERVAL: Duration = Duration::from_millis(250);
+    const COMMIT_BATCH: usize = 1000;
+
+    /// Commit on a blocking thread if any writes are pending
+    async fn commit(index: &Arc<FullTextIndex>, uncommitted: &AtomicUsize) -> Result<()> {
+        if uncommitted.swap(0, Ordering::AcqRel) == 0 {
+            return Ok(());
+        }
+        let index = index.clone();
+        tokio::task::spawn_blocking(move || index.commit())
+            .await
+            .context("Index commit task panicked")?
+    }
+}
+
+#[async_trait]
+impl EventSink for FullTextIndexer {
+    fn name(&self) -> &'static str {
+        "full_text"
+    }
+
+    async fn send(&self, event: &UserEvent) -> Result<()> {
+        let tenant = event.tenant();
+        match event {
+            UserEvent::UserCreated { user, .. } => self.index.upsert(tenant, user)?,
+            UserEvent::UserUpdated { user_id, .. } => {
+                let manager = self.manager.upgrade().context("User manager was dropped")?;
+                let current = match tenant {
+                    Some(tenant) => {
+                        manager
+                            .for_tenant(tenant.clone())
+                            .fetch_user(user_id)
+                            .await?
+                    }
+                    None => manager.fetch_user(user_id).await?,
+                };
+                match current {
+                    Some(user) => self.index.upsert(tenant, &user)?,
+                    None => self.index.remove(tenant, user_id),
+                }
+            }
+            UserEvent::UserDeleted { user_id, .. } | UserEvent::UserErased { user_id, .. } => {
+                self.index.remove(tenant, user_id)
+            }
+            _ => return Ok(()),
+        }
+        if self.uncommitted.fetch_add(1, Ordering::AcqRel) + 1 >= Self::COMMIT_BATCH {
+            Self::commit(&self.index, &self.uncommitted).await?;
+        }
+        Ok(())
+    }
+}
+
+impl UserManager {
+    /// Keep `index` updated from this manager's events until shutdown; pair with
+    /// `index_all_users` to load users written before it started
+    pub fn start_full_text_index(self: &Arc<Self>, index: Arc<FullTextIndex>) -> Result<()> {
+        let uncommitted = Arc::new(AtomicUsize::new(0));
+        self.start_event_sink(Arc::new(FullTextIndexer {
+            index: index.clone(),
+            manager: Arc::downgrade(self),
+            uncommitted: uncommitted.clone(),
+        }))?;
+        self.spawn_background(move |mut shutdown| async move {
+            let mut ticks = tokio::time::interval(FullTextIndexer::COMMIT_INTERVAL);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                let stopping = tokio::select! {
+                    _ = ticks.tick() => false,
+                    _ = shutdown.changed() => true,
+                };
+                if let Err(e) = FullTextIndexer::commit(&index, &uncommitted).await {
+                    tracing::warn!(error = redact_error(&e), "Failed to commit full-text index");
+                }
+                if stopping {
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Index every user of this manager's tenant, returning how many were indexed
+    pub async fn index_all_users(&self, index: &FullTextIndex) -> Result<usize> {
+        const PAGE_SIZE: usize = 500;
+
+        let mut indexed = 0;
+        loop {
+            let page = self.list_users(indexed, PAGE_SIZE).await?;
+            for user in &page {
+                index.upsert(self.tenant(), user)?;
+            }
+            indexed += page.len();
+            if page.len() < PAGE_SIZE {
+                break;
+            }
+        }
+        index.commit()?;
+        Ok(indexed)
+    }
+
+    /// Users of this manager's tenant matching `query`, most relevant first
+    pub async fn search_users_full_text(
+        &self,
+        index: &FullTextIndex,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        let ids: Vec<String> = index
+            .search(self.tenant(), query, limit)?
+            .into_iter()
+            .map(|hit| hit.user_id)
+            .collect();
+        let mut found = self.batch_fetch_users(&ids).await;
+        // A hit can outlive its user briefly, until the indexer sees the delete
+        Ok(ids
+            .iter()
+            .filter_map(|id| found.remove(id).flatten())
+            .collect())
+    }
+}
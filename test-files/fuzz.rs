@@ -0,0 +1,46 @@
+use super::*;
+
+/// Parsed users must re-serialize and round-trip, and feed statistics without overflow
+pub fn create_user_from_json(data: &[u8]) {
+    let Ok(json) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(user) = UserManager::create_user_from_json(json) {
+        let encoded = serde_json::to_string(&user).expect("parsed user re-serializes");
+        let decoded = UserManager::create_user_from_json(&encoded).expect("serialized user parses");
+        assert_eq!(decoded, user);
+        let _ = user.validate();
+        let _ = UserManager::get_user_statistics(std::slice::from_ref(&user));
+    }
+}
+
+pub fn import_ndjson(data: &[u8]) {
+    let input = String::from_utf8_lossy(data);
+    let report = UserManager::import_users_ndjson(&input);
+    let lines = input.lines().filter(|line| !line.trim().is_empty()).count();
+    assert_eq!(report.users.len() + report.errors.len(), lines);
+    let _ = UserManager::get_user_statistics(&report.users);
+    let _ = UserManager::export_users_json(&report.users);
+}
+
+pub fn api_response(data: &[u8]) {
+    if let Ok(response) = serde_json::from_slice::<ApiResponse<User>>(data) {
+        let _ = serde_json::to_vec(&response).expect("parsed response re-serializes");
+    }
+    if let Ok(response) = serde_json::from_slice::<ApiResponse<Vec<User>>>(data) {
+        let _ = UserManager::get_user_statistics(response.data.as_deref().unwrap_or_default());
+    }
+    let _ = serde_json::from_slice::<ApiResponse<ChangeSet>>(data);
+    if let Ok(response) = serde_json::from_slice::<ApiResponse<User, ApiErrorBody>>(data) {
+        let _ = HttpUserRepository::into_data(response);
+    }
+}
+
+/// Parsed filters must print a text form that parses back to the same filter
+pub fn filter_expression(data: &[u8]) {
+    let input = String::from_utf8_lossy(data);
+    if let Ok(filter) = input.parse::<Filter>() {
+        let reparsed: Filter = filter.to_string().parse().expect("printed filter parses");
+        assert_eq!(reparsed, filter);
+    }
+}
@@ -0,0 +1,60 @@
+use super::*;
+use std::path::Path;
+
+/// Set to rewrite golden files from the current output instead of comparing
+pub const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+/// Pretty JSON with object keys sorted at every level and a trailing newline
+pub fn canonical_json<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    let value = serde_json::to_value(value).context("Failed to serialize golden value")?;
+    let mut json = serde_json::to_string_pretty(&canonical_value(value))
+        .context("Failed to serialize golden value")?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Golden files live next to this source file, under `golden/`. `file!()` is relative
+/// to the workspace root, so it is anchored at the manifest directory to stay
+/// independent of the test's working directory.
+pub fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(Path::new(file!()))
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("golden")
+        .join(name)
+}
+
+/// Compare `actual` with the golden file `name`, or rewrite it when `UPDATE_GOLDEN` is set
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = golden_path(name);
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).expect("create golden directory");
+        }
+        std::fs::write(&path, actual).expect("write golden file");
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "missing golden file {} ({}); rerun with {}=1 to create it",
+            path.display(),
+            e,
+            UPDATE_ENV
+        )
+    });
+    assert!(
+        expected == actual,
+        "{} differs from the golden file; rerun with {}=1 if the change is intended\n--- expected\n{}\n--- actual\n{}",
+        path.display(),
+        UPDATE_ENV,
+        expected,
+        actual
+    );
+}
+
+/// `assert_golden` over the canonical JSON form of `value`
+pub fn assert_json_golden<T: Serialize + ?Sized>(name: &str, value: &T) {
+    let json = canonical_json(value).expect("serialize golden value");
+    assert_golden(name, &json);
+}
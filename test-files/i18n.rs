@@ -0,0 +1,159 @@
+use super::*;
+
+/// Languages with message tables; unknown tags fall back to English
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+}
+
+impl Locale {
+    /// Parse a tag such as `de`, `es-MX` or `fr_FR.UTF-8` by its language part
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let language = tag
+            .split(['-', '_', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::En),
+            "de" => Some(Locale::De),
+            "es" => Some(Locale::Es),
+            "fr" => Some(Locale::Fr),
+            _ => None,
+        }
+    }
+
+    /// The first of `LC_ALL`, `LC_MESSAGES` and `LANG` that names a known language
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find_map(|tag| Self::from_tag(&tag))
+            .unwrap_or_default()
+    }
+}
+
+/// Render a value in the caller's locale; `Display` stays English
+pub trait Localize {
+    fn localize(&self, locale: Locale) -> String;
+}
+
+// How long a user has been around, as reported by `get_age_category`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgeCategory {
+    New,
+    Regular,
+    Veteran,
+}
+
+impl AgeCategory {
+    pub fn from_days(days: i64) -> Self {
+        match days {
+            0..=30 => AgeCategory::New,
+            31..=365 => AgeCategory::Regular,
+            _ => AgeCategory::Veteran,
+        }
+    }
+}
+
+impl fmt::Display for AgeCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localize(Locale::En))
+    }
+}
+
+impl Localize for AgeCategory {
+    fn localize(&self, locale: Locale) -> String {
+        let text = match (locale, self) {
+            (Locale::En, AgeCategory::New) => "New",
+            (Locale::En, AgeCategory::Regular) => "Regular",
+            (Locale::En, AgeCategory::Veteran) => "Veteran",
+            (Locale::De, AgeCategory::New) => "Neu",
+            (Locale::De, AgeCategory::Regular) => "Regelmäßig",
+            (Locale::De, AgeCategory::Veteran) => "Veteran",
+            (Locale::Es, AgeCategory::New) => "Nuevo",
+            (Locale::Es, AgeCategory::Regular) => "Habitual",
+            (Locale::Es, AgeCategory::Veteran) => "Veterano",
+            (Locale::Fr, AgeCategory::New) => "Nouveau",
+            (Locale::Fr, AgeCategory::Regular) => "Habitué",
+            (Locale::Fr, AgeCategory::Veteran) => "Vétéran",
+        };
+        text.to_string()
+    }
+}
+
+impl Localize for UserStatus {
+    fn localize(&self, locale: Locale) -> String {
+        let text = match (locale, self) {
+            (Locale::En, _) => return self.to_string(),
+            (Locale::De, UserStatus::Active) => "Aktiv",
+            (Locale::De, UserStatus::Inactive) => "Inaktiv",
+            (Locale::De, UserStatus::Pending) => "Ausstehend",
+            (Locale::De, UserStatus::Suspended) => "Gesperrt",
+            (Locale::Es, UserStatus::Active) => "Activo",
+            (Locale::Es, UserStatus::Inactive) => "Inactivo",
+            (Locale::Es, UserStatus::Pending) => "Pendiente",
+            (Locale::Es, UserStatus::Suspended) => "Suspendido",
+            (Locale::Fr, UserStatus::Active) => "Actif",
+            (Locale::Fr, UserStatus::Inactive) => "Inactif",
+            (Locale::Fr, UserStatus::Pending) => "En attente",
+            (Locale::Fr, UserStatus::Suspended) => "Suspendu",
+        };
+        text.to_string()
+    }
+}
+
+impl Localize for UserError {
+    /// Validation errors are translated; the rest keep their English message
+    fn localize(&self, locale: Locale) -> String {
+        match (locale, self) {
+            (Locale::En, _) => self.to_string(),
+            (_, UserError::EmptyField { field }) => {
+                let field = field_name(locale, field);
+                match locale {
+                    Locale::De => format!("Das Feld {} darf nicht leer sein", field),
+                    Locale::Es => format!("El campo {} no puede estar vacío", field),
+                    _ => format!("Le champ {} ne peut pas être vide", field),
+                }
+            }
+            (_, UserError::InvalidEmail { email }) => {
+                let email = redact(email);
+                match locale {
+                    Locale::De => format!("Ungültiges E-Mail-Format: {}", email),
+                    Locale::Es => format!("Formato de correo no válido: {}", email),
+                    _ => format!("Format d'e-mail invalide : {}", email),
+                }
+            }
+            (_, UserError::InvalidUpdate { field, message }) => {
+                let field = field_name(locale, field);
+                match locale {
+                    Locale::De => format!("Ungültige Änderung an {}: {}", field, message),
+                    Locale::Es => format!("Cambio no válido en {}: {}", field, message),
+                    _ => format!("Modification invalide de {} : {}", field, message),
+                }
+            }
+            _ => self.to_string(),
+        }
+    }
+}
+
+fn field_name(locale: Locale, field: &str) -> String {
+    let text = match (locale, field) {
+        (Locale::De, "name") => "Name",
+        (Locale::De, "email") => "E-Mail",
+        (Locale::De, "status") => "Status",
+        (Locale::Es, "name") => "nombre",
+        (Locale::Es, "email") => "correo",
+        (Locale::Es, "status") => "estado",
+        (Locale::Fr, "name") => "nom",
+        (Locale::Fr, "email") => "e-mail",
+        (Locale::Fr, "status") => "statut",
+        (_, field) => field,
+    };
+    text.to_string()
+}
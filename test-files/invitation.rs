@@ -0,0 +1,427 @@
+use super::*;
+use crate::signing::{open_token, seal_token};
+
+// What an invite token vouches for, signed into the token itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvitationClaims {
+    pub user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    /// Changes on every resend, so only the latest token is accepted
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+// An outstanding invitation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingInvitation {
+    pub user_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// 1 for the first send, incremented by each resend
+    pub sends: u32,
+    #[serde(skip)]
+    nonce: String,
+}
+
+impl PendingInvitation {
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        now >= self.expires_at
+    }
+
+    fn key(&self) -> UserKey {
+        (self.tenant.clone(), self.user_id.clone())
+    }
+}
+
+// Where pending invitations are kept, one per tenant and user
+pub trait InvitationStore: fmt::Debug + Send + Sync {
+    fn get(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<Option<PendingInvitation>>;
+
+    /// Store `invitation`, replacing the user's earlier one
+    fn put(&self, invitation: &PendingInvitation) -> Result<()>;
+
+    /// Drop the user's invitation, returning whether there was one
+    fn remove(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<bool>;
+
+    /// Every pending invitation of `tenant`
+    fn list(&self, tenant: Option<&TenantId>) -> Result<Vec<PendingInvitation>>;
+}
+
+/// Keeps invitations in memory, so a restart forgets them; see `FileInvitationStore`
+#[derive(Debug, Default)]
+pub struct InMemoryInvitationStore {
+    pending: std::sync::Mutex<HashMap<UserKey, PendingInvitation>>,
+}
+
+impl InMemoryInvitationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl InvitationStore for InMemoryInvitationStore {
+    fn get(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<Option<PendingInvitation>> {
+        let pending = self.pending.lock().expect("invitations poisoned");
+        Ok(pending
+            .get(&(tenant.cloned(), user_id.to_string()))
+            .cloned())
+    }
+
+    fn put(&self, invitation: &PendingInvitation) -> Result<()> {
+        let mut pending = self.pending.lock().expect("invitations poisoned");
+        pending.insert(invitation.key(), invitation.clone());
+        Ok(())
+    }
+
+    fn remove(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<bool> {
+        let mut pending = self.pending.lock().expect("invitations poisoned");
+        Ok(pending
+            .remove(&(tenant.cloned(), user_id.to_string()))
+            .is_some())
+    }
+
+    fn list(&self, tenant: Option<&TenantId>) -> Result<Vec<PendingInvitation>> {
+        let pending = self.pending.lock().expect("invitations poisoned");
+        Ok(pending
+            .values()
+            .filter(|invitation| invitation.tenant.as_ref() == tenant)
+            .cloned()
+            .collect())
+    }
+}
+
+/// Keeps invitations as JSON lines in a file, rewritten on every change, so tokens sent
+/// before a restart can still be accepted after it
+#[derive(Debug)]
+pub struct FileInvitationStore {
+    path: PathBuf,
+    memory: InMemoryInvitationStore,
+}
+
+// One line of a `FileInvitationStore`; unlike the API view it keeps the nonce
+#[derive(Debug, Serialize, Deserialize)]
+struct InvitationRecord {
+    user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<TenantId>,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    sends: u32,
+    nonce: String,
+}
+
+impl FileInvitationStore {
+    /// Open the store at `path`, reloading anything left from a previous run
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read invitations {}", path.display()))
+            }
+        };
+        let memory = InMemoryInvitationStore::new();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let record: InvitationRecord =
+                serde_json::from_str(line).context("Corrupt invitation entry")?;
+            memory.put(&PendingInvitation {
+                user_id: record.user_id,
+                tenant: record.tenant,
+                issued_at: record.issued_at,
+                expires_at: record.expires_at,
+                sends: record.sends,
+                nonce: record.nonce,
+            })?;
+        }
+        Ok(Self { path, memory })
+    }
+
+    /// Rewrite the file from `pending`, which the caller holds locked
+    fn persist(&self, pending: &HashMap<UserKey, PendingInvitation>) -> Result<()> {
+        let mut contents = String::new();
+        for invitation in pending.values() {
+            let record = InvitationRecord {
+                user_id: invitation.user_id.clone(),
+                tenant: invitation.tenant.clone(),
+                issued_at: invitation.issued_at,
+                expires_at: invitation.expires_at,
+                sends: invitation.sends,
+                nonce: invitation.nonce.clone(),
+            };
+            contents.push_str(
+                &serde_json::to_string(&record).context("Failed to serialize invitation")?,
+            );
+            contents.push('\n');
+        }
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, contents)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .with_context(|| format!("Failed to persist invitations {}", self.path.display()))
+    }
+}
+
+impl InvitationStore for FileInvitationStore {
+    fn get(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<Option<PendingInvitation>> {
+        self.memory.get(tenant, user_id)
+    }
+
+    fn put(&self, invitation: &PendingInvitation) -> Result<()> {
+        let mut pending = self.memory.pending.lock().expect("invitations poisoned");
+        let previous = pending.insert(invitation.key(), invitation.clone());
+        if let Err(e) = self.persist(&pending) {
+            match previous {
+                Some(previous) => pending.insert(invitation.key(), previous),
+                None => pending.remove(&invitation.key()),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<bool> {
+        let mut pending = self.memory.pending.lock().expect("invitations poisoned");
+        let key = (tenant.cloned(), user_id.to_string());
+        let Some(removed) = pending.remove(&key) else {
+            return Ok(false);
+        };
+        if let Err(e) = self.persist(&pending) {
+            pending.insert(key, removed);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    fn list(&self, tenant: Option<&TenantId>) -> Result<Vec<PendingInvitation>> {
+        self.memory.list(tenant)
+    }
+}
+
+// A sent invitation, with the token to deliver to the invitee
+#[derive(Debug, Clone)]
+pub struct Invitation {
+    pub user: User,
+    pub token: Secret<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Issues and checks invite tokens, signed like `CursorCodec` tokens. Each invited user
+/// has one live token: a resend replaces it, and accepting, expiry or deleting the user
+/// ends it. Live tokens are tracked in an `InvitationStore`, in memory by default.
+#[derive(Debug)]
+pub struct Invitations {
+    secret: Secret<Vec<u8>>,
+    ttl: chrono::Duration,
+    store: Arc<dyn InvitationStore>,
+}
+
+impl Invitations {
+    /// The `purpose` claim of its tokens
+    const TOKEN_PURPOSE: &'static str = "invitation";
+    pub const DEFAULT_TTL_DAYS: i64 = 7;
+
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: Secret::new(secret.into()),
+            ttl: chrono::Duration::days(Self::DEFAULT_TTL_DAYS),
+            store: Arc::new(InMemoryInvitationStore::new()),
+        }
+    }
+
+    /// Track live tokens in `store`, such as a `FileInvitationStore` that survives restarts
+    pub fn with_store(mut self, store: Arc<dyn InvitationStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// How long an invitation can be accepted after it is sent
+    pub fn with_ttl(mut self, ttl: chrono::Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Issue a token for `user_id`, replacing any earlier one
+    pub fn issue_at(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(Secret<String>, PendingInvitation)> {
+        let claims = InvitationClaims {
+            user_id: user_id.to_string(),
+            tenant: tenant.cloned(),
+            nonce: uuid::Uuid::new_v4().simple().to_string(),
+            issued_at: now,
+            expires_at: now + self.ttl,
+        };
+        let token = Secret::new(seal_token(
+            self.secret.expose(),
+            Self::TOKEN_PURPOSE,
+            &claims,
+        ));
+        let sends = self
+            .store
+            .get(tenant, user_id)?
+            .map_or(0, |invitation| invitation.sends)
+            + 1;
+        let invitation = PendingInvitation {
+            user_id: claims.user_id,
+            tenant: claims.tenant,
+            issued_at: claims.issued_at,
+            expires_at: claims.expires_at,
+            sends,
+            nonce: claims.nonce,
+        };
+        self.store.put(&invitation)?;
+        Ok((token, invitation))
+    }
+
+    /// The claims of the user's live, unexpired token
+    pub fn verify_at(&self, token: &str, now: DateTime<Utc>) -> Result<InvitationClaims> {
+        let claims: InvitationClaims = open_token(self.secret.expose(), Self::TOKEN_PURPOSE, token)
+            .map_err(|fault| invalid(format!("token {}", fault)))?;
+        if now >= claims.expires_at {
+            return Err(invalid("invitation has expired"));
+        }
+        match self.store.get(claims.tenant.as_ref(), &claims.user_id)? {
+            Some(invitation) if invitation.nonce == claims.nonce => Ok(claims),
+            Some(_) => Err(invalid("invitation was replaced by a newer one")),
+            None => Err(invalid("invitation is no longer pending")),
+        }
+    }
+
+    pub fn pending(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+    ) -> Result<Option<PendingInvitation>> {
+        self.store.get(tenant, user_id)
+    }
+
+    /// Invitations that had expired by `now`, for resending or cleaning up
+    pub fn expired_at(
+        &self,
+        tenant: Option<&TenantId>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<PendingInvitation>> {
+        let mut expired: Vec<PendingInvitation> = self
+            .store
+            .list(tenant)?
+            .into_iter()
+            .filter(|invitation| invitation.is_expired_at(now))
+            .collect();
+        expired.sort_by(|a, b| {
+            a.expires_at
+                .cmp(&b.expires_at)
+                .then_with(|| a.user_id.cmp(&b.user_id))
+        });
+        Ok(expired)
+    }
+
+    /// End a user's invitation, returning whether one was pending
+    pub fn remove(&self, tenant: Option<&TenantId>, user_id: &str) -> Result<bool> {
+        self.store.remove(tenant, user_id)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> anyhow::Error {
+    UserError::InvalidInvitation {
+        message: message.into(),
+    }
+    .into()
+}
+
+impl UserManager {
+    fn require_invitations(&self) -> Result<&Arc<Invitations>> {
+        self.invitations().ok_or_else(|| {
+            UserError::InvalidConfig {
+                field: "invitations".to_string(),
+                message: "no invitations configured".to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Create `user` as `Pending` and issue an invite token for them
+    pub async fn invite_user(&self, user: &User) -> Result<Invitation> {
+        let invitations = self.require_invitations()?;
+        let user = self
+            .create_user(&user.clone().with_status(UserStatus::Pending))
+            .await?;
+        let (token, invitation) = invitations.issue_at(self.tenant(), &user.id, Utc::now())?;
+        tracing::info!(user_id = %redact_id(&user.id), "User invited");
+        Ok(Invitation {
+            user,
+            token,
+            expires_at: invitation.expires_at,
+        })
+    }
+
+    /// Issue a fresh token to a still-pending invitee, expired or not; earlier tokens stop working
+    pub async fn resend_invitation(&self, user_id: &str) -> Result<Invitation> {
+        let invitations = self.require_invitations()?;
+        let user = self
+            .fetch_user(user_id)
+            .await?
+            .ok_or_else(|| UserError::NotFound {
+                id: user_id.to_string(),
+            })?;
+        if user.status != UserStatus::Pending {
+            return Err(invalid(format!("user is {}, not pending", user.status)));
+        }
+        let (token, invitation) = invitations.issue_at(self.tenant(), user_id, Utc::now())?;
+        tracing::info!(
+            user_id = %redact_id(user_id),
+            sends = invitation.sends,
+            "Invitation resent"
+        );
+        Ok(Invitation {
+            user,
+            token,
+            expires_at: invitation.expires_at,
+        })
+    }
+
+    /// Accept an invitation, activating the pending user
+    pub async fn accept_invitation(&self, token: &str) -> Result<User> {
+        let invitations = self.require_invitations()?;
+        let claims = invitations.verify_at(token, Utc::now())?;
+        if claims.tenant.as_ref() != self.tenant() {
+            return Err(invalid("invitation belongs to another tenant"));
+        }
+        let user = self
+            .fetch_user(&claims.user_id)
+            .await?
+            .ok_or_else(|| UserError::NotFound {
+                id: claims.user_id.clone(),
+            })?;
+        if user.status != UserStatus::Pending {
+            invitations.remove(self.tenant(), &claims.user_id)?;
+            return Err(invalid(format!("user is {}, not pending", user.status)));
+        }
+        let activated = self
+            .update_user(
+                &claims.user_id,
+                UserUpdate::new().status(UserStatus::Active),
+            )
+            .await?;
+        // Deleted since the fetch; the invitation stays until it is cleaned up
+        if !activated {
+            return Err(UserError::NotFound { id: claims.user_id }.into());
+        }
+        invitations.remove(self.tenant(), &claims.user_id)?;
+        tracing::info!(user_id = %redact_id(&claims.user_id), "Invitation accepted");
+        Ok(user.with_status(UserStatus::Active))
+    }
+
+    /// Invitations of this manager's tenant that have expired unaccepted
+    pub fn expired_invitations(&self) -> Result<Vec<PendingInvitation>> {
+        self.require_invitations()?
+            .expired_at(self.tenant(), Utc::now())
+    }
+}
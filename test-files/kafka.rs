@@ -0,0 +1,177 @@
+use super::*;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+
+// How much the producer does to get each event to the brokers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryGuarantee {
+    /// `acks=0` without retries; events can be lost but never repeat
+    AtMostOnce,
+    /// `acks=all` with retries; a retried send can repeat or reorder an event
+    #[default]
+    AtLeastOnce,
+    /// Idempotent producer: `acks=all` and retries without duplicates, keeping each
+    /// user's events in order
+    Idempotent,
+}
+
+impl DeliveryGuarantee {
+    fn properties(self) -> [(&'static str, &'static str); 3] {
+        match self {
+            DeliveryGuarantee::AtMostOnce => [
+                ("acks", "0"),
+                ("enable.idempotence", "false"),
+                ("message.send.max.retries", "0"),
+            ],
+            DeliveryGuarantee::AtLeastOnce => [
+                ("acks", "all"),
+                ("enable.idempotence", "false"),
+                ("message.send.max.retries", "2147483647"),
+            ],
+            DeliveryGuarantee::Idempotent => [
+                ("acks", "all"),
+                ("enable.idempotence", "true"),
+                ("message.send.max.retries", "2147483647"),
+            ],
+        }
+    }
+}
+
+// Settings for a `KafkaEventSink`
+#[derive(Debug, Clone)]
+pub struct KafkaEventSinkBuilder {
+    brokers: String,
+    topic: String,
+    guarantee: DeliveryGuarantee,
+    delivery_timeout: Duration,
+    properties: Vec<(String, String)>,
+}
+
+impl KafkaEventSinkBuilder {
+    pub fn with_guarantee(mut self, guarantee: DeliveryGuarantee) -> Self {
+        self.guarantee = guarantee;
+        self
+    }
+
+    /// How long librdkafka keeps retrying an event before reporting it failed
+    pub fn with_delivery_timeout(mut self, timeout: Duration) -> Self {
+        self.delivery_timeout = timeout;
+        self
+    }
+
+    /// Set any librdkafka producer property, e.g. `security.protocol`; applied last
+    pub fn with_property(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.properties.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn build(self) -> Result<KafkaEventSink> {
+        if self.topic.is_empty() {
+            return Err(UserError::InvalidConfig {
+                field: "kafka.topic".to_string(),
+                message: "topic cannot be empty".to_string(),
+            }
+            .into());
+        }
+        let mut config = rdkafka::ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers).set(
+            "message.timeout.ms",
+            self.delivery_timeout.as_millis().max(1).to_string(),
+        );
+        for (key, value) in self.guarantee.properties() {
+            config.set(key, value);
+        }
+        for (key, value) in &self.properties {
+            config.set(key, value);
+        }
+        let producer: FutureProducer = config.create().map_err(|e| UserError::InvalidConfig {
+            field: "kafka".to_string(),
+            message: e.to_string(),
+        })?;
+        Ok(KafkaEventSink {
+            producer,
+            topic: self.topic,
+            guarantee: self.guarantee,
+            delivery_timeout: self.delivery_timeout,
+        })
+    }
+}
+
+// Produces user mutation events to a Kafka topic for the data platform. Records are keyed
+// by user id, so the default partitioner keeps each user's events on one partition; the
+// value is the versioned `UserEvent::to_stored` JSON.
+#[derive(Clone)]
+pub struct KafkaEventSink {
+    producer: FutureProducer,
+    topic: String,
+    guarantee: DeliveryGuarantee,
+    delivery_timeout: Duration,
+}
+
+impl fmt::Debug for KafkaEventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaEventSink")
+            .field("topic", &self.topic)
+            .field("guarantee", &self.guarantee)
+            .field("delivery_timeout", &self.delivery_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KafkaEventSink {
+    pub const EVENT_HEADER: &'static str = "event-type";
+    pub const TENANT_HEADER: &'static str = "tenant";
+    pub const DEFAULT_DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Sink for `topic` on the comma-separated `brokers`
+    pub fn builder(brokers: impl Into<String>, topic: impl Into<String>) -> KafkaEventSinkBuilder {
+        KafkaEventSinkBuilder {
+            brokers: brokers.into(),
+            topic: topic.into(),
+            guarantee: DeliveryGuarantee::default(),
+            delivery_timeout: Self::DEFAULT_DELIVERY_TIMEOUT,
+            properties: Vec::new(),
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn guarantee(&self) -> DeliveryGuarantee {
+        self.guarantee
+    }
+}
+
+#[async_trait]
+impl EventSink for KafkaEventSink {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    /// Produce one event and wait for the brokers to acknowledge it as the guarantee
+    /// requires
+    async fn send(&self, event: &UserEvent) -> Result<()> {
+        let payload = event.to_stored()?.to_string();
+        let mut headers = OwnedHeaders::new().insert(Header {
+            key: Self::EVENT_HEADER,
+            value: Some(event.kind()),
+        });
+        if let Some(tenant) = event.tenant() {
+            headers = headers.insert(Header {
+                key: Self::TENANT_HEADER,
+                value: Some(tenant.as_str()),
+            });
+        }
+        let record = FutureRecord::to(&self.topic)
+            .key(event.user_id())
+            .payload(&payload)
+            .headers(headers);
+        self.producer
+            .send(record, self.delivery_timeout)
+            .await
+            .map(|_| ())
+            .map_err(|(e, _)| anyhow::Error::new(e).context("Failed to produce event to Kafka"))
+    }
+}
@@ -0,0 +1,624 @@
+use super::*;
+use futures::future::BoxFuture;
+use std::collections::BTreeMap;
+
+type UserKey = (Option<TenantId>, String);
+
+// One repository operation as seen by a layer
+#[derive(Debug, Clone, Copy)]
+pub enum Call<'a> {
+    Get {
+        user_id: &'a str,
+    },
+    Create {
+        user: &'a User,
+    },
+    Update {
+        user_id: &'a str,
+        updates: &'a HashMap<String, serde_json::Value>,
+    },
+    /// The enrollments themselves stay out of sight of layers
+    SwapTwoFactor {
+        user_id: &'a str,
+    },
+    Delete {
+        user_id: &'a str,
+    },
+    List {
+        offset: usize,
+        limit: usize,
+    },
+    ListFiltered {
+        filter: &'a Filter,
+        offset: usize,
+        limit: usize,
+    },
+    ApplyBatch {
+        mutations: &'a [Mutation],
+    },
+    ChangesSince {
+        cursor: Option<&'a str>,
+        limit: usize,
+    },
+    ApplyIdempotent {
+        mutation: &'a Mutation,
+        idempotency_key: &'a str,
+    },
+    Ping,
+    NotifyErasure {
+        user_id: &'a str,
+    },
+}
+
+impl Call<'_> {
+    /// The `UserRepository` method being called
+    pub fn name(&self) -> &'static str {
+        match self {
+            Call::Get { .. } => "get",
+            Call::Create { .. } => "create",
+            Call::Update { .. } => "update",
+            Call::SwapTwoFactor { .. } => "swap_two_factor",
+            Call::Delete { .. } => "delete",
+            Call::List { .. } => "list",
+            Call::ListFiltered { .. } => "list_filtered",
+            Call::ApplyBatch { .. } => "apply_batch",
+            Call::ChangesSince { .. } => "changes_since",
+            Call::ApplyIdempotent { .. } => "apply_idempotent",
+            Call::Ping => "ping",
+            Call::NotifyErasure { .. } => "notify_erasure",
+        }
+    }
+
+    /// Whether repeating the call cannot change stored data twice
+    pub fn is_repeatable(&self) -> bool {
+        matches!(
+            self,
+            Call::Get { .. }
+                | Call::List { .. }
+                | Call::ListFiltered { .. }
+                | Call::ChangesSince { .. }
+                | Call::ApplyIdempotent { .. }
+                | Call::Ping
+        )
+    }
+
+    /// Permissions a caller needs for the call; empty for reads
+    pub fn permissions(&self) -> Vec<Permission> {
+        match self {
+            Call::Create { .. } => vec![Permission::Create],
+            Call::Update { .. } | Call::SwapTwoFactor { .. } => vec![Permission::Update],
+            Call::Delete { .. } => vec![Permission::Delete],
+            Call::NotifyErasure { .. } => vec![Permission::Erase],
+            Call::ApplyIdempotent { mutation, .. } => vec![Permission::for_mutation(mutation)],
+            Call::ApplyBatch { mutations } => {
+                let mut permissions: Vec<Permission> =
+                    mutations.iter().map(Permission::for_mutation).collect();
+                permissions.dedup();
+                permissions
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Users whose stored record the call may change
+    pub fn written_ids(&self) -> Vec<&str> {
+        match self {
+            Call::Create { user } => vec![user.id.as_str()],
+            Call::Update { user_id, .. }
+            | Call::SwapTwoFactor { user_id }
+            | Call::Delete { user_id }
+            | Call::NotifyErasure { user_id } => vec![user_id],
+            Call::ApplyIdempotent { mutation, .. } => vec![mutation.user_id()],
+            Call::ApplyBatch { mutations } => mutations.iter().map(Mutation::user_id).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// What a repository operation returned, one variant per return type
+#[derive(Debug, Clone)]
+pub enum Reply {
+    User(Option<User>),
+    Created(User),
+    Applied(bool),
+    Users(Vec<User>),
+    Batch(Vec<bool>),
+    Changes(ChangeSet),
+    Done,
+}
+
+type RunFn<'a> = dyn Fn() -> BoxFuture<'a, Result<Reply>> + Send + Sync + 'a;
+
+/// The rest of the stack below a layer; `run` may be called more than once, or not at all
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    run: &'a RunFn<'a>,
+}
+
+impl Next<'_> {
+    pub async fn run(&self) -> Result<Reply> {
+        (self.run)().await
+    }
+}
+
+// Middleware around every repository call, in the style of a tower layer. Stacked with
+// `UserManagerBuilder::layer` or `apply_layers`; the first layer added sees calls first.
+// Layers sit below the manager and add to its own retry policy, rate limiter, cache and
+// access policy rather than replacing them: with both configured, a call is retried at
+// each level, charged to both limiters and checked against both policies, and a user is
+// cached twice. Use a layer for what the manager does not already do.
+#[async_trait]
+pub trait Layer: fmt::Debug + Send + Sync {
+    /// Handle `call` for `tenant`, usually by doing something around `next.run()`
+    async fn call(
+        &self,
+        call: Call<'_>,
+        tenant: Option<&TenantId>,
+        next: Next<'_>,
+    ) -> Result<Reply>;
+
+    /// Name shown by the wrapped repository's `describe`
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+}
+
+/// Wrap `repository` in `layers`, the first outermost
+pub fn apply_layers(
+    repository: Arc<dyn UserRepository>,
+    layers: &[Arc<dyn Layer>],
+    tenant: Option<&TenantId>,
+) -> Arc<dyn UserRepository> {
+    layers.iter().rev().fold(repository, |inner, layer| {
+        Arc::new(LayeredRepository {
+            inner,
+            layer: layer.clone(),
+            tenant: tenant.cloned(),
+        })
+    })
+}
+
+// A repository with one layer in front of it
+#[derive(Debug)]
+pub struct LayeredRepository {
+    inner: Arc<dyn UserRepository>,
+    layer: Arc<dyn Layer>,
+    tenant: Option<TenantId>,
+}
+
+impl LayeredRepository {
+    async fn dispatch<'a>(&'a self, call: Call<'a>, run: &'a RunFn<'a>) -> Result<Reply> {
+        self.layer
+            .call(call, self.tenant.as_ref(), Next { run })
+            .await
+    }
+
+    fn mismatch(&self, call: &str, reply: &Reply) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Layer {} answered {} with {:?}",
+            self.layer.name(),
+            call,
+            reply
+        )
+    }
+}
+
+// Route a repository method through the layer and unwrap the reply it expects
+macro_rules! through_layer {
+    ($self:ident, $call:expr, $variant:ident, $body:expr) => {{
+        let run = || -> BoxFuture<'_, Result<Reply>> {
+            Box::pin(async move { $body.await.map(Reply::$variant) })
+        };
+        let call = $call;
+        match $self.dispatch(call, &run).await? {
+            Reply::$variant(value) => Ok(value),
+            other => Err($self.mismatch(call.name(), &other)),
+        }
+    }};
+}
+
+#[async_trait]
+impl UserRepository for LayeredRepository {
+    async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        through_layer!(self, Call::Get { user_id }, User, self.inner.get(user_id))
+    }
+
+    async fn create(&self, user: &User) -> Result<User> {
+        through_layer!(
+            self,
+            Call::Create { user },
+            Created,
+            self.inner.create(user)
+        )
+    }
+
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        through_layer!(
+            self,
+            Call::Update { user_id, updates },
+            Applied,
+            self.inner.update(user_id, updates)
+        )
+    }
+
+    async fn swap_two_factor(
+        &self,
+        user_id: &str,
+        expected: Option<&TwoFactor>,
+        replacement: Option<&TwoFactor>,
+    ) -> Result<bool> {
+        through_layer!(
+            self,
+            Call::SwapTwoFactor { user_id },
+            Applied,
+            self.inner.swap_two_factor(user_id, expected, replacement)
+        )
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool> {
+        through_layer!(
+            self,
+            Call::Delete { user_id },
+            Applied,
+            self.inner.delete(user_id)
+        )
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        through_layer!(
+            self,
+            Call::List { offset, limit },
+            Users,
+            self.inner.list(offset, limit)
+        )
+    }
+
+    async fn list_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        through_layer!(
+            self,
+            Call::ListFiltered {
+                filter,
+                offset,
+                limit
+            },
+            Users,
+            self.inner.list_filtered(filter, offset, limit)
+        )
+    }
+
+    async fn apply_batch(&self, mutations: &[Mutation]) -> Result<Vec<bool>> {
+        through_layer!(
+            self,
+            Call::ApplyBatch { mutations },
+            Batch,
+            self.inner.apply_batch(mutations)
+        )
+    }
+
+    fn supports_transactions(&self) -> bool {
+        self.inner.supports_transactions()
+    }
+
+    fn writes_outbox(&self) -> bool {
+        self.inner.writes_outbox()
+    }
+
+    async fn changes_since(&self, cursor: Option<&str>, limit: usize) -> Result<ChangeSet> {
+        through_layer!(
+            self,
+            Call::ChangesSince { cursor, limit },
+            Changes,
+            self.inner.changes_since(cursor, limit)
+        )
+    }
+
+    async fn apply_idempotent(&self, mutation: &Mutation, idempotency_key: &str) -> Result<bool> {
+        through_layer!(
+            self,
+            Call::ApplyIdempotent {
+                mutation,
+                idempotency_key
+            },
+            Applied,
+            self.inner.apply_idempotent(mutation, idempotency_key)
+        )
+    }
+
+    fn describe(&self) -> String {
+        format!("{}({})", self.layer.name(), self.inner.describe())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        let inner = &self.inner;
+        let run = || -> BoxFuture<'_, Result<Reply>> {
+            Box::pin(async move { inner.ping().await.map(|()| Reply::Done) })
+        };
+        match self.dispatch(Call::Ping, &run).await? {
+            Reply::Done => Ok(()),
+            other => Err(self.mismatch("ping", &other)),
+        }
+    }
+
+    async fn notify_erasure(&self, user_id: &str) -> Result<bool> {
+        through_layer!(
+            self,
+            Call::NotifyErasure { user_id },
+            Applied,
+            self.inner.notify_erasure(user_id)
+        )
+    }
+}
+
+// Retries repeatable calls while the backend is unreachable
+#[derive(Debug, Clone)]
+pub struct RetryLayer {
+    policy: RetryPolicy,
+}
+
+impl RetryLayer {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+#[async_trait]
+impl Layer for RetryLayer {
+    async fn call(
+        &self,
+        call: Call<'_>,
+        _tenant: Option<&TenantId>,
+        next: Next<'_>,
+    ) -> Result<Reply> {
+        let mut retry = 0;
+        loop {
+            match next.run().await {
+                Err(e)
+                    if call.is_repeatable()
+                        && retry < self.policy.max_retries
+                        && UserError::is_unreachable(&e) =>
+                {
+                    retry += 1;
+                    metrics::counter!(metric_names::RETRIES, "reason" => "layer").increment(1);
+                    tracing::debug!(retry, operation = call.name(), "Retrying repository call");
+                    tokio::time::sleep(self.policy.backoff(retry - 1)).await;
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+}
+
+// Takes every call from the tenant's budget in a `RateLimiter`
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+#[async_trait]
+impl Layer for RateLimitLayer {
+    async fn call(
+        &self,
+        _call: Call<'_>,
+        tenant: Option<&TenantId>,
+        next: Next<'_>,
+    ) -> Result<Reply> {
+        self.limiter.acquire(tenant)?;
+        next.run().await
+    }
+}
+
+// Read-through cache for `get`, dropping a user's entry once a call that may write it
+// completes. Full when `capacity` users are held, after which the entry stored longest ago
+// makes room.
+#[derive(Debug)]
+pub struct CacheLayer {
+    capacity: usize,
+    ttl: Option<Duration>,
+    entries: Mutex<CachedUsers>,
+}
+
+#[derive(Debug, Default)]
+struct CachedUsers {
+    users: HashMap<UserKey, (User, Instant, u64)>,
+    stored: BTreeMap<u64, UserKey>,
+    next_stamp: u64,
+    /// Bumped by every write, so a `get` that read before it does not store a stale copy
+    writes: u64,
+}
+
+impl CachedUsers {
+    fn remove(&mut self, key: &UserKey) {
+        if let Some((_, _, stamp)) = self.users.remove(key) {
+            self.stored.remove(&stamp);
+        }
+    }
+}
+
+impl CacheLayer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ttl: None,
+            entries: Mutex::new(CachedUsers::default()),
+        }
+    }
+
+    /// Re-read entries older than `ttl`
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .expect("layer cache poisoned")
+            .users
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cached user, or else the write count to pass to `store` after reading it
+    fn cached(&self, key: &UserKey) -> Result<User, u64> {
+        let entries = self.entries.lock().expect("layer cache poisoned");
+        match entries.users.get(key) {
+            Some((user, cached_at, _)) if self.ttl.is_none_or(|ttl| cached_at.elapsed() < ttl) => {
+                Ok(user.clone())
+            }
+            _ => Err(entries.writes),
+        }
+    }
+
+    /// Cache a user read when the write count was `writes`, unless a write has since ended
+    fn store(&self, key: UserKey, user: User, writes: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("layer cache poisoned");
+        if entries.writes != writes {
+            return;
+        }
+        entries.remove(&key);
+        while entries.users.len() >= self.capacity {
+            let Some((_, oldest)) = entries.stored.pop_first() else {
+                break;
+            };
+            entries.users.remove(&oldest);
+        }
+        let stamp = entries.next_stamp;
+        entries.next_stamp += 1;
+        entries.stored.insert(stamp, key.clone());
+        entries.users.insert(key, (user, Instant::now(), stamp));
+    }
+}
+
+#[async_trait]
+impl Layer for CacheLayer {
+    async fn call(
+        &self,
+        call: Call<'_>,
+        tenant: Option<&TenantId>,
+        next: Next<'_>,
+    ) -> Result<Reply> {
+        if let Call::Get { user_id } = call {
+            let key = (tenant.cloned(), user_id.to_string());
+            let writes = match self.cached(&key) {
+                Ok(user) => return Ok(Reply::User(Some(user))),
+                Err(writes) => writes,
+            };
+            let reply = next.run().await?;
+            if let Reply::User(Some(user)) = &reply {
+                self.store(key, user.clone(), writes);
+            }
+            return Ok(reply);
+        }
+        let written = call.written_ids();
+        if written.is_empty() {
+            return next.run().await;
+        }
+        // Dropped once the write is done, successful or not, so no read can cache what
+        // came before it
+        let outcome = next.run().await;
+        let mut entries = self.entries.lock().expect("layer cache poisoned");
+        entries.writes += 1;
+        for user_id in written {
+            entries.remove(&(tenant.cloned(), user_id.to_string()));
+        }
+        outcome
+    }
+}
+
+// Checks the current caller against an access policy before any call that writes
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    policy: Arc<AccessPolicy>,
+    caller: Option<Caller>,
+}
+
+impl AuthLayer {
+    pub fn new(policy: Arc<AccessPolicy>) -> Self {
+        Self {
+            policy,
+            caller: None,
+        }
+    }
+
+    /// Identity used outside any `as_caller` scope
+    pub fn with_caller(mut self, caller: Caller) -> Self {
+        self.caller = Some(caller);
+        self
+    }
+}
+
+#[async_trait]
+impl Layer for AuthLayer {
+    async fn call(
+        &self,
+        call: Call<'_>,
+        _tenant: Option<&TenantId>,
+        next: Next<'_>,
+    ) -> Result<Reply> {
+        let caller = current_caller().or_else(|| self.caller.clone());
+        for permission in call.permissions() {
+            let allowed = caller
+                .as_ref()
+                .is_some_and(|caller| self.policy.allows(caller, permission));
+            if !allowed {
+                return Err(UserError::Forbidden {
+                    caller: caller.map_or_else(|| "anonymous caller".to_string(), |c| c.id),
+                    permission: permission.to_string(),
+                }
+                .into());
+            }
+        }
+        next.run().await
+    }
+}
+
+// A debug span per repository call, with its duration and outcome
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingLayer;
+
+#[async_trait]
+impl Layer for TracingLayer {
+    async fn call(
+        &self,
+        call: Call<'_>,
+        tenant: Option<&TenantId>,
+        next: Next<'_>,
+    ) -> Result<Reply> {
+        use tracing::Instrument;
+
+        let span = tracing::debug_span!(
+            "repository_call",
+            operation = call.name(),
+            tenant = tenant.map(TenantId::as_str),
+            status = tracing::field::Empty,
+            duration_ms = tracing::field::Empty
+        );
+        let started = Instant::now();
+        let outcome = next.run().instrument(span.clone()).await;
+        span.record("status", if outcome.is_ok() { "ok" } else { "error" });
+        span.record("duration_ms", started.elapsed().as_millis() as u64);
+        outcome
+    }
+}
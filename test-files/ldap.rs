@@ -0,0 +1,429 @@
+use super::*;
+use ldap3::adapters::{Adapter, EntriesOnly, PagedResults};
+use ldap3::{LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use std::collections::BTreeMap;
+
+type Record = serde_json::Map<String, serde_json::Value>;
+
+// Which operational attribute marks an entry as changed since the last sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeAttribute {
+    /// Active Directory's `uSNChanged`; only comparable against the same domain controller
+    UsnChanged,
+    /// `modifyTimestamp`, in generalized time; supported by most directories
+    ModifyTimestamp,
+}
+
+impl ChangeAttribute {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeAttribute::UsnChanged => "uSNChanged",
+            ChangeAttribute::ModifyTimestamp => "modifyTimestamp",
+        }
+    }
+}
+
+// Highest change markers seen so far; persist between syncs to only fetch newer entries
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LdapWatermark {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usn_changed: Option<u64>,
+    /// Generalized time, as the directory returned it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modify_timestamp: Option<String>,
+}
+
+impl LdapWatermark {
+    /// Raise the markers to an entry's, if it is newer
+    pub fn observe(&mut self, attrs: &HashMap<String, Vec<String>>) {
+        if let Some(usn) = first_value(attrs, "uSNChanged").and_then(|usn| usn.parse().ok()) {
+            self.usn_changed = self.usn_changed.max(Some(usn));
+        }
+        if let Some(stamp) = first_value(attrs, "modifyTimestamp") {
+            let newer = match (&self.modify_timestamp, parse_generalized_time(stamp)) {
+                (None, _) => true,
+                (Some(current), Some(stamp)) => {
+                    parse_generalized_time(current).is_none_or(|current| stamp > current)
+                }
+                (Some(_), None) => false,
+            };
+            if newer {
+                self.modify_timestamp = Some(stamp.to_string());
+            }
+        }
+    }
+}
+
+/// Directory attributes to `User` fields; a dotted target like `metadata.department` nests.
+/// Attribute names match ignoring case, as LDAP's do. Multi-valued attributes keep their
+/// first value, except under `metadata` where they become arrays.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LdapAttributeMapping {
+    pub attributes: BTreeMap<String, String>,
+    /// Active Directory `userAccountControl`-style attribute; the disabled flag makes the user
+    /// `Inactive`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_control: Option<String>,
+}
+
+impl Default for LdapAttributeMapping {
+    /// `inetOrgPerson` entries, as in OpenLDAP
+    fn default() -> Self {
+        Self::empty()
+            .with_attribute("uid", "id")
+            .with_attribute("cn", "name")
+            .with_attribute("mail", "email")
+            .with_attribute("createTimestamp", "created_at")
+    }
+}
+
+impl LdapAttributeMapping {
+    const ACCOUNT_DISABLED: u32 = 0x2;
+
+    pub fn empty() -> Self {
+        Self {
+            attributes: BTreeMap::new(),
+            account_control: None,
+        }
+    }
+
+    /// Active Directory user objects
+    pub fn active_directory() -> Self {
+        Self {
+            account_control: Some("userAccountControl".to_string()),
+            ..Self::empty()
+                .with_attribute("sAMAccountName", "id")
+                .with_attribute("displayName", "name")
+                .with_attribute("mail", "email")
+                .with_attribute("whenCreated", "created_at")
+        }
+    }
+
+    pub fn with_attribute(
+        mut self,
+        attribute: impl Into<String>,
+        field: impl Into<String>,
+    ) -> Self {
+        self.attributes.insert(attribute.into(), field.into());
+        self
+    }
+
+    /// Attributes to request from the directory
+    pub fn requested(&self) -> Vec<String> {
+        self.attributes
+            .keys()
+            .chain(self.account_control.iter())
+            .cloned()
+            .collect()
+    }
+
+    /// The entry as a JSON user record, ready for an `ImportPipeline`. Directory times
+    /// become RFC 3339, and a missing status or creation time defaults to active and `now`.
+    pub fn record(
+        &self,
+        attrs: &HashMap<String, Vec<String>>,
+        now: DateTime<Utc>,
+    ) -> Result<Record> {
+        let mut record = Record::new();
+        for (attribute, field) in &self.attributes {
+            let Some(values) = values(attrs, attribute).filter(|values| !values.is_empty()) else {
+                continue;
+            };
+            let value = if field == "created_at" {
+                let created_at = parse_generalized_time(&values[0])
+                    .map_or_else(|| values[0].clone(), |at| at.to_rfc3339());
+                serde_json::Value::String(created_at)
+            } else if field.starts_with("metadata.") && values.len() > 1 {
+                serde_json::Value::from(values.clone())
+            } else {
+                serde_json::Value::String(values[0].clone())
+            };
+            insert_path(&mut record, field, value)?;
+        }
+        if let Some(control) = self
+            .account_control
+            .as_deref()
+            .and_then(|attribute| first_value(attrs, attribute))
+            .and_then(|control| control.parse::<u32>().ok())
+        {
+            let status = if control & Self::ACCOUNT_DISABLED != 0 {
+                UserStatus::Inactive
+            } else {
+                UserStatus::Active
+            };
+            record.insert("status".to_string(), serde_json::to_value(status)?);
+        }
+        record.entry("status").or_insert_with(|| {
+            serde_json::to_value(UserStatus::Active).expect("status serializes")
+        });
+        record
+            .entry("created_at")
+            .or_insert_with(|| serde_json::Value::String(now.to_rfc3339()));
+        record
+            .entry("metadata")
+            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+        Ok(record)
+    }
+}
+
+fn values<'a>(attrs: &'a HashMap<String, Vec<String>>, attribute: &str) -> Option<&'a Vec<String>> {
+    attrs
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+        .map(|(_, values)| values)
+}
+
+fn first_value<'a>(attrs: &'a HashMap<String, Vec<String>>, attribute: &str) -> Option<&'a str> {
+    values(attrs, attribute)
+        .and_then(|values| values.first())
+        .map(String::as_str)
+}
+
+/// `YYYYMMDDHHMMSS[.fraction]Z`, the form directories use for times
+pub fn parse_generalized_time(value: &str) -> Option<DateTime<Utc>> {
+    let seconds = value.get(..14)?;
+    if !value.ends_with('Z') {
+        return None;
+    }
+    chrono::NaiveDateTime::parse_from_str(seconds, "%Y%m%d%H%M%S")
+        .ok()
+        .map(|at| at.and_utc())
+}
+
+// Entries fetched by one search, with the watermark to store once they are imported
+#[derive(Debug, Clone)]
+pub struct LdapBatch {
+    pub records: Vec<Record>,
+    pub watermark: LdapWatermark,
+}
+
+/// A directory searched for users, page by page with the paged-results control. Given a
+/// watermark, only entries changed since it are fetched; entries deleted from the
+/// directory are not detected.
+#[derive(Debug, Clone)]
+pub struct LdapSource {
+    url: String,
+    bind: Option<(String, Secret<String>)>,
+    base_dn: String,
+    filter: String,
+    page_size: i32,
+    change_attribute: ChangeAttribute,
+    mapping: LdapAttributeMapping,
+    timeout: Duration,
+}
+
+impl LdapSource {
+    pub const DEFAULT_PAGE_SIZE: i32 = 500;
+    pub const DEFAULT_FILTER: &'static str = "(objectClass=person)";
+
+    /// Anonymous searches of `base_dn` at an `ldap://` or `ldaps://` URL
+    pub fn new(url: impl Into<String>, base_dn: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            bind: None,
+            base_dn: base_dn.into(),
+            filter: Self::DEFAULT_FILTER.to_string(),
+            page_size: Self::DEFAULT_PAGE_SIZE,
+            change_attribute: ChangeAttribute::ModifyTimestamp,
+            mapping: LdapAttributeMapping::default(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Simple bind as `dn` before searching
+    pub fn with_bind(mut self, dn: impl Into<String>, password: Secret<String>) -> Self {
+        self.bind = Some((dn.into(), password));
+        self
+    }
+
+    /// Which entries are users, e.g. `(&(objectClass=user)(!(objectClass=computer)))`
+    pub fn with_filter(mut self, filter: impl Into<String>) -> Self {
+        self.filter = filter.into();
+        self
+    }
+
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    pub fn with_change_attribute(mut self, attribute: ChangeAttribute) -> Self {
+        self.change_attribute = attribute;
+        self
+    }
+
+    pub fn with_mapping(mut self, mapping: LdapAttributeMapping) -> Self {
+        self.mapping = mapping;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn mapping(&self) -> &LdapAttributeMapping {
+        &self.mapping
+    }
+
+    /// The user filter, narrowed to entries changed after `watermark` when it has a marker
+    pub fn search_filter(&self, watermark: &LdapWatermark) -> String {
+        let filter = if self.filter.starts_with('(') {
+            self.filter.clone()
+        } else {
+            format!("({})", self.filter)
+        };
+        let since = match self.change_attribute {
+            ChangeAttribute::UsnChanged => watermark
+                .usn_changed
+                .map(|usn| format!("({}>={})", ChangeAttribute::UsnChanged.name(), usn + 1)),
+            // `>=` since the filter has no strict form; re-importing the boundary is harmless
+            ChangeAttribute::ModifyTimestamp => watermark
+                .modify_timestamp
+                .as_ref()
+                .map(|stamp| format!("(modifyTimestamp>={})", ldap3::ldap_escape(stamp.as_str()))),
+        };
+        match since {
+            Some(since) => format!("(&{}{})", filter, since),
+            None => filter,
+        }
+    }
+
+    /// Search for users changed since `watermark`, mapping each entry to a record
+    pub async fn fetch(&self, watermark: &LdapWatermark) -> Result<LdapBatch> {
+        let settings = LdapConnSettings::new().set_conn_timeout(self.timeout);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .with_context(|| format!("Failed to connect to {}", self.url))?;
+        ldap3::drive!(conn);
+        if let Some((dn, password)) = &self.bind {
+            ldap.simple_bind(dn, password.expose())
+                .await?
+                .success()
+                .context("LDAP bind failed")?;
+        }
+
+        let filter = self.search_filter(watermark);
+        let mut attributes = self.mapping.requested();
+        attributes.extend(["uSNChanged".to_string(), "modifyTimestamp".to_string()]);
+        let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(self.page_size)),
+        ];
+        let mut search = ldap
+            .streaming_search_with(adapters, &self.base_dn, Scope::Subtree, &filter, attributes)
+            .await
+            .context("LDAP search failed")?;
+        let now = Utc::now();
+        let mut batch = LdapBatch {
+            records: Vec::new(),
+            watermark: watermark.clone(),
+        };
+        while let Some(entry) = search.next().await? {
+            let entry = SearchEntry::construct(entry);
+            batch.watermark.observe(&entry.attrs);
+            batch.records.push(self.mapping.record(&entry.attrs, now)?);
+        }
+        search
+            .finish()
+            .await
+            .success()
+            .context("LDAP search failed")?;
+        ldap.unbind().await?;
+        tracing::debug!(entries = batch.records.len(), filter = %filter, "LDAP search complete");
+        Ok(batch)
+    }
+}
+
+// Outcome of one LDAP sync
+#[derive(Debug, Clone, Default)]
+pub struct LdapSyncReport {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Entries the pipeline rejected, by position in the search results
+    pub errors: Vec<ImportError>,
+    pub skipped: Vec<ImportError>,
+    /// Users that could not be stored, with the error
+    pub failed: BTreeMap<String, String>,
+    pub watermark: LdapWatermark,
+}
+
+impl UserManager {
+    /// Import users changed in the directory since `watermark` through `pipeline`, creating
+    /// new users and updating the name, email, status and metadata of existing ones. The
+    /// watermark only advances when every entry was stored, so failed ones are retried.
+    pub async fn sync_from_ldap(
+        &self,
+        source: &LdapSource,
+        pipeline: &ImportPipeline,
+        watermark: &mut LdapWatermark,
+    ) -> Result<LdapSyncReport> {
+        let batch = source.fetch(watermark).await?;
+        let import = pipeline.import_records(batch.records);
+        let mut report = LdapSyncReport {
+            errors: import.errors,
+            skipped: import.skipped,
+            ..Default::default()
+        };
+        for user in &import.users {
+            let stored = async {
+                let Some(existing) = self.fetch_user(&user.id).await? else {
+                    self.create_user(user).await?;
+                    report.created += 1;
+                    return Ok(());
+                };
+                let update = ldap_update(&existing, user);
+                if update.is_empty() {
+                    report.unchanged += 1;
+                } else if self.update_user(&user.id, update).await? {
+                    report.updated += 1;
+                } else {
+                    // Deleted since the fetch; nothing was stored
+                    return Err(UserError::NotFound {
+                        id: user.id.clone(),
+                    }
+                    .into());
+                }
+                Ok::<_, anyhow::Error>(())
+            }
+            .await;
+            if let Err(e) = stored {
+                report.failed.insert(user.id.clone(), redact_error(&e));
+            }
+        }
+        if report.errors.is_empty() && report.failed.is_empty() {
+            *watermark = batch.watermark;
+        }
+        report.watermark = watermark.clone();
+        tracing::info!(
+            created = report.created,
+            updated = report.updated,
+            unchanged = report.unchanged,
+            failed = report.errors.len() + report.failed.len(),
+            "LDAP sync complete"
+        );
+        Ok(report)
+    }
+}
+
+/// The directory-owned fields that differ; creation time and other local state are kept
+fn ldap_update(existing: &User, incoming: &User) -> UserUpdate {
+    let mut update = UserUpdate::new();
+    if existing.name != incoming.name {
+        update = update.name(incoming.name.clone());
+    }
+    if existing.email != incoming.email {
+        update = update.email(incoming.email.clone());
+    }
+    if existing.status != incoming.status {
+        update = update.status(incoming.status);
+    }
+    for (key, value) in &incoming.metadata {
+        if existing.metadata.get(key) != Some(value) {
+            update = update.set_metadata(key.to_string(), value.clone());
+        }
+    }
+    update
+}
@@ -0,0 +1,210 @@
+//! Procedural macros re-exported by the `users` crate. Expansions name `User`,
+//! `ApiResponse`, `IntoApiResponse` and `serde_json` unqualified, so callers need them in
+//! scope, as with `use users::*`.
+
+use proc_macro::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    braced, parse_macro_input, Expr, ExprLit, Ident, ItemFn, Lit, LitStr, ReturnType, Token,
+};
+
+/// `create_user!(id, name, email)`, optionally followed by a status or by named
+/// `status = ...` and `metadata = { "key" => value, ... }` arguments. Evaluates to
+/// `Result<User>`. A literal email is checked at compile time, with the error on the literal.
+#[proc_macro]
+pub fn create_user(input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(input as CreateUser);
+    if let Some(email) = &args.email_literal {
+        if !is_valid_email(&email.value()) {
+            return syn::Error::new(
+                email.span(),
+                format!(
+                    "invalid email literal {:?}: expected an address like user@example.com",
+                    email.value()
+                ),
+            )
+            .to_compile_error()
+            .into();
+        }
+    }
+
+    let CreateUser {
+        id,
+        name,
+        email,
+        status,
+        metadata,
+        ..
+    } = args;
+    let new = quote! {
+        User::new((#id).to_string(), (#name).to_string(), (#email).to_string())
+    };
+    if status.is_none() && metadata.is_empty() {
+        return new.into();
+    }
+    let status = status.map(|status| {
+        quote_spanned! {status.span()=> user = user.with_status(#status); }
+    });
+    let metadata = metadata.iter().map(|(key, value)| {
+        quote_spanned! {value.span()=> user.add_metadata(#key, serde_json::json!(#value)); }
+    });
+    quote! {
+        #new.map(|mut user| {
+            #status
+            #(#metadata)*
+            user
+        })
+    }
+    .into()
+}
+
+/// `#[api_handler]` on a function returning `Result<T, E>` (or `anyhow::Result<T>`) makes
+/// it return `ApiResponse<T>` instead, with `success`, `data`, `error` and `timestamp`
+/// filled the way `ApiResponse::into_result` reads them. `?` in the body works as in the
+/// unwrapped function; async functions and methods are supported.
+#[proc_macro_attribute]
+pub fn api_handler(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let args = proc_macro2::TokenStream::from(args);
+        return syn::Error::new(args.span(), "api_handler takes no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let ItemFn {
+        attrs,
+        vis,
+        mut sig,
+        block,
+    } = parse_macro_input!(item as ItemFn);
+    let ret = match &sig.output {
+        ReturnType::Type(_, ret) => ret.clone(),
+        ReturnType::Default => {
+            return syn::Error::new(
+                sig.ident.span(),
+                "api_handler needs a function returning a `Result`",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+    sig.output = syn::parse_quote_spanned! {ret.span()=>
+        -> ApiResponse<<#ret as IntoApiResponse>::Data>
+    };
+    let result = if sig.asyncness.is_some() {
+        quote! { async move #block.await }
+    } else {
+        quote! { (move || -> #ret #block)() }
+    };
+    quote! {
+        #(#attrs)*
+        #vis #sig {
+            let result: #ret = #result;
+            IntoApiResponse::into_api_response(result)
+        }
+    }
+    .into()
+}
+
+/// The rule `User::new` applies at run time
+fn is_valid_email(email: &str) -> bool {
+    email.contains('@') && email.contains('.')
+}
+
+struct CreateUser {
+    id: Expr,
+    name: Expr,
+    email: Expr,
+    email_literal: Option<LitStr>,
+    status: Option<Expr>,
+    metadata: Vec<(Expr, Expr)>,
+}
+
+impl Parse for CreateUser {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let id = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let name = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let email: Expr = input.parse()?;
+        let email_literal = match &email {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(literal),
+                ..
+            }) => Some(literal.clone()),
+            _ => None,
+        };
+        let mut args = Self {
+            id,
+            name,
+            email,
+            email_literal,
+            status: None,
+            metadata: Vec::new(),
+        };
+
+        let mut metadata_seen = false;
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            if !(input.peek(Ident) && input.peek2(Token![=])) {
+                // The positional status form
+                let status: Expr = input.parse()?;
+                if args.status.replace(status.clone()).is_some() {
+                    return Err(syn::Error::new(status.span(), "status given twice"));
+                }
+                continue;
+            }
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            match key.to_string().as_str() {
+                "status" => {
+                    if args.status.replace(input.parse()?).is_some() {
+                        return Err(syn::Error::new(key.span(), "status given twice"));
+                    }
+                }
+                "metadata" => {
+                    if std::mem::replace(&mut metadata_seen, true) {
+                        return Err(syn::Error::new(key.span(), "metadata given twice"));
+                    }
+                    let entries;
+                    braced!(entries in input);
+                    let entries: Punctuated<MetadataEntry, Token![,]> =
+                        entries.parse_terminated(MetadataEntry::parse, Token![,])?;
+                    args.metadata = entries
+                        .into_iter()
+                        .map(|entry| (entry.key, entry.value))
+                        .collect();
+                }
+                other => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!(
+                            "unknown argument `{}`; expected `status` or `metadata`",
+                            other
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+struct MetadataEntry {
+    key: Expr,
+    value: Expr,
+}
+
+impl Parse for MetadataEntry {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key = input.parse()?;
+        input.parse::<Token![=>]>()?;
+        let value = input.parse()?;
+        Ok(Self { key, value })
+    }
+}
@@ -0,0 +1,385 @@
+use super::*;
+use std::collections::BTreeMap;
+
+// How the secondary's answer to a read differed from the primary's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchKind {
+    /// The primary has the user and the secondary does not
+    MissingInSecondary,
+    /// The secondary has a user the primary does not
+    ExtraInSecondary,
+    /// Both have the user with different contents
+    Different,
+    /// The secondary failed the read
+    SecondaryFailed,
+}
+
+impl MismatchKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MismatchKind::MissingInSecondary => "missing_in_secondary",
+            MismatchKind::ExtraInSecondary => "extra_in_secondary",
+            MismatchKind::Different => "different",
+            MismatchKind::SecondaryFailed => "secondary_failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReadMismatch {
+    pub at: DateTime<Utc>,
+    /// The `UserRepository` method read
+    pub operation: &'static str,
+    /// Redacted with `redact_id`; `None` when the secondary failed a list
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    pub kind: MismatchKind,
+    /// Differing fields, or the secondary's error
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+// Running totals of a dual-read repository's comparisons
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DualReadReport {
+    pub compared: u64,
+    /// Reads with at least one mismatch
+    pub mismatched: u64,
+    /// Reads left uncompared because `max_in_flight` comparisons were already running
+    pub skipped: u64,
+    /// Latest mismatches, oldest first
+    pub recent: Vec<ReadMismatch>,
+}
+
+impl DualReadReport {
+    /// Fraction of compared reads that matched, 1.0 before any comparison
+    pub fn match_rate(&self) -> f64 {
+        if self.compared == 0 {
+            return 1.0;
+        }
+        1.0 - self.mismatched as f64 / self.compared as f64
+    }
+}
+
+#[derive(Debug, Default)]
+struct Comparisons {
+    pending: AtomicUsize,
+    settled: Notify,
+    report: Mutex<DualReadReport>,
+}
+
+impl Comparisons {
+    fn record(&self, mismatches: Vec<ReadMismatch>, keep: usize) {
+        for mismatch in &mismatches {
+            metrics::counter!(
+                metric_names::DUAL_READ_MISMATCHES,
+                "operation" => mismatch.operation,
+                "kind" => mismatch.kind.as_str()
+            )
+            .increment(1);
+            tracing::warn!(
+                operation = mismatch.operation,
+                user_id = mismatch.user_id.as_deref(),
+                kind = mismatch.kind.as_str(),
+                details = ?mismatch.details,
+                "Dual read mismatch"
+            );
+        }
+        let mut report = self.report.lock().expect("dual read report poisoned");
+        report.compared += 1;
+        if !mismatches.is_empty() {
+            report.mismatched += 1;
+        }
+        report.recent.extend(mismatches);
+        let excess = report.recent.len().saturating_sub(keep);
+        report.recent.drain(..excess);
+    }
+}
+
+/// For migrating between user stores: reads go to both the primary (old) and secondary
+/// (new) backend, callers get the primary's answer as soon as it arrives, and the
+/// secondary's is compared in the background with mismatches logged, counted and kept
+/// for `report`. Writes go to the primary only; keep the secondary in step with e.g. a
+/// `SyncEngine`. Swap the two once the secondary stops drifting.
+///
+/// At most `max_in_flight` comparisons run at once; reads beyond that are served but not
+/// compared, and counted as skipped. A secondary slower than `comparison_timeout` counts
+/// as failed. List comparisons assume both stores page users in the same order.
+#[derive(Debug)]
+pub struct DualReadRepository {
+    primary: Arc<dyn UserRepository>,
+    secondary: Arc<dyn UserRepository>,
+    keep: usize,
+    in_flight: Arc<tokio::sync::Semaphore>,
+    comparison_timeout: Duration,
+    comparisons: Arc<Comparisons>,
+}
+
+impl DualReadRepository {
+    pub const DEFAULT_KEEP: usize = 100;
+    pub const DEFAULT_MAX_IN_FLIGHT: usize = 64;
+    pub const DEFAULT_COMPARISON_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(primary: Arc<dyn UserRepository>, secondary: Arc<dyn UserRepository>) -> Self {
+        Self {
+            primary,
+            secondary,
+            keep: Self::DEFAULT_KEEP,
+            in_flight: Arc::new(tokio::sync::Semaphore::new(Self::DEFAULT_MAX_IN_FLIGHT)),
+            comparison_timeout: Self::DEFAULT_COMPARISON_TIMEOUT,
+            comparisons: Arc::default(),
+        }
+    }
+
+    /// How many recent mismatches `report` keeps
+    pub fn with_recent_mismatches(mut self, keep: usize) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Most comparisons running in the background at once
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.in_flight = Arc::new(tokio::sync::Semaphore::new(max));
+        self
+    }
+
+    /// How long the secondary gets to answer a read before the comparison fails
+    pub fn with_comparison_timeout(mut self, timeout: Duration) -> Self {
+        self.comparison_timeout = timeout;
+        self
+    }
+
+    pub fn report(&self) -> DualReadReport {
+        self.comparisons
+            .report
+            .lock()
+            .expect("dual read report poisoned")
+            .clone()
+    }
+
+    /// Wait for comparisons still running in the background
+    pub async fn settle(&self) {
+        loop {
+            let settled = self.comparisons.settled.notified();
+            if self.comparisons.pending.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            settled.await;
+        }
+    }
+
+    /// Run `read` on the secondary in the background and compare it with `primary`, or
+    /// skip it when `max_in_flight` comparisons are already running
+    fn compare<F>(&self, operation: &'static str, primary: Vec<User>, read: F)
+    where
+        F: FnOnce(
+                Arc<dyn UserRepository>,
+            ) -> futures::future::BoxFuture<'static, Result<Vec<User>>>
+            + Send
+            + 'static,
+    {
+        let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+            metrics::counter!(metric_names::DUAL_READ_SKIPPED).increment(1);
+            self.comparisons
+                .report
+                .lock()
+                .expect("dual read report poisoned")
+                .skipped += 1;
+            return;
+        };
+        let secondary = self.secondary.clone();
+        let (comparisons, keep) = (self.comparisons.clone(), self.keep);
+        let timeout = self.comparison_timeout;
+        comparisons.pending.fetch_add(1, Ordering::AcqRel);
+        tokio::spawn(async move {
+            let failed = |details: String| {
+                vec![ReadMismatch {
+                    at: Utc::now(),
+                    operation,
+                    user_id: None,
+                    kind: MismatchKind::SecondaryFailed,
+                    details: vec![details],
+                }]
+            };
+            let mismatches = match tokio::time::timeout(timeout, read(secondary)).await {
+                Ok(Ok(secondary)) => Self::mismatches(operation, &primary, &secondary),
+                Ok(Err(e)) => failed(redact_error(&e)),
+                Err(_) => failed(format!("timed out after {:?}", timeout)),
+            };
+            drop(permit);
+            comparisons.record(mismatches, keep);
+            if comparisons.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+                comparisons.settled.notify_waiters();
+            }
+        });
+    }
+
+    /// Users compared by id, so a page holding the same users in another order matches.
+    /// Stores sorting differently put different users on a page, which shows up as
+    /// missing and extra users.
+    fn mismatches(
+        operation: &'static str,
+        primary: &[User],
+        secondary: &[User],
+    ) -> Vec<ReadMismatch> {
+        let primary: BTreeMap<&str, &User> = primary
+            .iter()
+            .map(|user| (user.id.as_str(), user))
+            .collect();
+        let mut secondary: BTreeMap<&str, &User> = secondary
+            .iter()
+            .map(|user| (user.id.as_str(), user))
+            .collect();
+        let mismatch = |user_id: &str, kind, details| ReadMismatch {
+            at: Utc::now(),
+            operation,
+            user_id: Some(redact_id(user_id)),
+            kind,
+            details,
+        };
+        let mut mismatches = Vec::new();
+        for (user_id, user) in primary {
+            match secondary.remove(user_id) {
+                None => mismatches.push(mismatch(
+                    user_id,
+                    MismatchKind::MissingInSecondary,
+                    Vec::new(),
+                )),
+                Some(other) if other != user => mismatches.push(mismatch(
+                    user_id,
+                    MismatchKind::Different,
+                    Self::differing_fields(user, other),
+                )),
+                Some(_) => {}
+            }
+        }
+        mismatches.extend(
+            secondary
+                .into_keys()
+                .map(|user_id| mismatch(user_id, MismatchKind::ExtraInSecondary, Vec::new())),
+        );
+        mismatches
+    }
+
+    fn differing_fields(primary: &User, secondary: &User) -> Vec<String> {
+        let secondary = secondary.to_update_fields();
+        let mut fields: Vec<String> = primary
+            .to_update_fields()
+            .into_iter()
+            .filter(|(field, value)| secondary.get(field) != Some(value))
+            .map(|(field, _)| field)
+            .collect();
+        fields.sort();
+        fields
+    }
+}
+
+#[async_trait]
+impl UserRepository for DualReadRepository {
+    async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        let user = self.primary.get(user_id).await?;
+        let user_id = user_id.to_string();
+        self.compare("get", user.iter().cloned().collect(), move |secondary| {
+            Box::pin(async move { Ok(secondary.get(&user_id).await?.into_iter().collect()) })
+        });
+        Ok(user)
+    }
+
+    async fn create(&self, user: &User) -> Result<User> {
+        self.primary.create(user).await
+    }
+
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        self.primary.update(user_id, updates).await
+    }
+
+    async fn swap_two_factor(
+        &self,
+        user_id: &str,
+        expected: Option<&TwoFactor>,
+        replacement: Option<&TwoFactor>,
+    ) -> Result<bool> {
+        self.primary
+            .swap_two_factor(user_id, expected, replacement)
+            .await
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool> {
+        self.primary.delete(user_id).await
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        let users = self.primary.list(offset, limit).await?;
+        self.compare("list", users.clone(), move |secondary| {
+            Box::pin(async move { secondary.list(offset, limit).await })
+        });
+        Ok(users)
+    }
+
+    async fn list_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        let users = self.primary.list_filtered(filter, offset, limit).await?;
+        let filter = filter.clone();
+        self.compare("list_filtered", users.clone(), move |secondary| {
+            Box::pin(async move { secondary.list_filtered(&filter, offset, limit).await })
+        });
+        Ok(users)
+    }
+
+    async fn changes_since(&self, cursor: Option<&str>, limit: usize) -> Result<ChangeSet> {
+        self.primary.changes_since(cursor, limit).await
+    }
+
+    async fn apply_batch(&self, mutations: &[Mutation]) -> Result<Vec<bool>> {
+        self.primary.apply_batch(mutations).await
+    }
+
+    fn supports_transactions(&self) -> bool {
+        self.primary.supports_transactions()
+    }
+
+    fn writes_outbox(&self) -> bool {
+        self.primary.writes_outbox()
+    }
+
+    async fn apply_idempotent(&self, mutation: &Mutation, idempotency_key: &str) -> Result<bool> {
+        self.primary
+            .apply_idempotent(mutation, idempotency_key)
+            .await
+    }
+
+    fn describe(&self) -> String {
+        let report = self.report();
+        format!(
+            "dual_read(primary: {}, secondary: {}, mismatched: {}/{})",
+            self.primary.describe(),
+            self.secondary.describe(),
+            report.mismatched,
+            report.compared
+        )
+    }
+
+    /// Healthy while the primary is; the secondary only feeds comparisons
+    async fn ping(&self) -> Result<()> {
+        if let Err(e) = self.secondary.ping().await {
+            tracing::warn!(
+                error = redact_error(&e),
+                "Dual read secondary health check failed"
+            );
+        }
+        self.primary.ping().await
+    }
+
+    async fn notify_erasure(&self, user_id: &str) -> Result<bool> {
+        self.primary.notify_erasure(user_id).await
+    }
+}
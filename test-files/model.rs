@@ -0,0 +1,1410 @@
+use super::*;
+
+// User status enumeration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    Active,
+    Inactive,
+    Pending,
+    Suspended,
+}
+
+impl fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserStatus::Active => write!(f, "Active"),
+            UserStatus::Inactive => write!(f, "Inactive"),
+            UserStatus::Pending => write!(f, "Pending"),
+            UserStatus::Suspended => write!(f, "Suspended"),
+        }
+    }
+}
+
+impl UserStatus {
+    pub fn is_valid(&self) -> bool {
+        matches!(self, UserStatus::Active | UserStatus::Inactive | UserStatus::Pending)
+    }
+
+    /// Wire representation, matching the serde encoding
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserStatus::Active => "active",
+            UserStatus::Inactive => "inactive",
+            UserStatus::Pending => "pending",
+            UserStatus::Suspended => "suspended",
+        }
+    }
+}
+
+impl std::str::FromStr for UserStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "active" => Ok(UserStatus::Active),
+            "inactive" => Ok(UserStatus::Inactive),
+            "pending" => Ok(UserStatus::Pending),
+            "suspended" => Ok(UserStatus::Suspended),
+            _ => Err(anyhow::anyhow!("Unknown user status: {}", value)),
+        }
+    }
+}
+
+// Shared immutable string, interned process-wide when the `interning` feature is enabled
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InternedStr(pub(crate) Arc<str>);
+
+// Key type of `User::metadata`: interned when the `interning` feature is enabled
+#[cfg(feature = "interning")]
+pub type MetadataKey = InternedStr;
+#[cfg(not(feature = "interning"))]
+pub type MetadataKey = String;
+
+// Process-wide pool behind `InternedStr`, swept of unreferenced strings as it grows
+#[cfg(feature = "interning")]
+#[derive(Default)]
+struct Interner {
+    strings: std::collections::HashSet<Arc<str>>,
+    // Strings created since the last sweep, and how many the sweep kept
+    created: usize,
+    kept: usize,
+}
+
+#[cfg(feature = "interning")]
+impl Interner {
+    fn purge(&mut self) -> usize {
+        let before = self.strings.len();
+        self.strings.retain(|value| Arc::strong_count(value) > 1);
+        self.created = 0;
+        self.kept = self.strings.len();
+        before - self.kept
+    }
+}
+
+#[cfg(feature = "interning")]
+fn interner() -> &'static Mutex<Interner> {
+    static POOL: std::sync::OnceLock<Mutex<Interner>> = std::sync::OnceLock::new();
+    POOL.get_or_init(Default::default)
+}
+
+impl InternedStr {
+    /// Most distinct strings the interner holds; past it, new strings are not shared
+    #[cfg(feature = "interning")]
+    pub const POOL_CAPACITY: usize = 1 << 16;
+    /// Fewest new strings between sweeps of the interner
+    #[cfg(feature = "interning")]
+    const PURGE_INTERVAL: usize = 1024;
+
+    #[cfg(feature = "interning")]
+    pub fn new(value: &str) -> Self {
+        let mut pool = interner().lock().expect("interner poisoned");
+        if let Some(existing) = pool.strings.get(value) {
+            return Self(existing.clone());
+        }
+        // Sweeping after as many new strings as the last sweep kept keeps the cost amortized
+        // and the pool within twice its live strings
+        pool.created += 1;
+        if pool.created >= pool.kept.max(Self::PURGE_INTERVAL) {
+            pool.purge();
+        }
+        let shared: Arc<str> = Arc::from(value);
+        if pool.strings.len() < Self::POOL_CAPACITY {
+            pool.strings.insert(shared.clone());
+        }
+        Self(shared)
+    }
+
+    #[cfg(not(feature = "interning"))]
+    pub fn new(value: &str) -> Self {
+        Self(Arc::from(value))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Number of distinct strings held by the interner
+    #[cfg(feature = "interning")]
+    pub fn pool_size() -> usize {
+        interner().lock().expect("interner poisoned").strings.len()
+    }
+
+    /// Drop interned strings no longer referenced outside the pool, returning how many were freed
+    #[cfg(feature = "interning")]
+    pub fn purge_unused() -> usize {
+        interner().lock().expect("interner poisoned").purge()
+    }
+}
+
+impl Deref for InternedStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for InternedStr {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for InternedStr {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for InternedStr {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<String> for InternedStr {
+    fn from(value: String) -> Self {
+        Self::new(&value)
+    }
+}
+
+impl fmt::Debug for InternedStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for InternedStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for InternedStr {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for InternedStr {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let value = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Ok(Self::new(&value))
+    }
+}
+
+// Tenant identifier scoping storage, cache entries and statistics
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TenantId(InternedStr);
+
+impl TenantId {
+    pub fn new(id: &str) -> Self {
+        Self(InternedStr::new(id))
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl From<&str> for TenantId {
+    fn from(id: &str) -> Self {
+        Self::new(id)
+    }
+}
+
+impl From<String> for TenantId {
+    fn from(id: String) -> Self {
+        Self::new(&id)
+    }
+}
+
+impl fmt::Display for TenantId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// User data structure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub status: UserStatus,
+    pub created_at: DateTime<Utc>,
+    pub metadata: HashMap<MetadataKey, serde_json::Value>,
+    /// Consent history, oldest first; omitted from the wire when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub consents: Vec<Consent>,
+    /// Second-factor enrollment. Never serialized, so the secret stays out of responses,
+    /// events, caches and logs; backends store it apart and change it only through
+    /// `UserRepository::swap_two_factor`. Boxed since most users have none.
+    #[serde(skip)]
+    pub two_factor: Option<Box<TwoFactor>>,
+}
+
+// A user's TOTP enrollment. Backup codes are only kept as SHA-256 hashes; helpers to
+// generate and check codes need the `totp` feature.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwoFactor {
+    /// Shared TOTP secret, base32 without padding
+    pub totp_secret: String,
+    pub enrolled_at: DateTime<Utc>,
+    /// Hex SHA-256 of each unused backup code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub backup_code_hashes: Vec<String>,
+    /// Time step of the last accepted TOTP code, so a code cannot be replayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_step: Option<u64>,
+    /// Wrong codes since the last accepted one; verification locks out at a limit
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub failed_attempts: u32,
+}
+
+fn is_zero(count: &u32) -> bool {
+    *count == 0
+}
+
+/// Why updates may not carry `two_factor`: enrollments change only through the
+/// `UserManager` two-factor methods
+const TWO_FACTOR_NOT_UPDATABLE: &str = "set through the two-factor methods, not updates";
+
+impl TwoFactor {
+    /// Wrong codes in a row after which verification is refused until re-enrollment
+    pub const MAX_FAILED_ATTEMPTS: u32 = 5;
+
+    pub fn backup_codes_remaining(&self) -> usize {
+        self.backup_code_hashes.len()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.failed_attempts >= Self::MAX_FAILED_ATTEMPTS
+    }
+}
+
+impl fmt::Debug for TwoFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TwoFactor")
+            .field("totp_secret", &"<redacted>")
+            .field("enrolled_at", &self.enrolled_at)
+            .field("backup_codes_remaining", &self.backup_codes_remaining())
+            .field("last_used_step", &self.last_used_step)
+            .field("failed_attempts", &self.failed_attempts)
+            .finish()
+    }
+}
+
+// A user's agreement to one processing purpose under a version of the terms
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Consent {
+    pub purpose: String,
+    pub granted_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withdrawn_at: Option<DateTime<Utc>>,
+    pub terms_version: String,
+}
+
+impl Consent {
+    pub fn new(
+        purpose: impl Into<String>,
+        terms_version: impl Into<String>,
+        granted_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            purpose: purpose.into(),
+            granted_at,
+            withdrawn_at: None,
+            terms_version: terms_version.into(),
+        }
+    }
+
+    /// Granted by `at` and not withdrawn by then
+    pub fn is_active_at(&self, at: DateTime<Utc>) -> bool {
+        self.granted_at <= at && self.withdrawn_at.is_none_or(|withdrawn| withdrawn > at)
+    }
+}
+
+impl User {
+    pub fn new(id: String, name: String, email: String) -> Result<Self> {
+        if !Self::is_valid_email(&email) {
+            return Err(UserError::InvalidEmail { email }.into());
+        }
+
+        Ok(User {
+            id,
+            name,
+            email,
+            status: UserStatus::Active,
+            created_at: Utc::now(),
+            metadata: HashMap::new(),
+            consents: Vec::new(),
+            two_factor: None,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status == UserStatus::Active
+    }
+
+    pub fn display_name(&self) -> &str {
+        if !self.name.is_empty() {
+            &self.name
+        } else {
+            &self.email
+        }
+    }
+
+    pub fn days_active(&self) -> i64 {
+        self.days_active_at(Utc::now())
+    }
+
+    /// Typed form of `get_age_category`, for localized display
+    pub fn age_category(&self) -> AgeCategory {
+        AgeCategory::from_days(self.days_active())
+    }
+
+    /// Days active relative to a fixed point in time
+    pub fn days_active_at(&self, now: DateTime<Utc>) -> i64 {
+        (now - self.created_at).num_days()
+    }
+
+    /// Deepest metadata nesting accepted from untrusted input
+    pub const MAX_METADATA_DEPTH: usize = 32;
+
+    /// Reject metadata nested deeper than `MAX_METADATA_DEPTH`, checked without recursion
+    pub fn check_metadata_depth(&self) -> Result<()> {
+        let mut pending: Vec<(&serde_json::Value, usize)> =
+            self.metadata.values().map(|value| (value, 1)).collect();
+        while let Some((value, depth)) = pending.pop() {
+            if depth > Self::MAX_METADATA_DEPTH {
+                return Err(anyhow::anyhow!(
+                    "Metadata nested deeper than {} levels",
+                    Self::MAX_METADATA_DEPTH
+                ));
+            }
+            match value {
+                serde_json::Value::Array(items) => {
+                    pending.extend(items.iter().map(|item| (item, depth + 1)))
+                }
+                serde_json::Value::Object(fields) => {
+                    pending.extend(fields.values().map(|field| (field, depth + 1)))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Also checked on literals at compile time by `create_user!`; keep the two in step
+    pub(crate) fn is_valid_email(email: &str) -> bool {
+        email.contains('@') && email.contains('.')
+    }
+
+    /// Stricter than the rule applied on create: one `@`, a dot-atom local part of at most
+    /// 64 bytes and a domain of hyphenated labels ending in an alphabetic TLD
+    pub fn is_strict_email(email: &str) -> bool {
+        let Some((local, domain)) = email.split_once('@') else {
+            return false;
+        };
+        let dot_atom = |part: &str, allowed: fn(char) -> bool| {
+            part.split('.')
+                .all(|atom| !atom.is_empty() && atom.chars().all(allowed))
+        };
+        let local_ok = local.len() <= 64
+            && dot_atom(local, |c| {
+                c.is_ascii_alphanumeric() || "!#$%&'*+/=?^_`{|}~-".contains(c)
+            });
+        let labels: Vec<&str> = domain.split('.').collect();
+        let domain_ok = domain.len() <= 253
+            && labels.len() >= 2
+            && dot_atom(domain, |c| c.is_ascii_alphanumeric() || c == '-')
+            && labels
+                .iter()
+                .all(|label| label.len() <= 63 && !label.starts_with('-') && !label.ends_with('-'))
+            && labels
+                .last()
+                .is_some_and(|tld| tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()));
+        email.len() <= 254 && local_ok && domain_ok
+    }
+
+    pub fn with_status(mut self, status: UserStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn add_metadata(&mut self, key: impl Into<MetadataKey>, value: serde_json::Value) {
+        self.metadata.insert(key.into(), value);
+    }
+
+    /// Placeholder left behind by an erasure request: same id and creation time, no
+    /// personal data, inactive, and marked with `erased_at`
+    pub fn anonymized(&self, erased_at: DateTime<Utc>) -> User {
+        let mut user = User {
+            id: self.id.clone(),
+            name: "Erased user".to_string(),
+            email: "erased@erased.invalid".to_string(),
+            status: UserStatus::Inactive,
+            created_at: self.created_at,
+            metadata: HashMap::new(),
+            consents: Vec::new(),
+            two_factor: None,
+        };
+        user.add_metadata("erased_at", serde_json::json!(erased_at));
+        user
+    }
+
+    /// Every mutable field as an update map, for overwriting a stored record
+    pub fn to_update_fields(&self) -> HashMap<String, serde_json::Value> {
+        let serde_json::Value::Object(mut fields) =
+            serde_json::to_value(self).expect("User always serializes to an object")
+        else {
+            unreachable!("User always serializes to an object");
+        };
+        fields.remove("id");
+        fields
+            .entry("consents")
+            .or_insert_with(|| serde_json::json!([]));
+        fields.into_iter().collect()
+    }
+
+    /// Record consent to `purpose`, withdrawing any earlier grant it supersedes
+    pub fn grant_consent(
+        &mut self,
+        purpose: impl Into<String>,
+        terms_version: impl Into<String>,
+        at: DateTime<Utc>,
+    ) {
+        let purpose = purpose.into();
+        self.withdraw_consent(&purpose, at);
+        self.consents.push(Consent::new(purpose, terms_version, at));
+    }
+
+    /// Withdraw consent to `purpose`, returning whether any was active
+    pub fn withdraw_consent(&mut self, purpose: &str, at: DateTime<Utc>) -> bool {
+        let mut withdrawn = false;
+        for consent in &mut self.consents {
+            if consent.purpose == purpose && consent.is_active_at(at) {
+                consent.withdrawn_at = Some(at);
+                withdrawn = true;
+            }
+        }
+        withdrawn
+    }
+
+    /// The consent to `purpose` in force now, if any
+    pub fn consent(&self, purpose: &str) -> Option<&Consent> {
+        let now = Utc::now();
+        self.consents
+            .iter()
+            .rev()
+            .find(|consent| consent.purpose == purpose && consent.is_active_at(now))
+    }
+
+    pub fn has_valid_consent(&self, purpose: &str) -> bool {
+        self.consent(purpose).is_some()
+    }
+
+    /// Apply a partial update keyed by wire field name, rejecting unknown or immutable fields
+    pub fn apply_updates(&mut self, updates: &HashMap<String, serde_json::Value>) -> Result<()> {
+        let mut value = serde_json::to_value(&*self).context("Failed to serialize user")?;
+        let fields = value
+            .as_object_mut()
+            .expect("User always serializes to an object");
+
+        for (field, new_value) in updates {
+            if field == "id" {
+                return Err(UserError::InvalidUpdate {
+                    field: field.clone(),
+                    message: "field is immutable".to_string(),
+                }
+                .into());
+            }
+            if field == "two_factor" {
+                return Err(UserError::InvalidUpdate {
+                    field: field.clone(),
+                    message: TWO_FACTOR_NOT_UPDATABLE.to_string(),
+                }
+                .into());
+            }
+            // Omitted from the serialized user while empty, but always updatable
+            if !fields.contains_key(field) && field != "consents" {
+                return Err(UserError::InvalidUpdate {
+                    field: field.clone(),
+                    message: "unknown field".to_string(),
+                }
+                .into());
+            }
+            fields.insert(field.clone(), new_value.clone());
+        }
+
+        let mut updated: User =
+            serde_json::from_value(value).map_err(|e| UserError::InvalidUpdate {
+                field: updates.keys().cloned().collect::<Vec<_>>().join(", "),
+                message: e.to_string(),
+            })?;
+        if !Self::is_valid_email(&updated.email) {
+            return Err(UserError::InvalidEmail {
+                email: updated.email,
+            }
+            .into());
+        }
+
+        updated.two_factor = self.two_factor.take();
+        *self = updated;
+        Ok(())
+    }
+}
+
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "User(id={}, name={}, email={}, status={})",
+            self.id, self.name, self.email, self.status
+        )
+    }
+}
+
+// Typed partial update of a user; unset fields are left unchanged
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserUpdate {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub status: Option<UserStatus>,
+    pub metadata: Vec<MetadataOp>,
+    /// Replaces the whole consent history
+    pub consents: Option<Vec<Consent>>,
+}
+
+// One change to a user's metadata, applied in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataOp {
+    Set(String, serde_json::Value),
+    Remove(String),
+    Clear,
+}
+
+impl UserUpdate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    pub fn status(mut self, status: UserStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn set_metadata(mut self, key: impl Into<String>, value: serde_json::Value) -> Self {
+        self.metadata.push(MetadataOp::Set(key.into(), value));
+        self
+    }
+
+    pub fn remove_metadata(mut self, key: impl Into<String>) -> Self {
+        self.metadata.push(MetadataOp::Remove(key.into()));
+        self
+    }
+
+    pub fn clear_metadata(mut self) -> Self {
+        self.metadata.push(MetadataOp::Clear);
+        self
+    }
+
+    pub fn consents(mut self, consents: Vec<Consent>) -> Self {
+        self.consents = Some(consents);
+        self
+    }
+
+    /// Number of wire fields the update touches
+    pub fn len(&self) -> usize {
+        [
+            self.name.is_some(),
+            self.email.is_some(),
+            self.status.is_some(),
+            !self.metadata.is_empty(),
+            self.consents.is_some(),
+        ]
+        .into_iter()
+        .filter(|touched| *touched)
+        .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the metadata ops build on the stored metadata rather than replacing it
+    pub fn needs_current(&self) -> bool {
+        self.metadata
+            .first()
+            .is_some_and(|op| !matches!(op, MetadataOp::Clear))
+    }
+
+    /// Parse wire fields (`name`, `email`, `status`, `metadata`, `consents`, `two_factor`)
+    /// into a typed update
+    pub fn from_fields(
+        fields: impl IntoIterator<Item = (String, serde_json::Value)>,
+    ) -> Result<Self> {
+        let invalid = |field: &str, message: String| UserError::InvalidUpdate {
+            field: field.to_string(),
+            message,
+        };
+        let text = |field: &str, value: serde_json::Value| match value {
+            serde_json::Value::String(text) => Ok(text),
+            other => Err(invalid(field, format!("expected a string, got {}", other))),
+        };
+
+        let mut update = Self::new();
+        for (field, value) in fields {
+            match field.as_str() {
+                "name" => update.name = Some(text(&field, value)?),
+                "email" => update.email = Some(text(&field, value)?),
+                "status" => {
+                    update.status = Some(
+                        text(&field, value)?
+                            .parse()
+                            .map_err(|e: anyhow::Error| invalid(&field, e.to_string()))?,
+                    )
+                }
+                "metadata" => {
+                    let serde_json::Value::Object(entries) = value else {
+                        return Err(invalid(&field, "expected an object".to_string()).into());
+                    };
+                    update.metadata.push(MetadataOp::Clear);
+                    update.metadata.extend(
+                        entries
+                            .into_iter()
+                            .map(|(key, value)| MetadataOp::Set(key, value)),
+                    );
+                }
+                "consents" => {
+                    update.consents = Some(
+                        serde_json::from_value(value)
+                            .map_err(|e| invalid(&field, e.to_string()))?,
+                    )
+                }
+                "two_factor" => {
+                    return Err(invalid(&field, TWO_FACTOR_NOT_UPDATABLE.to_string()).into())
+                }
+                "id" => return Err(invalid(&field, "field is immutable".to_string()).into()),
+                _ => return Err(invalid(&field, "unknown field".to_string()).into()),
+            }
+        }
+        Ok(update)
+    }
+
+    /// The wire fields to send; metadata ops apply on top of `current`'s metadata
+    pub fn to_fields(&self, current: Option<&User>) -> HashMap<String, serde_json::Value> {
+        let mut fields = HashMap::new();
+        if let Some(name) = &self.name {
+            fields.insert("name".to_string(), serde_json::json!(name));
+        }
+        if let Some(email) = &self.email {
+            fields.insert("email".to_string(), serde_json::json!(email));
+        }
+        if let Some(status) = self.status {
+            fields.insert("status".to_string(), serde_json::json!(status));
+        }
+        if !self.metadata.is_empty() {
+            let mut metadata: serde_json::Map<String, serde_json::Value> = current
+                .filter(|_| self.needs_current())
+                .map(|user| {
+                    user.metadata
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.clone()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            for op in &self.metadata {
+                match op {
+                    MetadataOp::Set(key, value) => {
+                        metadata.insert(key.clone(), value.clone());
+                    }
+                    MetadataOp::Remove(key) => {
+                        metadata.remove(key);
+                    }
+                    MetadataOp::Clear => metadata.clear(),
+                }
+            }
+            fields.insert("metadata".to_string(), serde_json::Value::Object(metadata));
+        }
+        if let Some(consents) = &self.consents {
+            fields.insert("consents".to_string(), serde_json::json!(consents));
+        }
+        fields
+    }
+}
+
+impl User {
+    /// Version of the serialized shape written in a `UserEnvelope`; a change to the
+    /// serialized fields needs a bump and a matching `UserMigrator` step
+    pub const SCHEMA_VERSION: u32 = 1;
+
+    /// Apply a typed update in place, validating the result like `apply_updates`
+    pub fn apply_update(&mut self, update: &UserUpdate) -> Result<()> {
+        let fields = update.to_fields(Some(self));
+        self.apply_updates(&fields)
+    }
+
+    /// Rough bytes the user holds in memory, inline and on the heap; allocator overhead,
+    /// hash table slack and sharing of interned keys are not counted
+    pub fn estimated_size(&self) -> usize {
+        use std::mem::size_of;
+
+        fn value_size(value: &serde_json::Value) -> usize {
+            use serde_json::Value;
+            size_of::<Value>()
+                + match value {
+                    Value::String(text) => text.capacity(),
+                    Value::Array(items) => items.iter().map(value_size).sum(),
+                    Value::Object(fields) => fields
+                        .iter()
+                        .map(|(key, value)| {
+                            size_of::<String>() + key.capacity() + value_size(value)
+                        })
+                        .sum(),
+                    _ => 0,
+                }
+        }
+        let metadata: usize = self
+            .metadata
+            .iter()
+            .map(|(key, value)| size_of::<InternedStr>() + key.len() + value_size(value))
+            .sum();
+        let consents: usize = self
+            .consents
+            .iter()
+            .map(|consent| {
+                size_of::<Consent>() + consent.purpose.capacity() + consent.terms_version.capacity()
+            })
+            .sum();
+        let two_factor = self.two_factor.as_ref().map_or(0, |two_factor| {
+            size_of::<TwoFactor>()
+                + two_factor.totp_secret.capacity()
+                + two_factor
+                    .backup_code_hashes
+                    .iter()
+                    .map(|hash| size_of::<String>() + hash.capacity())
+                    .sum::<usize>()
+        });
+        size_of::<User>()
+            + self.id.capacity()
+            + self.name.capacity()
+            + self.email.capacity()
+            + metadata
+            + consents
+            + two_factor
+    }
+
+    /// Hex SHA-256 of the user's `canonical_json`; equal for users with equal content
+    /// however they were built or loaded, so it can detect changes and duplicates
+    pub fn content_hash(&self) -> String {
+        use sha2::{Digest, Sha256};
+        let json = canonical_json(self).expect("User always serializes");
+        encode_hex(&Sha256::digest(json.as_bytes()))
+    }
+}
+
+// API Response wrapper; `E` is the error payload, a message string unless a backend sends more
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiResponse<T, E = String> {
+    pub success: bool,
+    pub data: Option<T>,
+    pub error: Option<E>,
+    pub timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ResponseMeta>,
+    /// `UserError::kind_of` label of the error a failure was built from, which the server
+    /// maps to an HTTP status; never sent
+    #[serde(skip)]
+    pub error_kind: Option<&'static str>,
+}
+
+// Pagination and tracing details the API attaches to some responses
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResponseMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_page: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn success(data: T) -> Self {
+        Self {
+            success: true,
+            data: Some(data),
+            error: None,
+            timestamp: Utc::now(),
+            meta: None,
+            error_kind: None,
+        }
+    }
+
+    pub fn error(message: String) -> Self {
+        Self::failure(message)
+    }
+}
+
+impl<T, E> ApiResponse<T, E> {
+    pub fn failure(error: E) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error: Some(error),
+            timestamp: Utc::now(),
+            meta: None,
+            error_kind: None,
+        }
+    }
+
+    pub fn with_meta(mut self, meta: ResponseMeta) -> Self {
+        self.meta = Some(meta);
+        self
+    }
+
+    /// Transform the data, keeping the envelope
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> ApiResponse<U, E> {
+        ApiResponse {
+            success: self.success,
+            data: self.data.map(f),
+            error: self.error,
+            timestamp: self.timestamp,
+            meta: self.meta,
+            error_kind: self.error_kind,
+        }
+    }
+
+    /// Transform the error, keeping the envelope
+    pub fn map_err<F>(self, f: impl FnOnce(E) -> F) -> ApiResponse<T, F> {
+        ApiResponse {
+            success: self.success,
+            data: self.data,
+            error: self.error.map(f),
+            timestamp: self.timestamp,
+            meta: self.meta,
+            error_kind: self.error_kind,
+        }
+    }
+
+    /// Chain a call on the data of a successful response; failures pass through unchanged
+    pub fn and_then<U>(self, f: impl FnOnce(T) -> ApiResponse<U, E>) -> ApiResponse<U, E> {
+        match self.data {
+            Some(data) if self.success => f(data),
+            _ => ApiResponse {
+                success: self.success,
+                data: None,
+                error: self.error,
+                timestamp: self.timestamp,
+                meta: self.meta,
+                error_kind: self.error_kind,
+            },
+        }
+    }
+}
+
+// Handler results the server turns into an `ApiResponse`; failures carry the error's own
+// message, redacted, but not its causes, which can describe internals
+pub trait IntoApiResponse {
+    type Data;
+
+    fn into_api_response(self) -> ApiResponse<Self::Data>;
+}
+
+impl<T, E: Into<anyhow::Error>> IntoApiResponse for std::result::Result<T, E> {
+    type Data = T;
+
+    fn into_api_response(self) -> ApiResponse<T> {
+        match self {
+            Ok(data) => ApiResponse::success(data),
+            Err(error) => {
+                let error = error.into();
+                let mut response = ApiResponse::error(redact(&error.to_string()));
+                response.error_kind = Some(UserError::kind_of(&error));
+                response
+            }
+        }
+    }
+}
+
+impl<T, E: From<String>> ApiResponse<T, E> {
+    /// The data of a successful response, or `error`
+    pub fn ok_or<X>(self, error: X) -> std::result::Result<T, X> {
+        self.into_result().map_err(|_| error)
+    }
+
+    /// A failure without an error becomes "Unknown error"
+    pub fn into_result(self) -> std::result::Result<T, ApiError<E>> {
+        match (self.success, self.data) {
+            (true, Some(data)) => Ok(data),
+            (true, None) => Err(ApiError::MissingData),
+            (false, _) => Err(ApiError::Failed {
+                message: self
+                    .error
+                    .unwrap_or_else(|| E::from("Unknown error".to_string())),
+            }),
+        }
+    }
+}
+
+// Export ordering: canonical output is byte-identical for the same set of users
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportOrder {
+    /// Users sorted by id, object keys sorted at every level
+    #[default]
+    Canonical,
+    /// Users in input order, metadata in map iteration order
+    Input,
+}
+
+/// Rebuild a JSON value with object keys sorted at every level
+pub fn canonical_value(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonical_value(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(canonical_value).collect())
+        }
+        other => other,
+    }
+}
+
+/// Serialize `value` to canonical JSON for hashing and signing: compact, with object keys
+/// sorted at every level, floats holding whole numbers written as integers, and the
+/// `TIMESTAMP_FIELDS` rewritten in UTC with a `Z` suffix. Equal content always gives equal bytes.
+pub fn canonical_json<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    let value = serde_json::to_value(value).context("Failed to serialize to canonical JSON")?;
+    Ok(canonical_value(normalize_scalars(value, true)).to_string())
+}
+
+/// Fields `canonical_json` reads as RFC 3339 timestamps: `User::created_at` and the
+/// `Consent` times. Free-form values under `metadata` are left as written.
+pub const TIMESTAMP_FIELDS: [&str; 3] = ["created_at", "granted_at", "withdrawn_at"];
+
+/// Lowercase hex of `bytes`, as used for digests and signatures
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn normalize_scalars(value: serde_json::Value, timestamps: bool) -> serde_json::Value {
+    use serde_json::Value;
+
+    // Integers above 2^53 do not round-trip through f64, so such floats stay floats
+    const MAX_EXACT: f64 = 9_007_199_254_740_992.0;
+    match value {
+        Value::Number(number) => match number.as_f64() {
+            Some(float)
+                if !number.is_i64()
+                    && !number.is_u64()
+                    && float.fract() == 0.0
+                    && float.abs() <= MAX_EXACT =>
+            {
+                Value::from(float as i64)
+            }
+            _ => Value::Number(number),
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| normalize_scalars(item, timestamps))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(text)
+                            if timestamps && TIMESTAMP_FIELDS.contains(&key.as_str()) =>
+                        {
+                            normalize_timestamp(text)
+                        }
+                        value => normalize_scalars(value, timestamps && key != "metadata"),
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+fn normalize_timestamp(text: String) -> serde_json::Value {
+    match DateTime::parse_from_rfc3339(&text) {
+        Ok(at) => serde_json::Value::String(
+            at.with_timezone(&Utc)
+                .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+        ),
+        Err(_) => serde_json::Value::String(text),
+    }
+}
+
+// A stored user tagged with the `User` schema it was written under, serialized as
+// `{"user_version": 1, "payload": {...}}`, so a `UserMigrator` can upgrade snapshots and
+// exports written before the struct changed. The field is distinct from the migrator's
+// `schema_version` so a bare record carrying its own version is never read as an envelope.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserEnvelope {
+    pub user_version: u32,
+    pub payload: serde_json::Value,
+}
+
+impl UserEnvelope {
+    pub const VERSION_FIELD: &'static str = "user_version";
+
+    /// Wrap `user` at the current `User::SCHEMA_VERSION`
+    pub fn wrap(user: &User) -> Result<Self> {
+        Ok(Self {
+            user_version: User::SCHEMA_VERSION,
+            payload: serde_json::to_value(user).context("Failed to serialize user")?,
+        })
+    }
+
+    /// Whether `record` is an envelope rather than a bare user record
+    pub fn is_envelope(record: &serde_json::Value) -> bool {
+        record.get(Self::VERSION_FIELD).is_some()
+            && record
+                .get("payload")
+                .is_some_and(serde_json::Value::is_object)
+    }
+
+    /// Id of the user in an enveloped or bare record
+    pub fn record_id(record: &serde_json::Value) -> Option<&str> {
+        let user = if Self::is_envelope(record) {
+            &record["payload"]
+        } else {
+            record
+        };
+        user.get("id").and_then(|id| id.as_str())
+    }
+}
+
+// Users parsed from an import, plus the lines that failed or were skipped
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub users: Vec<User>,
+    pub errors: Vec<ImportError>,
+    /// Records dropped by a `Deduplicate` stage
+    pub skipped: Vec<ImportError>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportError {
+    /// 1-based line number in the input, or record position for `import_records`
+    pub line: usize,
+    pub message: String,
+}
+
+// How a `Normalize` stage rewrites string values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    Trim,
+    Lowercase,
+    Uppercase,
+    /// Runs of whitespace become one space, and the ends are trimmed
+    CollapseWhitespace,
+}
+
+impl Normalization {
+    pub fn apply(self, value: &str) -> String {
+        match self {
+            Normalization::Trim => value.trim().to_string(),
+            Normalization::Lowercase => value.to_lowercase(),
+            Normalization::Uppercase => value.to_uppercase(),
+            Normalization::CollapseWhitespace => {
+                value.split_whitespace().collect::<Vec<_>>().join(" ")
+            }
+        }
+    }
+}
+
+type EnrichFn =
+    Arc<dyn Fn(&mut serde_json::Map<String, serde_json::Value>) -> Result<()> + Send + Sync>;
+
+// One step of an import pipeline, run on each record's JSON object before it is read as a
+// `User`. Every stage but `Enrich` can be loaded from config, e.g.
+// `{"stage": "normalize", "fields": ["email"], "apply": ["trim", "lowercase"]}`.
+#[derive(Clone, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum ImportStage {
+    /// Move source fields to their `User` names; a dotted target like `metadata.team` nests
+    MapFields {
+        fields: std::collections::BTreeMap<String, String>,
+    },
+    /// Rewrite the string values of `fields`, applying each normalization in order
+    Normalize {
+        fields: Vec<String>,
+        apply: Vec<Normalization>,
+    },
+    /// Skip records whose `field` equals, ignoring case, that of an earlier record
+    Deduplicate { field: String },
+    /// Application callback that may add, change or reject fields; code only
+    #[serde(skip)]
+    Enrich(EnrichFn),
+}
+
+impl fmt::Debug for ImportStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportStage::MapFields { fields } => {
+                f.debug_struct("MapFields").field("fields", fields).finish()
+            }
+            ImportStage::Normalize { fields, apply } => f
+                .debug_struct("Normalize")
+                .field("fields", fields)
+                .field("apply", apply)
+                .finish(),
+            ImportStage::Deduplicate { field } => {
+                f.debug_struct("Deduplicate").field("field", field).finish()
+            }
+            ImportStage::Enrich(_) => f.write_str("Enrich(..)"),
+        }
+    }
+}
+
+// Stages run in order over every imported record, so messy source files can be cleaned
+// while importing. Loads from `{"stages": [...]}`; an empty pipeline imports as is.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ImportPipeline {
+    pub stages: Vec<ImportStage>,
+}
+
+impl ImportPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("Failed to parse import pipeline")
+    }
+
+    pub fn with_stage(mut self, stage: ImportStage) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Add an `Enrich` stage running `enrich` on each record
+    pub fn with_enrichment<F>(self, enrich: F) -> Self
+    where
+        F: Fn(&mut serde_json::Map<String, serde_json::Value>) -> Result<()>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.with_stage(ImportStage::Enrich(Arc::new(enrich)))
+    }
+
+    /// Parse newline-delimited JSON users through the stages, collecting per-line failures
+    /// instead of stopping
+    pub fn import_ndjson(&self, input: &str) -> ImportReport {
+        self.import_each(
+            input
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(index, line)| (index + 1, parse_record(line))),
+        )
+    }
+
+    /// Run already-parsed records, such as directory entries, through the stages. Errors
+    /// report each record's 1-based position as its line.
+    pub fn import_records<I>(&self, records: I) -> ImportReport
+    where
+        I: IntoIterator<Item = serde_json::Map<String, serde_json::Value>>,
+    {
+        self.import_each(
+            records
+                .into_iter()
+                .enumerate()
+                .map(|(index, record)| (index + 1, Ok(record))),
+        )
+    }
+
+    fn import_each(
+        &self,
+        records: impl Iterator<Item = (usize, Result<serde_json::Map<String, serde_json::Value>>)>,
+    ) -> ImportReport {
+        let mut report = ImportReport::default();
+        // Per `Deduplicate` stage: lowercased value to the line it was first seen on
+        let mut seen: Vec<HashMap<String, usize>> = vec![HashMap::new(); self.stages.len()];
+        for (line, record) in records {
+            let outcome = record
+                .map_err(ImportOutcome::from)
+                .and_then(|record| self.import_record(record, line, &mut seen));
+            match outcome {
+                Ok(user) => report.users.push(user),
+                Err(ImportOutcome::Skipped(message)) => {
+                    report.skipped.push(ImportError { line, message })
+                }
+                Err(ImportOutcome::Failed(e)) => report.errors.push(ImportError {
+                    line,
+                    message: redact_error(&e),
+                }),
+            }
+        }
+        report
+    }
+
+    fn import_record(
+        &self,
+        mut record: serde_json::Map<String, serde_json::Value>,
+        line_number: usize,
+        seen: &mut [HashMap<String, usize>],
+    ) -> Result<User, ImportOutcome> {
+        // Keys are only claimed once the record imports, so a line that fails later does
+        // not shadow a valid duplicate after it
+        let mut claims = Vec::new();
+        for (index, (stage, seen)) in self.stages.iter().zip(seen.iter()).enumerate() {
+            match stage {
+                ImportStage::MapFields { fields } => {
+                    for (from, to) in fields {
+                        if let Some(value) = record.remove(from) {
+                            insert_path(&mut record, to, value)?;
+                        }
+                    }
+                }
+                ImportStage::Normalize { fields, apply } => {
+                    for field in fields {
+                        if let Some(serde_json::Value::String(value)) = record.get_mut(field) {
+                            for normalization in apply {
+                                *value = normalization.apply(value);
+                            }
+                        }
+                    }
+                }
+                ImportStage::Deduplicate { field } => {
+                    let Some(value) = record.get(field) else {
+                        continue;
+                    };
+                    let key = match value {
+                        serde_json::Value::String(value) => value.to_lowercase(),
+                        other => other.to_string(),
+                    };
+                    if let Some(first) = seen.get(&key) {
+                        return Err(ImportOutcome::Skipped(format!(
+                            "duplicate {} of line {}",
+                            field, first
+                        )));
+                    }
+                    claims.push((index, key));
+                }
+                ImportStage::Enrich(enrich) => enrich(&mut record)?,
+            }
+        }
+        let user: User = serde_json::from_value(serde_json::Value::Object(record))
+            .context("Failed to deserialize user from JSON")?;
+        user.check_metadata_depth()?;
+        for (index, key) in claims {
+            seen[index].insert(key, line_number);
+        }
+        Ok(user)
+    }
+}
+
+fn parse_record(line: &str) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let value: serde_json::Value =
+        serde_json::from_str(line).context("Failed to deserialize user from JSON")?;
+    match value {
+        serde_json::Value::Object(record) => Ok(record),
+        _ => Err(anyhow::anyhow!("Expected a JSON object per line")),
+    }
+}
+
+enum ImportOutcome {
+    Skipped(String),
+    Failed(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ImportOutcome {
+    fn from(error: E) -> Self {
+        ImportOutcome::Failed(error.into())
+    }
+}
+
+/// Set `value` at a dotted `path`, creating objects along the way
+pub(crate) fn insert_path(
+    record: &mut serde_json::Map<String, serde_json::Value>,
+    path: &str,
+    value: serde_json::Value,
+) -> Result<()> {
+    let (parents, last) = match path.rsplit_once('.') {
+        Some((parents, last)) => (Some(parents), last),
+        None => (None, path),
+    };
+    let mut target = record;
+    for part in parents.into_iter().flat_map(|parents| parents.split('.')) {
+        let entry = target
+            .entry(part.to_string())
+            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+        target = entry
+            .as_object_mut()
+            .ok_or_else(|| UserError::InvalidUpdate {
+                field: path.to_string(),
+                message: format!("{} is not an object", part),
+            })?;
+    }
+    target.insert(last.to_string(), value);
+    Ok(())
+}
+
+// Trait for user operations
+pub trait UserOperations {
+    fn validate(&self) -> Result<()>;
+    fn get_age_category(&self) -> String;
+}
+
+impl UserOperations for User {
+    fn validate(&self) -> Result<()> {
+        for (field, value) in [("id", &self.id), ("name", &self.name)] {
+            if value.is_empty() {
+                return Err(UserError::EmptyField {
+                    field: field.to_string(),
+                }
+                .into());
+            }
+        }
+        if !Self::is_valid_email(&self.email) {
+            return Err(UserError::InvalidEmail {
+                email: self.email.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    fn get_age_category(&self) -> String {
+        self.age_category().to_string()
+    }
+}
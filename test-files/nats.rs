@@ -0,0 +1,110 @@
+use super::*;
+
+// Publishes user mutation events to a JetStream stream, waiting for the stream to
+// acknowledge each one. Every event carries a `Nats-Msg-Id`, so a publish retried after a
+// lost ack is dropped by the stream's duplicate window instead of stored twice.
+#[derive(Clone)]
+pub struct NatsEventSink {
+    jetstream: async_nats::jetstream::Context,
+    subject: SubjectTemplate,
+    retry_policy: RetryPolicy,
+}
+
+impl fmt::Debug for NatsEventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NatsEventSink")
+            .field("subject", &self.subject)
+            .field("retry_policy", &self.retry_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NatsEventSink {
+    pub const EVENT_HEADER: &'static str = "Users-Event";
+
+    /// Connect to the server at `url`; events go to `users.{tenant}.{event}` until changed
+    pub async fn connect(url: &str) -> Result<Self> {
+        let client = async_nats::connect(url)
+            .await
+            .context("Failed to connect to NATS")?;
+        Ok(Self::with_client(client))
+    }
+
+    pub fn with_client(client: async_nats::Client) -> Self {
+        Self {
+            jetstream: async_nats::jetstream::new(client),
+            subject: SubjectTemplate::default(),
+            retry_policy: RetryPolicy::new(3),
+        }
+    }
+
+    pub fn with_subject(mut self, subject: SubjectTemplate) -> Self {
+        self.subject = subject;
+        self
+    }
+
+    /// How long to wait for the stream's ack before retrying
+    pub fn with_ack_timeout(mut self, timeout: Duration) -> Self {
+        self.jetstream.set_timeout(timeout);
+        self
+    }
+
+    /// Retries for publishes that fail or go unacknowledged
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn subject(&self) -> &SubjectTemplate {
+        &self.subject
+    }
+}
+
+#[async_trait]
+impl EventSink for NatsEventSink {
+    fn name(&self) -> &'static str {
+        "nats"
+    }
+
+    /// Publish one event and wait for JetStream to store it, retrying under the policy
+    async fn send(&self, event: &UserEvent) -> Result<()> {
+        let subject = self.subject.render(event);
+        let payload = event.to_stored()?.to_string();
+        let mut headers = async_nats::HeaderMap::new();
+        headers.insert(
+            async_nats::header::NATS_MESSAGE_ID,
+            uuid::Uuid::new_v4().to_string().as_str(),
+        );
+        headers.insert(Self::EVENT_HEADER, event.kind());
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let published = match self
+                .jetstream
+                .publish_with_headers(subject.clone(), headers.clone(), payload.clone().into())
+                .await
+            {
+                Ok(ack) => ack.await,
+                Err(e) => Err(e),
+            };
+            match published {
+                Ok(ack) => {
+                    if ack.duplicate {
+                        tracing::debug!(stream = %ack.stream, "JetStream dropped a repeated event");
+                    }
+                    return Ok(());
+                }
+                Err(e) if attempts > self.retry_policy.max_retries => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to publish event to {}", subject));
+                }
+                Err(e) => {
+                    metrics::counter!(metric_names::RETRIES, "reason" => "nats").increment(1);
+                    tracing::debug!(attempts, error = %e, "Retrying JetStream publish");
+                    tokio::time::sleep(self.retry_policy.backoff(attempts - 1)).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,525 @@
+use super::*;
+
+// Messages sent to users about their account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Welcome,
+    SuspensionNotice,
+}
+
+// A value a template can insert
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplateField {
+    Id,
+    Name,
+    Email,
+    /// Localized
+    Status,
+    /// `YYYY-MM-DD`
+    CreatedAt,
+    /// String values as they are, others as JSON; empty when missing
+    Metadata(String),
+}
+
+impl TemplateField {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "id" => Some(TemplateField::Id),
+            "name" => Some(TemplateField::Name),
+            "email" => Some(TemplateField::Email),
+            "status" => Some(TemplateField::Status),
+            "created_at" => Some(TemplateField::CreatedAt),
+            _ => name
+                .strip_prefix("metadata.")
+                .filter(|key| !key.is_empty())
+                .map(|key| TemplateField::Metadata(key.to_string())),
+        }
+    }
+
+    fn render(&self, user: &User, locale: Locale) -> String {
+        match self {
+            TemplateField::Id => user.id.clone(),
+            TemplateField::Name => user.name.clone(),
+            TemplateField::Email => user.email.clone(),
+            TemplateField::Status => user.status.localize(locale),
+            TemplateField::CreatedAt => user.created_at.format("%Y-%m-%d").to_string(),
+            TemplateField::Metadata(key) => match user.metadata.get(key.as_str()) {
+                Some(serde_json::Value::String(value)) => value.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Field(TemplateField),
+}
+
+/// Text with `{{field}}` placeholders filled from a `User`: `id`, `name`, `email`,
+/// `status`, `created_at` or `metadata.<key>`. Unknown fields and unclosed braces are
+/// rejected when parsing, so a template that parses always renders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(source: impl Into<String>) -> Result<Self> {
+        let source = source.into();
+        let invalid = |message: String| UserError::InvalidConfig {
+            field: "template".to_string(),
+            message,
+        };
+        let mut segments = Vec::new();
+        let mut rest = source.as_str();
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let after = &rest[start + 2..];
+            let end = after
+                .find("}}")
+                .ok_or_else(|| invalid(format!("unclosed placeholder in {:?}", source)))?;
+            let name = after[..end].trim();
+            let field = TemplateField::parse(name)
+                .ok_or_else(|| invalid(format!("unknown placeholder {{{{{}}}}}", name)))?;
+            segments.push(Segment::Field(field));
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(Self { source, segments })
+    }
+
+    pub fn render(&self, user: &User, locale: Locale) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Field(field) => field.render(user, locale),
+            })
+            .collect()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
+
+// Subject and body of one kind of notification in one locale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageTemplate {
+    pub subject: Template,
+    pub body: Template,
+}
+
+impl MessageTemplate {
+    pub fn parse(subject: &str, body: &str) -> Result<Self> {
+        Ok(Self {
+            subject: Template::parse(subject)?,
+            body: Template::parse(body)?,
+        })
+    }
+}
+
+// A rendered message ready to send
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub user_id: String,
+    /// Recipient address
+    pub to: String,
+    pub locale: Locale,
+    pub subject: String,
+    pub body: String,
+}
+
+// Templates per kind and locale. A user's locale is read from a metadata field (`locale`
+// by default); kinds missing in that locale fall back to English.
+#[derive(Debug, Clone)]
+pub struct NotificationTemplates {
+    templates: HashMap<(NotificationKind, Locale), MessageTemplate>,
+    locale_key: String,
+}
+
+impl NotificationTemplates {
+    /// No templates at all; add them with `with_template`
+    pub fn empty() -> Self {
+        Self {
+            templates: HashMap::new(),
+            locale_key: "locale".to_string(),
+        }
+    }
+
+    /// Welcome and suspension messages in every supported locale
+    pub fn builtin() -> Self {
+        const BUILTIN: [(NotificationKind, Locale, &str, &str); 8] = [
+            (
+                NotificationKind::Welcome,
+                Locale::En,
+                "Welcome, {{name}}",
+                "Hi {{name}},\n\nyour account {{email}} is ready.\n",
+            ),
+            (
+                NotificationKind::Welcome,
+                Locale::De,
+                "Willkommen, {{name}}",
+                "Hallo {{name}},\n\nIhr Konto {{email}} ist eingerichtet.\n",
+            ),
+            (
+                NotificationKind::Welcome,
+                Locale::Es,
+                "Bienvenido, {{name}}",
+                "Hola {{name}}:\n\ntu cuenta {{email}} está lista.\n",
+            ),
+            (
+                NotificationKind::Welcome,
+                Locale::Fr,
+                "Bienvenue, {{name}}",
+                "Bonjour {{name}},\n\nvotre compte {{email}} est prêt.\n",
+            ),
+            (
+                NotificationKind::SuspensionNotice,
+                Locale::En,
+                "Your account has been suspended",
+                "Hi {{name}},\n\nyour account {{email}} is now {{status}}.\n",
+            ),
+            (
+                NotificationKind::SuspensionNotice,
+                Locale::De,
+                "Ihr Konto wurde gesperrt",
+                "Hallo {{name}},\n\nIhr Konto {{email}} ist jetzt {{status}}.\n",
+            ),
+            (
+                NotificationKind::SuspensionNotice,
+                Locale::Es,
+                "Tu cuenta ha sido suspendida",
+                "Hola {{name}}:\n\ntu cuenta {{email}} está ahora {{status}}.\n",
+            ),
+            (
+                NotificationKind::SuspensionNotice,
+                Locale::Fr,
+                "Votre compte a été suspendu",
+                "Bonjour {{name}},\n\nvotre compte {{email}} est désormais {{status}}.\n",
+            ),
+        ];
+        let mut templates = Self::empty();
+        for (kind, locale, subject, body) in BUILTIN {
+            let template = MessageTemplate::parse(subject, body).expect("builtin templates parse");
+            templates.templates.insert((kind, locale), template);
+        }
+        templates
+    }
+
+    /// Add or replace the template for `kind` in `locale`
+    pub fn with_template(
+        mut self,
+        kind: NotificationKind,
+        locale: Locale,
+        subject: &str,
+        body: &str,
+    ) -> Result<Self> {
+        self.templates
+            .insert((kind, locale), MessageTemplate::parse(subject, body)?);
+        Ok(self)
+    }
+
+    /// Read users' locales from this metadata field instead of `locale`
+    pub fn with_locale_key(mut self, key: impl Into<String>) -> Self {
+        self.locale_key = key.into();
+        self
+    }
+
+    /// The user's locale, English when unset or unknown
+    pub fn locale_for(&self, user: &User) -> Locale {
+        user.metadata
+            .get(self.locale_key.as_str())
+            .and_then(|tag| tag.as_str())
+            .and_then(Locale::from_tag)
+            .unwrap_or_default()
+    }
+
+    pub fn render(&self, kind: NotificationKind, user: &User) -> Result<Notification> {
+        let requested = self.locale_for(user);
+        let (locale, template) = [requested, Locale::En]
+            .into_iter()
+            .find_map(|locale| {
+                self.templates
+                    .get(&(kind, locale))
+                    .map(|template| (locale, template))
+            })
+            .ok_or_else(|| UserError::InvalidConfig {
+                field: "template".to_string(),
+                message: format!("no {:?} template", kind),
+            })?;
+        Ok(Notification {
+            kind,
+            user_id: user.id.clone(),
+            to: user.email.clone(),
+            locale,
+            subject: template.subject.render(user, locale),
+            body: template.body.render(user, locale),
+        })
+    }
+}
+
+impl Default for NotificationTemplates {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+// Delivers rendered notifications, e.g. by email or webhook
+#[async_trait]
+pub trait NotificationSender: fmt::Debug + Send + Sync {
+    /// Short name for logs and metrics, e.g. `smtp`
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, notification: &Notification) -> Result<()>;
+}
+
+// POSTs each notification as JSON, optionally signed
+#[derive(Debug, Clone)]
+pub struct WebhookNotificationSender {
+    client: reqwest::Client,
+    url: String,
+    signer: Option<RequestSigner>,
+}
+
+impl WebhookNotificationSender {
+    pub fn new(client: reqwest::Client, url: impl Into<String>) -> Self {
+        Self {
+            client,
+            url: url.into(),
+            signer: None,
+        }
+    }
+
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+}
+
+#[async_trait]
+impl NotificationSender for WebhookNotificationSender {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        let body = serde_json::to_vec(notification).context("Failed to serialize notification")?;
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .build()
+            .map_err(|e| UserError::ApiError {
+                message: format!("Invalid notification request: {}", e),
+            })?;
+        if let Some(signer) = &self.signer {
+            signer.sign(&mut request, Utc::now())?;
+        }
+        let status = self.client.execute(request).await?.status();
+        if !status.is_success() {
+            return Err(UserError::ApiError {
+                message: format!("Notification endpoint answered {}", status),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// Sends notifications as plain-text email through an SMTP relay
+#[cfg(feature = "smtp")]
+#[derive(Clone)]
+pub struct SmtpNotificationSender {
+    transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
+    from: lettre::message::Mailbox,
+}
+
+#[cfg(feature = "smtp")]
+impl SmtpNotificationSender {
+    /// Relay over TLS to `host`, signing in with `username` and `password`
+    pub fn relay(
+        host: &str,
+        username: impl Into<String>,
+        password: Secret<String>,
+        from: &str,
+    ) -> Result<Self> {
+        let transport = lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::relay(host)
+            .context("Invalid SMTP relay")?
+            .credentials(lettre::transport::smtp::authentication::Credentials::new(
+                username.into(),
+                password.expose().clone(),
+            ))
+            .build();
+        Self::new(transport, from)
+    }
+
+    pub fn new(
+        transport: lettre::AsyncSmtpTransport<lettre::Tokio1Executor>,
+        from: &str,
+    ) -> Result<Self> {
+        let from = from.parse().map_err(|_| UserError::InvalidConfig {
+            field: "from".to_string(),
+            message: format!("{:?} is not a valid sender address", from),
+        })?;
+        Ok(Self { transport, from })
+    }
+}
+
+#[cfg(feature = "smtp")]
+impl fmt::Debug for SmtpNotificationSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SmtpNotificationSender")
+            .field("from", &self.from.to_string())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "smtp")]
+#[async_trait]
+impl NotificationSender for SmtpNotificationSender {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, notification: &Notification) -> Result<()> {
+        use lettre::AsyncTransport;
+
+        let to = notification
+            .to
+            .parse()
+            .map_err(|_| UserError::InvalidEmail {
+                email: notification.to.clone(),
+            })?;
+        let message = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(notification.subject.as_str())
+            .header(lettre::message::header::ContentType::TEXT_PLAIN)
+            .body(notification.body.clone())
+            .context("Failed to build email")?;
+        self.transport
+            .send(message)
+            .await
+            .context("SMTP relay rejected the message")?;
+        Ok(())
+    }
+}
+
+// Renders and sends notifications. As an `EventSink` it welcomes created users and tells
+// users when they are suspended.
+#[derive(Debug)]
+pub struct Notifier {
+    templates: NotificationTemplates,
+    sender: Arc<dyn NotificationSender>,
+    manager: std::sync::Weak<UserManager>,
+}
+
+impl Notifier {
+    pub async fn notify(&self, kind: NotificationKind, user: &User) -> Result<Notification> {
+        let notification = self.templates.render(kind, user)?;
+        let result = self.sender.send(&notification).await;
+        metrics::counter!(
+            metric_names::NOTIFICATIONS,
+            "sender" => self.sender.name(),
+            "outcome" => if result.is_ok() { "sent" } else { "failed" }
+        )
+        .increment(1);
+        result?;
+        Ok(notification)
+    }
+}
+
+#[async_trait]
+impl EventSink for Notifier {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    fn wants(&self, event: &UserEvent) -> bool {
+        match event {
+            UserEvent::UserCreated { .. } => true,
+            UserEvent::UserUpdated { fields, .. } => fields.iter().any(|field| field == "status"),
+            _ => false,
+        }
+    }
+
+    async fn send(&self, event: &UserEvent) -> Result<()> {
+        match event {
+            UserEvent::UserCreated { user, .. } => {
+                self.notify(NotificationKind::Welcome, user).await?;
+            }
+            UserEvent::UserUpdated {
+                tenant, user_id, ..
+            } => {
+                let manager = self.manager.upgrade().context("User manager was dropped")?;
+                let current = match tenant {
+                    Some(tenant) => {
+                        manager
+                            .for_tenant(tenant.clone())
+                            .fetch_user(user_id)
+                            .await?
+                    }
+                    None => manager.fetch_user(user_id).await?,
+                };
+                if let Some(user) = current.filter(|user| user.status == UserStatus::Suspended) {
+                    self.notify(NotificationKind::SuspensionNotice, &user)
+                        .await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl UserManager {
+    /// Send welcome and suspension notices through `sender` as users are created and suspended
+    pub fn start_notifications(
+        self: &Arc<Self>,
+        templates: NotificationTemplates,
+        sender: Arc<dyn NotificationSender>,
+    ) -> Result<()> {
+        self.start_event_sink(Arc::new(self.notifier(templates, sender)))
+    }
+
+    /// A notifier for sending messages on demand, outside of the event bus
+    pub fn notifier(
+        self: &Arc<Self>,
+        templates: NotificationTemplates,
+        sender: Arc<dyn NotificationSender>,
+    ) -> Notifier {
+        Notifier {
+            templates,
+            sender,
+            manager: Arc::downgrade(self),
+        }
+    }
+
+    /// Render and send one notification to a stored user
+    pub async fn notify_user(
+        &self,
+        notifier: &Notifier,
+        kind: NotificationKind,
+        user_id: &str,
+    ) -> Result<Notification> {
+        let user = self
+            .fetch_user(user_id)
+            .await?
+            .ok_or_else(|| UserError::NotFound {
+                id: user_id.to_string(),
+            })?;
+        notifier.notify(kind, &user).await
+    }
+}
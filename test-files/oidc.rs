@@ -0,0 +1,266 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// Claims of a verified OIDC ID token (or userinfo response). Verifying the token is left
+/// to the OIDC library; only the claims are read here.
+pub type OidcClaims = serde_json::Map<String, serde_json::Value>;
+
+// Which claims fill which `User` fields. Loads from config like
+// `{"id_claim": "sub", "claims": {"groups": "groups", "https://example.com/team": "team"}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcClaimMapping {
+    /// Claim holding the user id; `sub` is stable for the issuer, unlike email
+    pub id_claim: String,
+    /// Prepended to the id, e.g. `google:`, when users sign in through several issuers.
+    /// Left empty with `sub` as the id claim, the `iss` claim and `|` are prepended, since
+    /// `sub` is only unique within its issuer.
+    pub id_prefix: String,
+    /// Tried in order for the name; `given_name` and `family_name` are the fallback
+    pub name_claims: Vec<String>,
+    pub email_claim: String,
+    /// Reject claim sets whose `email_verified` is false or missing
+    pub require_verified_email: bool,
+    /// Metadata key for the `locale` claim, matching `NotificationTemplates::with_locale_key`
+    pub locale_key: String,
+    /// Custom claims to metadata keys
+    pub claims: BTreeMap<String, String>,
+}
+
+impl Default for OidcClaimMapping {
+    fn default() -> Self {
+        Self {
+            id_claim: "sub".to_string(),
+            id_prefix: String::new(),
+            name_claims: vec!["name".to_string(), "preferred_username".to_string()],
+            email_claim: "email".to_string(),
+            require_verified_email: true,
+            locale_key: "locale".to_string(),
+            claims: BTreeMap::new(),
+        }
+    }
+}
+
+impl OidcClaimMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).context("Failed to parse OIDC claim mapping")
+    }
+
+    pub fn with_id_claim(mut self, claim: impl Into<String>) -> Self {
+        self.id_claim = claim.into();
+        self
+    }
+
+    pub fn with_id_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.id_prefix = prefix.into();
+        self
+    }
+
+    pub fn with_require_verified_email(mut self, required: bool) -> Self {
+        self.require_verified_email = required;
+        self
+    }
+
+    /// Copy `claim` into metadata under `key`
+    pub fn with_claim(mut self, claim: impl Into<String>, key: impl Into<String>) -> Self {
+        self.claims.insert(claim.into(), key.into());
+        self
+    }
+
+    /// The user id the claims map to
+    pub fn user_id(&self, claims: &OidcClaims) -> Result<String> {
+        let id = match claims.get(&self.id_claim) {
+            Some(serde_json::Value::String(id)) => id.clone(),
+            Some(serde_json::Value::Number(id)) => id.to_string(),
+            _ => String::new(),
+        };
+        if id.is_empty() {
+            return Err(UserError::EmptyField {
+                field: self.id_claim.clone(),
+            }
+            .into());
+        }
+        if self.id_prefix.is_empty() && self.id_claim == "sub" {
+            let issuer = claims
+                .get("iss")
+                .and_then(|issuer| issuer.as_str())
+                .filter(|issuer| !issuer.is_empty())
+                .ok_or_else(|| UserError::EmptyField {
+                    field: "iss".to_string(),
+                })?;
+            return Ok(format!("{}|{}", issuer, id));
+        }
+        Ok(format!("{}{}", self.id_prefix, id))
+    }
+
+    /// A new active user for a first login
+    pub fn user_from_claims(&self, claims: &OidcClaims) -> Result<User> {
+        let mut user = User::new(
+            self.user_id(claims)?,
+            self.name(claims).unwrap_or_default(),
+            self.email(claims)?,
+        )?;
+        for (key, value) in self.metadata(claims) {
+            user.add_metadata(key, value);
+        }
+        if user.name.is_empty() {
+            user.name = user.email.clone();
+        }
+        Ok(user)
+    }
+
+    /// Changes that bring `user` in line with the claims of a later login. Status, creation
+    /// time and metadata the mapping does not cover are left alone; an empty update means
+    /// nothing changed.
+    pub fn update_from_claims(&self, user: &User, claims: &OidcClaims) -> Result<UserUpdate> {
+        let user_id = self.user_id(claims)?;
+        if user_id != user.id {
+            return Err(UserError::InvalidUpdate {
+                field: self.id_claim.clone(),
+                message: format!(
+                    "claims are for {}, not {}",
+                    redact_id(&user_id),
+                    redact_id(&user.id)
+                ),
+            }
+            .into());
+        }
+        let email = self.email(claims)?;
+        let mut update = UserUpdate::new();
+        if let Some(name) = self.name(claims).filter(|name| *name != user.name) {
+            update = update.name(name);
+        }
+        if email != user.email {
+            update = update.email(email);
+        }
+        for (key, value) in self.metadata(claims) {
+            if user.metadata.get(key.as_str()) != Some(&value) {
+                update = update.set_metadata(key, value);
+            }
+        }
+        Ok(update)
+    }
+
+    fn name(&self, claims: &OidcClaims) -> Option<String> {
+        let text = |claim: &str| {
+            claims
+                .get(claim)
+                .and_then(|value| value.as_str())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        self.name_claims
+            .iter()
+            .find_map(|claim| text(claim))
+            .or_else(|| {
+                let parts: Vec<String> = ["given_name", "family_name"]
+                    .iter()
+                    .filter_map(|claim| text(claim))
+                    .collect();
+                (!parts.is_empty()).then(|| parts.join(" "))
+            })
+    }
+
+    fn email(&self, claims: &OidcClaims) -> Result<String> {
+        let email = claims
+            .get(&self.email_claim)
+            .and_then(|email| email.as_str())
+            .filter(|email| !email.is_empty())
+            .ok_or_else(|| UserError::EmptyField {
+                field: self.email_claim.clone(),
+            })?;
+        // Some providers send `email_verified` as the string "true"; one that says
+        // nothing has not vouched for the address
+        let verified = match claims.get("email_verified") {
+            Some(serde_json::Value::Bool(verified)) => *verified,
+            Some(serde_json::Value::String(verified)) => verified.eq_ignore_ascii_case("true"),
+            _ => false,
+        };
+        if self.require_verified_email && !verified {
+            return Err(UserError::InvalidUpdate {
+                field: self.email_claim.clone(),
+                message: "email is not verified".to_string(),
+            }
+            .into());
+        }
+        Ok(email.to_string())
+    }
+
+    fn metadata(&self, claims: &OidcClaims) -> Vec<(String, serde_json::Value)> {
+        let locale = claims
+            .get("locale")
+            .filter(|locale| locale.is_string())
+            .map(|locale| (self.locale_key.clone(), locale.clone()));
+        locale
+            .into_iter()
+            .chain(self.claims.iter().filter_map(|(claim, key)| {
+                claims
+                    .get(claim)
+                    .filter(|value| !value.is_null())
+                    .map(|value| (key.clone(), value.clone()))
+            }))
+            .collect()
+    }
+}
+
+// What a login did to the user store
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcProvisioning {
+    pub user: User,
+    pub created: bool,
+    /// Fields changed on an existing user
+    pub updated: usize,
+}
+
+#[cfg(feature = "client")]
+impl UserManager {
+    /// Create the user on first login, or refresh an existing user's mapped fields from the
+    /// claims. Suspended users are refused.
+    pub async fn provision_from_claims(
+        &self,
+        claims: &OidcClaims,
+        mapping: &OidcClaimMapping,
+    ) -> Result<OidcProvisioning> {
+        let user_id = mapping.user_id(claims)?;
+        let Some(existing) = self.fetch_user(&user_id).await? else {
+            let user = self.create_user(&mapping.user_from_claims(claims)?).await?;
+            tracing::info!(user_id = %redact_id(&user.id), "User provisioned from OIDC claims");
+            return Ok(OidcProvisioning {
+                user,
+                created: true,
+                updated: 0,
+            });
+        };
+        if existing.status == UserStatus::Suspended {
+            return Err(UserError::Vetoed {
+                operation: "login".to_string(),
+                reason: "user is suspended".to_string(),
+            }
+            .into());
+        }
+        let update = mapping.update_from_claims(&existing, claims)?;
+        let updated = update.len();
+        if update.is_empty() {
+            return Ok(OidcProvisioning {
+                user: existing,
+                created: false,
+                updated,
+            });
+        }
+        self.update_user(&user_id, update).await?;
+        let user = self
+            .fetch_user(&user_id)
+            .await?
+            .ok_or(UserError::NotFound { id: user_id })?;
+        Ok(OidcProvisioning {
+            user,
+            created: false,
+            updated,
+        })
+    }
+}
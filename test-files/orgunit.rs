@@ -0,0 +1,337 @@
+use super::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+// A department, team or other unit of the organization
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrgUnit {
+    pub id: String,
+    pub name: String,
+    /// `None` for a top-level unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+impl OrgUnit {
+    pub fn new(id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            name: name.into(),
+            parent: None,
+        }
+    }
+
+    pub fn with_parent(mut self, parent: impl Into<String>) -> Self {
+        self.parent = Some(parent.into());
+        self
+    }
+}
+
+// Statistics for one unit: its own members and everyone in its subtree
+#[derive(Debug, Clone, Serialize)]
+pub struct UnitStatistics {
+    pub unit_id: String,
+    pub name: String,
+    /// 0 for top-level units
+    pub depth: usize,
+    pub direct: UserStatistics,
+    pub rollup: UserStatistics,
+}
+
+/// Organization units as a tree (or several, one per top-level unit), with each user a
+/// member of at most one unit. Units always have a known parent and never sit under
+/// themselves, so walks up and down the tree terminate.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OrgTree {
+    units: BTreeMap<String, OrgUnit>,
+    /// Parent unit id to the ids of its child units, kept in step with `units`
+    #[serde(skip)]
+    children: BTreeMap<String, BTreeSet<String>>,
+    /// User id to unit id
+    members: BTreeMap<String, String>,
+}
+
+impl OrgTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a tree from units in any order, e.g. as loaded from config; an id appearing
+    /// twice is an error rather than the later unit winning
+    pub fn from_units(units: impl IntoIterator<Item = OrgUnit>) -> Result<Self> {
+        let mut pending: Vec<OrgUnit> = units.into_iter().collect();
+        let mut ids = BTreeSet::new();
+        if let Some(unit) = pending.iter().find(|unit| !ids.insert(unit.id.as_str())) {
+            return Err(Self::invalid(format!("unit {} is given twice", unit.id)));
+        }
+        let mut tree = Self::new();
+        while !pending.is_empty() {
+            let before = pending.len();
+            let (ready, waiting): (Vec<OrgUnit>, Vec<OrgUnit>) =
+                pending.into_iter().partition(|unit| {
+                    unit.parent
+                        .as_ref()
+                        .is_none_or(|parent| tree.units.contains_key(parent))
+                });
+            for unit in ready {
+                tree.insert(unit)?;
+            }
+            pending = waiting;
+            if pending.len() == before {
+                // Whatever is left has a missing parent or sits in a cycle
+                let unit = &pending[0];
+                return Err(Self::invalid(format!(
+                    "parent {} of {} is unknown or under it",
+                    unit.parent.as_deref().unwrap_or_default(),
+                    unit.id
+                )));
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Add a unit, or rename and move an existing one along with its subtree
+    pub fn insert(&mut self, unit: OrgUnit) -> Result<()> {
+        if unit.id.is_empty() {
+            return Err(UserError::EmptyField {
+                field: "org_unit.id".to_string(),
+            }
+            .into());
+        }
+        if let Some(parent) = &unit.parent {
+            if !self.units.contains_key(parent) {
+                return Err(Self::invalid(format!("unknown parent unit {}", parent)));
+            }
+            if *parent == unit.id || self.is_ancestor(&unit.id, parent) {
+                return Err(Self::invalid(format!(
+                    "{} cannot be moved under its own subtree",
+                    unit.id
+                )));
+            }
+        }
+        if let Some(parent) = &unit.parent {
+            self.children
+                .entry(parent.clone())
+                .or_default()
+                .insert(unit.id.clone());
+        }
+        if let Some(previous) = self.units.insert(unit.id.clone(), unit) {
+            let parent = self.units[&previous.id].parent.as_ref();
+            if previous.parent.as_ref() != parent {
+                self.unlink_child(previous.parent.as_deref(), &previous.id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a unit, moving its child units and members up to its parent; members of a
+    /// removed top-level unit are left without one
+    pub fn remove(&mut self, unit_id: &str) -> Option<OrgUnit> {
+        let unit = self.units.remove(unit_id)?;
+        self.unlink_child(unit.parent.as_deref(), unit_id);
+        for child_id in self.children.remove(unit_id).unwrap_or_default() {
+            if let Some(child) = self.units.get_mut(&child_id) {
+                child.parent = unit.parent.clone();
+            }
+            if let Some(parent) = &unit.parent {
+                self.children
+                    .entry(parent.clone())
+                    .or_default()
+                    .insert(child_id);
+            }
+        }
+        match &unit.parent {
+            Some(parent) => {
+                for unit in self.members.values_mut() {
+                    if unit == unit_id {
+                        *unit = parent.clone();
+                    }
+                }
+            }
+            None => self.members.retain(|_, unit| unit != unit_id),
+        }
+        Some(unit)
+    }
+
+    pub fn get(&self, unit_id: &str) -> Option<&OrgUnit> {
+        self.units.get(unit_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// Every unit, by id
+    pub fn units(&self) -> impl Iterator<Item = &OrgUnit> {
+        self.units.values()
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = &OrgUnit> {
+        self.units.values().filter(|unit| unit.parent.is_none())
+    }
+
+    pub fn children(&self, unit_id: &str) -> impl Iterator<Item = &OrgUnit> {
+        self.children
+            .get(unit_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|child_id| self.units.get(child_id))
+    }
+
+    /// Units above `unit_id`, nearest first
+    pub fn ancestors(&self, unit_id: &str) -> Vec<&OrgUnit> {
+        let mut ancestors = Vec::new();
+        let mut current = self
+            .units
+            .get(unit_id)
+            .and_then(|unit| unit.parent.as_ref());
+        while let Some(unit) = current.and_then(|id| self.units.get(id)) {
+            ancestors.push(unit);
+            current = unit.parent.as_ref();
+        }
+        ancestors
+    }
+
+    /// Units below `unit_id`, depth first with each parent before its children
+    pub fn descendants(&self, unit_id: &str) -> Vec<&OrgUnit> {
+        let mut descendants = Vec::new();
+        // Reversed so the stack pops them in id order
+        let mut stack: Vec<&OrgUnit> = self.children(unit_id).collect();
+        stack.reverse();
+        while let Some(unit) = stack.pop() {
+            descendants.push(unit);
+            let start = stack.len();
+            stack.extend(self.children(&unit.id));
+            stack[start..].reverse();
+        }
+        descendants
+    }
+
+    /// Whether `ancestor` is above `unit_id` in the tree
+    pub fn is_ancestor(&self, ancestor: &str, unit_id: &str) -> bool {
+        self.ancestors(unit_id)
+            .iter()
+            .any(|unit| unit.id == ancestor)
+    }
+
+    /// Unit names from the top of the tree down to `unit_id`
+    pub fn path(&self, unit_id: &str) -> Vec<&str> {
+        let mut path: Vec<&str> = self
+            .ancestors(unit_id)
+            .into_iter()
+            .map(|unit| unit.name.as_str())
+            .collect();
+        path.reverse();
+        path.extend(self.units.get(unit_id).map(|unit| unit.name.as_str()));
+        path
+    }
+
+    /// Make the user a member of `unit_id`, returning the unit it left
+    pub fn assign(&mut self, user_id: impl Into<String>, unit_id: &str) -> Result<Option<String>> {
+        if !self.units.contains_key(unit_id) {
+            return Err(Self::invalid(format!("unknown unit {}", unit_id)));
+        }
+        Ok(self.members.insert(user_id.into(), unit_id.to_string()))
+    }
+
+    pub fn unassign(&mut self, user_id: &str) -> Option<String> {
+        self.members.remove(user_id)
+    }
+
+    /// Assign each user to the unit named by their `key` metadata, returning the ids of
+    /// users naming an unknown unit; users without the key are left as they are
+    pub fn assign_from_metadata(&mut self, users: &[User], key: &str) -> Vec<String> {
+        let mut unknown = Vec::new();
+        for user in users {
+            let Some(unit_id) = user.metadata.get(key).and_then(|unit| unit.as_str()) else {
+                continue;
+            };
+            if self.assign(user.id.clone(), unit_id).is_err() {
+                unknown.push(user.id.clone());
+            }
+        }
+        unknown
+    }
+
+    pub fn unit_of(&self, user_id: &str) -> Option<&OrgUnit> {
+        self.members
+            .get(user_id)
+            .and_then(|unit_id| self.units.get(unit_id))
+    }
+
+    /// Ids of the unit's own members
+    pub fn members<'a>(&'a self, unit_id: &'a str) -> impl Iterator<Item = &'a str> {
+        self.members
+            .iter()
+            .filter(move |(_, unit)| *unit == unit_id)
+            .map(|(user_id, _)| user_id.as_str())
+    }
+
+    /// Whether the user belongs to `unit_id` or a unit below it
+    pub fn is_member(&self, user_id: &str, unit_id: &str) -> bool {
+        self.members
+            .get(user_id)
+            .is_some_and(|unit| unit == unit_id || self.is_ancestor(unit_id, unit))
+    }
+
+    /// Statistics for every unit over `users`, each unit's rollup covering its whole
+    /// subtree; users in no unit are left out
+    pub fn statistics_at(&self, users: &[User], now: DateTime<Utc>) -> Vec<UnitStatistics> {
+        let mut direct: BTreeMap<&str, Vec<&User>> = BTreeMap::new();
+        let mut rollup: BTreeMap<&str, Vec<&User>> = BTreeMap::new();
+        for user in users {
+            let Some(unit) = self.unit_of(&user.id) else {
+                continue;
+            };
+            direct.entry(&unit.id).or_default().push(user);
+            for unit in std::iter::once(unit).chain(self.ancestors(&unit.id)) {
+                rollup.entry(&unit.id).or_default().push(user);
+            }
+        }
+        self.units
+            .values()
+            .map(|unit| {
+                let stats = |users: &BTreeMap<&str, Vec<&User>>| {
+                    UserStatistics::from_users_at(
+                        users.get(unit.id.as_str()).into_iter().flatten().copied(),
+                        now,
+                    )
+                };
+                UnitStatistics {
+                    unit_id: unit.id.clone(),
+                    name: unit.name.clone(),
+                    depth: self.ancestors(&unit.id).len(),
+                    direct: stats(&direct),
+                    rollup: stats(&rollup),
+                }
+            })
+            .collect()
+    }
+
+    pub fn statistics(&self, users: &[User]) -> Vec<UnitStatistics> {
+        self.statistics_at(users, Utc::now())
+    }
+
+    fn unlink_child(&mut self, parent: Option<&str>, unit_id: &str) {
+        let Some(parent) = parent else {
+            return;
+        };
+        if let Some(children) = self.children.get_mut(parent) {
+            children.remove(unit_id);
+            if children.is_empty() {
+                self.children.remove(parent);
+            }
+        }
+    }
+
+    fn invalid(message: String) -> anyhow::Error {
+        UserError::InvalidUpdate {
+            field: "org_unit".to_string(),
+            message,
+        }
+        .into()
+    }
+}
@@ -0,0 +1,351 @@
+use super::*;
+use crate::signing::{open_token, seal_token};
+
+// What a reset token vouches for, signed into the token itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordResetClaims {
+    pub user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<TenantId>,
+    /// Makes every token distinct and is what marks it used
+    pub nonce: String,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+// A freshly issued token, to be delivered to the user out of band
+#[derive(Debug, Clone)]
+pub struct PasswordResetToken {
+    pub token: Secret<String>,
+    pub claims: PasswordResetClaims,
+}
+
+// Where passwords live; the user record itself holds none
+#[async_trait]
+pub trait CredentialStore: fmt::Debug + Send + Sync {
+    async fn set_password(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        password: &Secret<String>,
+    ) -> Result<()>;
+}
+
+/// Issues and redeems password-reset tokens: `<claims>.<signature>`, both base64url without
+/// padding, with an HMAC-SHA256 signature over the claims' JSON. A token is rejected once
+/// expired, once redeemed, and once any later reset of the same user has completed.
+#[derive(Debug)]
+pub struct PasswordReset {
+    secret: Secret<Vec<u8>>,
+    ttl: chrono::Duration,
+    credentials: Arc<dyn CredentialStore>,
+    /// Where `state` is saved, so used tokens stay used across restarts
+    path: Option<PathBuf>,
+    state: std::sync::Mutex<ResetState>,
+    /// Nonces of tokens whose new password is being stored
+    in_flight: std::sync::Mutex<std::collections::HashSet<String>>,
+}
+
+#[derive(Debug, Default)]
+struct ResetState {
+    /// Redeemed nonces until their tokens expire
+    redeemed: HashMap<String, DateTime<Utc>>,
+    /// When each user last completed a reset, until no token it supersedes can be valid
+    completed: HashMap<UserKey, DateTime<Utc>>,
+}
+
+// One line of a persisted `ResetState`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ResetRecord {
+    Redeemed {
+        nonce: String,
+        expires_at: DateTime<Utc>,
+    },
+    Completed {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<TenantId>,
+        user_id: String,
+        completed_at: DateTime<Utc>,
+    },
+}
+
+impl PasswordReset {
+    /// The `purpose` claim of its tokens
+    const TOKEN_PURPOSE: &'static str = "password_reset";
+    pub const DEFAULT_TTL_MINUTES: i64 = 30;
+    pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+    /// Tokens are tracked in memory only, so a restart forgets which were used; see
+    /// `with_state_file`
+    pub fn new(secret: impl Into<Vec<u8>>, credentials: Arc<dyn CredentialStore>) -> Self {
+        Self {
+            secret: Secret::new(secret.into()),
+            ttl: chrono::Duration::minutes(Self::DEFAULT_TTL_MINUTES),
+            credentials,
+            path: None,
+            state: Default::default(),
+            in_flight: Default::default(),
+        }
+    }
+
+    /// How long tokens stay valid after being issued
+    pub fn with_ttl(mut self, ttl: chrono::Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Keep used tokens and completed resets as JSON lines at `path`, reloading anything
+    /// left from a previous run
+    pub fn with_state_file(mut self, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read reset state {}", path.display()))
+            }
+        };
+        let mut state = ResetState::default();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line).context("Corrupt reset state entry")? {
+                ResetRecord::Redeemed { nonce, expires_at } => {
+                    state.redeemed.insert(nonce, expires_at);
+                }
+                ResetRecord::Completed {
+                    tenant,
+                    user_id,
+                    completed_at,
+                } => {
+                    state.completed.insert((tenant, user_id), completed_at);
+                }
+            }
+        }
+        self.path = Some(path);
+        self.state = std::sync::Mutex::new(state);
+        Ok(self)
+    }
+
+    fn persist(&self, state: &ResetState) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let redeemed = state
+            .redeemed
+            .iter()
+            .map(|(nonce, expires_at)| ResetRecord::Redeemed {
+                nonce: nonce.clone(),
+                expires_at: *expires_at,
+            });
+        let completed = state
+            .completed
+            .iter()
+            .map(|((tenant, user_id), completed_at)| ResetRecord::Completed {
+                tenant: tenant.clone(),
+                user_id: user_id.clone(),
+                completed_at: *completed_at,
+            });
+        let mut contents = String::new();
+        for record in redeemed.chain(completed) {
+            contents.push_str(
+                &serde_json::to_string(&record).context("Failed to serialize reset state")?,
+            );
+            contents.push('\n');
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, contents)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .with_context(|| format!("Failed to persist reset state {}", path.display()))
+    }
+
+    pub fn issue_at(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        now: DateTime<Utc>,
+    ) -> PasswordResetToken {
+        let claims = PasswordResetClaims {
+            user_id: user_id.to_string(),
+            tenant: tenant.cloned(),
+            nonce: uuid::Uuid::new_v4().simple().to_string(),
+            issued_at: now,
+            expires_at: now + self.ttl,
+        };
+        PasswordResetToken {
+            token: Secret::new(seal_token(
+                self.secret.expose(),
+                Self::TOKEN_PURPOSE,
+                &claims,
+            )),
+            claims,
+        }
+    }
+
+    /// Check a token's signature, expiry and freshness without using it up
+    pub fn verify_at(&self, token: &str, now: DateTime<Utc>) -> Result<PasswordResetClaims> {
+        let claims: PasswordResetClaims =
+            open_token(self.secret.expose(), Self::TOKEN_PURPOSE, token)
+                .map_err(|fault| invalid(format!("token {}", fault)))?;
+        if now >= claims.expires_at {
+            return Err(invalid("token has expired"));
+        }
+        let state = self.state.lock().expect("reset tokens poisoned");
+        if state.redeemed.contains_key(&claims.nonce) {
+            return Err(invalid("token has already been used"));
+        }
+        let key = (claims.tenant.clone(), claims.user_id.clone());
+        if state
+            .completed
+            .get(&key)
+            .is_some_and(|completed_at| claims.issued_at <= *completed_at)
+        {
+            return Err(invalid("token was superseded by a later reset"));
+        }
+        Ok(claims)
+    }
+
+    /// Verify a token and mark it used, so only the first of concurrent redemptions wins
+    pub fn redeem_at(&self, token: &str, now: DateTime<Utc>) -> Result<PasswordResetClaims> {
+        let claims = self.reserve_at(token, now)?;
+        self.settle(&claims, now, true)?;
+        Ok(claims)
+    }
+
+    /// Verify a token and hold it while its new password is stored, so only the first of
+    /// concurrent redemptions proceeds; `settle` then uses it up or frees it
+    fn reserve_at(&self, token: &str, now: DateTime<Utc>) -> Result<PasswordResetClaims> {
+        let mut in_flight = self.in_flight.lock().expect("reset tokens poisoned");
+        let claims = self.verify_at(token, now)?;
+        if !in_flight.insert(claims.nonce.clone()) {
+            return Err(invalid("token is already being used"));
+        }
+        Ok(claims)
+    }
+
+    /// Use up a reserved token once its password is stored, or free it for a retry
+    fn settle(&self, claims: &PasswordResetClaims, now: DateTime<Utc>, stored: bool) -> Result<()> {
+        let saved = if stored {
+            let mut state = self.state.lock().expect("reset tokens poisoned");
+            state.redeemed.retain(|_, expires_at| now < *expires_at);
+            state
+                .redeemed
+                .insert(claims.nonce.clone(), claims.expires_at);
+            let oldest_valid = now - self.ttl;
+            state
+                .completed
+                .retain(|_, completed_at| *completed_at >= oldest_valid);
+            state
+                .completed
+                .insert((claims.tenant.clone(), claims.user_id.clone()), now);
+            self.persist(&state)
+        } else {
+            Ok(())
+        };
+        self.in_flight
+            .lock()
+            .expect("reset tokens poisoned")
+            .remove(&claims.nonce);
+        saved
+    }
+
+    pub fn credentials(&self) -> &Arc<dyn CredentialStore> {
+        &self.credentials
+    }
+}
+
+fn invalid(message: impl Into<String>) -> anyhow::Error {
+    UserError::InvalidResetToken {
+        message: message.into(),
+    }
+    .into()
+}
+
+impl UserManager {
+    fn require_password_reset(&self) -> Result<&Arc<PasswordReset>> {
+        self.password_reset().ok_or_else(|| {
+            UserError::InvalidConfig {
+                field: "password_reset".to_string(),
+                message: "no password reset configured".to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Issue a reset token for an existing, unsuspended user; delivering it is up to the caller
+    pub async fn request_password_reset(&self, user_id: &str) -> Result<PasswordResetToken> {
+        let reset = self.require_password_reset()?;
+        let user = self
+            .fetch_user(user_id)
+            .await?
+            .ok_or_else(|| UserError::NotFound {
+                id: user_id.to_string(),
+            })?;
+        if user.status == UserStatus::Suspended {
+            return Err(UserError::Vetoed {
+                operation: "password reset".to_string(),
+                reason: "user is suspended".to_string(),
+            }
+            .into());
+        }
+        metrics::counter!(metric_names::PASSWORD_RESETS, "outcome" => "requested").increment(1);
+        tracing::info!(user_id = %redact_id(user_id), "Password reset requested");
+        Ok(reset.issue_at(self.tenant(), user_id, Utc::now()))
+    }
+
+    /// Set a new password with a reset token, then revoke the user's sessions. The token is
+    /// only used up once the password is accepted, so a rejected password can be retried.
+    pub async fn complete_password_reset(
+        &self,
+        token: &str,
+        new_password: Secret<String>,
+    ) -> Result<String> {
+        let reset = self.require_password_reset()?;
+        let result = async {
+            let now = Utc::now();
+            let claims = reset.verify_at(token, now)?;
+            if claims.tenant.as_ref() != self.tenant() {
+                return Err(invalid("token belongs to another tenant"));
+            }
+            if new_password.expose().chars().count() < PasswordReset::MIN_PASSWORD_LENGTH {
+                return Err(UserError::InvalidUpdate {
+                    field: "password".to_string(),
+                    message: format!(
+                        "must be at least {} characters",
+                        PasswordReset::MIN_PASSWORD_LENGTH
+                    ),
+                }
+                .into());
+            }
+            if self.fetch_user(&claims.user_id).await?.is_none() {
+                return Err(UserError::NotFound { id: claims.user_id }.into());
+            }
+            let claims = reset.reserve_at(token, now)?;
+            let stored = reset
+                .credentials()
+                .set_password(self.tenant(), &claims.user_id, &new_password)
+                .await;
+            reset.settle(&claims, now, stored.is_ok())?;
+            stored?;
+            Ok(claims.user_id)
+        }
+        .await;
+        let outcome = if result.is_ok() {
+            "completed"
+        } else {
+            "rejected"
+        };
+        metrics::counter!(metric_names::PASSWORD_RESETS, "outcome" => outcome).increment(1);
+        let user_id = result?;
+        let sessions_revoked = match self.session_store() {
+            Some(store) => store.revoke_all(self.tenant(), &user_id),
+            None => 0,
+        };
+        tracing::info!(
+            user_id = %redact_id(&user_id),
+            sessions_revoked,
+            "Password reset completed"
+        );
+        Ok(user_id)
+    }
+}
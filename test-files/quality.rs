@@ -0,0 +1,614 @@
+use super::*;
+use std::collections::BTreeMap;
+
+// How much a data-quality issue matters, least first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(anyhow::anyhow!("Unknown severity: {}", value)),
+        }
+    }
+}
+
+// What is wrong with a user record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// Fails `User::is_strict_email`
+    InvalidEmail,
+    EmptyName,
+    /// Same email as another user, ignoring case
+    DuplicateEmail,
+    /// Same email as another user once a `+tag` in the local part is ignored
+    SimilarEmail,
+    /// Same name as another user with a different email
+    DuplicateName,
+    /// Pending or inactive for longer than the analyzer's stale period
+    StaleAccount,
+    /// A metadata value whose JSON type differs from most users' value for the key
+    MetadataType,
+}
+
+impl IssueKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IssueKind::InvalidEmail => "invalid_email",
+            IssueKind::EmptyName => "empty_name",
+            IssueKind::DuplicateEmail => "duplicate_email",
+            IssueKind::SimilarEmail => "similar_email",
+            IssueKind::DuplicateName => "duplicate_name",
+            IssueKind::StaleAccount => "stale_account",
+            IssueKind::MetadataType => "metadata_type",
+        }
+    }
+
+    pub fn severity(self) -> Severity {
+        match self {
+            IssueKind::InvalidEmail | IssueKind::EmptyName | IssueKind::DuplicateEmail => {
+                Severity::Error
+            }
+            IssueKind::SimilarEmail | IssueKind::StaleAccount | IssueKind::MetadataType => {
+                Severity::Warning
+            }
+            IssueKind::DuplicateName => Severity::Info,
+        }
+    }
+}
+
+// One finding about one user; messages name ids and fields, never personal data
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityIssue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+    pub user_id: String,
+    /// Other users involved, e.g. the first user sharing a duplicate email
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub related: Vec<String>,
+    pub message: String,
+}
+
+// Findings over a user set, most severe first; serializes for machine consumption
+#[derive(Debug, Clone, Serialize)]
+pub struct QualityReport {
+    pub generated_at: DateTime<Utc>,
+    pub users_scanned: usize,
+    pub counts: BTreeMap<Severity, usize>,
+    pub issues: Vec<QualityIssue>,
+    /// Per-user scores, when the report asked for them; worst first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scores: Vec<QualityScore>,
+}
+
+impl QualityReport {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.counts.get(&severity).copied().unwrap_or(0)
+    }
+
+    /// Severity of the worst issue, `None` for a clean set
+    pub fn worst(&self) -> Option<Severity> {
+        self.issues.iter().map(|issue| issue.severity).max()
+    }
+
+    pub fn at_least(&self, severity: Severity) -> impl Iterator<Item = &QualityIssue> {
+        self.issues
+            .iter()
+            .filter(move |issue| issue.severity >= severity)
+    }
+}
+
+impl fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for score in &self.scores {
+            writeln!(f, "{}", score)?;
+        }
+        for issue in &self.issues {
+            writeln!(
+                f,
+                "{:<7} {:<15} {}: {}",
+                issue.severity,
+                issue.kind.as_str(),
+                issue.user_id,
+                issue.message
+            )?;
+        }
+        write!(
+            f,
+            "{} users scanned: {} errors, {} warnings, {} info",
+            self.users_scanned,
+            self.count(Severity::Error),
+            self.count(Severity::Warning),
+            self.count(Severity::Info)
+        )
+    }
+}
+
+// Scans a user set for data-quality problems
+#[derive(Debug, Clone)]
+pub struct QualityAnalyzer {
+    stale_after: chrono::Duration,
+}
+
+impl Default for QualityAnalyzer {
+    fn default() -> Self {
+        Self {
+            stale_after: chrono::Duration::days(Self::DEFAULT_STALE_DAYS),
+        }
+    }
+}
+
+impl QualityAnalyzer {
+    pub const DEFAULT_STALE_DAYS: i64 = 180;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long a pending or inactive account may sit before it counts as stale
+    pub fn with_stale_after(mut self, stale_after: chrono::Duration) -> Self {
+        self.stale_after = stale_after;
+        self
+    }
+
+    pub fn analyze(&self, users: &[User]) -> QualityReport {
+        self.analyze_at(users, Utc::now())
+    }
+
+    /// Analyze relative to a fixed point in time, which decides staleness
+    pub fn analyze_at(&self, users: &[User], now: DateTime<Utc>) -> QualityReport {
+        let mut issues = Vec::new();
+        let mut issue = |kind: IssueKind, user: &User, related: Vec<String>, message: String| {
+            issues.push(QualityIssue {
+                severity: kind.severity(),
+                kind,
+                user_id: user.id.clone(),
+                related,
+                message,
+            })
+        };
+
+        let mut emails: HashMap<String, &User> = HashMap::new();
+        let mut similar_emails: HashMap<String, &User> = HashMap::new();
+        let mut names: HashMap<String, &User> = HashMap::new();
+        for user in users {
+            if !User::is_strict_email(&user.email) {
+                issue(
+                    IssueKind::InvalidEmail,
+                    user,
+                    Vec::new(),
+                    "email fails strict validation".to_string(),
+                );
+            }
+            let name = user.name.split_whitespace().collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                issue(
+                    IssueKind::EmptyName,
+                    user,
+                    Vec::new(),
+                    "name is empty".to_string(),
+                );
+            }
+
+            let email = user.email.trim().to_lowercase();
+            let similar = Self::canonical_email(&email);
+            if let Some(first) = emails.get(&email) {
+                issue(
+                    IssueKind::DuplicateEmail,
+                    user,
+                    vec![first.id.clone()],
+                    format!("email already used by {}", first.id),
+                );
+            } else if let Some(first) = similar_emails.get(&similar) {
+                issue(
+                    IssueKind::SimilarEmail,
+                    user,
+                    vec![first.id.clone()],
+                    format!("email differs from {}'s only by a +tag", first.id),
+                );
+            } else if let Some(first) = names.get(&name.to_lowercase()) {
+                issue(
+                    IssueKind::DuplicateName,
+                    user,
+                    vec![first.id.clone()],
+                    format!("same name as {}", first.id),
+                );
+            }
+            emails.entry(email).or_insert(user);
+            similar_emails.entry(similar).or_insert(user);
+            if !name.is_empty() {
+                names.entry(name.to_lowercase()).or_insert(user);
+            }
+
+            if matches!(user.status, UserStatus::Pending | UserStatus::Inactive)
+                && now - user.created_at > self.stale_after
+            {
+                issue(
+                    IssueKind::StaleAccount,
+                    user,
+                    Vec::new(),
+                    format!(
+                        "{} for {} days",
+                        user.status.as_str(),
+                        user.days_active_at(now)
+                    ),
+                );
+            }
+        }
+
+        // Most common JSON type of each metadata key; ties go to the alphabetically first type
+        let mut types: BTreeMap<&str, BTreeMap<&'static str, usize>> = BTreeMap::new();
+        for user in users {
+            for (key, value) in &user.metadata {
+                *types
+                    .entry(key.as_ref())
+                    .or_default()
+                    .entry(Self::json_type(value))
+                    .or_default() += 1;
+            }
+        }
+        let expected: HashMap<&str, &'static str> = types
+            .iter()
+            .filter(|(_, counts)| counts.len() > 1)
+            .map(|(key, counts)| {
+                let (expected, _) = counts
+                    .iter()
+                    .max_by(|(a, x), (b, y)| x.cmp(y).then(b.cmp(a)))
+                    .expect("metadata key seen at least once");
+                (*key, *expected)
+            })
+            .collect();
+        for user in users {
+            let mut keys: Vec<&str> = user.metadata.keys().map(AsRef::as_ref).collect();
+            keys.sort_unstable();
+            for key in keys {
+                let actual = Self::json_type(&user.metadata[key]);
+                if let Some(expected) = expected.get(key).filter(|expected| **expected != actual) {
+                    issue(
+                        IssueKind::MetadataType,
+                        user,
+                        Vec::new(),
+                        format!("metadata {:?} is {}, usually {}", key, actual, expected),
+                    );
+                }
+            }
+        }
+
+        issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+        let mut counts = BTreeMap::new();
+        for issue in &issues {
+            *counts.entry(issue.severity).or_default() += 1;
+        }
+        QualityReport {
+            generated_at: now,
+            users_scanned: users.len(),
+            counts,
+            issues,
+            scores: Vec::new(),
+        }
+    }
+
+    /// Lowercased email without any `+tag` in the local part
+    pub(crate) fn canonical_email(email: &str) -> String {
+        match email.split_once('@') {
+            Some((local, domain)) => {
+                let local = local.split_once('+').map_or(local, |(base, _)| base);
+                format!("{}@{}", local, domain)
+            }
+            None => email.to_string(),
+        }
+    }
+
+    fn json_type(value: &serde_json::Value) -> &'static str {
+        match value {
+            serde_json::Value::Null => "null",
+            serde_json::Value::Bool(_) => "boolean",
+            serde_json::Value::Number(_) => "number",
+            serde_json::Value::String(_) => "string",
+            serde_json::Value::Array(_) => "array",
+            serde_json::Value::Object(_) => "object",
+        }
+    }
+}
+
+// A part of a user's quality score
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreComponent {
+    /// 1 for a strictly valid email, 0.5 for one that only passes the create rule
+    Email,
+    /// 1 for a real name, 0.5 for a placeholder such as the email or id
+    Name,
+    /// Share of the expected metadata keys holding a non-empty value
+    Metadata,
+    /// 1 when seen recently, falling to 0 at the dormancy cutoff or when never seen
+    Activity,
+}
+
+impl ScoreComponent {
+    pub const ALL: [ScoreComponent; 4] = [
+        ScoreComponent::Email,
+        ScoreComponent::Name,
+        ScoreComponent::Metadata,
+        ScoreComponent::Activity,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScoreComponent::Email => "email",
+            ScoreComponent::Name => "name",
+            ScoreComponent::Metadata => "metadata",
+            ScoreComponent::Activity => "activity",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ComponentScore {
+    pub component: ScoreComponent,
+    pub weight: u32,
+    /// From 0 to 1
+    pub value: f64,
+    pub detail: String,
+}
+
+// A user's 0-100 quality score and the components behind it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityScore {
+    pub user_id: String,
+    pub score: u8,
+    /// Components that counted, i.e. had a non-zero weight
+    pub components: Vec<ComponentScore>,
+}
+
+impl QualityScore {
+    pub fn component(&self, component: ScoreComponent) -> Option<&ComponentScore> {
+        self.components
+            .iter()
+            .find(|score| score.component == component)
+    }
+}
+
+impl fmt::Display for QualityScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>3} {}", self.score, self.user_id)?;
+        for component in &self.components {
+            write!(
+                f,
+                " {}={:.2}",
+                component.component.as_str(),
+                component.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Scores each user's completeness and validity from 0 to 100, as the weighted mean of
+/// the `ScoreComponent`s. A zero weight leaves a component out, as does `Metadata` while
+/// no keys are expected.
+#[derive(Debug, Clone)]
+pub struct QualityScorer {
+    weights: BTreeMap<ScoreComponent, u32>,
+    expected_metadata: Vec<String>,
+    active_within: chrono::Duration,
+    dormant_after: chrono::Duration,
+}
+
+impl Default for QualityScorer {
+    fn default() -> Self {
+        Self {
+            weights: BTreeMap::from([
+                (ScoreComponent::Email, 35),
+                (ScoreComponent::Name, 20),
+                (ScoreComponent::Metadata, 20),
+                (ScoreComponent::Activity, 25),
+            ]),
+            expected_metadata: Vec::new(),
+            active_within: chrono::Duration::days(Self::DEFAULT_ACTIVE_DAYS),
+            dormant_after: chrono::Duration::days(Self::DEFAULT_DORMANT_DAYS),
+        }
+    }
+}
+
+impl QualityScorer {
+    pub const DEFAULT_ACTIVE_DAYS: i64 = 30;
+    pub const DEFAULT_DORMANT_DAYS: i64 = 365;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_weight(mut self, component: ScoreComponent, weight: u32) -> Self {
+        self.weights.insert(component, weight);
+        self
+    }
+
+    /// Metadata keys every user should have filled
+    pub fn with_expected_metadata<I, S>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.expected_metadata = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Full activity credit when last seen within `active_within`, none after `dormant_after`
+    pub fn with_activity_window(
+        mut self,
+        active_within: chrono::Duration,
+        dormant_after: chrono::Duration,
+    ) -> Self {
+        self.active_within = active_within;
+        self.dormant_after = dormant_after.max(active_within);
+        self
+    }
+
+    pub fn weight(&self, component: ScoreComponent) -> u32 {
+        match component {
+            ScoreComponent::Metadata if self.expected_metadata.is_empty() => 0,
+            component => self.weights.get(&component).copied().unwrap_or(0),
+        }
+    }
+
+    /// Score `user`, last seen at `last_seen`, relative to `now`
+    pub fn score_at(
+        &self,
+        user: &User,
+        last_seen: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> QualityScore {
+        let components: Vec<ComponentScore> = ScoreComponent::ALL
+            .into_iter()
+            .filter(|component| self.weight(*component) > 0)
+            .map(|component| {
+                let (value, detail) = match component {
+                    ScoreComponent::Email => Self::email(&user.email),
+                    ScoreComponent::Name => Self::name(user),
+                    ScoreComponent::Metadata => self.metadata(user),
+                    ScoreComponent::Activity => self.activity(last_seen, now),
+                };
+                ComponentScore {
+                    component,
+                    weight: self.weight(component),
+                    value,
+                    detail,
+                }
+            })
+            .collect();
+        // Summed wide, since any weight may be up to `u32::MAX`
+        let total: u64 = components
+            .iter()
+            .map(|component| u64::from(component.weight))
+            .sum();
+        let earned: f64 = components
+            .iter()
+            .map(|component| f64::from(component.weight) * component.value)
+            .sum();
+        let score = if total == 0 {
+            100
+        } else {
+            (100.0 * earned / total as f64).round() as u8
+        };
+        QualityScore {
+            user_id: user.id.clone(),
+            score,
+            components,
+        }
+    }
+
+    /// Score every user, worst first; `last_seen` looks up a user's last activity
+    pub fn score_all_at(
+        &self,
+        users: &[User],
+        last_seen: impl Fn(&User) -> Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Vec<QualityScore> {
+        let mut scores: Vec<QualityScore> = users
+            .iter()
+            .map(|user| self.score_at(user, last_seen(user), now))
+            .collect();
+        scores.sort_by(|a, b| {
+            a.score
+                .cmp(&b.score)
+                .then_with(|| a.user_id.cmp(&b.user_id))
+        });
+        scores
+    }
+
+    fn email(email: &str) -> (f64, String) {
+        if User::is_strict_email(email) {
+            (1.0, "valid".to_string())
+        } else if User::is_valid_email(email) {
+            (0.5, "fails strict validation".to_string())
+        } else {
+            (0.0, "invalid".to_string())
+        }
+    }
+
+    fn name(user: &User) -> (f64, String) {
+        let name = user.name.trim();
+        if name.is_empty() {
+            (0.0, "empty".to_string())
+        } else if name.contains('@') || name == user.id {
+            (0.5, "placeholder".to_string())
+        } else {
+            (1.0, "present".to_string())
+        }
+    }
+
+    fn metadata(&self, user: &User) -> (f64, String) {
+        let filled = self
+            .expected_metadata
+            .iter()
+            .filter(|key| match user.metadata.get(key.as_str()) {
+                None | Some(serde_json::Value::Null) => false,
+                Some(serde_json::Value::String(value)) => !value.trim().is_empty(),
+                Some(serde_json::Value::Array(items)) => !items.is_empty(),
+                Some(serde_json::Value::Object(fields)) => !fields.is_empty(),
+                Some(_) => true,
+            })
+            .count();
+        let expected = self.expected_metadata.len();
+        (
+            filled as f64 / expected as f64,
+            format!("{} of {} expected keys filled", filled, expected),
+        )
+    }
+
+    fn activity(&self, last_seen: Option<DateTime<Utc>>, now: DateTime<Utc>) -> (f64, String) {
+        let Some(last_seen) = last_seen else {
+            return (0.0, "never seen".to_string());
+        };
+        let idle = now - last_seen;
+        let value = if idle <= self.active_within {
+            1.0
+        } else if idle >= self.dormant_after {
+            0.0
+        } else {
+            let span = (self.dormant_after - self.active_within).num_seconds() as f64;
+            1.0 - (idle - self.active_within).num_seconds() as f64 / span
+        };
+        (value, format!("last seen {} days ago", idle.num_days()))
+    }
+}
+
+#[cfg(feature = "client")]
+impl UserManager {
+    /// Score `users`, worst first. Activity comes from the activity log; without one the
+    /// activity component is left out rather than counted as never seen.
+    pub fn quality_scores(&self, users: &[User], scorer: &QualityScorer) -> Vec<QualityScore> {
+        let now = Utc::now();
+        match self.activity_log() {
+            Some(log) => {
+                scorer.score_all_at(users, |user| log.last_seen(self.tenant(), &user.id), now)
+            }
+            None => scorer
+                .clone()
+                .with_weight(ScoreComponent::Activity, 0)
+                .score_all_at(users, |_| None, now),
+        }
+    }
+}
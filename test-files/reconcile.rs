@@ -0,0 +1,373 @@
+use super::*;
+use std::collections::{BTreeSet, HashSet};
+
+// How a local copy of a user differs from the backend's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscrepancyKind {
+    /// The cache holds an outdated copy
+    StaleCache,
+    /// The cache holds a user the backend no longer has
+    OrphanedCache,
+    /// The local repository lacks a backend user
+    MissingLocal,
+    /// The local repository's copy differs from the backend's
+    DifferentLocal,
+    /// The local repository has a user the backend does not
+    ExtraLocal,
+}
+
+impl DiscrepancyKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiscrepancyKind::StaleCache => "stale_cache",
+            DiscrepancyKind::OrphanedCache => "orphaned_cache",
+            DiscrepancyKind::MissingLocal => "missing_local",
+            DiscrepancyKind::DifferentLocal => "different_local",
+            DiscrepancyKind::ExtraLocal => "extra_local",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Discrepancy {
+    pub user_id: String,
+    pub kind: DiscrepancyKind,
+    /// Whether the local side now matches the backend
+    pub fixed: bool,
+    /// Why the fix failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Outcome of one reconciliation run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReconcileReport {
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    /// Users compared, including local ones the backend no longer has
+    pub checked: usize,
+    pub discrepancies: Vec<Discrepancy>,
+    /// Whether the run finished a walk over every user; the next run starts a new one
+    pub walk_completed: bool,
+}
+
+impl ReconcileReport {
+    pub fn count(&self, kind: DiscrepancyKind) -> usize {
+        self.discrepancies
+            .iter()
+            .filter(|discrepancy| discrepancy.kind == kind)
+            .count()
+    }
+
+    /// Discrepancies whose fix failed
+    pub fn unfixed(&self) -> impl Iterator<Item = &Discrepancy> {
+        self.discrepancies
+            .iter()
+            .filter(|discrepancy| discrepancy.error.is_some())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Walk {
+    /// Index of the backend being listed, when a listing spans several regions
+    backend: usize,
+    offset: usize,
+    seen: HashSet<String>,
+}
+
+/// Slowly walks every backend user, comparing the manager's cache and an optional local
+/// repository (such as a `SyncEngine`'s) against the backend and fixing drift in the
+/// backend's favour. Each run checks at most `batch` users at `per_second` and the next
+/// run resumes where it stopped, so a scheduled job spreads one walk over many ticks.
+/// Local users the walk never reached are checked once it ends; users created or deleted
+/// mid-walk may be skipped until the next one.
+#[derive(Debug)]
+pub struct Reconciler {
+    local: Option<Arc<dyn UserRepository>>,
+    per_second: f64,
+    page_size: usize,
+    batch: usize,
+    fix: bool,
+    walk: Mutex<Walk>,
+    last_report: Mutex<Option<ReconcileReport>>,
+}
+
+impl Default for Reconciler {
+    fn default() -> Self {
+        Self {
+            local: None,
+            per_second: Self::DEFAULT_PER_SECOND,
+            page_size: 100,
+            batch: Self::DEFAULT_BATCH,
+            fix: true,
+            walk: Mutex::new(Walk::default()),
+            last_report: Mutex::new(None),
+        }
+    }
+}
+
+impl Reconciler {
+    pub const DEFAULT_PER_SECOND: f64 = 20.0;
+    pub const DEFAULT_BATCH: usize = 1_000;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also reconcile `local`, creating, overwriting and deleting users to match the backend
+    pub fn with_local(mut self, local: Arc<dyn UserRepository>) -> Self {
+        self.local = Some(local);
+        self
+    }
+
+    /// Users checked per second, at least one every ten seconds
+    pub fn with_rate(mut self, per_second: f64) -> Self {
+        self.per_second = per_second.max(0.1);
+        self
+    }
+
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Users checked per run; `usize::MAX` walks everything in one run
+    pub fn with_batch(mut self, batch: usize) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+
+    /// Report drift without fixing it
+    pub fn report_only(mut self) -> Self {
+        self.fix = false;
+        self
+    }
+
+    /// Report of the most recent run, if any has finished
+    pub fn last_report(&self) -> Option<ReconcileReport> {
+        self.last_report
+            .lock()
+            .expect("reconciler report poisoned")
+            .clone()
+    }
+
+    /// Wait until checking the next user keeps the run at `per_second`
+    async fn throttle(&self, started: Instant, checked: usize) {
+        let due = Duration::from_secs_f64(checked as f64 / self.per_second);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl UserManager {
+    /// Run one batch of `reconciler`'s walk against this manager's backends, region by
+    /// region when users are routed to several, checking each user against the backend it
+    /// is routed to. Failing to read either side aborts the run, which resumes from the same
+    /// place next time; failed fixes are reported on their discrepancy.
+    pub async fn reconcile(&self, reconciler: &Reconciler) -> Result<ReconcileReport> {
+        let backends = self.listing_repositories(self.tenant()).await?;
+        let mut walk = std::mem::take(&mut *reconciler.walk.lock().expect("reconciler poisoned"));
+        let outcome = self.reconcile_batch(reconciler, &backends, &mut walk).await;
+        *reconciler.walk.lock().expect("reconciler poisoned") = walk;
+        let report = outcome?;
+
+        tracing::info!(
+            checked = report.checked,
+            discrepancies = report.discrepancies.len(),
+            unfixed = report.unfixed().count(),
+            walk_completed = report.walk_completed,
+            "Reconciliation run finished"
+        );
+        *reconciler
+            .last_report
+            .lock()
+            .expect("reconciler report poisoned") = Some(report.clone());
+        Ok(report)
+    }
+
+    async fn reconcile_batch(
+        &self,
+        reconciler: &Reconciler,
+        backends: &[Arc<dyn UserRepository>],
+        walk: &mut Walk,
+    ) -> Result<ReconcileReport> {
+        let started = Instant::now();
+        let mut report = ReconcileReport {
+            started_at: Utc::now(),
+            duration: Duration::ZERO,
+            checked: 0,
+            discrepancies: Vec::new(),
+            walk_completed: false,
+        };
+        while let Some(backend) = backends.get(walk.backend) {
+            let limit = reconciler.page_size.min(reconciler.batch - report.checked);
+            if limit == 0 {
+                report.duration = started.elapsed();
+                return Ok(report);
+            }
+            let page = backend
+                .list(walk.offset, limit)
+                .await
+                .context("Failed to list users to reconcile")?;
+            let fetched = page.len();
+            for user in page {
+                reconciler.throttle(started, report.checked).await;
+                self.reconcile_user(reconciler, &user.id, Some(&user), &mut report)
+                    .await?;
+                walk.seen.insert(user.id);
+                walk.offset += 1;
+                report.checked += 1;
+            }
+            if fetched < limit {
+                walk.backend += 1;
+                walk.offset = 0;
+            }
+        }
+
+        // Local users the walk never saw may be gone from the backend
+        let mut unseen: BTreeSet<String> =
+            self.cached_ids(self.tenant()).await.into_iter().collect();
+        if let Some(local) = &reconciler.local {
+            let mut offset = 0;
+            loop {
+                let page = local.list(offset, reconciler.page_size).await?;
+                let fetched = page.len();
+                offset += fetched;
+                unseen.extend(page.into_iter().map(|user| user.id));
+                if fetched < reconciler.page_size {
+                    break;
+                }
+            }
+        }
+        unseen.retain(|user_id| !walk.seen.contains(user_id));
+        for user_id in unseen {
+            reconciler.throttle(started, report.checked).await;
+            let current = self
+                .user_repository(self.tenant(), &user_id)
+                .await?
+                .get(&user_id)
+                .await?;
+            self.reconcile_user(reconciler, &user_id, current.as_ref(), &mut report)
+                .await?;
+            report.checked += 1;
+        }
+        *walk = Walk::default();
+        report.walk_completed = true;
+        report.duration = started.elapsed();
+        Ok(report)
+    }
+
+    /// Compare the local copies of one user with the backend's `current` one
+    async fn reconcile_user(
+        &self,
+        reconciler: &Reconciler,
+        user_id: &str,
+        current: Option<&User>,
+        report: &mut ReconcileReport,
+    ) -> Result<()> {
+        if let Some((cached, version)) = self.peek_cached(self.tenant(), user_id).await {
+            if current != Some(&cached) {
+                // The walk's copy may predate the cached one, so judge by a read made after
+                // the peek, from the backend that holds the user
+                let fresh = self
+                    .user_repository(self.tenant(), user_id)
+                    .await?
+                    .get(user_id)
+                    .await?;
+                if fresh.as_ref() != Some(&cached) {
+                    let kind = if fresh.is_some() {
+                        DiscrepancyKind::StaleCache
+                    } else {
+                        DiscrepancyKind::OrphanedCache
+                    };
+                    if reconciler.fix {
+                        self.settle_cached(self.tenant(), user_id, version, fresh.as_ref())
+                            .await;
+                    }
+                    Self::record_discrepancy(report, user_id, kind, reconciler.fix, None);
+                }
+            }
+        }
+
+        let Some(local) = &reconciler.local else {
+            return Ok(());
+        };
+        let kind = match (local.get(user_id).await?, current) {
+            (Some(existing), Some(current)) if existing == *current => return Ok(()),
+            (None, None) => return Ok(()),
+            (None, Some(_)) => DiscrepancyKind::MissingLocal,
+            (Some(_), Some(_)) => DiscrepancyKind::DifferentLocal,
+            (Some(_), None) => DiscrepancyKind::ExtraLocal,
+        };
+        if !reconciler.fix {
+            Self::record_discrepancy(report, user_id, kind, false, None);
+            return Ok(());
+        }
+        let fixed = match current {
+            Some(user) if kind == DiscrepancyKind::MissingLocal => {
+                local.create(user).await.map(|_| ())
+            }
+            Some(user) => local
+                .update(user_id, &user.to_update_fields())
+                .await
+                .map(|_| ()),
+            None => local.delete(user_id).await.map(|_| ()),
+        };
+        let error = fixed.err().map(|e| redact_error(&e));
+        Self::record_discrepancy(report, user_id, kind, error.is_none(), error);
+        Ok(())
+    }
+
+    fn record_discrepancy(
+        report: &mut ReconcileReport,
+        user_id: &str,
+        kind: DiscrepancyKind,
+        fixed: bool,
+        error: Option<String>,
+    ) {
+        metrics::counter!(
+            metric_names::RECONCILE_DISCREPANCIES,
+            "kind" => kind.as_str(),
+            "fixed" => fixed.to_string()
+        )
+        .increment(1);
+        tracing::warn!(
+            user_id = %redact_id(user_id),
+            kind = kind.as_str(),
+            fixed,
+            error = error.as_deref(),
+            "Local state drifted from the backend"
+        );
+        report.discrepancies.push(Discrepancy {
+            user_id: user_id.to_string(),
+            kind,
+            fixed,
+            error,
+        });
+    }
+
+    /// Register `reconciler` with `scheduler` as the `reconciliation` job. Like the
+    /// maintenance jobs it holds the manager weakly; a run with unfixed drift fails.
+    pub fn register_reconciliation(
+        self: &Arc<Self>,
+        scheduler: &Scheduler,
+        schedule: Schedule,
+        reconciler: Arc<Reconciler>,
+    ) -> Result<()> {
+        let manager = Arc::downgrade(self);
+        scheduler.register("reconciliation", schedule, move || {
+            let manager = manager.upgrade();
+            let reconciler = reconciler.clone();
+            async move {
+                let manager = manager.context("User manager was dropped")?;
+                let report = manager.reconcile(&reconciler).await?;
+                match report.unfixed().count() {
+                    0 => Ok(()),
+                    unfixed => anyhow::bail!("{} discrepancies could not be fixed", unfixed),
+                }
+            }
+        })
+    }
+}
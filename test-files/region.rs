@@ -0,0 +1,211 @@
+use super::*;
+use std::collections::BTreeMap;
+
+type RegionFactory = Box<dyn Fn(Option<&TenantId>) -> Arc<dyn UserRepository> + Send + Sync>;
+type ScopedRepositories = HashMap<(String, Option<TenantId>), Arc<dyn UserRepository>>;
+type UserKey = (Option<TenantId>, String);
+
+/// Which region a tenant's or user's records belong in. Tenants without an entry use
+/// `home`; with `metadata_key` set, a user's own metadata field overrides the tenant.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RegionRule {
+    pub home: String,
+    pub tenants: HashMap<TenantId, String>,
+    pub metadata_key: Option<String>,
+}
+
+impl RegionRule {
+    pub fn new(home: impl Into<String>) -> Self {
+        Self {
+            home: home.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<TenantId>, region: impl Into<String>) -> Self {
+        self.tenants.insert(tenant.into(), region.into());
+        self
+    }
+
+    /// Place each user in the region named by this metadata field, e.g. `region`
+    pub fn with_metadata_key(mut self, key: impl Into<String>) -> Self {
+        self.metadata_key = Some(key.into());
+        self
+    }
+
+    /// Region a tenant's users live in unless their metadata says otherwise
+    pub fn region_for_tenant(&self, tenant: Option<&TenantId>) -> &str {
+        tenant
+            .and_then(|tenant| self.tenants.get(tenant))
+            .unwrap_or(&self.home)
+    }
+
+    /// Region a user with this metadata belongs in
+    pub fn region_for_user(
+        &self,
+        tenant: Option<&TenantId>,
+        metadata: &HashMap<MetadataKey, serde_json::Value>,
+    ) -> Result<String> {
+        let Some(value) = self
+            .metadata_key
+            .as_deref()
+            .and_then(|key| metadata.get(key))
+        else {
+            return Ok(self.region_for_tenant(tenant).to_string());
+        };
+        match value.as_str() {
+            Some(region) => Ok(region.to_string()),
+            None => Err(UserError::ResidencyViolation {
+                region: value.to_string(),
+                message: "region metadata must be a string".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+// Regional backends for data residency. The manager's own repository serves the home
+// region; the others are built per tenant and cached like tenant repositories.
+pub struct RegionRouting {
+    rule: RegionRule,
+    regions: BTreeMap<String, RegionFactory>,
+    scoped: Mutex<ScopedRepositories>,
+    located: Mutex<Directory>,
+}
+
+// Where users were last found, forgetting the least recently placed beyond a capacity;
+// a forgotten user is found again by asking each region
+#[derive(Debug, Default)]
+struct Directory {
+    regions: HashMap<UserKey, (String, u64)>,
+    placed: BTreeMap<u64, UserKey>,
+    next_stamp: u64,
+}
+
+impl fmt::Debug for RegionRouting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegionRouting")
+            .field("rule", &self.rule)
+            .field("regions", &self.regions.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RegionRouting {
+    pub fn new(rule: RegionRule) -> Self {
+        Self {
+            rule,
+            regions: BTreeMap::new(),
+            scoped: Mutex::new(HashMap::new()),
+            located: Mutex::new(Directory::default()),
+        }
+    }
+
+    /// Most users whose region is remembered at once
+    pub const LOCATED_CAPACITY: usize = 10_000;
+
+    /// Serve `region` from the repository `factory` builds for each tenant, or for
+    /// untenanted calls when given `None`
+    pub fn with_region<F>(mut self, region: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(Option<&TenantId>) -> Arc<dyn UserRepository> + Send + Sync + 'static,
+    {
+        self.regions.insert(region.into(), Box::new(factory));
+        self
+    }
+
+    pub fn rule(&self) -> &RegionRule {
+        &self.rule
+    }
+
+    /// The home region followed by the other configured regions
+    pub fn regions(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.rule.home.as_str()).chain(
+            self.regions
+                .keys()
+                .map(String::as_str)
+                .filter(|region| *region != self.rule.home),
+        )
+    }
+
+    /// Reject tenants pinned to a region with no backend
+    pub fn validate(&self) -> Result<()> {
+        for (tenant, region) in &self.rule.tenants {
+            if !self.regions().any(|known| known == region) {
+                return Err(UserError::InvalidConfig {
+                    field: "regions".to_string(),
+                    message: format!("tenant {} is pinned to unknown region {}", tenant, region),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Backend for a region other than home
+    pub(crate) fn repository(
+        &self,
+        tenant: Option<&TenantId>,
+        region: &str,
+    ) -> Result<Arc<dyn UserRepository>> {
+        let Some(factory) = self.regions.get(region) else {
+            return Err(UserError::ResidencyViolation {
+                region: region.to_string(),
+                message: "no endpoint configured".to_string(),
+            }
+            .into());
+        };
+        let mut scoped = self.scoped.lock().expect("regional repositories poisoned");
+        Ok(scoped
+            .entry((region.to_string(), tenant.cloned()))
+            .or_insert_with(|| factory(tenant))
+            .clone())
+    }
+
+    pub(crate) fn located(&self, tenant: Option<&TenantId>, user_id: &str) -> Option<String> {
+        self.located
+            .lock()
+            .expect("region directory poisoned")
+            .regions
+            .get(&(tenant.cloned(), user_id.to_string()))
+            .map(|(region, _)| region.clone())
+    }
+
+    /// Remember where a user lives, or forget it with `None`
+    pub(crate) fn remember(&self, tenant: Option<&TenantId>, user_id: &str, region: Option<&str>) {
+        let mut located = self.located.lock().expect("region directory poisoned");
+        let located = &mut *located;
+        let key = (tenant.cloned(), user_id.to_string());
+        let previous = match region {
+            Some(region) => {
+                let stamp = located.next_stamp;
+                located.next_stamp += 1;
+                located.placed.insert(stamp, key.clone());
+                located.regions.insert(key, (region.to_string(), stamp))
+            }
+            None => located.regions.remove(&key),
+        };
+        if let Some((_, stamp)) = previous {
+            located.placed.remove(&stamp);
+        }
+        while located.regions.len() > Self::LOCATED_CAPACITY {
+            let Some((_, oldest)) = located.placed.pop_first() else {
+                break;
+            };
+            located.regions.remove(&oldest);
+        }
+    }
+}
+
+/// One page of a listing spread over regions, each listed from the start up to the end of
+/// the page, merged in the order a single backend lists: oldest first, then by id
+pub(crate) fn merge_pages(pages: Vec<Vec<User>>, offset: usize, limit: usize) -> Vec<User> {
+    let mut users: Vec<User> = pages.into_iter().flatten().collect();
+    users.sort_by(|a, b| {
+        a.created_at
+            .cmp(&b.created_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    users.into_iter().skip(offset).take(limit).collect()
+}
@@ -0,0 +1,346 @@
+use super::*;
+use futures::future::BoxFuture;
+
+type JobFn = dyn Fn() -> BoxFuture<'static, Result<()>> + Send + Sync;
+
+// When a job runs: every `interval`, each wait stretched by a random amount up to
+// `jitter` so instances started together do not hit the backend at the same moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    pub interval: Duration,
+    pub jitter: Duration,
+    /// Run as soon as the job starts rather than after the first wait
+    pub immediately: bool,
+}
+
+impl Schedule {
+    pub fn every(interval: Duration) -> Self {
+        Self {
+            interval,
+            jitter: Duration::ZERO,
+            immediately: false,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn immediately(mut self) -> Self {
+        self.immediately = true;
+        self
+    }
+
+    /// Wait before the next run: the interval plus a random share of the jitter
+    pub fn next_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.interval;
+        }
+        use rand::Rng;
+        self.interval + self.jitter.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+// Outcome of one run of a job
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobRun {
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JobRun {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+// What a job has done so far, as reported by `Scheduler::statuses`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobStatus {
+    pub name: String,
+    pub interval: Duration,
+    pub jitter: Duration,
+    /// Whether the job is started, not whether a run is in progress
+    pub started: bool,
+    pub runs: u64,
+    pub failures: u64,
+    pub last_run: Option<JobRun>,
+}
+
+#[derive(Debug, Default)]
+struct JobState {
+    runs: u64,
+    failures: u64,
+    last_run: Option<JobRun>,
+}
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    run: Box<JobFn>,
+    state: Mutex<JobState>,
+    // Held for the length of a run so a manual run never overlaps a scheduled one
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Job {
+    async fn run_once(&self) -> Result<()> {
+        let _running = self.running.lock().await;
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let outcome = (self.run)().await;
+        let run = JobRun {
+            started_at,
+            duration: started.elapsed(),
+            error: outcome.as_ref().err().map(redact_error),
+        };
+        let label = if run.succeeded() { "ok" } else { "failed" };
+        metrics::counter!(metric_names::SCHEDULED_JOBS, "job" => self.name.clone(), "outcome" => label)
+            .increment(1);
+        if let Some(error) = &run.error {
+            tracing::warn!(job = %self.name, error = %error, "Scheduled job failed");
+        } else {
+            tracing::debug!(
+                job = %self.name,
+                duration_ms = run.duration.as_millis() as u64,
+                "Scheduled job finished"
+            );
+        }
+        let mut state = self.state.lock().expect("job state poisoned");
+        state.runs += 1;
+        if !run.succeeded() {
+            state.failures += 1;
+        }
+        state.last_run = Some(run);
+        outcome
+    }
+}
+
+struct Registered {
+    job: Arc<Job>,
+    task: Option<(watch::Sender<bool>, JoinHandle<()>)>,
+}
+
+// Runs named periodic jobs, such as the manager's maintenance jobs, each on its own
+// schedule. Jobs are registered stopped; a failed run is recorded and retried at the next
+// tick rather than stopping the job.
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Mutex<std::collections::BTreeMap<String, Registered>>,
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let jobs = self.jobs.lock().expect("scheduler jobs poisoned");
+        f.debug_struct("Scheduler")
+            .field("jobs", &jobs.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a job under a unique `name`; it does not run until started
+    pub fn register<F, Fut>(
+        &self,
+        name: impl Into<String>,
+        schedule: Schedule,
+        run: F,
+    ) -> Result<()>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<()>> + Send + 'static,
+    {
+        let name = name.into();
+        if schedule.interval.is_zero() {
+            return Err(UserError::InvalidConfig {
+                field: format!("scheduler.{}.interval", name),
+                message: "interval must be greater than zero".to_string(),
+            }
+            .into());
+        }
+        let mut jobs = self.jobs.lock().expect("scheduler jobs poisoned");
+        if jobs.contains_key(&name) {
+            return Err(UserError::InvalidConfig {
+                field: format!("scheduler.{}", name),
+                message: "a job with this name is already registered".to_string(),
+            }
+            .into());
+        }
+        let job = Arc::new(Job {
+            name: name.clone(),
+            schedule,
+            run: Box::new(move || Box::pin(run())),
+            state: Mutex::new(JobState::default()),
+            running: tokio::sync::Mutex::new(()),
+        });
+        jobs.insert(name, Registered { job, task: None });
+        Ok(())
+    }
+
+    /// Start running `name` on its schedule; starting a started job does nothing
+    pub fn start(&self, name: &str) -> Result<()> {
+        let mut jobs = self.jobs.lock().expect("scheduler jobs poisoned");
+        let registered = jobs.get_mut(name).ok_or_else(|| Self::unknown(name))?;
+        if registered.task.is_none() {
+            registered.task = Some(Self::spawn(registered.job.clone()));
+        }
+        Ok(())
+    }
+
+    pub fn start_all(&self) {
+        let mut jobs = self.jobs.lock().expect("scheduler jobs poisoned");
+        for registered in jobs.values_mut() {
+            if registered.task.is_none() {
+                registered.task = Some(Self::spawn(registered.job.clone()));
+            }
+        }
+    }
+
+    /// Stop `name`, waiting for a run in progress to finish; returns whether it was started
+    pub async fn stop(&self, name: &str) -> Result<bool> {
+        let task = {
+            let mut jobs = self.jobs.lock().expect("scheduler jobs poisoned");
+            let registered = jobs.get_mut(name).ok_or_else(|| Self::unknown(name))?;
+            registered.task.take()
+        };
+        let Some((stop, handle)) = task else {
+            return Ok(false);
+        };
+        let _ = stop.send(true);
+        let _ = handle.await;
+        Ok(true)
+    }
+
+    pub async fn stop_all(&self) {
+        let tasks: Vec<_> = self
+            .jobs
+            .lock()
+            .expect("scheduler jobs poisoned")
+            .values_mut()
+            .filter_map(|registered| registered.task.take())
+            .collect();
+        for (stop, _) in &tasks {
+            let _ = stop.send(true);
+        }
+        for (_, handle) in tasks {
+            let _ = handle.await;
+        }
+    }
+
+    /// Run `name` now, outside its schedule, returning the job's own result
+    pub async fn run_now(&self, name: &str) -> Result<()> {
+        let job = self
+            .jobs
+            .lock()
+            .expect("scheduler jobs poisoned")
+            .get(name)
+            .map(|registered| registered.job.clone())
+            .ok_or_else(|| Self::unknown(name))?;
+        job.run_once().await
+    }
+
+    pub fn status(&self, name: &str) -> Option<JobStatus> {
+        self.jobs
+            .lock()
+            .expect("scheduler jobs poisoned")
+            .get(name)
+            .map(Self::status_of)
+    }
+
+    /// Status of every job, by name
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        self.jobs
+            .lock()
+            .expect("scheduler jobs poisoned")
+            .values()
+            .map(Self::status_of)
+            .collect()
+    }
+
+    fn status_of(registered: &Registered) -> JobStatus {
+        let job = &registered.job;
+        let state = job.state.lock().expect("job state poisoned");
+        JobStatus {
+            name: job.name.clone(),
+            interval: job.schedule.interval,
+            jitter: job.schedule.jitter,
+            started: registered
+                .task
+                .as_ref()
+                .is_some_and(|(_, handle)| !handle.is_finished()),
+            runs: state.runs,
+            failures: state.failures,
+            last_run: state.last_run.clone(),
+        }
+    }
+
+    fn spawn(job: Arc<Job>) -> (watch::Sender<bool>, JoinHandle<()>) {
+        let (stop_tx, mut stop) = watch::channel(false);
+        let handle = tokio::spawn(async move {
+            let mut delay = if job.schedule.immediately {
+                Duration::ZERO
+            } else {
+                job.schedule.next_delay()
+            };
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = stop.changed() => break,
+                }
+                let _ = job.run_once().await;
+                delay = job.schedule.next_delay();
+            }
+        });
+        (stop_tx, handle)
+    }
+
+    fn unknown(name: &str) -> anyhow::Error {
+        UserError::InvalidConfig {
+            field: "scheduler".to_string(),
+            message: format!("no job named {}", name),
+        }
+        .into()
+    }
+}
+
+// Which of the manager's maintenance jobs to schedule; `None` leaves a job out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaintenanceJobs {
+    /// Re-read cached users from the backend (`cache_refresh`)
+    pub cache_refresh: Option<Schedule>,
+    /// Recompute per-tenant statistics over the cache (`statistics`)
+    pub statistics: Option<Schedule>,
+    /// Move committed outbox entries onto the event bus (`outbox_relay`)
+    pub outbox_relay: Option<Schedule>,
+    /// Probe the backend (`health_check`)
+    pub health_check: Option<Schedule>,
+}
+
+impl MaintenanceJobs {
+    pub fn with_cache_refresh(mut self, schedule: Schedule) -> Self {
+        self.cache_refresh = Some(schedule);
+        self
+    }
+
+    pub fn with_statistics(mut self, schedule: Schedule) -> Self {
+        self.statistics = Some(schedule);
+        self
+    }
+
+    pub fn with_outbox_relay(mut self, schedule: Schedule) -> Self {
+        self.outbox_relay = Some(schedule);
+        self
+    }
+
+    pub fn with_health_check(mut self, schedule: Schedule) -> Self {
+        self.health_check = Some(schedule);
+        self
+    }
+}
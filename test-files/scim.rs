@@ -0,0 +1,599 @@
+use super::*;
+use crate::filter::json;
+
+pub const SCIM_USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const SCIM_ENTERPRISE_USER_SCHEMA: &str =
+    "urn:ietf:params:scim:schemas:extension:enterprise:2.0:User";
+/// Our own extension, carrying the exact status and the metadata SCIM has no attribute for
+pub const SCIM_USERS_EXTENSION_SCHEMA: &str = "urn:users:params:scim:schemas:extension:2.0:User";
+pub const SCIM_LIST_RESPONSE_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const SCIM_PATCH_OP_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+
+/// Enterprise extension attributes and the metadata keys they are kept under
+const ENTERPRISE_ATTRIBUTES: &[(&str, &str)] = &[
+    ("employeeNumber", "employee_number"),
+    ("costCenter", "cost_center"),
+    ("organization", "organization"),
+    ("division", "division"),
+    ("department", "department"),
+    ("manager", "manager"),
+];
+
+fn enterprise_attribute(metadata_key: &str) -> Option<&'static str> {
+    ENTERPRISE_ATTRIBUTES
+        .iter()
+        .find(|(_, key)| *key == metadata_key)
+        .map(|(attribute, _)| *attribute)
+}
+
+/// `manager` is a complex attribute referencing the manager's id
+fn enterprise_value(attribute: &str, value: &serde_json::Value) -> serde_json::Value {
+    if attribute == "manager" {
+        serde_json::json!({ "value": value })
+    } else {
+        value.clone()
+    }
+}
+
+impl User {
+    /// The user as a SCIM 2.0 `User` resource. The id becomes `userName`, since the service
+    /// provider assigns resource ids; enterprise metadata keys (`department`, `manager`, ...)
+    /// map to the enterprise extension and the rest to ours.
+    pub fn to_scim(&self) -> serde_json::Value {
+        let mut enterprise = serde_json::Map::new();
+        let mut metadata = serde_json::Map::new();
+        for (key, value) in &self.metadata {
+            match enterprise_attribute(key) {
+                Some(attribute) => {
+                    enterprise.insert(attribute.to_string(), enterprise_value(attribute, value));
+                }
+                None => {
+                    metadata.insert(key.to_string(), value.clone());
+                }
+            }
+        }
+        let mut schemas = vec![SCIM_USER_SCHEMA];
+        if !enterprise.is_empty() {
+            schemas.push(SCIM_ENTERPRISE_USER_SCHEMA);
+        }
+        schemas.push(SCIM_USERS_EXTENSION_SCHEMA);
+        let mut resource = serde_json::json!({
+            "schemas": schemas,
+            "userName": self.id,
+            "displayName": self.name,
+            "name": { "formatted": self.name },
+            "emails": [{ "value": self.email, "type": "work", "primary": true }],
+            "active": self.status == UserStatus::Active,
+            "meta": { "resourceType": "User", "created": self.created_at },
+            SCIM_USERS_EXTENSION_SCHEMA: { "status": self.status, "metadata": metadata },
+        });
+        if !enterprise.is_empty() {
+            resource[SCIM_ENTERPRISE_USER_SCHEMA] = serde_json::Value::Object(enterprise);
+        }
+        resource
+    }
+
+    /// Read a SCIM `User` resource, as sent by an identity provider or returned by a service
+    /// provider. Without our extension, `active` decides between `Active` and `Inactive`.
+    /// The email is checked as `User::new` checks it; a missing `meta.created` means now,
+    /// but one that is not a timestamp is an error.
+    pub fn from_scim(resource: &serde_json::Value) -> Result<User> {
+        let text = |value: &serde_json::Value| value.as_str().map(str::to_string);
+        let id = text(&resource["userName"])
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| UserError::EmptyField {
+                field: "userName".to_string(),
+            })?;
+        let name = text(&resource["displayName"])
+            .or_else(|| text(&resource["name"]["formatted"]))
+            .or_else(|| {
+                let parts: Vec<String> = ["givenName", "familyName"]
+                    .iter()
+                    .filter_map(|part| text(&resource["name"][part]))
+                    .collect();
+                (!parts.is_empty()).then(|| parts.join(" "))
+            })
+            .unwrap_or_else(|| id.clone());
+        let emails = resource["emails"].as_array().cloned().unwrap_or_default();
+        let email = emails
+            .iter()
+            .find(|email| email["primary"] == true)
+            .or(emails.first())
+            .and_then(|email| text(&email["value"]))
+            .filter(|email| !email.is_empty())
+            .ok_or_else(|| UserError::EmptyField {
+                field: "emails".to_string(),
+            })?;
+        let extension = &resource[SCIM_USERS_EXTENSION_SCHEMA];
+        let status = match serde_json::from_value(extension["status"].clone()) {
+            Ok(status) => status,
+            Err(_) if resource["active"] == false => UserStatus::Inactive,
+            Err(_) => UserStatus::Active,
+        };
+        let created_at = match &resource["meta"]["created"] {
+            serde_json::Value::Null => Utc::now(),
+            created => serde_json::from_value(created.clone())
+                .with_context(|| format!("SCIM meta.created is not a timestamp: {}", created))?,
+        };
+
+        let mut metadata = HashMap::new();
+        if let Some(extra) = extension["metadata"].as_object() {
+            for (key, value) in extra {
+                metadata.insert(MetadataKey::from(key.as_str()), value.clone());
+            }
+        }
+        if let Some(enterprise) = resource[SCIM_ENTERPRISE_USER_SCHEMA].as_object() {
+            for (attribute, key) in ENTERPRISE_ATTRIBUTES {
+                let value = match enterprise.get(*attribute) {
+                    Some(serde_json::Value::Object(manager)) => manager.get("value").cloned(),
+                    value => value.cloned(),
+                };
+                if let Some(value) = value.filter(|value| !value.is_null()) {
+                    metadata.insert(MetadataKey::from(*key), value);
+                }
+            }
+        }
+        let mut user = User::new(id, name, email)?.with_status(status);
+        user.created_at = created_at;
+        user.metadata = metadata;
+        Ok(user)
+    }
+}
+
+/// Whether `key` can stand as a SCIM attribute name (RFC 7644 §3.10), so a filter on it
+/// cannot carry operators or quotes of its own
+fn is_scim_attribute_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Filter {
+    /// The filter in SCIM syntax (RFC 7644 §3.4.2.2), over the attributes `to_scim` writes.
+    /// Fails for metadata keys SCIM cannot name.
+    pub fn to_scim(&self) -> Result<String> {
+        let metadata_path = |key: &str| -> Result<String> {
+            Ok(match enterprise_attribute(key) {
+                Some("manager") => format!("{}:manager.value", SCIM_ENTERPRISE_USER_SCHEMA),
+                Some(attribute) => format!("{}:{}", SCIM_ENTERPRISE_USER_SCHEMA, attribute),
+                None if is_scim_attribute_name(key) => {
+                    format!("{}:metadata.{}", SCIM_USERS_EXTENSION_SCHEMA, key)
+                }
+                None => {
+                    return Err(UserError::InvalidFilter {
+                        message: format!("metadata key {:?} has no SCIM form", key),
+                    }
+                    .into())
+                }
+            })
+        };
+        Ok(match self {
+            Filter::Status(UserStatus::Active) => "active eq true".to_string(),
+            Filter::Status(status) => {
+                format!("{}:status eq {}", SCIM_USERS_EXTENSION_SCHEMA, json(status))
+            }
+            Filter::CreatedAfter(at) => format!("meta.created gt {}", json(at)),
+            Filter::CreatedBefore(at) => format!("meta.created lt {}", json(at)),
+            Filter::MetadataExists(key) => format!("{} pr", metadata_path(key)?),
+            Filter::MetadataEquals(key, value) => {
+                format!("{} eq {}", metadata_path(key)?, value)
+            }
+            Filter::QualityBelow(_) => {
+                return Err(UserError::InvalidFilter {
+                    message: "quality scores have no SCIM form".to_string(),
+                }
+                .into())
+            }
+            Filter::And(all) => scim_joined(all, " and ")?,
+            Filter::Or(any) => scim_joined(any, " or ")?,
+            Filter::Not(filter) => format!("not ({})", filter.to_scim()?),
+        })
+    }
+}
+
+fn scim_joined(filters: &[Filter], separator: &str) -> Result<String> {
+    Ok(filters
+        .iter()
+        .map(|filter| match filter {
+            Filter::And(_) | Filter::Or(_) => Ok(format!("({})", filter.to_scim()?)),
+            _ => filter.to_scim(),
+        })
+        .collect::<Result<Vec<_>>>()?
+        .join(separator))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScimPatchOp {
+    #[serde(alias = "Add")]
+    Add,
+    #[serde(alias = "Replace")]
+    Replace,
+    #[serde(alias = "Remove")]
+    Remove,
+}
+
+// One operation of a SCIM PATCH request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScimPatchOperation {
+    pub op: ScimPatchOp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+}
+
+impl ScimPatchOperation {
+    pub fn replace(path: impl Into<String>, value: serde_json::Value) -> Self {
+        Self {
+            op: ScimPatchOp::Replace,
+            path: Some(path.into()),
+            value: Some(value),
+        }
+    }
+
+    pub fn remove(path: impl Into<String>) -> Self {
+        Self {
+            op: ScimPatchOp::Remove,
+            path: Some(path.into()),
+            value: None,
+        }
+    }
+
+    /// PATCH operations for a repository update, as produced by `UserUpdate::to_fields`.
+    /// `metadata` is the whole new map, so enterprise attributes missing from it are removed.
+    /// Consents and two-factor enrollment have no SCIM form and are rejected.
+    pub fn from_updates(updates: &HashMap<String, serde_json::Value>) -> Result<Vec<Self>> {
+        if let Some(field) = updates
+            .keys()
+            .find(|field| !matches!(field.as_str(), "name" | "email" | "status" | "metadata"))
+        {
+            return Err(UserError::InvalidUpdate {
+                field: field.clone(),
+                message: "not supported over SCIM".to_string(),
+            }
+            .into());
+        }
+        let mut operations = Vec::new();
+        if let Some(name) = updates.get("name") {
+            operations.push(Self::replace("displayName", name.clone()));
+            operations.push(Self::replace("name.formatted", name.clone()));
+        }
+        if let Some(email) = updates.get("email") {
+            operations.push(Self::replace(
+                "emails",
+                serde_json::json!([{ "value": email, "type": "work", "primary": true }]),
+            ));
+        }
+        if let Some(status) = updates.get("status") {
+            let status: UserStatus =
+                serde_json::from_value(status.clone()).context("Failed to read status update")?;
+            operations.push(Self::replace(
+                "active",
+                serde_json::Value::Bool(status == UserStatus::Active),
+            ));
+            operations.push(Self::replace(
+                format!("{}:status", SCIM_USERS_EXTENSION_SCHEMA),
+                serde_json::json!(status),
+            ));
+        }
+        if let Some(metadata) = updates.get("metadata") {
+            let mut metadata =
+                metadata
+                    .as_object()
+                    .cloned()
+                    .ok_or_else(|| UserError::InvalidUpdate {
+                        field: "metadata".to_string(),
+                        message: "must be an object".to_string(),
+                    })?;
+            for (attribute, key) in ENTERPRISE_ATTRIBUTES {
+                let path = format!("{}:{}", SCIM_ENTERPRISE_USER_SCHEMA, attribute);
+                operations.push(match metadata.remove(*key) {
+                    Some(value) => Self::replace(path, enterprise_value(attribute, &value)),
+                    None => Self::remove(path),
+                });
+            }
+            operations.push(Self::replace(
+                format!("{}:metadata", SCIM_USERS_EXTENSION_SCHEMA),
+                serde_json::Value::Object(metadata),
+            ));
+        }
+        Ok(operations)
+    }
+}
+
+/// Backend speaking SCIM 2.0 to a service provider's `/Users` endpoint, for directories
+/// provisioned by an identity provider. Users are found by `userName`, and the resource
+/// ids needed to update or delete them are remembered as they are seen, up to about
+/// `max_resource_ids`; forgotten ones are looked up again.
+#[cfg(feature = "client")]
+#[derive(Debug)]
+pub struct ScimUserRepository {
+    base_url: String,
+    client: reqwest::Client,
+    max_resource_ids: usize,
+    resource_ids: std::sync::Mutex<ResourceIds>,
+}
+
+// Remembered resource ids by `userName`, with when each was last used
+#[cfg(feature = "client")]
+#[derive(Debug, Default)]
+struct ResourceIds {
+    ids: HashMap<String, (String, u64)>,
+    clock: u64,
+}
+
+#[cfg(feature = "client")]
+impl ScimUserRepository {
+    pub const CONTENT_TYPE: &'static str = "application/scim+json";
+    pub const DEFAULT_MAX_RESOURCE_IDS: usize = 100_000;
+
+    pub fn new(base_url: impl Into<String>, client: reqwest::Client) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            client,
+            max_resource_ids: Self::DEFAULT_MAX_RESOURCE_IDS,
+            resource_ids: Default::default(),
+        }
+    }
+
+    /// Remember at most about `max` resource ids, forgetting the longest unused beyond that
+    pub fn with_max_resource_ids(mut self, max: usize) -> Self {
+        self.max_resource_ids = max.max(1);
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn users_url(&self) -> String {
+        format!("{}/Users", self.base_url)
+    }
+
+    fn request(&self, method: reqwest::Method, url: String) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(reqwest::header::ACCEPT, Self::CONTENT_TYPE)
+    }
+
+    fn with_body(
+        request: reqwest::RequestBuilder,
+        body: &serde_json::Value,
+    ) -> reqwest::RequestBuilder {
+        request
+            .header(reqwest::header::CONTENT_TYPE, Self::CONTENT_TYPE)
+            .body(body.to_string())
+    }
+
+    /// Read a resource, remembering its id
+    fn user_from(&self, resource: &serde_json::Value) -> Result<User> {
+        let user = User::from_scim(resource)?;
+        if let Some(resource_id) = resource["id"].as_str() {
+            self.remember(&user.id, resource_id);
+        }
+        Ok(user)
+    }
+
+    fn remember(&self, user_id: &str, resource_id: &str) {
+        let mut remembered = self
+            .resource_ids
+            .lock()
+            .expect("SCIM resource ids poisoned");
+        remembered.clock += 1;
+        let used = remembered.clock;
+        remembered
+            .ids
+            .insert(user_id.to_string(), (resource_id.to_string(), used));
+        // Trim in batches, so the sort is paid once per tenth of the limit
+        if remembered.ids.len() > self.max_resource_ids + self.max_resource_ids / 10 {
+            let mut idle: Vec<(u64, String)> = remembered
+                .ids
+                .iter()
+                .map(|(user_id, (_, used))| (*used, user_id.clone()))
+                .collect();
+            idle.sort_unstable();
+            let excess = remembered.ids.len() - self.max_resource_ids;
+            for (_, user_id) in idle.into_iter().take(excess) {
+                remembered.ids.remove(&user_id);
+            }
+        }
+    }
+
+    async fn error(operation: &str, response: reqwest::Response) -> anyhow::Error {
+        let status = response.status();
+        let detail = response
+            .json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|body| body["detail"].as_str().map(str::to_string));
+        UserError::ApiError {
+            message: match detail {
+                Some(detail) => format!("SCIM {} failed: {}: {}", operation, status, detail),
+                None => format!("SCIM {} failed: {}", operation, status),
+            },
+        }
+        .into()
+    }
+
+    async fn search(
+        &self,
+        filter: Option<&str>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<serde_json::Value>> {
+        let mut request = self
+            .request(reqwest::Method::GET, self.users_url())
+            .query(&[("startIndex", offset + 1), ("count", limit)]);
+        if let Some(filter) = filter {
+            request = request.query(&[("filter", filter)]);
+        }
+        let response = request
+            .send()
+            .await
+            .context("Failed to send SCIM request")?;
+        if !response.status().is_success() {
+            return Err(Self::error("search", response).await);
+        }
+        let body: serde_json::Value = response
+            .json()
+            .await
+            .context("Failed to parse SCIM list response")?;
+        Ok(body["Resources"].as_array().cloned().unwrap_or_default())
+    }
+
+    async fn find(&self, user_id: &str) -> Result<Option<serde_json::Value>> {
+        let filter = format!("userName eq {}", serde_json::json!(user_id));
+        Ok(self.search(Some(&filter), 0, 1).await?.into_iter().next())
+    }
+
+    async fn resource_id(&self, user_id: &str) -> Result<Option<String>> {
+        {
+            let mut remembered = self
+                .resource_ids
+                .lock()
+                .expect("SCIM resource ids poisoned");
+            remembered.clock += 1;
+            let now = remembered.clock;
+            if let Some((resource_id, used)) = remembered.ids.get_mut(user_id) {
+                *used = now;
+                return Ok(Some(resource_id.clone()));
+            }
+        }
+        let Some(resource) = self.find(user_id).await? else {
+            return Ok(None);
+        };
+        self.user_from(&resource)?;
+        Ok(resource["id"].as_str().map(str::to_string))
+    }
+
+    fn forget(&self, user_id: &str) {
+        self.resource_ids
+            .lock()
+            .expect("SCIM resource ids poisoned")
+            .ids
+            .remove(user_id);
+    }
+}
+
+#[cfg(feature = "client")]
+#[async_trait]
+impl UserRepository for ScimUserRepository {
+    async fn get(&self, user_id: &str) -> Result<Option<User>> {
+        self.find(user_id)
+            .await?
+            .map(|resource| self.user_from(&resource))
+            .transpose()
+    }
+
+    async fn create(&self, user: &User) -> Result<User> {
+        let request = Self::with_body(
+            self.request(reqwest::Method::POST, self.users_url()),
+            &user.to_scim(),
+        );
+        let response = request
+            .send()
+            .await
+            .context("Failed to send SCIM request")?;
+        if !response.status().is_success() {
+            return Err(Self::error("create", response).await);
+        }
+        let resource: serde_json::Value =
+            response.json().await.context("Failed to parse SCIM user")?;
+        self.user_from(&resource)
+    }
+
+    async fn update(
+        &self,
+        user_id: &str,
+        updates: &HashMap<String, serde_json::Value>,
+    ) -> Result<bool> {
+        let operations = ScimPatchOperation::from_updates(updates)?;
+        let Some(resource_id) = self.resource_id(user_id).await? else {
+            return Ok(false);
+        };
+        let body = serde_json::json!({
+            "schemas": [SCIM_PATCH_OP_SCHEMA],
+            "Operations": operations,
+        });
+        let request = Self::with_body(
+            self.request(
+                reqwest::Method::PATCH,
+                format!("{}/{}", self.users_url(), resource_id),
+            ),
+            &body,
+        );
+        let response = request
+            .send()
+            .await
+            .context("Failed to send SCIM request")?;
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            reqwest::StatusCode::NOT_FOUND => {
+                self.forget(user_id);
+                Ok(false)
+            }
+            _ => Err(Self::error("patch", response).await),
+        }
+    }
+
+    async fn delete(&self, user_id: &str) -> Result<bool> {
+        let Some(resource_id) = self.resource_id(user_id).await? else {
+            return Ok(false);
+        };
+        let request = self.request(
+            reqwest::Method::DELETE,
+            format!("{}/{}", self.users_url(), resource_id),
+        );
+        let response = request
+            .send()
+            .await
+            .context("Failed to send SCIM request")?;
+        match response.status() {
+            status if status.is_success() => {
+                self.forget(user_id);
+                Ok(true)
+            }
+            reqwest::StatusCode::NOT_FOUND => {
+                self.forget(user_id);
+                Ok(false)
+            }
+            _ => Err(Self::error("delete", response).await),
+        }
+    }
+
+    async fn list(&self, offset: usize, limit: usize) -> Result<Vec<User>> {
+        self.search(None, offset, limit)
+            .await?
+            .iter()
+            .map(|resource| self.user_from(resource))
+            .collect()
+    }
+
+    async fn list_filtered(
+        &self,
+        filter: &Filter,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<User>> {
+        self.search(Some(&filter.to_scim()?), offset, limit)
+            .await?
+            .iter()
+            .map(|resource| self.user_from(resource))
+            .collect()
+    }
+
+    fn describe(&self) -> String {
+        match reqwest::Url::parse(&self.base_url) {
+            Ok(mut url) => {
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                url.set_query(None);
+                format!("scim {}", url)
+            }
+            Err(_) => "scim <invalid url>".to_string(),
+        }
+    }
+}
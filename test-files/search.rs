@@ -0,0 +1,255 @@
+use super::*;
+use std::ops::Range;
+
+// A field searched by `UserCollection::search`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchField {
+    Name,
+    Email,
+}
+
+// How a query matched one field; highlight ranges are byte offsets into the field's
+// original text, sorted and non-overlapping
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldMatch {
+    pub field: SearchField,
+    pub score: f64,
+    pub highlights: Vec<Range<usize>>,
+}
+
+// One ranked search result
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchHit<'a> {
+    pub user: &'a User,
+    /// Best field score, from 0.0 to 1.0
+    pub score: f64,
+    /// Every field that matched, best first
+    pub matches: Vec<FieldMatch>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchOptions {
+    pub limit: usize,
+    /// Results scoring below this are dropped
+    pub min_score: f64,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            limit: 10,
+            min_score: 0.3,
+        }
+    }
+}
+
+impl SearchOptions {
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn with_min_score(mut self, min_score: f64) -> Self {
+        self.min_score = min_score;
+        self
+    }
+}
+
+impl UserCollection {
+    /// Fuzzy search over names and emails with the default options
+    pub fn search(&self, query: &str) -> Vec<SearchHit<'_>> {
+        self.search_with(query, &SearchOptions::default())
+    }
+
+    /// Fuzzy search over names and emails, best first. A field containing the query scores
+    /// 0.9 or more; otherwise the score is the larger of the share of the query's trigrams
+    /// found in the field and the edit-distance similarity of its words, scaled by 0.9 so
+    /// substring matches always rank first.
+    pub fn search_with(&self, query: &str, options: &SearchOptions) -> Vec<SearchHit<'_>> {
+        let query = Folded::new(query.trim());
+        if query.chars.is_empty() {
+            return Vec::new();
+        }
+        let mut hits: Vec<SearchHit<'_>> = self
+            .iter()
+            .filter_map(|user| {
+                let mut matches: Vec<FieldMatch> = [
+                    (SearchField::Name, user.name.as_str()),
+                    (SearchField::Email, user.email.as_str()),
+                ]
+                .into_iter()
+                .filter_map(|(field, text)| query.score(field, text))
+                .filter(|m| m.score >= options.min_score)
+                .collect();
+                matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+                let score = matches.first()?.score;
+                Some(SearchHit {
+                    user,
+                    score,
+                    matches,
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.user.name.cmp(&b.user.name))
+                .then_with(|| a.user.id.cmp(&b.user.id))
+        });
+        hits.truncate(options.limit);
+        hits
+    }
+}
+
+// Text lowercased one char at a time, remembering each char's byte range in the original
+struct Folded {
+    chars: Vec<char>,
+    spans: Vec<Range<usize>>,
+}
+
+impl Folded {
+    fn new(text: &str) -> Self {
+        let (chars, spans) = text
+            .char_indices()
+            .map(|(start, c)| {
+                let lower = c.to_lowercase().next().unwrap_or(c);
+                (lower, start..start + c.len_utf8())
+            })
+            .unzip();
+        Self { chars, spans }
+    }
+
+    fn span(&self, chars: Range<usize>) -> Range<usize> {
+        self.spans[chars.start].start..self.spans[chars.end - 1].end
+    }
+
+    /// Char ranges of whitespace-separated words
+    fn words(&self) -> Vec<Range<usize>> {
+        let mut words = Vec::new();
+        let mut start = None;
+        for (i, c) in self.chars.iter().enumerate() {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(i),
+                (true, Some(s)) => {
+                    words.push(s..i);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            words.push(s..self.chars.len());
+        }
+        words
+    }
+
+    /// How well this query matches `text`, or `None` when nothing matches
+    fn score(&self, field: SearchField, text: &str) -> Option<FieldMatch> {
+        let target = Folded::new(text);
+        let query = &self.chars;
+        if let Some(start) = target
+            .chars
+            .windows(query.len())
+            .position(|window| window == query.as_slice())
+        {
+            let word_start = start == 0 || !target.chars[start - 1].is_alphanumeric();
+            let score = match (start, word_start) {
+                (0, _) => 1.0,
+                (_, true) => 0.95,
+                _ => 0.9,
+            };
+            return Some(FieldMatch {
+                field,
+                score,
+                highlights: vec![target.span(start..start + query.len())],
+            });
+        }
+
+        let (trigram_score, trigram_hits) = self.trigrams(&target);
+        let (word_score, word_hits) = self.words_against(&target);
+        let (score, hits) = if trigram_score >= word_score {
+            (trigram_score, trigram_hits)
+        } else {
+            (word_score, word_hits)
+        };
+        if score <= 0.0 {
+            return None;
+        }
+        Some(FieldMatch {
+            field,
+            score: score * 0.9,
+            highlights: merge_ranges(hits.into_iter().map(|hit| target.span(hit)).collect()),
+        })
+    }
+
+    /// Share of the query's trigrams found in `target`, with the char ranges they matched
+    fn trigrams(&self, target: &Folded) -> (f64, Vec<Range<usize>>) {
+        if self.chars.len() < 3 || target.chars.len() < 3 {
+            return (0.0, Vec::new());
+        }
+        let wanted: Vec<&[char]> = self.chars.windows(3).collect();
+        let found: std::collections::HashSet<&[char]> = target
+            .chars
+            .windows(3)
+            .filter(|trigram| wanted.contains(trigram))
+            .collect();
+        let hits = target
+            .chars
+            .windows(3)
+            .enumerate()
+            .filter(|(_, trigram)| found.contains(trigram))
+            .map(|(i, _)| i..i + 3)
+            .collect();
+        let matched = wanted
+            .iter()
+            .filter(|trigram| found.contains(*trigram))
+            .count();
+        (matched as f64 / wanted.len() as f64, hits)
+    }
+
+    /// Mean, over query words, of the best edit-distance similarity to a word of `target`
+    fn words_against(&self, target: &Folded) -> (f64, Vec<Range<usize>>) {
+        let query_words = self.words();
+        let target_words: Vec<(Range<usize>, String)> = target
+            .words()
+            .into_iter()
+            .map(|range| {
+                let word = target.chars[range.clone()].iter().collect();
+                (range, word)
+            })
+            .collect();
+        if query_words.is_empty() || target_words.is_empty() {
+            return (0.0, Vec::new());
+        }
+        let mut total = 0.0;
+        let mut hits = Vec::new();
+        for range in &query_words {
+            let word: String = self.chars[range.clone()].iter().collect();
+            let best = target_words
+                .iter()
+                .map(|(range, candidate)| {
+                    let similarity = StringSimilarity::Levenshtein.similarity(&word, candidate);
+                    (similarity, range)
+                })
+                .max_by(|a, b| a.0.total_cmp(&b.0));
+            if let Some((similarity, range)) = best.filter(|(similarity, _)| *similarity >= 0.5) {
+                total += similarity;
+                hits.push(range.clone());
+            }
+        }
+        (total / query_words.len() as f64, hits)
+    }
+}
+
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
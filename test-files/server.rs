@@ -0,0 +1,330 @@
+use super::*;
+use axum::extract::{FromRequestParts, Path, Query};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+
+type Repository = Arc<dyn UserRepository>;
+type TenantFactory = Arc<dyn Fn(&TenantId) -> Repository + Send + Sync>;
+
+// Where each request's users live: the default repository, or the tenant's own
+#[derive(Clone)]
+struct Repositories {
+    default: Repository,
+    factory: TenantFactory,
+    tenants: Arc<Mutex<HashMap<TenantId, Repository>>>,
+}
+
+impl Repositories {
+    fn for_tenant(&self, tenant: &TenantId) -> Repository {
+        self.tenants
+            .lock()
+            .expect("server tenants poisoned")
+            .entry(tenant.clone())
+            .or_insert_with(|| (self.factory)(tenant))
+            .clone()
+    }
+}
+
+// Repository for the request's `{tenant}` path segment, if any
+struct Scoped(Repository);
+
+impl FromRequestParts<Repositories> for Scoped {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        repositories: &Repositories,
+    ) -> std::result::Result<Self, Response> {
+        let Path(params) = Path::<HashMap<String, String>>::from_request_parts(parts, repositories)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Scoped(match params.get("tenant") {
+            Some(tenant) => repositories.for_tenant(&TenantId::new(tenant)),
+            None => repositories.default.clone(),
+        }))
+    }
+}
+
+/// Routes: `GET/POST /users`, `GET /users/changes`, `GET/HEAD/PUT/DELETE /users/{id}`, the
+/// same under `/tenants/{tenant}`, and `GET /health`. Each tenant gets its own in-memory
+/// repository; use `router_with_tenants` to choose them.
+pub fn router(repository: Repository) -> Router {
+    router_with_tenants(repository, |_| Arc::new(InMemoryUserRepository::new()))
+}
+
+/// `router`, with `factory` building each tenant's repository on its first request
+pub fn router_with_tenants<F>(repository: Repository, factory: F) -> Router
+where
+    F: Fn(&TenantId) -> Repository + Send + Sync + 'static,
+{
+    let users = Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/changes", get(changes))
+        .route(
+            "/users/{id}",
+            get(get_user).put(update_user).delete(delete_user),
+        );
+    Router::new()
+        .route("/health", get(|| async { StatusCode::OK }))
+        .merge(users.clone())
+        .nest("/tenants/{tenant}", users)
+        .with_state(Repositories {
+            default: repository,
+            factory: Arc::new(factory),
+            tenants: Arc::default(),
+        })
+}
+
+// Server bound to an ephemeral local port, stopped on drop
+#[derive(Debug)]
+pub struct TestServer {
+    addr: std::net::SocketAddr,
+    shutdown: Option<tokio::sync::oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start(repository: Repository) -> Result<Self> {
+        Self::bind("127.0.0.1:0", repository).await
+    }
+
+    pub async fn bind(addr: &str, repository: Repository) -> Result<Self> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind {}", addr))?;
+        let addr = listener.local_addr()?;
+        let (shutdown, stopped) = tokio::sync::oneshot::channel();
+        let task = tokio::spawn(async move {
+            let served = axum::serve(listener, router(repository))
+                .with_graceful_shutdown(async {
+                    let _ = stopped.await;
+                })
+                .await;
+            if let Err(e) = served {
+                tracing::error!(error = %e, "Embedded user API server failed");
+            }
+        });
+        Ok(Self {
+            addr,
+            shutdown: Some(shutdown),
+            task,
+        })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Manager using the HTTP backend against this server
+    pub fn manager(&self) -> UserManager {
+        UserManager::builder(self.url())
+            .build()
+            .expect("server URL is valid")
+    }
+
+    pub async fn stop(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+// Lets `#[api_handler]` functions serve as routes: `200 OK` for a success, and for a
+// failure the status the built-in routes give its error kind
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+    fn into_response(self) -> Response {
+        let status = if self.success {
+            StatusCode::OK
+        } else {
+            status_for(self.error_kind.unwrap_or("other"))
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+fn ok<T: Serialize>(status: StatusCode, data: T) -> Response {
+    (status, Json(ApiResponse::success(data))).into_response()
+}
+
+fn failure(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(ApiResponse::<()>::error(message.into()))).into_response()
+}
+
+/// Status for an error by its `UserError::kind_of` label
+fn status_for(kind: &str) -> StatusCode {
+    match kind {
+        "not_found" | "session_not_found" => StatusCode::NOT_FOUND,
+        "invalid_email" | "invalid_update" | "residency_violation" => {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        "unavailable" => StatusCode::SERVICE_UNAVAILABLE,
+        "forbidden" => StatusCode::FORBIDDEN,
+        "cursor_expired" => StatusCode::GONE,
+        "invalid_cursor" | "invalid_reset_token" | "invalid_invitation" => StatusCode::BAD_REQUEST,
+        "rate_limited" | "quota_exceeded" => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn from_error(error: anyhow::Error) -> Response {
+    let status = status_for(UserError::kind_of(&error));
+    let mut response = failure(status, redact_error(&error));
+    if let Some(UserError::RateLimited { retry_after, .. }) = error.downcast_ref() {
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert(axum::http::header::RETRY_AFTER, seconds.into());
+    }
+    response
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Idempotency-Key")
+        .and_then(|value| value.to_str().ok())
+}
+
+// `{id}`, with `{tenant}` already taken by `Scoped`
+#[derive(Deserialize)]
+struct UserPath {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "Page::default_limit")]
+    limit: usize,
+    cursor: Option<String>,
+    filter: Option<String>,
+}
+
+impl Page {
+    fn default_limit() -> usize {
+        100
+    }
+}
+
+async fn list_users(Scoped(repository): Scoped, Query(page): Query<Page>) -> Response {
+    let listed = match page.filter.as_deref().map(str::parse::<Filter>) {
+        Some(Ok(filter)) => {
+            repository
+                .list_filtered(&filter, page.offset, page.limit)
+                .await
+        }
+        Some(Err(e)) => return failure(StatusCode::BAD_REQUEST, e.to_string()),
+        None => repository.list(page.offset, page.limit).await,
+    };
+    match listed {
+        Ok(users) => ok(StatusCode::OK, users),
+        Err(e) => from_error(e),
+    }
+}
+
+async fn changes(Scoped(repository): Scoped, Query(page): Query<Page>) -> Response {
+    match repository
+        .changes_since(page.cursor.as_deref(), page.limit)
+        .await
+    {
+        Ok(changes) => ok(StatusCode::OK, changes),
+        Err(e) => from_error(e),
+    }
+}
+
+async fn get_user(Scoped(repository): Scoped, Path(UserPath { id }): Path<UserPath>) -> Response {
+    match repository.get(&id).await {
+        Ok(Some(user)) => ok(StatusCode::OK, user),
+        Ok(None) => failure(
+            StatusCode::NOT_FOUND,
+            format!("User not found: {}", redact_id(&id)),
+        ),
+        Err(e) => from_error(e),
+    }
+}
+
+async fn create_user(
+    Scoped(repository): Scoped,
+    headers: HeaderMap,
+    Json(user): Json<User>,
+) -> Response {
+    if let Err(e) = user.validate() {
+        return from_error(e);
+    }
+    let result = match idempotency_key(&headers) {
+        Some(key) => repository
+            .apply_idempotent(&Mutation::Create(user.clone()), key)
+            .await
+            .map(|_| user),
+        None => repository.create(&user).await,
+    };
+    match result {
+        Ok(user) => ok(StatusCode::CREATED, user),
+        Err(e) => from_error(e),
+    }
+}
+
+async fn update_user(
+    Scoped(repository): Scoped,
+    Path(UserPath { id }): Path<UserPath>,
+    headers: HeaderMap,
+    Json(updates): Json<HashMap<String, serde_json::Value>>,
+) -> Response {
+    let result = match idempotency_key(&headers) {
+        Some(key) => {
+            let mutation = Mutation::Update {
+                user_id: id.clone(),
+                updates,
+            };
+            repository.apply_idempotent(&mutation, key).await
+        }
+        None => repository.update(&id, &updates).await,
+    };
+    match result {
+        Ok(true) => match repository.get(&id).await {
+            Ok(user) => ok(StatusCode::OK, user),
+            Err(e) => from_error(e),
+        },
+        Ok(false) => failure(
+            StatusCode::NOT_FOUND,
+            format!("User not found: {}", redact_id(&id)),
+        ),
+        Err(e) => from_error(e),
+    }
+}
+
+async fn delete_user(
+    Scoped(repository): Scoped,
+    Path(UserPath { id }): Path<UserPath>,
+    headers: HeaderMap,
+) -> Response {
+    let result = match idempotency_key(&headers) {
+        Some(key) => {
+            let mutation = Mutation::Delete {
+                user_id: id.clone(),
+            };
+            repository.apply_idempotent(&mutation, key).await
+        }
+        None => repository.delete(&id).await,
+    };
+    match result {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => failure(
+            StatusCode::NOT_FOUND,
+            format!("User not found: {}", redact_id(&id)),
+        ),
+        Err(e) => from_error(e),
+    }
+}
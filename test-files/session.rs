@@ -0,0 +1,251 @@
+use super::*;
+
+// Where a session was opened from
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionMetadata {
+    /// Device or user agent, as reported by the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<std::net::IpAddr>,
+}
+
+impl SessionMetadata {
+    pub fn with_device(mut self, device: impl Into<String>) -> Self {
+        self.device = Some(device.into());
+        self
+    }
+
+    pub fn with_ip(mut self, ip: std::net::IpAddr) -> Self {
+        self.ip = Some(ip);
+        self
+    }
+}
+
+// One signed-in session of a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    #[serde(flatten)]
+    pub metadata: SessionMetadata,
+    pub created_at: DateTime<Utc>,
+    /// `None` for sessions that only end when revoked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    pub fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires| now < expires)
+    }
+}
+
+// Sessions by tenant and id, kept in memory. Revoked and expired sessions are kept until
+// `purge_inactive` so their metadata stays available to security tooling.
+#[derive(Debug, Default)]
+pub struct SessionStore {
+    ttl: Option<chrono::Duration>,
+    sessions: std::sync::Mutex<HashMap<(Option<TenantId>, String), Session>>,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expire sessions `ttl` after they are opened
+    pub fn with_ttl(mut self, ttl: chrono::Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn open(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        metadata: SessionMetadata,
+    ) -> Session {
+        let created_at = Utc::now();
+        let session = Session {
+            id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            metadata,
+            created_at,
+            expires_at: self.ttl.map(|ttl| created_at + ttl),
+            revoked_at: None,
+        };
+        let mut sessions = self.sessions.lock().expect("session store poisoned");
+        sessions.insert((tenant.cloned(), session.id.clone()), session.clone());
+        session
+    }
+
+    pub fn get(&self, tenant: Option<&TenantId>, session_id: &str) -> Option<Session> {
+        let sessions = self.sessions.lock().expect("session store poisoned");
+        sessions
+            .get(&(tenant.cloned(), session_id.to_string()))
+            .cloned()
+    }
+
+    /// The user's sessions still active at `now`, oldest first
+    pub fn active_at(
+        &self,
+        tenant: Option<&TenantId>,
+        user_id: &str,
+        now: DateTime<Utc>,
+    ) -> Vec<Session> {
+        let sessions = self.sessions.lock().expect("session store poisoned");
+        let mut active: Vec<Session> = sessions
+            .iter()
+            .filter(|((session_tenant, _), session)| {
+                session_tenant.as_ref() == tenant
+                    && session.user_id == user_id
+                    && session.is_active_at(now)
+            })
+            .map(|(_, session)| session.clone())
+            .collect();
+        active.sort_by(|a, b| {
+            a.created_at
+                .cmp(&b.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        active
+    }
+
+    /// Revoke one session; false if it does not exist or was already revoked
+    pub fn revoke(&self, tenant: Option<&TenantId>, session_id: &str) -> bool {
+        let mut sessions = self.sessions.lock().expect("session store poisoned");
+        match sessions.get_mut(&(tenant.cloned(), session_id.to_string())) {
+            Some(session) if session.revoked_at.is_none() => {
+                session.revoked_at = Some(Utc::now());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Revoke every unrevoked session of a user, returning how many were revoked
+    pub fn revoke_all(&self, tenant: Option<&TenantId>, user_id: &str) -> usize {
+        let now = Utc::now();
+        let mut sessions = self.sessions.lock().expect("session store poisoned");
+        let mut revoked = 0;
+        for ((session_tenant, _), session) in sessions.iter_mut() {
+            if session_tenant.as_ref() == tenant
+                && session.user_id == user_id
+                && session.revoked_at.is_none()
+            {
+                session.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+        revoked
+    }
+
+    /// Forget every session of a user, e.g. when erasing them
+    pub fn remove_user(&self, tenant: Option<&TenantId>, user_id: &str) -> usize {
+        let mut sessions = self.sessions.lock().expect("session store poisoned");
+        let before = sessions.len();
+        sessions.retain(|(session_tenant, _), session| {
+            session_tenant.as_ref() != tenant || session.user_id != user_id
+        });
+        before - sessions.len()
+    }
+
+    /// Drop sessions that were revoked or had expired by `now`
+    pub fn purge_inactive(&self, now: DateTime<Utc>) -> usize {
+        let mut sessions = self.sessions.lock().expect("session store poisoned");
+        let before = sessions.len();
+        sessions.retain(|_, session| session.is_active_at(now));
+        before - sessions.len()
+    }
+}
+
+#[cfg(feature = "client")]
+impl UserManager {
+    fn require_session_store(&self) -> Result<&Arc<SessionStore>> {
+        self.session_store().ok_or_else(|| {
+            UserError::InvalidConfig {
+                field: "session_store".to_string(),
+                message: "no session store configured".to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Open a session for an existing user who is active or pending; suspended and
+    /// inactive users are refused
+    pub async fn open_session(&self, user_id: &str, metadata: SessionMetadata) -> Result<Session> {
+        let store = self.require_session_store()?;
+        let user = self
+            .fetch_user(user_id)
+            .await?
+            .ok_or_else(|| UserError::NotFound {
+                id: user_id.to_string(),
+            })?;
+        if matches!(user.status, UserStatus::Suspended | UserStatus::Inactive) {
+            return Err(UserError::Vetoed {
+                operation: "session".to_string(),
+                reason: format!("user is {}", user.status.as_str()),
+            }
+            .into());
+        }
+        Ok(store.open(self.tenant(), user_id, metadata))
+    }
+
+    /// A user's active sessions, oldest first
+    pub fn list_sessions(&self, user_id: &str) -> Result<Vec<Session>> {
+        Ok(self
+            .require_session_store()?
+            .active_at(self.tenant(), user_id, Utc::now()))
+    }
+
+    /// Metadata of any session, including revoked and expired ones
+    pub fn session(&self, session_id: &str) -> Result<Session> {
+        self.require_session_store()?
+            .get(self.tenant(), session_id)
+            .ok_or_else(|| {
+                UserError::SessionNotFound {
+                    id: session_id.to_string(),
+                }
+                .into()
+            })
+    }
+
+    /// Revoke one session; false if it was already revoked
+    pub fn revoke_session(&self, session_id: &str) -> Result<bool> {
+        let store = self.require_session_store()?;
+        self.authorize(Permission::Update)?;
+        let session = self.session(session_id)?;
+        let revoked = store.revoke(self.tenant(), session_id);
+        if revoked {
+            metrics::counter!(metric_names::SESSIONS_REVOKED).increment(1);
+            tracing::info!(user_id = %redact_id(&session.user_id), "Session revoked");
+        }
+        Ok(revoked)
+    }
+
+    /// Revoke a user's sessions once a write has suspended or deleted them; the sessions
+    /// are kept, revoked, for security tooling
+    pub(crate) fn end_sessions(&self, tenant: Option<&TenantId>, user_id: &str) {
+        let Some(store) = self.session_store() else {
+            return;
+        };
+        let revoked = store.revoke_all(tenant, user_id);
+        if revoked > 0 {
+            metrics::counter!(metric_names::SESSIONS_REVOKED).increment(revoked as u64);
+            tracing::info!(user_id = %redact_id(user_id), revoked, "Sessions revoked");
+        }
+    }
+
+    /// Revoke every session of a user, e.g. after a password reset
+    pub fn revoke_all_sessions(&self, user_id: &str) -> Result<usize> {
+        let store = self.require_session_store()?;
+        self.authorize(Permission::Update)?;
+        let revoked = store.revoke_all(self.tenant(), user_id);
+        metrics::counter!(metric_names::SESSIONS_REVOKED).increment(revoked as u64);
+        tracing::info!(user_id = %redact_id(user_id), revoked, "Sessions revoked");
+        Ok(revoked)
+    }
+}
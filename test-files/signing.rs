@@ -0,0 +1,240 @@
+use super::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs outgoing requests with HMAC-SHA256 for APIs that take a shared secret instead of
+/// a bearer token. The signed message is `METHOD\npath?query\ntimestamp\n` followed by the
+/// raw body, with the timestamp in Unix seconds; the signature is sent as lowercase hex.
+#[derive(Debug, Clone)]
+pub struct RequestSigner {
+    secret: Secret<Vec<u8>>,
+    key_id: Option<String>,
+    signature_header: String,
+    timestamp_header: String,
+    key_id_header: String,
+}
+
+impl RequestSigner {
+    pub const SIGNATURE_HEADER: &'static str = "X-Signature";
+    pub const TIMESTAMP_HEADER: &'static str = "X-Signature-Timestamp";
+    pub const KEY_ID_HEADER: &'static str = "X-Signature-Key-Id";
+
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: Secret::new(secret.into()),
+            key_id: None,
+            signature_header: Self::SIGNATURE_HEADER.to_string(),
+            timestamp_header: Self::TIMESTAMP_HEADER.to_string(),
+            key_id_header: Self::KEY_ID_HEADER.to_string(),
+        }
+    }
+
+    /// Identify the secret to the server, for APIs that rotate keys
+    pub fn with_key_id(mut self, key_id: impl Into<String>) -> Self {
+        self.key_id = Some(key_id.into());
+        self
+    }
+
+    pub fn with_signature_header(mut self, name: impl Into<String>) -> Self {
+        self.signature_header = name.into();
+        self
+    }
+
+    pub fn with_timestamp_header(mut self, name: impl Into<String>) -> Self {
+        self.timestamp_header = name.into();
+        self
+    }
+
+    pub fn with_key_id_header(mut self, name: impl Into<String>) -> Self {
+        self.key_id_header = name.into();
+        self
+    }
+
+    pub fn signature_header(&self) -> &str {
+        &self.signature_header
+    }
+
+    pub fn timestamp_header(&self) -> &str {
+        &self.timestamp_header
+    }
+
+    /// Hex signature of one request
+    pub fn signature(&self, method: &str, path: &str, timestamp: i64, body: &[u8]) -> String {
+        encode_hex(
+            &self
+                .mac(method, path, timestamp, body)
+                .finalize()
+                .into_bytes(),
+        )
+    }
+
+    /// Check a received signature in constant time, for servers and test doubles
+    pub fn verify(
+        &self,
+        method: &str,
+        path: &str,
+        timestamp: i64,
+        body: &[u8],
+        signature: &str,
+    ) -> bool {
+        let Some(bytes) = decode_hex(signature) else {
+            return false;
+        };
+        self.mac(method, path, timestamp, body)
+            .verify_slice(&bytes)
+            .is_ok()
+    }
+
+    fn mac(&self, method: &str, path: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.secret.expose())
+            .expect("HMAC accepts keys of any length");
+        mac.update(
+            format!("{}\n{}\n{}\n", method.to_ascii_uppercase(), path, timestamp).as_bytes(),
+        );
+        mac.update(body);
+        mac
+    }
+
+    /// Signature, timestamp and key id header names, rejecting ones HTTP does not allow
+    pub(crate) fn header_names(&self) -> Result<[reqwest::header::HeaderName; 3]> {
+        let parse = |name: &str| {
+            reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                UserError::InvalidConfig {
+                    field: "request_signer".to_string(),
+                    message: format!("{:?}: {}", name, e),
+                }
+            })
+        };
+        Ok([
+            parse(&self.signature_header)?,
+            parse(&self.timestamp_header)?,
+            parse(&self.key_id_header)?,
+        ])
+    }
+
+    /// Add the signature headers to a built request, replacing any from an earlier attempt
+    pub(crate) fn sign(&self, request: &mut reqwest::Request, now: DateTime<Utc>) -> Result<()> {
+        use reqwest::header::HeaderValue;
+
+        let url = request.url();
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        let body = match request.body() {
+            Some(body) => body
+                .as_bytes()
+                .context("Cannot sign a streaming request body")?,
+            None => &[],
+        };
+        let timestamp = now.timestamp();
+        let signature = self.signature(request.method().as_str(), &path, timestamp, body);
+
+        let [signature_header, timestamp_header, key_id_header] = self.header_names()?;
+        let headers = request.headers_mut();
+        let mut signature = HeaderValue::from_str(&signature)?;
+        signature.set_sensitive(true);
+        headers.insert(signature_header, signature);
+        headers.insert(timestamp_header, HeaderValue::from(timestamp));
+        if let Some(key_id) = &self.key_id {
+            let value = HeaderValue::from_str(key_id).map_err(|_| UserError::InvalidConfig {
+                field: "request_signer".to_string(),
+                message: "key id contains characters not allowed in a header".to_string(),
+            })?;
+            headers.insert(key_id_header, value);
+        }
+        Ok(())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+// Why `open_token` rejected a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenFault {
+    Malformed,
+    BadSignature,
+    /// Signed with the same secret, but for another kind of token
+    WrongPurpose,
+}
+
+impl fmt::Display for TokenFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TokenFault::Malformed => "is malformed",
+            TokenFault::BadSignature => "signature does not match",
+            TokenFault::WrongPurpose => "was issued for another purpose",
+        })
+    }
+}
+
+// Claims as sealed, tagged with the kind of token they make
+#[derive(Serialize)]
+struct Sealed<'a, T> {
+    purpose: &'a str,
+    #[serde(flatten)]
+    claims: &'a T,
+}
+
+#[derive(Deserialize)]
+struct Opened<T> {
+    purpose: String,
+    #[serde(flatten)]
+    claims: T,
+}
+
+/// Seal claims into `<payload>.<signature>`: the claims' JSON, with a `purpose` field
+/// added, and its HMAC-SHA256, both base64url without padding, so clients can hold the
+/// token but not alter it. Claims must serialize as an object.
+pub(crate) fn seal_token<T: Serialize>(secret: &[u8], purpose: &str, claims: &T) -> String {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine as _;
+
+    let json = serde_json::to_vec(&Sealed { purpose, claims }).expect("token claims serialize");
+    let payload = URL_SAFE_NO_PAD.encode(json);
+    let signature = URL_SAFE_NO_PAD.encode(token_mac(secret, &payload).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+}
+
+/// The claims of a token sealed with the same secret for the same purpose, so a token
+/// of one kind is never accepted as another even when they share a secret
+pub(crate) fn open_token<T: serde::de::DeserializeOwned>(
+    secret: &[u8],
+    purpose: &str,
+    token: &str,
+) -> std::result::Result<T, TokenFault> {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine as _;
+
+    let (payload, signature) = token.split_once('.').ok_or(TokenFault::Malformed)?;
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| TokenFault::Malformed)?;
+    token_mac(secret, payload)
+        .verify_slice(&signature)
+        .map_err(|_| TokenFault::BadSignature)?;
+    let json = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| TokenFault::Malformed)?;
+    let opened: Opened<T> = serde_json::from_slice(&json).map_err(|_| TokenFault::Malformed)?;
+    if opened.purpose != purpose {
+        return Err(TokenFault::WrongPurpose);
+    }
+    Ok(opened.claims)
+}
+
+fn token_mac(secret: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    mac
+}
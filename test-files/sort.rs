@@ -0,0 +1,100 @@
+use super::*;
+use std::cmp::Ordering;
+
+type CompareFn = Box<dyn Fn(&User, &User) -> Ordering + Send + Sync>;
+
+/// A user ordering, chained with `then` and flipped with `reverse`
+pub struct UserComparator(CompareFn);
+
+impl UserComparator {
+    pub fn new(compare: impl Fn(&User, &User) -> Ordering + Send + Sync + 'static) -> Self {
+        UserComparator(Box::new(compare))
+    }
+
+    pub fn compare(&self, a: &User, b: &User) -> Ordering {
+        (self.0)(a, b)
+    }
+
+    /// Break ties with `next`
+    pub fn then(self, next: UserComparator) -> Self {
+        Self::new(move |a, b| self.compare(a, b).then_with(|| next.compare(a, b)))
+    }
+
+    pub fn reverse(self) -> Self {
+        Self::new(move |a, b| self.compare(b, a))
+    }
+
+    /// Stable sort, so users that compare equal keep their order
+    pub fn sort(&self, users: &mut [User]) {
+        users.sort_by(|a, b| self.compare(a, b));
+    }
+
+    pub fn sort_refs(&self, users: &mut [&User]) {
+        users.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+impl fmt::Debug for UserComparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("UserComparator")
+    }
+}
+
+/// Oldest first
+pub fn by_created_at() -> UserComparator {
+    UserComparator::new(|a, b| a.created_at.cmp(&b.created_at))
+}
+
+pub fn by_id() -> UserComparator {
+    UserComparator::new(|a, b| a.id.cmp(&b.id))
+}
+
+/// Display name ignoring case and common Latin accents, so "émile" sorts with "Emile"
+/// between "Edgar" and "Fay"; exact ties fall back to code point order.
+/// An approximation of locale collation that needs no ICU data.
+pub fn by_name_locale_aware() -> UserComparator {
+    UserComparator::new(|a, b| {
+        let (a, b) = (a.display_name(), b.display_name());
+        collation_key(a)
+            .cmp(collation_key(b))
+            .then_with(|| a.cmp(b))
+    })
+}
+
+/// Active, then pending, suspended and inactive
+pub fn by_status_priority() -> UserComparator {
+    UserComparator::new(|a, b| status_priority(a.status).cmp(&status_priority(b.status)))
+}
+
+fn status_priority(status: UserStatus) -> u8 {
+    match status {
+        UserStatus::Active => 0,
+        UserStatus::Pending => 1,
+        UserStatus::Suspended => 2,
+        UserStatus::Inactive => 3,
+    }
+}
+
+fn collation_key(name: &str) -> impl Iterator<Item = char> + '_ {
+    name.chars().flat_map(char::to_lowercase).map(fold_accent)
+}
+
+fn fold_accent(c: char) -> char {
+    match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ą' => 'a',
+        'ç' | 'ć' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ę' | 'ě' => 'e',
+        'ì' | 'í' | 'î' | 'ï' | 'ī' => 'i',
+        'ł' => 'l',
+        'ñ' | 'ń' | 'ň' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' => 'o',
+        'ř' => 'r',
+        'ś' | 'š' => 's',
+        'ť' => 't',
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' => 'u',
+        'ý' | 'ÿ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        other => other,
+    }
+}
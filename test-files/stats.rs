@@ -0,0 +1,314 @@
+use super::*;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+// User statistics structure
+#[derive(Debug, Clone, Serialize)]
+pub struct UserStatistics {
+    pub total: usize,
+    pub active: usize,
+    pub inactive: usize,
+    pub pending: usize,
+    pub suspended: usize,
+    pub average_days_active: f64,
+    /// Engagement from an `ActivityLog`, when one was consulted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activity: Option<ActivityStatistics>,
+}
+
+impl fmt::Display for UserStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "UserStats(total={}, active={}, inactive={}, pending={}, suspended={}, avg_days={:.2})",
+            self.total, self.active, self.inactive, self.pending, self.suspended, self.average_days_active
+        )?;
+        if let Some(activity) = &self.activity {
+            write!(
+                f,
+                " activity(last {}d: active={}, dormant={}, never_seen={})",
+                activity.window_days,
+                activity.active_in_window,
+                activity.dormant,
+                activity.never_seen
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl UserStatistics {
+    /// Statistics over any set of users relative to a fixed point in time, in a single pass
+    pub fn from_users_at<'a>(
+        users: impl IntoIterator<Item = &'a User>,
+        now: DateTime<Utc>,
+    ) -> UserStatistics {
+        let mut total = 0;
+        let mut active = 0;
+        let mut inactive = 0;
+        let mut pending = 0;
+        let mut suspended = 0;
+        let mut total_days: i64 = 0;
+
+        for user in users {
+            total += 1;
+            match user.status {
+                UserStatus::Active => active += 1,
+                UserStatus::Inactive => inactive += 1,
+                UserStatus::Pending => pending += 1,
+                UserStatus::Suspended => suspended += 1,
+            }
+            total_days += user.days_active_at(now);
+        }
+
+        let average_days_active = if total > 0 {
+            total_days as f64 / total as f64
+        } else {
+            0.0
+        };
+
+        UserStatistics {
+            total,
+            active,
+            inactive,
+            pending,
+            suspended,
+            average_days_active,
+            activity: None,
+        }
+    }
+
+    pub fn with_activity(mut self, activity: ActivityStatistics) -> Self {
+        self.activity = Some(activity);
+        self
+    }
+}
+
+/// `UserStatistics` accumulated by many tasks at once, e.g. the workers of a parallel
+/// ingestion pipeline. Recording is lock-free; `snapshot` retries a bounded number of times
+/// while a record is half applied, so it sees whole users unless recording never pauses.
+#[derive(Debug)]
+pub struct AtomicUserStatistics {
+    now: DateTime<Utc>,
+    // Records begun and finished; equal when no record is in progress
+    begun: AtomicUsize,
+    finished: AtomicUsize,
+    active: AtomicUsize,
+    inactive: AtomicUsize,
+    pending: AtomicUsize,
+    suspended: AtomicUsize,
+    total_days: AtomicI64,
+}
+
+impl Default for AtomicUserStatistics {
+    fn default() -> Self {
+        Self::at(Utc::now())
+    }
+}
+
+impl AtomicUserStatistics {
+    /// Consistent reads `snapshot` tries for before settling for a best-effort one
+    const SNAPSHOT_ATTEMPTS: usize = 10_000;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accumulate with days active measured up to `now`
+    pub fn at(now: DateTime<Utc>) -> Self {
+        Self {
+            now,
+            begun: AtomicUsize::new(0),
+            finished: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            inactive: AtomicUsize::new(0),
+            pending: AtomicUsize::new(0),
+            suspended: AtomicUsize::new(0),
+            total_days: AtomicI64::new(0),
+        }
+    }
+
+    pub fn record(&self, user: &User) {
+        let status = match user.status {
+            UserStatus::Active => &self.active,
+            UserStatus::Inactive => &self.inactive,
+            UserStatus::Pending => &self.pending,
+            UserStatus::Suspended => &self.suspended,
+        };
+        self.begun.fetch_add(1, Ordering::SeqCst);
+        status.fetch_add(1, Ordering::SeqCst);
+        self.total_days
+            .fetch_add(user.days_active_at(self.now), Ordering::SeqCst);
+        self.finished.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn record_all<'a>(&self, users: impl IntoIterator<Item = &'a User>) {
+        for user in users {
+            self.record(user);
+        }
+    }
+
+    /// Users recorded so far, counting any being recorded right now
+    pub fn len(&self) -> usize {
+        self.begun.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Totals over every user whose record finished, with none counted halfway. Under
+    /// recording that never pauses it gives up after `SNAPSHOT_ATTEMPTS` tries and returns
+    /// the totals as read, which may count a user's status without their days or vice versa.
+    pub fn snapshot(&self) -> UserStatistics {
+        for _ in 0..Self::SNAPSHOT_ATTEMPTS {
+            let finished = self.finished.load(Ordering::SeqCst);
+            if self.begun.load(Ordering::SeqCst) != finished {
+                std::hint::spin_loop();
+                continue;
+            }
+            let statistics = self.read_totals();
+            // A record that began after the first check has bumped `begun` by now
+            if self.begun.load(Ordering::SeqCst) == finished {
+                return statistics;
+            }
+        }
+        self.read_totals()
+    }
+
+    fn read_totals(&self) -> UserStatistics {
+        let active = self.active.load(Ordering::SeqCst);
+        let inactive = self.inactive.load(Ordering::SeqCst);
+        let pending = self.pending.load(Ordering::SeqCst);
+        let suspended = self.suspended.load(Ordering::SeqCst);
+        let total_days = self.total_days.load(Ordering::SeqCst);
+        let total = active + inactive + pending + suspended;
+        UserStatistics {
+            total,
+            active,
+            inactive,
+            pending,
+            suspended,
+            average_days_active: if total > 0 {
+                total_days as f64 / total as f64
+            } else {
+                0.0
+            },
+            activity: None,
+        }
+    }
+}
+
+// Log-scale latency histogram; percentiles are accurate to one bucket (~19%)
+#[cfg(feature = "client")]
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+// Percentile summary of one operation's latencies
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+#[cfg(feature = "client")]
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; Self::BUCKETS],
+            count: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl LatencyHistogram {
+    /// Four buckets per doubling from 1µs, topping out above 10 minutes
+    const BUCKETS: usize = 4 * 30;
+
+    fn bucket_for(latency: Duration) -> usize {
+        let micros = latency.as_micros().max(1) as f64;
+        ((micros.log2() * 4.0).ceil() as usize).min(Self::BUCKETS - 1)
+    }
+
+    fn upper_bound(bucket: usize) -> Duration {
+        Duration::from_micros(2f64.powf(bucket as f64 / 4.0).round() as u64)
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        self.buckets[Self::bucket_for(latency)] += 1;
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Latency at or below which `quantile` (0.0..=1.0) of samples fall
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, samples) in self.buckets.iter().enumerate() {
+            seen += samples;
+            if seen >= rank {
+                return Self::upper_bound(bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count,
+            // Divided as nanoseconds since `Duration` only divides by `u32`
+            mean: self
+                .total
+                .as_nanos()
+                .checked_div(u128::from(self.count))
+                .map_or(Duration::ZERO, |nanos| {
+                    u64::try_from(nanos).map_or(Duration::MAX, Duration::from_nanos)
+                }),
+            p50: self.percentile(0.50),
+            p90: self.percentile(0.90),
+            p99: self.percentile(0.99),
+            max: self.max,
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl UserManager {
+    /// Filter users by status
+    pub fn filter_users_by_status(users: &[User], status: UserStatus) -> Vec<&User> {
+        users
+            .iter()
+            .filter(|user| user.status == status)
+            .collect()
+    }
+
+    /// Get user statistics
+    pub fn get_user_statistics(users: &[User]) -> UserStatistics {
+        Self::get_user_statistics_at(users, Utc::now())
+    }
+
+    /// Get user statistics relative to a fixed point in time, in a single pass
+    pub fn get_user_statistics_at(users: &[User], now: DateTime<Utc>) -> UserStatistics {
+        UserStatistics::from_users_at(users, now)
+    }
+}
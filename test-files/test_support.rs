@@ -0,0 +1,479 @@
+use super::*;
+use std::collections::BTreeMap;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+// Stateful stand-in for the user API: `GET/POST /users`, `GET/PUT/DELETE /users/{id}` and
+// `POST /users:batchGet`
+pub struct MockUserApi {
+    server: MockServer,
+    users: Arc<Mutex<BTreeMap<String, User>>>,
+}
+
+impl MockUserApi {
+    /// Start a server holding `users`
+    pub async fn start(users: impl IntoIterator<Item = User>) -> Self {
+        let server = MockServer::start().await;
+        let users = Arc::new(Mutex::new(
+            users
+                .into_iter()
+                .map(|user| (user.id.clone(), user))
+                .collect(),
+        ));
+        Mock::given(wiremock::matchers::any())
+            .respond_with(UserApiResponder {
+                users: users.clone(),
+            })
+            .mount(&server)
+            .await;
+        Self { server, users }
+    }
+
+    pub fn uri(&self) -> String {
+        self.server.uri()
+    }
+
+    pub fn server(&self) -> &MockServer {
+        &self.server
+    }
+
+    /// Builder for a manager using the HTTP backend against this server
+    pub fn manager_builder(&self) -> UserManagerBuilder {
+        UserManager::builder(self.uri())
+    }
+
+    /// Manager using the HTTP backend against this server
+    pub fn manager(&self) -> UserManager {
+        self.manager_builder()
+            .build()
+            .expect("mock server URL is valid")
+    }
+
+    /// Current server-side state of a user
+    pub fn user(&self, user_id: &str) -> Option<User> {
+        self.users
+            .lock()
+            .expect("mock state poisoned")
+            .get(user_id)
+            .cloned()
+    }
+
+    /// Answer every request for `user_id` with `status` and an error body
+    pub async fn fail_user(&self, user_id: &str, status: u16) {
+        Mock::given(path(format!("/users/{}", user_id)))
+            .respond_with(error_response(status, "Injected failure"))
+            .with_priority(1)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Answer list requests with `status` and an error body
+    pub async fn fail_list(&self, status: u16) {
+        Mock::given(method("GET"))
+            .and(path("/users"))
+            .respond_with(error_response(status, "Injected failure"))
+            .with_priority(1)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Delay every response by `delay`, e.g. to exercise timeouts
+    pub async fn delay_all(&self, delay: Duration) {
+        Mock::given(wiremock::matchers::any())
+            .respond_with(DelayedResponder {
+                inner: UserApiResponder {
+                    users: self.users.clone(),
+                },
+                delay,
+            })
+            .with_priority(2)
+            .mount(&self.server)
+            .await;
+    }
+}
+
+// Failure injected by `FaultInjectingTransport`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Wait, then fail as a timed-out request
+    Timeout(Duration),
+    /// Answer with this status and an error body, without reaching the backend
+    ServerError(u16),
+    /// Answer 200 with a body that is not valid JSON
+    MalformedJson,
+    /// Delay the real request
+    Slow(Duration),
+    /// Fail as a dropped connection
+    ConnectionReset,
+}
+
+impl Fault {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Fault::Timeout(_) => "timeout",
+            Fault::ServerError(_) => "server_error",
+            Fault::MalformedJson => "malformed_json",
+            Fault::Slow(_) => "slow",
+            Fault::ConnectionReset => "connection_reset",
+        }
+    }
+}
+
+// Transport injecting faults at configured probabilities, reproducibly for a given seed
+#[derive(Debug)]
+pub struct FaultInjectingTransport {
+    inner: Arc<dyn HttpTransport>,
+    faults: Vec<(Fault, f64)>,
+    rng: Mutex<rand::rngs::StdRng>,
+    injected: Mutex<HashMap<&'static str, usize>>,
+}
+
+impl FaultInjectingTransport {
+    pub fn new(inner: Arc<dyn HttpTransport>, seed: u64) -> Self {
+        use rand::SeedableRng;
+        Self {
+            inner,
+            faults: Vec::new(),
+            rng: Mutex::new(rand::rngs::StdRng::seed_from_u64(seed)),
+            injected: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Inject `fault` on this fraction of requests; at most one fault fires per request
+    pub fn with_fault(mut self, fault: Fault, probability: f64) -> Self {
+        self.faults.push((fault, probability.clamp(0.0, 1.0)));
+        self
+    }
+
+    /// How often each fault has fired, by `Fault::label`
+    pub fn injected(&self) -> HashMap<&'static str, usize> {
+        self.injected
+            .lock()
+            .expect("fault counters poisoned")
+            .clone()
+    }
+
+    fn pick(&self) -> Option<Fault> {
+        use rand::Rng;
+        let roll: f64 = self.rng.lock().expect("fault rng poisoned").gen();
+        let mut threshold = 0.0;
+        for (fault, probability) in &self.faults {
+            threshold += probability;
+            if roll < threshold {
+                *self
+                    .injected
+                    .lock()
+                    .expect("fault counters poisoned")
+                    .entry(fault.label())
+                    .or_default() += 1;
+                return Some(fault.clone());
+            }
+        }
+        None
+    }
+}
+
+fn canned_response(status: u16, body: impl Into<reqwest::Body>) -> reqwest::Response {
+    http::Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(body.into())
+        .expect("valid canned response")
+        .into()
+}
+
+#[async_trait]
+impl HttpTransport for FaultInjectingTransport {
+    async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        match self.pick() {
+            None => self.inner.execute(request).await,
+            Some(Fault::Slow(delay)) => {
+                tokio::time::sleep(delay).await;
+                self.inner.execute(request).await
+            }
+            Some(Fault::Timeout(after)) => {
+                tokio::time::sleep(after).await;
+                Err(UserError::Unavailable {
+                    message: format!("injected timeout after {:?}", after),
+                }
+                .into())
+            }
+            Some(Fault::ConnectionReset) => Err(UserError::Unavailable {
+                message: "injected connection reset".to_string(),
+            }
+            .into()),
+            Some(Fault::ServerError(status)) => {
+                let body = serde_json::to_vec(&ApiResponse::<()>::error(
+                    "Injected server error".to_string(),
+                ))?;
+                Ok(canned_response(status, body))
+            }
+            Some(Fault::MalformedJson) => Ok(canned_response(200, "{\"success\": tru")),
+        }
+    }
+}
+
+// One recorded request/response pair; URLs keep only path and query so cassettes are host-independent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<String>,
+    pub status: u16,
+    pub response_body: String,
+}
+
+#[derive(Debug)]
+enum CassetteMode {
+    Record(Arc<dyn HttpTransport>),
+    Replay,
+}
+
+// VCR-style transport: records real traffic to a JSON file, or replays it without a network
+#[derive(Debug)]
+pub struct CassetteTransport {
+    path: PathBuf,
+    mode: CassetteMode,
+    interactions: Mutex<Vec<(Interaction, bool)>>,
+}
+
+impl CassetteTransport {
+    /// Pass requests to `inner`, saving every interaction to `path`
+    pub fn record(inner: Arc<dyn HttpTransport>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: CassetteMode::Record(inner),
+            interactions: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answer requests from the cassette at `path`; each interaction is used once, in order
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+        let interactions: Vec<Interaction> =
+            serde_json::from_str(&contents).context("Corrupt cassette")?;
+        Ok(Self {
+            path,
+            mode: CassetteMode::Replay,
+            interactions: Mutex::new(interactions.into_iter().map(|i| (i, false)).collect()),
+        })
+    }
+
+    /// Replay when the cassette exists, otherwise record; `CASSETTE_MODE=record` forces recording
+    pub fn auto(inner: Arc<dyn HttpTransport>, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let force = std::env::var("CASSETTE_MODE").is_ok_and(|mode| mode == "record");
+        if force || !path.exists() {
+            Ok(Self::record(inner, path))
+        } else {
+            Self::replay(path)
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.mode, CassetteMode::Record(_))
+    }
+
+    /// Interactions recorded so far, or loaded for replay
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.interactions
+            .lock()
+            .expect("cassette poisoned")
+            .iter()
+            .map(|(interaction, _)| interaction.clone())
+            .collect()
+    }
+
+    fn save(&self, interactions: &[(Interaction, bool)]) -> Result<()> {
+        let interactions: Vec<&Interaction> = interactions.iter().map(|(i, _)| i).collect();
+        let json =
+            serde_json::to_string_pretty(&interactions).context("Failed to serialize cassette")?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        std::fs::write(&self.path, json)
+            .with_context(|| format!("Failed to write cassette {}", self.path.display()))
+    }
+}
+
+fn request_key(request: &reqwest::Request) -> (String, String, Option<String>) {
+    let url = request.url();
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+    (request.method().to_string(), path, body)
+}
+
+#[async_trait]
+impl HttpTransport for CassetteTransport {
+    async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        let (method, path, request_body) = request_key(&request);
+        match &self.mode {
+            CassetteMode::Record(inner) => {
+                let response = inner.execute(request).await?;
+                let status = response.status().as_u16();
+                let response_body = response.text().await.context("Failed to read response")?;
+                let mut interactions = self.interactions.lock().expect("cassette poisoned");
+                interactions.push((
+                    Interaction {
+                        method,
+                        path,
+                        request_body,
+                        status,
+                        response_body: response_body.clone(),
+                    },
+                    true,
+                ));
+                self.save(&interactions)?;
+                Ok(canned_response(status, response_body))
+            }
+            CassetteMode::Replay => {
+                let mut interactions = self.interactions.lock().expect("cassette poisoned");
+                let (interaction, used) = interactions
+                    .iter_mut()
+                    .find(|(i, used)| {
+                        !*used
+                            && i.method == method
+                            && i.path == path
+                            && i.request_body == request_body
+                    })
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "No recorded interaction for {} {} in {}",
+                            method,
+                            path,
+                            self.path.display()
+                        )
+                    })?;
+                *used = true;
+                Ok(canned_response(
+                    interaction.status,
+                    interaction.response_body.clone(),
+                ))
+            }
+        }
+    }
+}
+
+fn error_response(status: u16, message: &str) -> ResponseTemplate {
+    ResponseTemplate::new(status).set_body_json(ApiResponse::<()>::error(message.to_string()))
+}
+
+struct DelayedResponder {
+    inner: UserApiResponder,
+    delay: Duration,
+}
+
+impl Respond for DelayedResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        self.inner.respond(request).set_delay(self.delay)
+    }
+}
+
+struct UserApiResponder {
+    users: Arc<Mutex<BTreeMap<String, User>>>,
+}
+
+impl Respond for UserApiResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let segments: Vec<&str> = request
+            .url
+            .path_segments()
+            .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        let mut users = self.users.lock().expect("mock state poisoned");
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["users"]) => {
+                let query: HashMap<_, _> = request.url.query_pairs().collect();
+                let param = |name: &str, default: usize| {
+                    query
+                        .get(name)
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or(default)
+                };
+                let filter = match query.get("filter").map(|filter| filter.parse::<Filter>()) {
+                    Some(Ok(filter)) => Some(filter),
+                    Some(Err(e)) => return error_response(400, &e.to_string()),
+                    None => None,
+                };
+                let matching: Vec<&User> = users
+                    .values()
+                    .filter(|user| filter.as_ref().is_none_or(|filter| filter.matches(user)))
+                    .collect();
+                let (offset, limit) = (param("offset", 0), param("limit", 100));
+                let page: Vec<User> = matching
+                    .iter()
+                    .skip(offset)
+                    .take(limit)
+                    .map(|user| (*user).clone())
+                    .collect();
+                let meta = ResponseMeta {
+                    page: (limit > 0).then(|| (offset / limit + 1) as u64),
+                    per_page: Some(limit as u64),
+                    total: Some(matching.len() as u64),
+                    ..Default::default()
+                };
+                ResponseTemplate::new(200).set_body_json(ApiResponse::success(page).with_meta(meta))
+            }
+            ("POST", ["users"]) => match serde_json::from_slice::<User>(&request.body) {
+                Ok(user) if users.contains_key(&user.id) => {
+                    error_response(409, "User already exists")
+                }
+                Ok(user) => {
+                    users.insert(user.id.clone(), user.clone());
+                    ResponseTemplate::new(201).set_body_json(ApiResponse::success(user))
+                }
+                Err(e) => error_response(400, &e.to_string()),
+            },
+            ("POST", ["users:batchGet"]) => {
+                #[derive(Deserialize)]
+                struct BatchGet {
+                    ids: Vec<String>,
+                }
+                match serde_json::from_slice::<BatchGet>(&request.body) {
+                    Ok(batch) => {
+                        let found: Vec<&User> =
+                            batch.ids.iter().filter_map(|id| users.get(id)).collect();
+                        ResponseTemplate::new(200).set_body_json(ApiResponse::success(found))
+                    }
+                    Err(e) => error_response(400, &e.to_string()),
+                }
+            }
+            ("GET", ["users", id]) | ("HEAD", ["users", id]) => match users.get(*id) {
+                Some(user) => ResponseTemplate::new(200).set_body_json(ApiResponse::success(user)),
+                None => error_response(404, "User not found"),
+            },
+            ("PUT", ["users", id]) => {
+                let updates: HashMap<String, serde_json::Value> =
+                    match serde_json::from_slice(&request.body) {
+                        Ok(updates) => updates,
+                        Err(e) => return error_response(400, &e.to_string()),
+                    };
+                match users.get_mut(*id) {
+                    Some(user) => match user.apply_updates(&updates) {
+                        Ok(()) => ResponseTemplate::new(200)
+                            .set_body_json(ApiResponse::success(user.clone())),
+                        Err(e) => error_response(422, &e.to_string()),
+                    },
+                    None => error_response(404, "User not found"),
+                }
+            }
+            ("POST", ["users", id, "erasure"]) if users.contains_key(*id) => {
+                ResponseTemplate::new(202).set_body_json(ApiResponse::success(()))
+            }
+            ("DELETE", ["users", id]) => match users.remove(*id) {
+                Some(_) => ResponseTemplate::new(204),
+                None => error_response(404, "User not found"),
+            },
+            _ => error_response(404, "No such endpoint"),
+        }
+    }
+}
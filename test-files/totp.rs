@@ -0,0 +1,402 @@
+use super::*;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+type HmacSha1 = Hmac<sha1::Sha1>;
+
+/// RFC 6238 time-based one-time passwords over HMAC-SHA1, the variant authenticator apps
+/// support. Codes from `skew` steps either side of the current one are also accepted.
+#[derive(Clone)]
+pub struct Totp {
+    secret: Secret<Vec<u8>>,
+    digits: u32,
+    period: u64,
+    skew: u64,
+}
+
+impl Totp {
+    pub const DEFAULT_DIGITS: u32 = 6;
+    pub const DEFAULT_PERIOD_SECS: u64 = 30;
+    const SECRET_BYTES: usize = 20;
+
+    /// Generator for a base32 secret (padding, spaces and case are ignored)
+    pub fn new(secret: &str) -> Result<Self> {
+        let normalized: String = secret
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        let secret = data_encoding::BASE32_NOPAD
+            .decode(normalized.as_bytes())
+            .ok()
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| UserError::InvalidConfig {
+                field: "totp_secret".to_string(),
+                message: "not a base32 secret".to_string(),
+            })?;
+        Ok(Self {
+            secret: Secret::new(secret),
+            digits: Self::DEFAULT_DIGITS,
+            period: Self::DEFAULT_PERIOD_SECS,
+            skew: 1,
+        })
+    }
+
+    /// A random 160-bit secret, base32 encoded
+    pub fn generate_secret() -> String {
+        let mut secret = [0u8; Self::SECRET_BYTES];
+        rand::thread_rng().fill_bytes(&mut secret);
+        data_encoding::BASE32_NOPAD.encode(&secret)
+    }
+
+    pub fn with_digits(mut self, digits: u32) -> Self {
+        self.digits = digits.clamp(6, 8);
+        self
+    }
+
+    pub fn with_skew(mut self, steps: u64) -> Self {
+        self.skew = steps;
+        self
+    }
+
+    /// Time step containing `at`
+    pub fn step_at(&self, at: DateTime<Utc>) -> u64 {
+        at.timestamp().max(0) as u64 / self.period
+    }
+
+    pub fn code_at(&self, at: DateTime<Utc>) -> String {
+        self.code_for_step(self.step_at(at))
+    }
+
+    /// The step a code matches near `at`, if it is valid then
+    pub fn verify_at(&self, code: &str, at: DateTime<Utc>) -> Option<u64> {
+        let code = code.trim();
+        let current = self.step_at(at);
+        (current.saturating_sub(self.skew)..=current + self.skew)
+            .find(|step| constant_time_eq(self.code_for_step(*step).as_bytes(), code.as_bytes()))
+    }
+
+    /// `otpauth://` URI for enrolling the secret in an authenticator app by QR code
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&digits={}&period={}",
+            percent_encode(issuer),
+            percent_encode(account),
+            data_encoding::BASE32_NOPAD.encode(self.secret.expose()),
+            percent_encode(issuer),
+            self.digits,
+            self.period
+        )
+    }
+
+    fn code_for_step(&self, step: u64) -> String {
+        let mut mac = HmacSha1::new_from_slice(self.secret.expose())
+            .expect("HMAC accepts keys of any length");
+        mac.update(&step.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(self.digits),
+            width = self.digits as usize
+        )
+    }
+}
+
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Totp")
+            .field("secret", &self.secret)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .field("skew", &self.skew)
+            .finish()
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// Single-use recovery codes shown to the user once, formatted `xxxxx-xxxxx`
+pub struct BackupCodes;
+
+impl BackupCodes {
+    pub const DEFAULT_COUNT: usize = 10;
+    const ALPHABET: &'static [u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+    /// `count` new codes, with the hashes to store
+    pub fn generate(count: usize) -> (Vec<String>, Vec<String>) {
+        let mut rng = rand::thread_rng();
+        let codes: Vec<String> = (0..count)
+            .map(|_| {
+                let mut code = String::with_capacity(11);
+                for i in 0..10 {
+                    if i == 5 {
+                        code.push('-');
+                    }
+                    let index = (rng.next_u32() as usize) % Self::ALPHABET.len();
+                    code.push(Self::ALPHABET[index] as char);
+                }
+                code
+            })
+            .collect();
+        let hashes = codes.iter().map(|code| Self::hash(code)).collect();
+        (codes, hashes)
+    }
+
+    /// Hex SHA-256 of a code, ignoring case, dashes and spaces
+    pub fn hash(code: &str) -> String {
+        let normalized: String = code
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        encode_hex(&Sha256::digest(normalized.as_bytes()))
+    }
+}
+
+// Codes handed out at enrollment; only shown once
+#[derive(Debug, Clone)]
+pub struct TwoFactorEnrollment {
+    pub secret: Secret<String>,
+    pub backup_codes: Vec<Secret<String>>,
+}
+
+impl TwoFactor {
+    /// A new enrollment with a fresh secret and `BackupCodes::DEFAULT_COUNT` backup codes
+    pub fn enroll(now: DateTime<Utc>) -> (Self, TwoFactorEnrollment) {
+        let secret = Totp::generate_secret();
+        let (codes, hashes) = BackupCodes::generate(BackupCodes::DEFAULT_COUNT);
+        let two_factor = TwoFactor {
+            totp_secret: secret.clone(),
+            enrolled_at: now,
+            backup_code_hashes: hashes,
+            last_used_step: None,
+            failed_attempts: 0,
+        };
+        let enrollment = TwoFactorEnrollment {
+            secret: Secret::new(secret),
+            backup_codes: codes.into_iter().map(Secret::new).collect(),
+        };
+        (two_factor, enrollment)
+    }
+
+    pub fn totp(&self) -> Result<Totp> {
+        Totp::new(&self.totp_secret)
+    }
+
+    /// Accept a TOTP code newer than the last one accepted, recording its step
+    pub fn verify_totp_at(&mut self, code: &str, at: DateTime<Utc>) -> Result<bool> {
+        let Some(step) = self.totp()?.verify_at(code, at) else {
+            return Ok(false);
+        };
+        if self.last_used_step.is_some_and(|last| step <= last) {
+            return Ok(false);
+        }
+        self.last_used_step = Some(step);
+        Ok(true)
+    }
+
+    /// Accept a backup code once, removing it
+    pub fn redeem_backup_code(&mut self, code: &str) -> bool {
+        let hash = BackupCodes::hash(code);
+        let before = self.backup_code_hashes.len();
+        self.backup_code_hashes
+            .retain(|stored| !constant_time_eq(stored.as_bytes(), hash.as_bytes()));
+        self.backup_code_hashes.len() < before
+    }
+
+    /// Replace every backup code, returning the new ones
+    pub fn regenerate_backup_codes(&mut self) -> Vec<Secret<String>> {
+        let (codes, hashes) = BackupCodes::generate(BackupCodes::DEFAULT_COUNT);
+        self.backup_code_hashes = hashes;
+        codes.into_iter().map(Secret::new).collect()
+    }
+}
+
+// Which factor a successful second-factor check used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecondFactor {
+    Totp,
+    BackupCode,
+}
+
+#[cfg(feature = "client")]
+impl UserManager {
+    /// Times a check or change retries when the enrollment changes under it
+    const TWO_FACTOR_SWAP_ATTEMPTS: usize = 3;
+
+    /// The user's enrollment read from its backend, not the cache, with the backend to
+    /// swap it in
+    async fn two_factor_of(
+        &self,
+        user_id: &str,
+    ) -> Result<(Arc<dyn UserRepository>, Option<TwoFactor>)> {
+        let repository = self.user_repository(self.tenant(), user_id).await?;
+        let user = repository
+            .get(user_id)
+            .await?
+            .ok_or_else(|| UserError::NotFound {
+                id: user_id.to_string(),
+            })?;
+        Ok((repository, user.two_factor.map(|two_factor| *two_factor)))
+    }
+
+    async fn enrolled(&self, user_id: &str) -> Result<(Arc<dyn UserRepository>, TwoFactor)> {
+        let (repository, two_factor) = self.two_factor_of(user_id).await?;
+        let two_factor = two_factor.ok_or_else(|| UserError::InvalidUpdate {
+            field: "two_factor".to_string(),
+            message: format!("user {} has not enrolled", redact_id(user_id)),
+        })?;
+        Ok((repository, two_factor))
+    }
+
+    /// Swap in `replacement` if the stored enrollment is still `expected`, dropping the
+    /// cached user on success
+    async fn swap_two_factor(
+        &self,
+        repository: &dyn UserRepository,
+        user_id: &str,
+        expected: Option<&TwoFactor>,
+        replacement: Option<&TwoFactor>,
+    ) -> Result<bool> {
+        let swapped = repository
+            .swap_two_factor(user_id, expected, replacement)
+            .await?;
+        if swapped {
+            self.invalidate_cached(self.tenant(), user_id).await;
+        }
+        Ok(swapped)
+    }
+
+    fn two_factor_contended() -> anyhow::Error {
+        UserError::Unavailable {
+            message: "two-factor enrollment changed concurrently; retry".to_string(),
+        }
+        .into()
+    }
+
+    /// Enroll a user in TOTP, replacing any earlier enrollment
+    pub async fn enroll_two_factor(&self, user_id: &str) -> Result<TwoFactorEnrollment> {
+        self.authorize(Permission::Update)?;
+        for _ in 0..Self::TWO_FACTOR_SWAP_ATTEMPTS {
+            let (repository, stored) = self.two_factor_of(user_id).await?;
+            let (two_factor, enrollment) = TwoFactor::enroll(Utc::now());
+            if self
+                .swap_two_factor(&*repository, user_id, stored.as_ref(), Some(&two_factor))
+                .await?
+            {
+                tracing::info!(user_id = %redact_id(user_id), "Two-factor enrolled");
+                return Ok(enrollment);
+            }
+        }
+        Err(Self::two_factor_contended())
+    }
+
+    /// Check a TOTP or backup code, persisting the replay guard or the used-up backup code.
+    /// `None` when the code is wrong. The check only counts if the enrollment it read is
+    /// still the stored one, so a code cannot be accepted twice by concurrent checks.
+    /// After `TwoFactor::MAX_FAILED_ATTEMPTS` wrong codes in a row every check is refused
+    /// until the user enrolls again.
+    pub async fn verify_second_factor(
+        &self,
+        user_id: &str,
+        code: &str,
+    ) -> Result<Option<SecondFactor>> {
+        self.authorize(Permission::Update)?;
+        for _ in 0..Self::TWO_FACTOR_SWAP_ATTEMPTS {
+            let (repository, stored) = self.enrolled(user_id).await?;
+            if stored.is_locked() {
+                tracing::warn!(user_id = %redact_id(user_id), "Second factor locked out");
+                return Err(UserError::Vetoed {
+                    operation: "second factor".to_string(),
+                    reason: "too many failed attempts".to_string(),
+                }
+                .into());
+            }
+            let mut two_factor = stored.clone();
+            let factor = if two_factor.verify_totp_at(code, Utc::now())? {
+                Some(SecondFactor::Totp)
+            } else if two_factor.redeem_backup_code(code) {
+                Some(SecondFactor::BackupCode)
+            } else {
+                None
+            };
+            two_factor.failed_attempts = match factor {
+                Some(_) => 0,
+                None => stored.failed_attempts + 1,
+            };
+            if self
+                .swap_two_factor(&*repository, user_id, Some(&stored), Some(&two_factor))
+                .await?
+            {
+                if factor.is_none() {
+                    tracing::warn!(
+                        user_id = %redact_id(user_id),
+                        failed_attempts = two_factor.failed_attempts,
+                        "Second factor rejected"
+                    );
+                }
+                return Ok(factor);
+            }
+        }
+        Err(Self::two_factor_contended())
+    }
+
+    /// Replace a user's backup codes, returning the new ones
+    pub async fn regenerate_backup_codes(&self, user_id: &str) -> Result<Vec<Secret<String>>> {
+        self.authorize(Permission::Update)?;
+        for _ in 0..Self::TWO_FACTOR_SWAP_ATTEMPTS {
+            let (repository, stored) = self.enrolled(user_id).await?;
+            let mut two_factor = stored.clone();
+            let codes = two_factor.regenerate_backup_codes();
+            if self
+                .swap_two_factor(&*repository, user_id, Some(&stored), Some(&two_factor))
+                .await?
+            {
+                return Ok(codes);
+            }
+        }
+        Err(Self::two_factor_contended())
+    }
+
+    /// Remove a user's enrollment, returning whether there was one
+    pub async fn disable_two_factor(&self, user_id: &str) -> Result<bool> {
+        self.authorize(Permission::Update)?;
+        for _ in 0..Self::TWO_FACTOR_SWAP_ATTEMPTS {
+            let (repository, stored) = self.two_factor_of(user_id).await?;
+            let Some(stored) = stored else {
+                return Ok(false);
+            };
+            if self
+                .swap_two_factor(&*repository, user_id, Some(&stored), None)
+                .await?
+            {
+                tracing::info!(user_id = %redact_id(user_id), "Two-factor disabled");
+                return Ok(true);
+            }
+        }
+        Err(Self::two_factor_contended())
+    }
+}
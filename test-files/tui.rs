@@ -0,0 +1,212 @@
+use super::*;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+
+const REFRESH_EVERY: Duration = Duration::from_secs(2);
+/// Users loaded into the table; search filters within them
+const TABLE_LIMIT: usize = 500;
+
+/// Dashboard state: `refresh` pulls from the manager, `handle_key` applies input
+#[derive(Debug, Default)]
+pub struct Dashboard {
+    users: UserCollection,
+    query: String,
+    editing: bool,
+    table: TableState,
+    snapshot: Option<DebugSnapshot>,
+    operations: Vec<RecordedOperation>,
+    error: Option<String>,
+}
+
+impl Dashboard {
+    pub async fn refresh(&mut self, manager: &UserManager) {
+        match manager.list_users(0, TABLE_LIMIT).await {
+            Ok(users) => {
+                self.users = users.into();
+                self.users
+                    .sort_by(&sort::by_status_priority().then(sort::by_name_locale_aware()));
+                self.error = None;
+            }
+            Err(e) => self.error = Some(redact_error(&e)),
+        }
+        self.snapshot = Some(manager.debug_snapshot().await);
+        self.operations = manager.recent_operations();
+        self.clamp_selection();
+    }
+
+    /// Users whose id, name or email contain the search query, ignoring case
+    pub fn visible_users(&self) -> Vec<&User> {
+        let query = self.query.to_lowercase();
+        self.users.filter(|user| {
+            query.is_empty()
+                || user.id.to_lowercase().contains(&query)
+                || user.name.to_lowercase().contains(&query)
+                || user.email.to_lowercase().contains(&query)
+        })
+    }
+
+    pub fn selected(&self) -> Option<&User> {
+        self.table
+            .selected()
+            .and_then(|index| self.visible_users().get(index).copied())
+    }
+
+    /// Apply a key press; returns false once the user asked to quit
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if key.kind != KeyEventKind::Press {
+            return true;
+        }
+        if self.editing {
+            match key.code {
+                KeyCode::Enter | KeyCode::Esc => self.editing = false,
+                KeyCode::Backspace => {
+                    self.query.pop();
+                }
+                KeyCode::Char(c) => self.query.push(c),
+                _ => {}
+            }
+        } else {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return false,
+                KeyCode::Char('/') => self.editing = true,
+                KeyCode::Down | KeyCode::Char('j') => self.table.select_next(),
+                KeyCode::Up | KeyCode::Char('k') => self.table.select_previous(),
+                _ => {}
+            }
+        }
+        self.clamp_selection();
+        true
+    }
+
+    fn clamp_selection(&mut self) {
+        let len = self.visible_users().len();
+        match self.table.selected() {
+            _ if len == 0 => self.table.select(None),
+            None => self.table.select(Some(0)),
+            Some(index) if index >= len => self.table.select(Some(len - 1)),
+            Some(_) => {}
+        }
+    }
+
+    pub fn render(&mut self, frame: &mut Frame) {
+        let [top, middle, footer] = Layout::vertical([
+            Constraint::Length(10),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [cache_area, operations_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)]).areas(top);
+
+        let mut cache = Vec::new();
+        if let Some(snapshot) = &self.snapshot {
+            cache.push(format!("Entries: {}", snapshot.cache.entries));
+            for (status, count) in &snapshot.cache.by_status {
+                cache.push(format!("  {}: {}", status, count));
+            }
+            if let Some(health) = &snapshot.health {
+                let state = if health.healthy {
+                    "healthy"
+                } else {
+                    "unhealthy"
+                };
+                cache.push(format!("Backend: {} ({:?})", state, health.latency));
+            }
+            cache.push(format!("Recent errors: {}", snapshot.recent_errors.len()));
+        }
+        frame.render_widget(
+            Paragraph::new(cache.join("\n")).block(Block::bordered().title("Cache")),
+            cache_area,
+        );
+
+        let operations: Vec<ListItem> = self
+            .operations
+            .iter()
+            .rev()
+            .map(|op| {
+                let item = ListItem::new(format!(
+                    "{} {:<20} {:>5} {:>10.1?}",
+                    op.at.format("%H:%M:%S"),
+                    op.operation,
+                    op.status,
+                    op.duration
+                ));
+                if op.status == "ok" {
+                    item
+                } else {
+                    item.red()
+                }
+            })
+            .collect();
+        frame.render_widget(
+            List::new(operations).block(Block::bordered().title("Recent operations")),
+            operations_area,
+        );
+
+        let rows: Vec<Row> = self
+            .visible_users()
+            .into_iter()
+            .map(|user| {
+                Row::new([
+                    user.id.clone(),
+                    user.status.to_string(),
+                    user.name.clone(),
+                    user.email.clone(),
+                ])
+            })
+            .collect();
+        let title = format!("Users ({})", rows.len());
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(12),
+                Constraint::Length(10),
+                Constraint::Percentage(40),
+                Constraint::Percentage(60),
+            ],
+        )
+        .header(Row::new(["ID", "Status", "Name", "Email"]).bold())
+        .block(Block::bordered().title(title))
+        .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table, middle, &mut self.table);
+
+        let help = if self.editing {
+            format!("Search: {}_", self.query)
+        } else if let Some(error) = &self.error {
+            format!("Error: {}", error)
+        } else {
+            format!("/ search  j/k move  q quit    filter: {:?}", self.query)
+        };
+        frame.render_widget(Paragraph::new(help), footer);
+    }
+}
+
+/// Run the dashboard until `q`, restoring the terminal afterwards
+pub async fn run(manager: &UserManager) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, manager).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(terminal: &mut ratatui::DefaultTerminal, manager: &UserManager) -> Result<()> {
+    let mut dashboard = Dashboard::default();
+    let mut refreshed: Option<Instant> = None;
+    loop {
+        if refreshed.is_none_or(|at| at.elapsed() >= REFRESH_EVERY) {
+            dashboard.refresh(manager).await;
+            refreshed = Some(Instant::now());
+        }
+        terminal.draw(|frame| dashboard.render(frame))?;
+        if event::poll(Duration::from_millis(250))? {
+            if let Event::Key(key) = event::read()? {
+                if !dashboard.handle_key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,8 @@
+use users::*;
+
+#[api_handler]
+fn ping() {}
+
+fn main() {
+    ping();
+}
@@ -0,0 +1,5 @@
+use users::*;
+
+fn main() {
+    let _ = create_user!("1", "Test User", "test@example.com", role = "admin");
+}
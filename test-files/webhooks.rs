@@ -0,0 +1,252 @@
+use super::*;
+use std::collections::VecDeque;
+
+// A URL receiving user events, optionally signed and limited to some event kinds
+#[derive(Debug, Clone)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    pub signer: Option<RequestSigner>,
+    /// `UserEvent::kind` names to deliver; `None` delivers every mutation event
+    pub events: Option<Vec<String>>,
+}
+
+impl WebhookEndpoint {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            signer: None,
+            events: None,
+        }
+    }
+
+    /// Sign each delivery with HMAC-SHA256 so the receiver can check where it came from
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    pub fn with_events<I, S>(mut self, kinds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.events = Some(kinds.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn wants(&self, event: &UserEvent) -> bool {
+        match &self.events {
+            Some(kinds) => kinds.iter().any(|kind| kind == event.kind()),
+            None => event.is_mutation(),
+        }
+    }
+}
+
+// Body POSTed to webhook endpoints
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WebhookDelivery {
+    /// Same across retries, so receivers can drop duplicates
+    pub id: String,
+    pub occurred_at: DateTime<Utc>,
+    pub event: UserEvent,
+}
+
+// A delivery given up on after its retries, kept for inspection and redelivery
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub url: String,
+    pub delivery: WebhookDelivery,
+    pub attempts: u32,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+// Posts user events to webhook endpoints, retrying transport failures, 429 and 5xx under
+// the retry policy. Other responses fail at once. Failed deliveries go to a bounded
+// dead-letter queue, oldest dropped first.
+#[derive(Debug)]
+pub struct WebhookDispatcher {
+    client: reqwest::Client,
+    transport: Arc<dyn HttpTransport>,
+    endpoints: Vec<WebhookEndpoint>,
+    retry_policy: RetryPolicy,
+    dead_letters: Mutex<VecDeque<DeadLetter>>,
+    dead_letter_capacity: usize,
+}
+
+impl WebhookDispatcher {
+    pub const ID_HEADER: &'static str = "X-Webhook-Id";
+    pub const EVENT_HEADER: &'static str = "X-Webhook-Event";
+    pub const DEFAULT_DEAD_LETTER_CAPACITY: usize = 1000;
+
+    pub fn new(client: reqwest::Client) -> Self {
+        Self {
+            transport: Arc::new(client.clone()),
+            client,
+            endpoints: Vec::new(),
+            retry_policy: RetryPolicy::new(3),
+            dead_letters: Mutex::new(VecDeque::new()),
+            dead_letter_capacity: Self::DEFAULT_DEAD_LETTER_CAPACITY,
+        }
+    }
+
+    pub fn with_endpoint(mut self, endpoint: WebhookEndpoint) -> Self {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Send requests through `transport` instead of the client directly
+    pub fn with_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn with_dead_letter_capacity(mut self, capacity: usize) -> Self {
+        self.dead_letter_capacity = capacity;
+        self
+    }
+
+    pub fn endpoints(&self) -> &[WebhookEndpoint] {
+        &self.endpoints
+    }
+
+    /// Deliver one event to every endpoint that wants it, returning how many accepted it
+    pub async fn dispatch(&self, event: &UserEvent) -> usize {
+        let delivery = WebhookDelivery {
+            id: uuid::Uuid::new_v4().to_string(),
+            occurred_at: Utc::now(),
+            event: event.clone(),
+        };
+        let deliveries = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.wants(event))
+            .map(|endpoint| self.deliver(endpoint, &delivery));
+        futures::future::join_all(deliveries)
+            .await
+            .into_iter()
+            .filter(|delivered| *delivered)
+            .count()
+    }
+
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.dead_letters
+            .lock()
+            .expect("dead letters poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Try every dead letter once more under the retry policy, returning how many went
+    /// through; the rest are queued again
+    pub async fn redeliver_dead_letters(&self) -> usize {
+        let letters =
+            std::mem::take(&mut *self.dead_letters.lock().expect("dead letters poisoned"));
+        let mut delivered = 0;
+        for letter in letters {
+            let endpoint = self
+                .endpoints
+                .iter()
+                .find(|endpoint| endpoint.url == letter.url)
+                .cloned()
+                .unwrap_or_else(|| WebhookEndpoint::new(letter.url.clone()));
+            if self.deliver(&endpoint, &letter.delivery).await {
+                delivered += 1;
+            }
+        }
+        delivered
+    }
+
+    async fn deliver(&self, endpoint: &WebhookEndpoint, delivery: &WebhookDelivery) -> bool {
+        let started = Instant::now();
+        let kind = delivery.event.kind();
+        let mut attempts = 0;
+        let error = loop {
+            attempts += 1;
+            let error = match self.attempt(endpoint, delivery).await {
+                Ok(()) => {
+                    metrics::counter!(metric_names::WEBHOOK_DELIVERIES, "event" => kind, "outcome" => "delivered")
+                        .increment(1);
+                    metrics::histogram!(metric_names::WEBHOOK_DURATION, "outcome" => "delivered")
+                        .record(started.elapsed().as_secs_f64());
+                    return true;
+                }
+                Err(error) => error,
+            };
+            let retryable = !matches!(
+                error.downcast_ref(),
+                Some(UserError::ApiError { .. } | UserError::InvalidConfig { .. })
+            );
+            if !retryable || attempts > self.retry_policy.max_retries {
+                break error;
+            }
+            metrics::counter!(metric_names::RETRIES, "reason" => "webhook").increment(1);
+            tracing::debug!(attempts, "Retrying webhook delivery");
+            tokio::time::sleep(self.retry_policy.backoff(attempts - 1)).await;
+        };
+
+        metrics::counter!(metric_names::WEBHOOK_DELIVERIES, "event" => kind, "outcome" => "dead_lettered")
+            .increment(1);
+        metrics::histogram!(metric_names::WEBHOOK_DURATION, "outcome" => "dead_lettered")
+            .record(started.elapsed().as_secs_f64());
+        tracing::warn!(
+            url = %redact(&endpoint.url),
+            delivery_id = %delivery.id,
+            attempts,
+            error = redact_error(&error),
+            "Webhook delivery failed; dead-lettering"
+        );
+        let mut dead_letters = self.dead_letters.lock().expect("dead letters poisoned");
+        if self.dead_letter_capacity == 0 {
+            return false;
+        }
+        if dead_letters.len() >= self.dead_letter_capacity {
+            dead_letters.pop_front();
+        }
+        dead_letters.push_back(DeadLetter {
+            url: endpoint.url.clone(),
+            delivery: delivery.clone(),
+            attempts,
+            error: redact_error(&error),
+            failed_at: Utc::now(),
+        });
+        false
+    }
+
+    /// One POST; transport failures, 429 and 5xx are retryable, other failures are `ApiError`
+    async fn attempt(&self, endpoint: &WebhookEndpoint, delivery: &WebhookDelivery) -> Result<()> {
+        let body = serde_json::to_vec(delivery).context("Failed to serialize webhook")?;
+        let mut request = self
+            .client
+            .post(&endpoint.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(Self::ID_HEADER, &delivery.id)
+            .header(Self::EVENT_HEADER, delivery.event.kind())
+            .body(body)
+            .build()
+            .map_err(|e| UserError::ApiError {
+                message: format!("Invalid webhook request: {}", e),
+            })?;
+        if let Some(signer) = &endpoint.signer {
+            signer.sign(&mut request, Utc::now())?;
+        }
+        let response = self.transport.execute(request).await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            anyhow::bail!("Webhook endpoint answered {}", status);
+        }
+        Err(UserError::ApiError {
+            message: format!("Webhook endpoint rejected delivery with {}", status),
+        }
+        .into())
+    }
+}